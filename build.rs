@@ -0,0 +1,4 @@
+// The migrations are embedded in the binary, so adding one must rebuild it
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
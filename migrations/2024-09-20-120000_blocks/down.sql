@@ -0,0 +1,5 @@
+-- down.sql
+
+-- Drop the blocks table and associated indexes
+DROP INDEX IF EXISTS idx_blocks_timestamp;
+DROP TABLE IF EXISTS blocks;
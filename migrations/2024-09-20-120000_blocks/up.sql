@@ -0,0 +1,11 @@
+-- up.sql
+-- Block headers for every block that carried a processed log (or every block when requested)
+CREATE TABLE blocks (
+    number INTEGER PRIMARY KEY,                 -- Block number
+    hash BYTEA NOT NULL,                        -- 32-byte block hash
+    parent_hash BYTEA NOT NULL,                 -- 32-byte parent block hash
+    timestamp BIGINT NOT NULL                   -- Unix timestamp (seconds) of the block
+);
+
+-- Index for time-range scans (e.g. daily series)
+CREATE INDEX idx_blocks_timestamp ON blocks (timestamp);
@@ -0,0 +1,31 @@
+-- up.sql
+-- Registered webhook endpoints and the changes they are notified about
+CREATE TABLE webhooks (
+    id SERIAL PRIMARY KEY,
+    url TEXT NOT NULL,                          -- Endpoint receiving POSTed JSON payloads
+    secret TEXT NOT NULL,                       -- HMAC-SHA256 key used to sign payloads
+    wallet_address BYTEA,                       -- Only changes involving this 20-byte wallet (NULL for any)
+    token_address BYTEA,                        -- Only changes of this 20-byte token (NULL for any)
+    event_kind VARCHAR(16),                     -- 'transfer', 'approval' or 'supply' (NULL for all)
+    min_amount TEXT,                            -- Minimum transfer value, allowance or supply change, as a decimal string
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- One row per payload sent to a webhook, updated after every attempt
+CREATE TABLE webhook_deliveries (
+    id SERIAL PRIMARY KEY,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event_kind VARCHAR(16) NOT NULL,
+    block_number INTEGER NOT NULL,              -- Block of the change that triggered the delivery
+    payload JSONB NOT NULL,                     -- Body as sent
+    status VARCHAR(16) NOT NULL,                -- 'pending', 'delivered' or 'failed'
+    attempts INTEGER NOT NULL DEFAULT 0,
+    response_status INTEGER,                    -- HTTP status of the last attempt, if a response was received
+    error TEXT,                                 -- Error of the last failed attempt
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP                      -- When the delivery succeeded or was given up on
+);
+
+-- Index for listing the delivery log of a webhook
+CREATE INDEX idx_webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id, id);
@@ -0,0 +1,6 @@
+-- down.sql
+
+-- Drop the contract_events table and associated indexes
+DROP INDEX IF EXISTS idx_contract_events_name;
+DROP INDEX IF EXISTS idx_contract_events_contract;
+DROP TABLE IF EXISTS contract_events;
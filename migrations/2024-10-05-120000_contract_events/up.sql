@@ -0,0 +1,19 @@
+-- up.sql
+-- Events of the contracts configured with an ABI, decoded generically
+CREATE TABLE contract_events (
+    id SERIAL PRIMARY KEY,
+    contract_address BYTEA NOT NULL,            -- 20-byte address of the emitting contract
+    contract_name TEXT NOT NULL,                -- Name given to the contract in the contracts file
+    event_name TEXT NOT NULL,                   -- e.g. 'Deposit'
+    event_signature TEXT NOT NULL,              -- Canonical signature, e.g. 'Deposit(address,uint256)'
+    params JSONB NOT NULL,                      -- Decoded parameters by name; integers as decimal strings
+    block_number INTEGER NOT NULL,
+    transaction_hash BYTEA NOT NULL,            -- 32-byte hash of the emitting transaction
+    transaction_index INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,                 -- Position of the log in its block
+    UNIQUE (transaction_hash, log_index)
+);
+
+-- Indexes for listing the events of a contract, or of one kind, by block
+CREATE INDEX idx_contract_events_contract ON contract_events (contract_address, block_number);
+CREATE INDEX idx_contract_events_name ON contract_events (event_name, block_number);
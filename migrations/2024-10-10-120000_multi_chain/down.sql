@@ -0,0 +1,56 @@
+-- down.sql
+
+-- Keep only Ethereum mainnet's rows and restore the single-chain keys
+DELETE FROM contract_events WHERE chain_id <> 1;
+DELETE FROM blocks WHERE chain_id <> 1;
+DELETE FROM balances WHERE chain_id <> 1;
+DELETE FROM token_supplies WHERE chain_id <> 1;
+DELETE FROM allowances WHERE chain_id <> 1;
+DELETE FROM token_ids WHERE chain_id <> 1;
+DELETE FROM tokens WHERE chain_id <> 1;
+
+DROP INDEX IF EXISTS idx_contract_events_name;
+CREATE INDEX idx_contract_events_name ON contract_events (event_name, block_number);
+DROP INDEX IF EXISTS idx_contract_events_contract;
+CREATE INDEX idx_contract_events_contract ON contract_events (contract_address, block_number);
+DROP INDEX IF EXISTS idx_blocks_timestamp;
+CREATE INDEX idx_blocks_timestamp ON blocks (timestamp);
+DROP INDEX IF EXISTS idx_balance_token;
+CREATE INDEX idx_balance_token ON balances (token_address);
+DROP INDEX IF EXISTS idx_balance_wallet;
+CREATE INDEX idx_balance_wallet ON balances (wallet_address);
+DROP INDEX IF EXISTS idx_token_supply_address;
+CREATE INDEX idx_token_supply_address ON token_supplies (token_address);
+DROP INDEX IF EXISTS idx_token_address;
+CREATE INDEX idx_token_address ON allowances (token_address);
+DROP INDEX IF EXISTS idx_spender_address;
+CREATE INDEX idx_spender_address ON allowances (spender_address);
+DROP INDEX IF EXISTS idx_owner_address;
+CREATE INDEX idx_owner_address ON allowances (owner_address);
+DROP INDEX IF EXISTS idx_token_ids_contract_address;
+CREATE INDEX idx_token_ids_contract_address ON token_ids (contract_address);
+
+ALTER TABLE contract_events DROP CONSTRAINT contract_events_chain_id_transaction_hash_log_index_key;
+ALTER TABLE contract_events ADD UNIQUE (transaction_hash, log_index);
+
+ALTER TABLE blocks DROP CONSTRAINT blocks_pkey;
+ALTER TABLE blocks ADD PRIMARY KEY (number);
+
+ALTER TABLE balances DROP CONSTRAINT balances_chain_id_token_address_fkey;
+ALTER TABLE token_supplies DROP CONSTRAINT token_supplies_chain_id_token_address_fkey;
+ALTER TABLE allowances DROP CONSTRAINT allowances_chain_id_token_address_fkey;
+ALTER TABLE token_ids DROP CONSTRAINT token_ids_chain_id_contract_address_fkey;
+ALTER TABLE tokens DROP CONSTRAINT tokens_pkey;
+ALTER TABLE tokens ADD PRIMARY KEY (token_address);
+ALTER TABLE token_ids ADD FOREIGN KEY (contract_address) REFERENCES tokens (token_address);
+ALTER TABLE allowances ADD FOREIGN KEY (token_address) REFERENCES tokens (token_address);
+ALTER TABLE token_supplies ADD FOREIGN KEY (token_address) REFERENCES tokens (token_address);
+ALTER TABLE balances ADD FOREIGN KEY (token_address) REFERENCES tokens (token_address);
+
+ALTER TABLE contract_events DROP COLUMN chain_id;
+ALTER TABLE blocks DROP COLUMN chain_id;
+ALTER TABLE balances DROP COLUMN chain_id;
+ALTER TABLE token_supplies DROP COLUMN chain_id;
+ALTER TABLE allowances DROP COLUMN chain_id;
+ALTER TABLE token_ids DROP COLUMN chain_id;
+ALTER TABLE tokens DROP COLUMN chain_id;
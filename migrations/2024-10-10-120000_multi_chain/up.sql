@@ -0,0 +1,58 @@
+-- up.sql
+-- Every indexed row belongs to the chain it was read from; rows indexed before are Ethereum mainnet's
+ALTER TABLE tokens ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE token_ids ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE allowances ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE token_supplies ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE balances ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE blocks ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE contract_events ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 1;
+
+-- New rows always name their chain
+ALTER TABLE tokens ALTER COLUMN chain_id DROP DEFAULT;
+ALTER TABLE token_ids ALTER COLUMN chain_id DROP DEFAULT;
+ALTER TABLE allowances ALTER COLUMN chain_id DROP DEFAULT;
+ALTER TABLE token_supplies ALTER COLUMN chain_id DROP DEFAULT;
+ALTER TABLE balances ALTER COLUMN chain_id DROP DEFAULT;
+ALTER TABLE blocks ALTER COLUMN chain_id DROP DEFAULT;
+ALTER TABLE contract_events ALTER COLUMN chain_id DROP DEFAULT;
+
+-- Addresses, block numbers and transaction hashes are only unique within a chain
+ALTER TABLE token_ids DROP CONSTRAINT token_ids_contract_address_fkey;
+ALTER TABLE allowances DROP CONSTRAINT allowances_token_address_fkey;
+ALTER TABLE token_supplies DROP CONSTRAINT token_supplies_token_address_fkey;
+ALTER TABLE balances DROP CONSTRAINT balances_token_address_fkey;
+ALTER TABLE tokens DROP CONSTRAINT tokens_pkey;
+ALTER TABLE tokens ADD PRIMARY KEY (chain_id, token_address);
+ALTER TABLE token_ids ADD FOREIGN KEY (chain_id, contract_address) REFERENCES tokens (chain_id, token_address);
+ALTER TABLE allowances ADD FOREIGN KEY (chain_id, token_address) REFERENCES tokens (chain_id, token_address);
+ALTER TABLE token_supplies ADD FOREIGN KEY (chain_id, token_address) REFERENCES tokens (chain_id, token_address);
+ALTER TABLE balances ADD FOREIGN KEY (chain_id, token_address) REFERENCES tokens (chain_id, token_address);
+
+ALTER TABLE blocks DROP CONSTRAINT blocks_pkey;
+ALTER TABLE blocks ADD PRIMARY KEY (chain_id, number);
+
+ALTER TABLE contract_events DROP CONSTRAINT contract_events_transaction_hash_log_index_key;
+ALTER TABLE contract_events ADD UNIQUE (chain_id, transaction_hash, log_index);
+
+-- Lookups by address or time are always within a chain
+DROP INDEX idx_token_ids_contract_address;
+CREATE INDEX idx_token_ids_contract_address ON token_ids (chain_id, contract_address);
+DROP INDEX idx_owner_address;
+CREATE INDEX idx_owner_address ON allowances (chain_id, owner_address);
+DROP INDEX idx_spender_address;
+CREATE INDEX idx_spender_address ON allowances (chain_id, spender_address);
+DROP INDEX idx_token_address;
+CREATE INDEX idx_token_address ON allowances (chain_id, token_address);
+DROP INDEX idx_token_supply_address;
+CREATE INDEX idx_token_supply_address ON token_supplies (chain_id, token_address);
+DROP INDEX idx_balance_wallet;
+CREATE INDEX idx_balance_wallet ON balances (chain_id, wallet_address);
+DROP INDEX idx_balance_token;
+CREATE INDEX idx_balance_token ON balances (chain_id, token_address);
+DROP INDEX idx_blocks_timestamp;
+CREATE INDEX idx_blocks_timestamp ON blocks (chain_id, timestamp);
+DROP INDEX idx_contract_events_contract;
+CREATE INDEX idx_contract_events_contract ON contract_events (chain_id, contract_address, block_number);
+DROP INDEX idx_contract_events_name;
+CREATE INDEX idx_contract_events_name ON contract_events (chain_id, event_name, block_number);
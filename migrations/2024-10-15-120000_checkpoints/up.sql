@@ -0,0 +1,9 @@
+-- up.sql
+-- The last block each stream (a handler's events, or one processor applied to its logs) is indexed up to
+CREATE TABLE checkpoints (
+    chain_id BIGINT NOT NULL,
+    handler TEXT NOT NULL,                      -- Handler name, e.g. 'ERC20', or '*' for the block header processors
+    processor TEXT NOT NULL,                    -- 'events' for the handler's own events, else the processor, e.g. 'balances'
+    block_number BIGINT NOT NULL,               -- Last indexed block
+    PRIMARY KEY (chain_id, handler, processor)
+);
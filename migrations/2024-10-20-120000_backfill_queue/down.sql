@@ -0,0 +1,4 @@
+-- down.sql
+DROP TABLE IF EXISTS backfill_changes;
+DROP INDEX IF EXISTS idx_backfill_items_chain_id;
+DROP TABLE IF EXISTS backfill_items;
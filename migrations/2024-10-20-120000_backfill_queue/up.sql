@@ -0,0 +1,32 @@
+-- Block ranges of a backfill, claimed by the scrapers working on the queue
+CREATE TABLE backfill_items (
+    id SERIAL PRIMARY KEY,
+    chain_id BIGINT NOT NULL,
+    from_block BIGINT NOT NULL,
+    to_block BIGINT NOT NULL,                   -- Last block of the range (inclusive)
+    status VARCHAR(16) NOT NULL DEFAULT 'pending', -- 'pending', 'running', 'done', 'merged' or 'failed'
+    worker TEXT,                                -- Scraper that claimed the range last
+    attempts INTEGER NOT NULL DEFAULT 0,        -- Claims so far; tags the changes staged by each
+    error TEXT,                                 -- Error of the last failed attempt
+    claimed_at TIMESTAMP,                       -- Last claim or progress report of the worker
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- Index for claiming and merging the ranges of a chain in block order
+CREATE INDEX idx_backfill_items_chain_id ON backfill_items (chain_id, status, from_block);
+
+-- Balance, allowance and supply changes decoded from a range, applied by the merge in block order
+CREATE TABLE backfill_changes (
+    item_id INTEGER NOT NULL REFERENCES backfill_items (id) ON DELETE CASCADE,
+    attempt INTEGER NOT NULL,                   -- Claim of the range that decoded the change
+    seq INTEGER NOT NULL,                       -- Position of the change in the range
+    kind VARCHAR(16) NOT NULL,                  -- 'balance', 'allowance' or 'supply'
+    token_address BYTEA NOT NULL,
+    wallet_address BYTEA,                       -- Holder of a balance, owner of an allowance
+    spender_address BYTEA,                      -- Spender of an allowance
+    token_id SMALLINT,
+    token_type VARCHAR(16),
+    amount TEXT NOT NULL,                       -- Signed delta, or the allowance
+    block_number INTEGER NOT NULL,
+    PRIMARY KEY (item_id, attempt, seq)
+);
@@ -0,0 +1,2 @@
+-- down.sql
+ALTER TABLE webhooks DROP COLUMN IF EXISTS chain_id;
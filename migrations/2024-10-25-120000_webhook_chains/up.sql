@@ -0,0 +1,3 @@
+-- up.sql
+-- Webhooks notified of the changes of a single chain, as the same address is often in use on several
+ALTER TABLE webhooks ADD COLUMN chain_id BIGINT;  -- Only changes on this chain (NULL for any)
@@ -0,0 +1,2 @@
+-- down.sql
+ALTER TABLE webhook_deliveries DROP COLUMN IF EXISTS attempted_at;
@@ -0,0 +1,4 @@
+-- up.sql
+-- When a delivery was last attempted, so pending deliveries left behind by a stopped scraper can be told
+-- apart from those being retried, and resumed
+ALTER TABLE webhook_deliveries ADD COLUMN attempted_at TIMESTAMP NOT NULL DEFAULT NOW();  -- Creation or last attempt
@@ -0,0 +1,260 @@
+//! Contract addresses whose logs are the only ones indexed (allowlist) or are never indexed
+//! (denylist), and wallets whose balances are the only ones indexed (watchlist). Lists are given in
+//! the config or in files of one address per line, which are read again whenever they change.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use ethers::types::Address;
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::events::IndexedEvent;
+
+pub type FilterResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Addresses of the allowlist, and wallets of the watchlist, per `eth_getLogs` request unless set
+/// with `address_limit`.
+pub const DEFAULT_ADDRESS_LIMIT: usize = 1000;
+
+/// The `addresses` table of a profile or chain.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AddressFilterConfig {
+    pub allowlist: Option<Vec<Address>>,  // Only the logs of these contracts are indexed
+    pub allowlist_file: Option<PathBuf>,  // Also allowed, one address per line; enables the allowlist
+    pub denylist: Vec<Address>,           // The logs of these contracts are skipped
+    pub denylist_file: Option<PathBuf>,
+    pub watchlist: Option<Vec<Address>>,  // Only the logs involving these wallets are indexed
+    pub watchlist_file: Option<PathBuf>,  // Also watched; enables the watchlist
+}
+
+impl AddressFilterConfig {
+    /// This config with its files resolved against `base_dir`.
+    pub fn relative_to(self, base_dir: &Path) -> AddressFilterConfig {
+        AddressFilterConfig {
+            allowlist_file: self.allowlist_file.map(|file| base_dir.join(file)),
+            denylist_file: self.denylist_file.map(|file| base_dir.join(file)),
+            watchlist_file: self.watchlist_file.map(|file| base_dir.join(file)),
+            ..self
+        }
+    }
+}
+
+/// The addresses a range is indexed with.
+#[derive(Clone, Debug, Default)]
+pub struct AddressLists {
+    pub allowed: Option<Arc<BTreeSet<Address>>>, // None when every address is allowed
+    pub denied: Arc<BTreeSet<Address>>,
+    pub watched: Option<Arc<BTreeSet<Address>>>, // None when every wallet is indexed
+}
+
+impl AddressLists {
+    /// Whether the logs of `address` are indexed.
+    pub fn accepts(&self, address: &Address) -> bool {
+        !self.denied.contains(address) && self.allowed.as_ref().is_none_or(|allowed| allowed.contains(address))
+    }
+
+    /// Whether `event` is written. With a watchlist, only the balances and allowances of watched
+    /// wallets are, and no total supply, since the logs of the other wallets are not fetched.
+    pub fn keeps(&self, event: &IndexedEvent) -> bool {
+        let Some(watched) = &self.watched else {
+            return true;
+        };
+        match event {
+            IndexedEvent::BalanceChange(change) => watched.contains(&change.wallet_address),
+            IndexedEvent::AllowanceChange(change) => watched.contains(&change.owner_address),
+            IndexedEvent::SupplyChange(_) => false,
+            IndexedEvent::Transfer(_) | IndexedEvent::ContractEvent(_) => true,
+        }
+    }
+}
+
+/// An allowlist, a denylist and a watchlist, each made of the addresses given inline and those of
+/// its file.
+#[derive(Default)]
+pub struct AddressFilter {
+    allowlist: Option<AddressList>,
+    denylist: AddressList,
+    watchlist: Option<AddressList>,
+}
+
+#[derive(Default)]
+struct AddressList {
+    inline: BTreeSet<Address>,
+    file: Option<PathBuf>,
+    loaded: Mutex<LoadedList>,
+}
+
+#[derive(Default)]
+struct LoadedList {
+    modified: Option<SystemTime>,         // Modification time of the file when it was read
+    addresses: Arc<BTreeSet<Address>>,    // Inline and file addresses
+}
+
+impl AddressFilter {
+    /// A filter letting every address through.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A filter with the lists of `config`, their files read once already.
+    pub fn from_config(config: &AddressFilterConfig) -> FilterResult<Self> {
+        let mut filter = AddressFilter::new().deny(config.denylist.iter().copied());
+        if let Some(allowlist) = &config.allowlist {
+            filter = filter.allow(allowlist.iter().copied());
+        }
+        if let Some(file) = &config.allowlist_file {
+            filter = filter.allowlist_file(file);
+        }
+        if let Some(file) = &config.denylist_file {
+            filter = filter.denylist_file(file);
+        }
+        if let Some(watchlist) = &config.watchlist {
+            filter = filter.watch(watchlist.iter().copied());
+        }
+        if let Some(file) = &config.watchlist_file {
+            filter = filter.watchlist_file(file);
+        }
+        filter.lists()?;
+        Ok(filter)
+    }
+
+    /// Only indexes the logs of `addresses`, and of those allowed by other calls.
+    pub fn allow(mut self, addresses: impl IntoIterator<Item = Address>) -> Self {
+        self.allowlist.get_or_insert_with(AddressList::default).inline.extend(addresses);
+        self
+    }
+
+    /// Only indexes the logs of the addresses listed in `path`, and of those allowed by other calls.
+    pub fn allowlist_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.allowlist.get_or_insert_with(AddressList::default).file = Some(path.into());
+        self
+    }
+
+    /// Skips the logs of `addresses`.
+    pub fn deny(mut self, addresses: impl IntoIterator<Item = Address>) -> Self {
+        self.denylist.inline.extend(addresses);
+        self
+    }
+
+    /// Skips the logs of the addresses listed in `path`.
+    pub fn denylist_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.denylist.file = Some(path.into());
+        self
+    }
+
+    /// Only indexes the logs involving the wallets `addresses`, and those watched by other calls.
+    pub fn watch(mut self, addresses: impl IntoIterator<Item = Address>) -> Self {
+        self.watchlist.get_or_insert_with(AddressList::default).inline.extend(addresses);
+        self
+    }
+
+    /// Only indexes the logs involving the wallets listed in `path`, and those watched by other calls.
+    pub fn watchlist_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.watchlist.get_or_insert_with(AddressList::default).file = Some(path.into());
+        self
+    }
+
+    /// The current lists, with the files that changed since they were last read read again. A file
+    /// that cannot be read fails the first time only; afterwards its previous addresses are kept.
+    pub fn lists(&self) -> FilterResult<AddressLists> {
+        let allowed = match &self.allowlist {
+            Some(allowlist) => Some(allowlist.addresses("allowlist")?),
+            None => None,
+        };
+        let watched = match &self.watchlist {
+            Some(watchlist) => Some(watchlist.addresses("watchlist")?),
+            None => None,
+        };
+        Ok(AddressLists { allowed, denied: self.denylist.addresses("denylist")?, watched })
+    }
+}
+
+impl AddressList {
+    fn addresses(&self, name: &str) -> FilterResult<Arc<BTreeSet<Address>>> {
+        let Some(file) = &self.file else {
+            return Ok(Arc::new(self.inline.clone()));
+        };
+        let mut loaded = self.loaded.lock().unwrap();
+
+        let first = loaded.modified.is_none();
+        let modified = match fs::metadata(file).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(e) if first => return Err(format!("Cannot read {} {}: {}", name, file.display(), e).into()),
+            Err(e) => {
+                warn!("Cannot read {} {}, keeping its previous addresses: {}", name, file.display(), e);
+                return Ok(loaded.addresses.clone());
+            }
+        };
+        if loaded.modified == Some(modified) {
+            return Ok(loaded.addresses.clone());
+        }
+
+        match read_addresses(file) {
+            Ok(addresses) => {
+                let mut all = self.inline.clone();
+                all.extend(addresses);
+                if !first {
+                    info!("Reloaded {} {}: {} addresses", name, file.display(), all.len());
+                }
+                *loaded = LoadedList { modified: Some(modified), addresses: Arc::new(all) };
+            }
+            Err(e) if first => return Err(format!("Invalid {} {}: {}", name, file.display(), e).into()),
+            Err(e) => {
+                warn!("Invalid {} {}, keeping its previous addresses: {}", name, file.display(), e);
+                loaded.modified = Some(modified);
+            }
+        }
+        Ok(loaded.addresses.clone())
+    }
+}
+
+// The addresses of a list file: one per line, `#` starting a comment
+fn read_addresses(path: &Path) -> FilterResult<Vec<Address>> {
+    let contents = fs::read_to_string(path)?;
+    let mut addresses = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let address = line.split('#').next().unwrap_or_default().trim();
+        if address.is_empty() {
+            continue;
+        }
+        let address = address.parse::<Address>().map_err(|e| format!("line {}: `{}` is not an address ({})", number + 1, address, e))?;
+        addresses.push(address);
+    }
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: &str = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+    const SECOND: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+
+    fn read(name: &str, contents: &str) -> FilterResult<Vec<Address>> {
+        let path = std::env::temp_dir().join(format!("address-filter-{}-{}.txt", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let addresses = read_addresses(&path);
+        fs::remove_file(&path).unwrap();
+        addresses
+    }
+
+    #[test]
+    fn read_addresses_skips_comments_and_blank_lines() {
+        let contents = format!("# Stablecoins\n\n{}\n  {}  # USDC\n#{}\n", FIRST, SECOND, FIRST);
+        let addresses = read("comments", &contents).unwrap();
+        assert_eq!(addresses, vec![FIRST.parse().unwrap(), SECOND.parse().unwrap()]);
+    }
+
+    #[test]
+    fn read_addresses_reports_the_invalid_line() {
+        let contents = format!("{}\n\n0x1234 # too short\n", FIRST);
+        let error = read("invalid", &contents).unwrap_err().to_string();
+        assert!(error.starts_with("line 3: `0x1234` is not an address"), "{}", error);
+    }
+}
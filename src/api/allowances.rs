@@ -0,0 +1,167 @@
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Bytea, Integer, Nullable, SmallInt};
+use serde::Serialize;
+
+use crate::api::{checksum, parse_address_bytes, with_conn, ApiError, ApiResult, ApiState, Page, PageParams, RowQuery};
+use crate::models::allowance::Allowance;
+use crate::PgPooledConnection;
+
+#[derive(Serialize)]
+pub struct AllowanceView {
+    pub chain_id: i64,
+    pub owner_address: String,
+    pub spender_address: String,
+    pub token_address: String,
+    pub token_id: Option<i16>,
+    pub token_type: String,
+    pub allowance: Option<String>,
+    pub block_number: i32,
+}
+
+impl From<Allowance> for AllowanceView {
+    fn from(row: Allowance) -> Self {
+        AllowanceView {
+            chain_id: row.chain_id,
+            owner_address: checksum(&row.owner_address),
+            spender_address: checksum(&row.spender_address),
+            token_address: checksum(&row.token_address),
+            token_id: row.token_id,
+            token_type: row.token_type,
+            allowance: row.allowance,
+            block_number: row.block_number,
+        }
+    }
+}
+
+/// Keyset position over `(counterparty, token_address, token_id)`.
+pub type AllowanceCursor = (Vec<u8>, Vec<u8>, Option<i16>);
+
+pub fn allowance_cursor(counterparty: &[u8], token: &[u8], token_id: Option<i16>) -> String {
+    match token_id {
+        Some(token_id) => format!("{}:{}:{}", checksum(counterparty), checksum(token), token_id),
+        None => format!("{}:{}", checksum(counterparty), checksum(token)),
+    }
+}
+
+pub fn parse_allowance_cursor(cursor: &str) -> Result<AllowanceCursor, ApiError> {
+    let parts: Vec<&str> = cursor.split(':').collect();
+    match parts.as_slice() {
+        [counterparty, token] => Ok((parse_address_bytes(counterparty)?, parse_address_bytes(token)?, None)),
+        [counterparty, token, token_id] => Ok((
+            parse_address_bytes(counterparty)?,
+            parse_address_bytes(token)?,
+            Some(token_id.parse().map_err(|_| ApiError::BadRequest(format!("Invalid cursor: {}", cursor)))?),
+        )),
+        _ => Err(ApiError::BadRequest(format!("Invalid cursor: {}", cursor))),
+    }
+}
+
+// Both loaders return the latest allowance per (counterparty, token, token_id) as of `at_block` of
+// each of `parties`, ordered by that key, in a single query (up to `limit` rows per party), and differ
+// only in which side of the approval the parties are on.
+macro_rules! latest_allowances_loader {
+    ($(#[$doc:meta])* $name:ident, $party:ident, $counterparty:ident) => {
+        $(#[$doc])*
+        pub fn $name(conn: &mut PgPooledConnection, parties: Vec<Vec<u8>>, query: RowQuery<AllowanceCursor>) -> QueryResult<Vec<Allowance>> {
+            let (after_party, after_token, after_id) = match query.after {
+                Some((party, token, id)) => (Some(party), Some(token), id),
+                None => (None, None, None),
+            };
+            // Rows without a token ID sort last within a token, so a cursor without an ID exhausts the token
+            diesel::sql_query(format!(
+                "SELECT page.* FROM unnest($1) AS parent(address) CROSS JOIN LATERAL (
+                     SELECT DISTINCT ON ({counterparty}, token_address, token_id) * FROM allowances
+                     WHERE chain_id = $2 AND {party} = parent.address
+                       AND ($3::bytea IS NULL OR token_address = $3)
+                       AND ($4::int4 IS NULL OR block_number <= $4)
+                       AND ($5::bytea IS NULL OR {counterparty} > $5 OR ({counterparty} = $5
+                            AND (token_address > $6::bytea
+                                 OR (token_address = $6 AND $7::int2 IS NOT NULL AND (token_id > $7 OR token_id IS NULL)))))
+                     ORDER BY {counterparty}, token_address, token_id, block_number DESC, id DESC
+                     LIMIT $8
+                 ) page
+                 ORDER BY page.{party}, page.{counterparty}, page.token_address, page.token_id",
+                party = stringify!($party),
+                counterparty = stringify!($counterparty),
+            ))
+            .bind::<Array<Bytea>, _>(parties)
+            .bind::<BigInt, _>(query.chain_id)
+            .bind::<Nullable<Bytea>, _>(query.token)
+            .bind::<Nullable<Integer>, _>(query.at_block)
+            .bind::<Nullable<Bytea>, _>(after_party)
+            .bind::<Nullable<Bytea>, _>(after_token)
+            .bind::<Nullable<SmallInt>, _>(after_id)
+            .bind::<BigInt, _>(query.limit)
+            .load(conn)
+        }
+    };
+}
+
+latest_allowances_loader!(
+    /// Loads the current allowances granted by each of `parties`, per spender and token.
+    load_owner_allowances, owner_address, spender_address
+);
+
+latest_allowances_loader!(
+    /// Loads the current allowances granted to each of `parties`, per owner and token.
+    load_spender_allowances, spender_address, owner_address
+);
+
+/// `GET /v1/owners/:address/allowances` — current allowances granted by an owner, per spender and token.
+pub async fn owner_allowances(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    Query(params): Query<PageParams>,
+) -> ApiResult<Page<AllowanceView>> {
+    let owner = parse_address_bytes(&address)?;
+    let query = params.row_query(state.chain_id, parse_allowance_cursor)?;
+    let limit = query.limit;
+
+    let rows = with_conn(&state, move |conn| Ok(load_owner_allowances(conn, vec![owner], query)?)).await?;
+
+    Ok(Json(Page::from_rows(
+        rows,
+        limit,
+        |row| allowance_cursor(&row.spender_address, &row.token_address, row.token_id),
+        |row| Some(row.into()),
+    )))
+}
+
+/// `GET /v1/spenders/:address/allowances` — current allowances granted to a spender, per owner and token.
+pub async fn spender_allowances(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    Query(params): Query<PageParams>,
+) -> ApiResult<Page<AllowanceView>> {
+    let spender = parse_address_bytes(&address)?;
+    let query = params.row_query(state.chain_id, parse_allowance_cursor)?;
+    let limit = query.limit;
+
+    let rows = with_conn(&state, move |conn| Ok(load_spender_allowances(conn, vec![spender], query)?)).await?;
+
+    Ok(Json(Page::from_rows(
+        rows,
+        limit,
+        |row| allowance_cursor(&row.owner_address, &row.token_address, row.token_id),
+        |row| Some(row.into()),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowance_cursors_round_trip() {
+        let counterparty = parse_address_bytes("0xdAC17F958D2ee523a2206206994597C13D831ec7").unwrap();
+        let token = parse_address_bytes("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        for token_id in [None, Some(7)] {
+            let cursor = allowance_cursor(&counterparty, &token, token_id);
+            assert_eq!(parse_allowance_cursor(&cursor).unwrap(), (counterparty.clone(), token.clone(), token_id));
+        }
+        assert!(parse_allowance_cursor("0xdAC17F958D2ee523a2206206994597C13D831ec7").is_err());
+        assert!(parse_allowance_cursor(&format!("{}:x", allowance_cursor(&counterparty, &token, None))).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::Arc;
+
+use async_graphql::dataloader::Loader;
+
+use crate::api::allowances::{load_owner_allowances, load_spender_allowances, AllowanceCursor};
+use crate::api::graphql::types::TokenObject;
+use crate::api::tokens::{load_latest_supplies, load_supply_history, load_token_ids, load_tokens_by_address};
+use crate::api::wallets::{load_balance_history, load_holders, load_holdings};
+use crate::api::{with_conn, ApiError, ApiState, RowQuery};
+use crate::models::allowance::Allowance;
+use crate::models::balance::Balance;
+use crate::models::token_supply::TokenSupply;
+use crate::models::TokenID;
+
+/// A token address on a chain.
+pub type TokenKey = (i64, Vec<u8>);
+
+// Addresses grouped by chain, so a batch costs one query per chain it spans
+fn by_chain(keys: &[TokenKey]) -> BTreeMap<i64, Vec<Vec<u8>>> {
+    let mut chains: BTreeMap<i64, Vec<Vec<u8>>> = BTreeMap::new();
+    for (chain, address) in keys {
+        chains.entry(*chain).or_default().push(address.clone());
+    }
+    chains
+}
+
+/// Batches `Token` lookups by address, so a list of balances resolves its tokens in one query.
+pub struct TokenLoader {
+    pub state: ApiState,
+}
+
+impl Loader<TokenKey> for TokenLoader {
+    type Value = TokenObject;
+    type Error = Arc<ApiError>;
+
+    async fn load(&self, keys: &[TokenKey]) -> Result<HashMap<TokenKey, Self::Value>, Self::Error> {
+        let mut tokens = HashMap::new();
+        for (chain, addresses) in by_chain(keys) {
+            let rows = with_conn(&self.state, move |conn| Ok(load_tokens_by_address(conn, chain, addresses)?))
+                .await
+                .map_err(Arc::new)?;
+            tokens.extend(rows.into_iter().map(|token| ((chain, token.token_address.clone()), token.into())));
+        }
+        Ok(tokens)
+    }
+}
+
+/// Batches latest total supply lookups by token address.
+pub struct LatestSupplyLoader {
+    pub state: ApiState,
+}
+
+impl Loader<TokenKey> for LatestSupplyLoader {
+    type Value = String;
+    type Error = Arc<ApiError>;
+
+    async fn load(&self, keys: &[TokenKey]) -> Result<HashMap<TokenKey, Self::Value>, Self::Error> {
+        let mut supplies = HashMap::new();
+        for (chain, addresses) in by_chain(keys) {
+            let rows = with_conn(&self.state, move |conn| Ok(load_latest_supplies(conn, chain, addresses)?))
+                .await
+                .map_err(Arc::new)?;
+            supplies.extend(rows.into_iter().map(|supply| ((chain, supply.token_address), supply.total_supply)));
+        }
+        Ok(supplies)
+    }
+}
+
+/// A page of a nested connection: the parent's address plus the validated paging arguments, which
+/// carry the chain.
+pub type PageKey<C> = (Vec<u8>, RowQuery<C>);
+
+// Parents grouped by the page they ask for, so a batch costs one query per distinct set of arguments
+fn by_page<C: Clone + Eq + Hash>(keys: &[PageKey<C>]) -> HashMap<RowQuery<C>, Vec<Vec<u8>>> {
+    let mut pages: HashMap<RowQuery<C>, Vec<Vec<u8>>> = HashMap::new();
+    for (parent, query) in keys {
+        pages.entry(query.clone()).or_default().push(parent.clone());
+    }
+    pages
+}
+
+// A loader batching one nested connection across its parents: `$load` takes every parent of a batch
+// and `$parent` is the row field the rows are split back by.
+macro_rules! page_loader {
+    ($(#[$doc:meta])* $name:ident, $cursor:ty, $row:ty, $load:path, $parent:ident) => {
+        $(#[$doc])*
+        pub struct $name {
+            pub state: ApiState,
+        }
+
+        impl Loader<PageKey<$cursor>> for $name {
+            type Value = Vec<$row>;
+            type Error = Arc<ApiError>;
+
+            async fn load(&self, keys: &[PageKey<$cursor>]) -> Result<HashMap<PageKey<$cursor>, Self::Value>, Self::Error> {
+                let mut pages = HashMap::new();
+                for (query, parents) in by_page(keys) {
+                    // Parents without rows still get their (empty) page
+                    let mut rows_of: HashMap<Vec<u8>, Vec<$row>> = parents.iter().map(|parent| (parent.clone(), Vec::new())).collect();
+                    let batch = query.clone();
+                    let rows = with_conn(&self.state, move |conn| Ok($load(conn, parents, batch)?)).await.map_err(Arc::new)?;
+                    for row in rows {
+                        rows_of.entry(row.$parent.clone()).or_default().push(row);
+                    }
+                    pages.extend(rows_of.into_iter().map(|(parent, rows)| ((parent, query.clone()), rows)));
+                }
+                Ok(pages)
+            }
+        }
+    };
+}
+
+page_loader!(
+    /// Batches `Token.holders` across tokens.
+    HoldersLoader, (Vec<u8>, Option<i16>), Balance, load_holders, token_address
+);
+
+page_loader!(
+    /// Batches `Token.supplyHistory` across tokens.
+    SupplyHistoryLoader, (i32, i32), TokenSupply, load_supply_history, token_address
+);
+
+page_loader!(
+    /// Batches `Token.tokenIds` across contracts.
+    TokenIdsLoader, i32, TokenID, load_token_ids, contract_address
+);
+
+page_loader!(
+    /// Batches `Wallet.balances` across wallets.
+    BalanceHistoryLoader, (i32, i32), Balance, load_balance_history, wallet_address
+);
+
+page_loader!(
+    /// Batches `Wallet.holdings` across wallets.
+    HoldingsLoader, (Vec<u8>, Option<i16>), Balance, load_holdings, wallet_address
+);
+
+page_loader!(
+    /// Batches `Wallet.allowances` across owners.
+    OwnerAllowancesLoader, AllowanceCursor, Allowance, load_owner_allowances, owner_address
+);
+
+page_loader!(
+    /// Batches `Wallet.receivedAllowances` across spenders.
+    SpenderAllowancesLoader, AllowanceCursor, Allowance, load_spender_allowances, spender_address
+);
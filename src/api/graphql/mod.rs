@@ -0,0 +1,188 @@
+mod loaders;
+mod types;
+
+use async_graphql::dataloader::DataLoader;
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{EmptyMutation, EmptySubscription, Schema};
+use axum::extract::State;
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+
+use crate::api::ApiState;
+use loaders::{
+    BalanceHistoryLoader, HoldersLoader, HoldingsLoader, LatestSupplyLoader, OwnerAllowancesLoader, SpenderAllowancesLoader, SupplyHistoryLoader,
+    TokenIdsLoader, TokenLoader,
+};
+use types::QueryRoot;
+
+// Deepest selection we accept, so nested token -> holders -> wallet -> ... queries stay bounded
+const MAX_QUERY_DEPTH: usize = 12;
+
+pub type ScraperSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Builds the GraphQL schema; the data loaders batch lookups across all resolvers of a request.
+pub fn build_schema(state: ApiState) -> ScraperSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(DataLoader::new(TokenLoader { state: state.clone() }, tokio::spawn))
+        .data(DataLoader::new(LatestSupplyLoader { state: state.clone() }, tokio::spawn))
+        .data(DataLoader::new(HoldersLoader { state: state.clone() }, tokio::spawn))
+        .data(DataLoader::new(SupplyHistoryLoader { state: state.clone() }, tokio::spawn))
+        .data(DataLoader::new(TokenIdsLoader { state: state.clone() }, tokio::spawn))
+        .data(DataLoader::new(BalanceHistoryLoader { state: state.clone() }, tokio::spawn))
+        .data(DataLoader::new(HoldingsLoader { state: state.clone() }, tokio::spawn))
+        .data(DataLoader::new(OwnerAllowancesLoader { state: state.clone() }, tokio::spawn))
+        .data(DataLoader::new(SpenderAllowancesLoader { state: state.clone() }, tokio::spawn))
+        .data(state)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .finish()
+}
+
+/// `POST /graphql` executes a query, `GET /graphql` serves the GraphiQL explorer.
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/graphql", get(graphiql).post(graphql_handler))
+        .with_state(build_schema(state))
+}
+
+async fn graphql_handler(State(schema): State<ScraperSchema>, Json(request): Json<async_graphql::Request>) -> Json<async_graphql::Response> {
+    Json(schema.execute(request).await)
+}
+
+async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use diesel::connection::{Instrumentation, InstrumentationEvent};
+    use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+    use diesel::{Connection, PgConnection};
+    use ethers::types::Address;
+    use serde_json::json;
+
+    use super::*;
+    use crate::events::{BalanceChange, TotalSupplyChange};
+    use crate::models::{NewToken, NewTokenID};
+    use crate::storage::{Storage, DEFAULT_CHAIN_ID};
+    use crate::testing::TestDatabase;
+
+    // Counts the statements run on every connection of a pool
+    #[derive(Debug)]
+    struct CountQueries(Arc<AtomicUsize>);
+
+    impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for CountQueries {
+        fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+            let queries = self.0.clone();
+            let counter = move |event: InstrumentationEvent<'_>| {
+                if matches!(event, InstrumentationEvent::StartQuery { .. }) {
+                    queries.fetch_add(1, Ordering::SeqCst);
+                }
+            };
+            conn.set_instrumentation(Box::new(counter) as Box<dyn Instrumentation>);
+            Ok(())
+        }
+    }
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    // Tokens 1 to 3, each held by wallets 11 to 13 and minted twice; token 3 also has two token IDs
+    fn seed(database: &TestDatabase) {
+        let storage = database.storage(DEFAULT_CHAIN_ID);
+        for token in 1..=3u64 {
+            let token_address = address(token);
+            let new_token = NewToken {
+                token_address: token_address.as_bytes(),
+                block_number: 1,
+                token_type: "ERC20",
+                name: None,
+                symbol: None,
+                decimals: Some(18),
+                granularity: None,
+            };
+            storage.insert_token(&new_token).unwrap();
+            for wallet in 11..=13u64 {
+                let change = BalanceChange {
+                    wallet_address: address(wallet),
+                    token_address,
+                    delta: "100".to_string(),
+                    token_id: None,
+                    token_type: "ERC20",
+                    block_number: (token * 100 + wallet) as i32,
+                    chain_id: DEFAULT_CHAIN_ID as u64,
+                };
+                storage.apply_balance_change(&change).unwrap();
+            }
+            for block_number in [10, 20] {
+                let change = TotalSupplyChange {
+                    token_address,
+                    delta: "150".to_string(),
+                    block_number,
+                    chain_id: DEFAULT_CHAIN_ID as u64,
+                };
+                storage.apply_supply_change(&change).unwrap();
+            }
+        }
+        let contract = address(3);
+        for token_id in [1, 2] {
+            let new_token_id = NewTokenID { contract_address: contract.as_bytes(), token_id, token_uri: None };
+            storage.insert_token_id(&new_token_id).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn nested_connections_are_loaded_once_per_level() {
+        let Some(database) = TestDatabase::create("graphql_batching") else { return };
+        seed(&database);
+        let queries = Arc::new(AtomicUsize::new(0));
+        let pool = Pool::builder()
+            .max_size(8)
+            .test_on_check_out(false)
+            .connection_customizer(Box::new(CountQueries(queries.clone())))
+            .build(ConnectionManager::<PgConnection>::new(database.url()))
+            .unwrap();
+        let schema = build_schema(ApiState { pool, chain_id: DEFAULT_CHAIN_ID });
+
+        let response = schema
+            .execute(
+                "{ tokens(first: 3) { nodes {
+                     holders(first: 2) { nodes { wallet {
+                         balances { nodes { blockNumber } }
+                         holdings(first: 2) { nodes { tokenAddress } }
+                         allowances { nodes { allowance } }
+                         receivedAllowances { nodes { allowance } }
+                     } } nextCursor }
+                     supplyHistory { nodes { totalSupply } }
+                     tokenIds { nodes { tokenId } }
+                } } }",
+            )
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        // tokens, then holders, supply history and token IDs of all tokens, then each connection of all holders
+        assert_eq!(queries.load(Ordering::SeqCst), 8);
+        let data = response.data.into_json().unwrap();
+        let tokens = data["tokens"]["nodes"].as_array().unwrap();
+        assert_eq!(tokens.len(), 3);
+        for token in tokens {
+            let holders = token["holders"]["nodes"].as_array().unwrap();
+            assert_eq!(holders.len(), 2);
+            assert!(token["holders"]["nextCursor"].is_string());
+            for holder in holders {
+                let blocks: Vec<i64> = holder["wallet"]["balances"]["nodes"].as_array().unwrap().iter().map(|row| row["blockNumber"].as_i64().unwrap()).collect();
+                assert_eq!(blocks.len(), 3);
+                assert!(blocks.windows(2).all(|pair| pair[0] < pair[1]));
+                assert_eq!(holder["wallet"]["holdings"]["nodes"].as_array().unwrap().len(), 2);
+                assert!(holder["wallet"]["allowances"]["nodes"].as_array().unwrap().is_empty());
+            }
+            assert_eq!(token["supplyHistory"]["nodes"], json!([{ "totalSupply": "150" }, { "totalSupply": "300" }]));
+        }
+        assert!(tokens[0]["tokenIds"]["nodes"].as_array().unwrap().is_empty());
+        assert_eq!(tokens[2]["tokenIds"]["nodes"], json!([{ "tokenId": 1 }, { "tokenId": 2 }]));
+    }
+}
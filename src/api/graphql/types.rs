@@ -0,0 +1,383 @@
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, Object, OutputType, Result, SimpleObject};
+
+use crate::api::allowances::{allowance_cursor, parse_allowance_cursor};
+use crate::api::graphql::loaders::{
+    BalanceHistoryLoader, HoldersLoader, HoldingsLoader, LatestSupplyLoader, OwnerAllowancesLoader, SpenderAllowancesLoader, SupplyHistoryLoader,
+    TokenIdsLoader, TokenLoader,
+};
+use crate::api::tokens::load_tokens;
+use crate::api::{
+    block_cursor, checksum, parse_address_bytes, parse_block_cursor, parse_id_cursor, parse_token_cursor, token_cursor, with_conn, ApiError,
+    ApiState, Page, PageParams, RowQuery,
+};
+use crate::models::allowance::Allowance;
+use crate::models::balance::Balance;
+use crate::models::token_supply::TokenSupply;
+use crate::models::{Token, TokenID};
+
+/// A page of nodes plus the cursor to pass as `after` for the next page.
+#[derive(SimpleObject)]
+#[graphql(concrete(name = "TokenPage", params(TokenObject)))]
+#[graphql(concrete(name = "BalancePage", params(BalanceObject)))]
+#[graphql(concrete(name = "AllowancePage", params(AllowanceObject)))]
+#[graphql(concrete(name = "SupplyPage", params(SupplyObject)))]
+#[graphql(concrete(name = "TokenIdPage", params(TokenIdObject)))]
+pub struct Connection<T: OutputType> {
+    pub nodes: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T: OutputType> From<Page<T>> for Connection<T> {
+    fn from(page: Page<T>) -> Self {
+        Connection {
+            nodes: page.data,
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
+/// Validates GraphQL paging arguments the same way the REST layer validates query parameters.
+fn row_query<C>(
+    chain_id: i64,
+    first: Option<i32>,
+    after: Option<String>,
+    at_block: Option<i32>,
+    token: Option<String>,
+    parse_cursor: impl Fn(&str) -> std::result::Result<C, ApiError>,
+) -> std::result::Result<RowQuery<C>, ApiError> {
+    PageParams { cursor: after, limit: first.map(i64::from), at_block, token, chain_id: None }.row_query(chain_id, parse_cursor)
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(name = "Token", complex)]
+pub struct TokenObject {
+    pub chain_id: i64,
+    pub address: String,
+    pub block_number: i32,
+    pub token_type: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<i16>,
+    pub granularity: Option<String>,
+    #[graphql(skip)]
+    pub raw_address: Vec<u8>,
+}
+
+impl From<Token> for TokenObject {
+    fn from(token: Token) -> Self {
+        TokenObject {
+            chain_id: token.chain_id,
+            address: checksum(&token.token_address),
+            block_number: token.block_number,
+            token_type: token.token_type,
+            name: token.name,
+            symbol: token.symbol,
+            decimals: token.decimals,
+            granularity: token.granularity,
+            raw_address: token.token_address,
+        }
+    }
+}
+
+#[ComplexObject]
+impl TokenObject {
+    /// The most recent total supply recorded for the token.
+    async fn total_supply(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        Ok(ctx.data_unchecked::<DataLoader<LatestSupplyLoader>>().load_one((self.chain_id, self.raw_address.clone())).await?)
+    }
+
+    /// The latest non-zero balance per holder (and token ID), as of `at_block` when given.
+    async fn holders(
+        &self,
+        ctx: &Context<'_>,
+        at_block: Option<i32>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<BalanceObject>> {
+        let query = row_query(self.chain_id, first, after, at_block, None, parse_token_cursor)?;
+        let limit = query.limit;
+        let rows = ctx.data_unchecked::<DataLoader<HoldersLoader>>().load_one((self.raw_address.clone(), query)).await?.unwrap_or_default();
+
+        Ok(Page::from_rows(rows, limit, |row| token_cursor(&row.wallet_address, row.token_id), |row| {
+            (row.balance != "0").then(|| row.into())
+        })
+        .into())
+    }
+
+    /// Total supply history, in block order.
+    async fn supply_history(
+        &self,
+        ctx: &Context<'_>,
+        at_block: Option<i32>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<SupplyObject>> {
+        let query = row_query(self.chain_id, first, after, at_block, None, parse_block_cursor)?;
+        let limit = query.limit;
+        let rows = ctx.data_unchecked::<DataLoader<SupplyHistoryLoader>>().load_one((self.raw_address.clone(), query)).await?.unwrap_or_default();
+
+        Ok(Page::from_rows(rows, limit, |supply| block_cursor(supply.block_number, supply.id), |supply| Some(supply.into())).into())
+    }
+
+    /// ERC721/1155 token IDs and their URIs.
+    async fn token_ids(&self, ctx: &Context<'_>, first: Option<i32>, after: Option<String>) -> Result<Connection<TokenIdObject>> {
+        let query = row_query(self.chain_id, first, after, None, None, parse_id_cursor)?;
+        let limit = query.limit;
+        let rows = ctx.data_unchecked::<DataLoader<TokenIdsLoader>>().load_one((self.raw_address.clone(), query)).await?.unwrap_or_default();
+
+        Ok(Page::from_rows(rows, limit, |row| row.id.to_string(), |row| Some(row.into())).into())
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Balance", complex)]
+pub struct BalanceObject {
+    pub chain_id: i64,
+    pub wallet_address: String,
+    pub token_address: String,
+    pub token_id: Option<i16>,
+    pub token_type: String,
+    pub balance: String,
+    pub block_number: i32,
+    #[graphql(skip)]
+    pub raw_wallet: Vec<u8>,
+    #[graphql(skip)]
+    pub raw_token: Vec<u8>,
+}
+
+impl From<Balance> for BalanceObject {
+    fn from(row: Balance) -> Self {
+        BalanceObject {
+            chain_id: row.chain_id,
+            wallet_address: checksum(&row.wallet_address),
+            token_address: checksum(&row.token_address),
+            token_id: row.token_id,
+            token_type: row.token_type,
+            balance: row.balance,
+            block_number: row.block_number,
+            raw_wallet: row.wallet_address,
+            raw_token: row.token_address,
+        }
+    }
+}
+
+#[ComplexObject]
+impl BalanceObject {
+    /// Metadata of the token this balance is held in.
+    async fn token(&self, ctx: &Context<'_>) -> Result<Option<TokenObject>> {
+        Ok(ctx.data_unchecked::<DataLoader<TokenLoader>>().load_one((self.chain_id, self.raw_token.clone())).await?)
+    }
+
+    /// The wallet holding this balance.
+    async fn wallet(&self) -> WalletObject {
+        WalletObject { chain_id: self.chain_id, raw_address: self.raw_wallet.clone() }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Allowance", complex)]
+pub struct AllowanceObject {
+    pub chain_id: i64,
+    pub owner_address: String,
+    pub spender_address: String,
+    pub token_address: String,
+    pub token_id: Option<i16>,
+    pub token_type: String,
+    pub allowance: Option<String>,
+    pub block_number: i32,
+    #[graphql(skip)]
+    pub raw_token: Vec<u8>,
+}
+
+impl From<Allowance> for AllowanceObject {
+    fn from(row: Allowance) -> Self {
+        AllowanceObject {
+            chain_id: row.chain_id,
+            owner_address: checksum(&row.owner_address),
+            spender_address: checksum(&row.spender_address),
+            token_address: checksum(&row.token_address),
+            token_id: row.token_id,
+            token_type: row.token_type,
+            allowance: row.allowance,
+            block_number: row.block_number,
+            raw_token: row.token_address,
+        }
+    }
+}
+
+#[ComplexObject]
+impl AllowanceObject {
+    /// Metadata of the approved token.
+    async fn token(&self, ctx: &Context<'_>) -> Result<Option<TokenObject>> {
+        Ok(ctx.data_unchecked::<DataLoader<TokenLoader>>().load_one((self.chain_id, self.raw_token.clone())).await?)
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "TokenSupply")]
+pub struct SupplyObject {
+    pub chain_id: i64,
+    pub token_address: String,
+    pub total_supply: String,
+    pub block_number: i32,
+}
+
+impl From<TokenSupply> for SupplyObject {
+    fn from(supply: TokenSupply) -> Self {
+        SupplyObject {
+            chain_id: supply.chain_id,
+            token_address: checksum(&supply.token_address),
+            total_supply: supply.total_supply,
+            block_number: supply.block_number,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "TokenId")]
+pub struct TokenIdObject {
+    pub chain_id: i64,
+    pub contract_address: String,
+    pub token_id: i16,
+    pub token_uri: Option<String>,
+}
+
+impl From<TokenID> for TokenIdObject {
+    fn from(row: TokenID) -> Self {
+        TokenIdObject {
+            chain_id: row.chain_id,
+            contract_address: checksum(&row.contract_address),
+            token_id: row.token_id,
+            token_uri: row.token_uri,
+        }
+    }
+}
+
+pub struct WalletObject {
+    pub chain_id: i64,
+    pub raw_address: Vec<u8>,
+}
+
+#[Object(name = "Wallet")]
+impl WalletObject {
+    async fn chain_id(&self) -> i64 {
+        self.chain_id
+    }
+
+    async fn address(&self) -> String {
+        checksum(&self.raw_address)
+    }
+
+    /// Every historical balance row of the wallet, in block order.
+    async fn balances(
+        &self,
+        ctx: &Context<'_>,
+        token: Option<String>,
+        at_block: Option<i32>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<BalanceObject>> {
+        let query = row_query(self.chain_id, first, after, at_block, token, parse_block_cursor)?;
+        let limit = query.limit;
+        let rows = ctx.data_unchecked::<DataLoader<BalanceHistoryLoader>>().load_one((self.raw_address.clone(), query)).await?.unwrap_or_default();
+
+        Ok(Page::from_rows(rows, limit, |row| block_cursor(row.block_number, row.id), |row| Some(row.into())).into())
+    }
+
+    /// The latest non-zero balance per token (and token ID), as of `at_block` when given.
+    async fn holdings(
+        &self,
+        ctx: &Context<'_>,
+        token: Option<String>,
+        at_block: Option<i32>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<BalanceObject>> {
+        let query = row_query(self.chain_id, first, after, at_block, token, parse_token_cursor)?;
+        let limit = query.limit;
+        let rows = ctx.data_unchecked::<DataLoader<HoldingsLoader>>().load_one((self.raw_address.clone(), query)).await?.unwrap_or_default();
+
+        Ok(Page::from_rows(rows, limit, |row| token_cursor(&row.token_address, row.token_id), |row| {
+            (row.balance != "0").then(|| row.into())
+        })
+        .into())
+    }
+
+    /// Current allowances granted by the wallet, per spender and token.
+    async fn allowances(
+        &self,
+        ctx: &Context<'_>,
+        token: Option<String>,
+        at_block: Option<i32>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<AllowanceObject>> {
+        let query = row_query(self.chain_id, first, after, at_block, token, parse_allowance_cursor)?;
+        let limit = query.limit;
+        let rows = ctx.data_unchecked::<DataLoader<OwnerAllowancesLoader>>().load_one((self.raw_address.clone(), query)).await?.unwrap_or_default();
+
+        Ok(Page::from_rows(rows, limit, |row| allowance_cursor(&row.spender_address, &row.token_address, row.token_id), |row| {
+            Some(row.into())
+        })
+        .into())
+    }
+
+    /// Current allowances granted to the wallet as a spender, per owner and token.
+    async fn received_allowances(
+        &self,
+        ctx: &Context<'_>,
+        token: Option<String>,
+        at_block: Option<i32>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<AllowanceObject>> {
+        let query = row_query(self.chain_id, first, after, at_block, token, parse_allowance_cursor)?;
+        let limit = query.limit;
+        let rows = ctx.data_unchecked::<DataLoader<SpenderAllowancesLoader>>().load_one((self.raw_address.clone(), query)).await?.unwrap_or_default();
+
+        Ok(Page::from_rows(rows, limit, |row| allowance_cursor(&row.owner_address, &row.token_address, row.token_id), |row| {
+            Some(row.into())
+        })
+        .into())
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A single token by address, on `chain_id` or the server's chain.
+    async fn token(&self, ctx: &Context<'_>, address: String, chain_id: Option<i64>) -> Result<Option<TokenObject>> {
+        let address_bytes = parse_address_bytes(&address)?;
+        let chain_id = chain_id.unwrap_or(ctx.data_unchecked::<ApiState>().chain_id);
+        Ok(ctx.data_unchecked::<DataLoader<TokenLoader>>().load_one((chain_id, address_bytes)).await?)
+    }
+
+    /// All indexed tokens of `chain_id` or the server's chain, ordered by address.
+    async fn tokens(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<i64>,
+        at_block: Option<i32>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<TokenObject>> {
+        let chain_id = chain_id.unwrap_or(ctx.data_unchecked::<ApiState>().chain_id);
+        let query = row_query(chain_id, first, after, at_block, None, parse_address_bytes)?;
+        let limit = query.limit;
+
+        let rows = with_conn(ctx.data_unchecked::<ApiState>(), move |conn| Ok(load_tokens(conn, query)?)).await?;
+
+        Ok(Page::from_rows(rows, limit, |token| checksum(&token.token_address), |token| Some(token.into())).into())
+    }
+
+    /// A wallet by address on `chain_id` or the server's chain; its balances and allowances are
+    /// resolved lazily.
+    async fn wallet(&self, ctx: &Context<'_>, address: String, chain_id: Option<i64>) -> Result<WalletObject> {
+        Ok(WalletObject {
+            chain_id: chain_id.unwrap_or(ctx.data_unchecked::<ApiState>().chain_id),
+            raw_address: parse_address_bytes(&address)?,
+        })
+    }
+}
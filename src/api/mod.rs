@@ -0,0 +1,401 @@
+pub mod tokens;
+pub mod wallets;
+pub mod allowances;
+pub mod graphql;
+pub mod subscriptions;
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use ethers::types::Address;
+use ethers::utils::to_checksum;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::db::DbPool;
+use crate::PgPooledConnection;
+
+// Page size used when the client does not pass `limit`, and the largest page we serve
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Shared state handed to every request handler.
+#[derive(Clone)]
+pub struct ApiState {
+    pub pool: DbPool,
+    pub chain_id: i64,                // Chain served to requests that do not pass `chain_id`
+}
+
+/// Builds the REST and GraphQL routers over the indexed tables.
+pub fn router(pool: DbPool, chain_id: i64) -> Router {
+    let state = ApiState { pool, chain_id };
+    Router::new()
+        .route("/v1/tokens", get(tokens::list_tokens))
+        .route("/v1/tokens/:address", get(tokens::get_token))
+        .route("/v1/tokens/:address/holders", get(tokens::token_holders))
+        .route("/v1/tokens/:address/supply", get(tokens::token_supply_history))
+        .route("/v1/tokens/:address/token_ids", get(tokens::list_token_ids))
+        .route("/v1/tokens/:address/token_ids/:token_id", get(tokens::get_token_id))
+        .route("/v1/wallets/:address/balances", get(wallets::wallet_balances))
+        .route("/v1/wallets/:address/holdings", get(wallets::wallet_holdings))
+        .route("/v1/owners/:address/allowances", get(allowances::owner_allowances))
+        .route("/v1/spenders/:address/allowances", get(allowances::spender_allowances))
+        .route("/v1/subscribe", get(subscriptions::subscribe_changes))
+        .with_state(state.clone())
+        .merge(graphql::router(state))
+}
+
+/// Serves the REST and GraphQL APIs on `addr` until the process is stopped, answering for
+/// `chain_id` unless a request names another chain.
+pub async fn serve(pool: DbPool, chain_id: i64, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("API server listening on {}", addr);
+    axum::serve(listener, router(pool, chain_id)).await
+}
+
+/// Errors returned to API clients as `{"error": "..."}` with a matching status code.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    Internal(String),
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::BadRequest(message) | ApiError::NotFound(message) => write!(f, "{}", message),
+            // Internal details are logged, not returned to clients
+            ApiError::Internal(_) => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::Internal(message) => {
+                error!("API error: {}", message);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
+            }
+        };
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(e: diesel::result::Error) -> Self {
+        ApiError::Internal(format!("Database error: {:?}", e))
+    }
+}
+
+impl From<r2d2::Error> for ApiError {
+    fn from(e: r2d2::Error) -> Self {
+        ApiError::Internal(format!("Failed to get connection from pool: {:?}", e))
+    }
+}
+
+pub type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// Runs a blocking Diesel query on Tokio's blocking pool with a connection from the shared pool.
+pub async fn with_conn<T, F>(state: &ApiState, query: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&mut PgPooledConnection) -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = state.pool.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn: &mut PgPooledConnection = &mut pool.get()?;
+        query(conn)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(format!("Query task failed: {:?}", e)))?
+}
+
+/// Query parameter selecting the chain of the single-row endpoints.
+#[derive(Deserialize)]
+pub struct ChainParams {
+    pub chain_id: Option<i64>,        // The server's chain when unset
+}
+
+impl ChainParams {
+    pub fn chain_id(&self, state: &ApiState) -> i64 {
+        self.chain_id.unwrap_or(state.chain_id)
+    }
+}
+
+/// Query parameters shared by the paginated endpoints.
+#[derive(Deserialize)]
+pub struct PageParams {
+    pub cursor: Option<String>,       // Opaque cursor returned as `next_cursor` by the previous page
+    pub limit: Option<i64>,           // Page size, capped at `MAX_PAGE_SIZE`
+    pub at_block: Option<i32>,        // Only consider rows written at or before this block
+    pub token: Option<String>,        // Optional token address filter (wallet and allowance endpoints)
+    pub chain_id: Option<i64>,        // The server's chain when unset
+}
+
+impl PageParams {
+    /// Validates the parameters into a `RowQuery` over the requested chain (or `default_chain_id`),
+    /// decoding the cursor with `parse_cursor`.
+    pub fn row_query<C>(&self, default_chain_id: i64, parse_cursor: impl Fn(&str) -> Result<C, ApiError>) -> Result<RowQuery<C>, ApiError> {
+        Ok(RowQuery {
+            chain_id: self.chain_id.unwrap_or(default_chain_id),
+            limit: page_size(self.limit),
+            after: self.cursor.as_deref().map(parse_cursor).transpose()?,
+            at_block: self.at_block,
+            token: self.token.as_deref().map(parse_address_bytes).transpose()?,
+        })
+    }
+}
+
+/// Clamps a requested page size to `[1, MAX_PAGE_SIZE]`, defaulting to `DEFAULT_PAGE_SIZE`.
+pub fn page_size(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+/// Validated paging and filtering options for a keyset query, shared by the REST and GraphQL layers.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct RowQuery<C> {
+    pub chain_id: i64,
+    pub limit: i64,
+    pub after: Option<C>,             // Decoded cursor: only rows after this keyset position
+    pub at_block: Option<i32>,
+    pub token: Option<Vec<u8>>,
+}
+
+/// A page of results plus the cursor for the next page, if there may be one.
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from the raw rows of a keyset query; a full page means there may be more rows.
+    pub fn from_rows<R>(rows: Vec<R>, limit: i64, cursor_of: impl Fn(&R) -> String, view: impl Fn(R) -> Option<T>) -> Self {
+        let next_cursor = if rows.len() as i64 == limit { rows.last().map(&cursor_of) } else { None };
+        Page {
+            data: rows.into_iter().filter_map(view).collect(),
+            next_cursor,
+        }
+    }
+}
+
+/// Parses a hex address from a path or query parameter into its 20 raw bytes.
+pub fn parse_address_bytes(value: &str) -> Result<Vec<u8>, ApiError> {
+    Address::from_str(value)
+        .map(|address| address.as_bytes().to_vec())
+        .map_err(|_| ApiError::BadRequest(format!("Invalid address: {}", value)))
+}
+
+/// Renders a stored 20-byte address as an EIP-55 checksummed hex string.
+pub fn checksum(address_bytes: &[u8]) -> String {
+    if address_bytes.len() != 20 {
+        return format!("0x{}", hex_string(address_bytes));
+    }
+    to_checksum(&Address::from_slice(address_bytes), None)
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Encodes a `(block_number, id)` keyset position as a cursor.
+pub fn block_cursor(block_number: i32, id: i32) -> String {
+    format!("{}:{}", block_number, id)
+}
+
+/// Decodes a cursor produced by `block_cursor`.
+pub fn parse_block_cursor(cursor: &str) -> Result<(i32, i32), ApiError> {
+    let invalid = || ApiError::BadRequest(format!("Invalid cursor: {}", cursor));
+    let (block_number, id) = cursor.split_once(':').ok_or_else(invalid)?;
+    Ok((block_number.parse().map_err(|_| invalid())?, id.parse().map_err(|_| invalid())?))
+}
+
+/// Decodes a cursor that is a plain row id.
+pub fn parse_id_cursor(cursor: &str) -> Result<i32, ApiError> {
+    cursor.parse().map_err(|_| ApiError::BadRequest(format!("Invalid cursor: {}", cursor)))
+}
+
+/// Encodes an `(address, token_id)` keyset position as a cursor.
+pub fn token_cursor(address: &[u8], token_id: Option<i16>) -> String {
+    match token_id {
+        Some(token_id) => format!("{}:{}", checksum(address), token_id),
+        None => checksum(address),
+    }
+}
+
+/// Decodes a cursor produced by `token_cursor`.
+pub fn parse_token_cursor(cursor: &str) -> Result<(Vec<u8>, Option<i16>), ApiError> {
+    match cursor.split_once(':') {
+        Some((address, token_id)) => {
+            let token_id = token_id.parse().map_err(|_| ApiError::BadRequest(format!("Invalid cursor: {}", cursor)))?;
+            Ok((parse_address_bytes(address)?, Some(token_id)))
+        }
+        None => Ok((parse_address_bytes(cursor)?, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::events::BalanceChange;
+    use crate::models::NewToken;
+    use crate::storage::{Storage, DEFAULT_CHAIN_ID};
+    use crate::testing::TestDatabase;
+
+    const ADDRESS: &str = "0xdAC17F958D2ee523a2206206994597C13D831ec7";
+
+    #[test]
+    fn block_cursors_round_trip() {
+        assert_eq!(parse_block_cursor(&block_cursor(18_000_000, 42)).unwrap(), (18_000_000, 42));
+        assert!(parse_block_cursor("18000000").is_err());
+        assert!(parse_block_cursor("18000000:x").is_err());
+        assert_eq!(parse_id_cursor("42").unwrap(), 42);
+        assert!(parse_id_cursor("").is_err());
+    }
+
+    #[test]
+    fn token_cursors_round_trip() {
+        let address = parse_address_bytes(ADDRESS).unwrap();
+        assert_eq!(token_cursor(&address, None), ADDRESS);
+        assert_eq!(parse_token_cursor(&token_cursor(&address, None)).unwrap(), (address.clone(), None));
+        assert_eq!(parse_token_cursor(&token_cursor(&address, Some(-3))).unwrap(), (address, Some(-3)));
+        assert!(parse_token_cursor("0x1234").is_err());
+        assert!(parse_token_cursor(&format!("{}:x", ADDRESS)).is_err());
+    }
+
+    async fn get(router: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = router.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    // Tokens 1 to 3, the first held by wallets 11 to 13
+    fn seed(database: &TestDatabase) {
+        let storage = database.storage(DEFAULT_CHAIN_ID);
+        for token in 1..=3 {
+            let token_address = address(token);
+            let new_token = NewToken {
+                token_address: token_address.as_bytes(),
+                block_number: 1,
+                token_type: "ERC20",
+                name: None,
+                symbol: None,
+                decimals: Some(18),
+                granularity: None,
+            };
+            storage.insert_token(&new_token).unwrap();
+        }
+        for wallet in 11..=13 {
+            let change = BalanceChange {
+                wallet_address: address(wallet),
+                token_address: address(1),
+                delta: (wallet * 100).to_string(),
+                token_id: None,
+                token_type: "ERC20",
+                block_number: wallet as i32,
+                chain_id: DEFAULT_CHAIN_ID as u64,
+            };
+            storage.apply_balance_change(&change).unwrap();
+        }
+    }
+
+    fn addresses(page: &serde_json::Value, field: &str) -> Vec<String> {
+        page["data"].as_array().unwrap().iter().map(|row| row[field].as_str().unwrap().to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn tokens_are_paged_by_cursor() {
+        let Some(database) = TestDatabase::create("api_tokens") else { return };
+        seed(&database);
+        let router = router(database.pool.clone(), DEFAULT_CHAIN_ID);
+
+        let (status, first) = get(&router, "/v1/tokens?limit=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(addresses(&first, "address"), vec![checksum(address(1).as_bytes()), checksum(address(2).as_bytes())]);
+        let cursor = first["next_cursor"].as_str().unwrap();
+
+        let (_, second) = get(&router, &format!("/v1/tokens?limit=2&cursor={}", cursor)).await;
+        assert_eq!(addresses(&second, "address"), vec![checksum(address(3).as_bytes())]);
+        assert!(second["next_cursor"].is_null());
+    }
+
+    #[tokio::test]
+    async fn holders_are_paged_by_cursor() {
+        let Some(database) = TestDatabase::create("api_holders") else { return };
+        seed(&database);
+        let router = router(database.pool.clone(), DEFAULT_CHAIN_ID);
+        let holders = format!("/v1/tokens/{}/holders?limit=2", checksum(address(1).as_bytes()));
+
+        let (status, first) = get(&router, &holders).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["data"].as_array().unwrap().len(), 2);
+        let (_, second) = get(&router, &format!("{}&cursor={}", holders, first["next_cursor"].as_str().unwrap())).await;
+
+        let mut wallets = addresses(&first, "wallet_address");
+        wallets.extend(addresses(&second, "wallet_address"));
+        assert_eq!(wallets, (11..=13).map(|wallet| checksum(address(wallet).as_bytes())).collect::<Vec<_>>());
+        assert!(second["next_cursor"].is_null());
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_are_not_found() {
+        let Some(database) = TestDatabase::create("api_not_found") else { return };
+        seed(&database);
+        let router = router(database.pool.clone(), DEFAULT_CHAIN_ID);
+
+        let (status, body) = get(&router, &format!("/v1/tokens/{}", checksum(address(4).as_bytes()))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["error"].as_str().unwrap().contains("not found"));
+        // The same token on another chain
+        let (status, _) = get(&router, &format!("/v1/tokens/{}?chain_id=10", checksum(address(1).as_bytes()))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn bad_addresses_and_cursors_are_rejected() {
+        let Some(database) = TestDatabase::create("api_bad_requests") else { return };
+        let router = router(database.pool.clone(), DEFAULT_CHAIN_ID);
+        let token = checksum(address(1).as_bytes());
+
+        for uri in [
+            "/v1/tokens/0x1234".to_string(),
+            "/v1/wallets/not-an-address/balances".to_string(),
+            "/v1/tokens?cursor=0x1234".to_string(),
+            format!("/v1/tokens/{}/holders?cursor={}:x", token, token),
+            format!("/v1/tokens/{}/supply?cursor=18000000", token),
+            format!("/v1/owners/{}/allowances?cursor={}", token, token),
+        ] {
+            let (status, body) = get(&router, &uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+            assert!(body["error"].as_str().unwrap().starts_with("Invalid"), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_are_served_under_v1() {
+        let Some(database) = TestDatabase::create("api_prefix") else { return };
+        seed(&database);
+        let router = router(database.pool.clone(), DEFAULT_CHAIN_ID);
+
+        assert_eq!(get(&router, "/v1/tokens").await.0, StatusCode::OK);
+        assert_eq!(get(&router, "/tokens").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(get(&router, &format!("/tokens/{}/holders", checksum(address(1).as_bytes()))).await.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use diesel::prelude::*;
+use futures::stream::{self, Stream};
+use log::error;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+
+use crate::api::allowances::AllowanceView;
+use crate::api::wallets::BalanceView;
+use crate::api::{parse_address_bytes, with_conn, ApiError, ApiState};
+use crate::events::{subscribe, ChangeEvent};
+use crate::models::allowance::Allowance;
+use crate::models::balance::Balance;
+use crate::PgPooledConnection;
+
+// Rows read per table per replay query
+const REPLAY_CHUNK_SIZE: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct SubscribeParams {
+    pub wallet: Option<String>,       // Changes to this wallet's balances, and allowances it granted or received
+    pub token: Option<String>,        // Changes to balances and allowances of this token
+    pub from_block: Option<i32>,      // Replay stored changes from this block before streaming live ones
+    pub chain_id: Option<i64>,        // The server's chain when unset
+}
+
+/// Which changes a subscriber asked for; both filters must match when both are set.
+#[derive(Clone)]
+struct ChangeFilter {
+    chain_id: i64,
+    wallet: Option<Vec<u8>>,
+    token: Option<Vec<u8>>,
+}
+
+impl ChangeFilter {
+    fn matches(&self, event: &ChangeEvent) -> bool {
+        if event.chain_id() != self.chain_id {
+            return false;
+        }
+        let (token_matches, wallet_matches) = match event {
+            ChangeEvent::Balance(balance) => (
+                self.token.as_ref().is_none_or(|token| *token == balance.token_address),
+                self.wallet.as_ref().is_none_or(|wallet| *wallet == balance.wallet_address),
+            ),
+            ChangeEvent::Allowance(allowance) => (
+                self.token.as_ref().is_none_or(|token| *token == allowance.token_address),
+                self.wallet
+                    .as_ref()
+                    .is_none_or(|wallet| *wallet == allowance.owner_address || *wallet == allowance.spender_address),
+            ),
+            // Only stored rows are streamed, so they can be replayed on reconnect
+            ChangeEvent::Supply(_) | ChangeEvent::Transfer(_) => return false,
+        };
+        token_matches && wallet_matches
+    }
+}
+
+/// `GET /v1/subscribe` — Server-Sent Events stream of balance and allowance changes for a wallet and/or token
+/// on one chain.
+///
+/// With `from_block` (or the `Last-Event-ID` header an `EventSource` sends on reconnect), stored changes from
+/// that block on are replayed first. Each event's id is its block number, so delivery is at-least-once for the
+/// block a client resumes from. Live changes are only published while the scraper runs in the same process.
+pub async fn subscribe_changes(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<SubscribeParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let filter = ChangeFilter {
+        chain_id: params.chain_id.unwrap_or(state.chain_id),
+        wallet: params.wallet.as_deref().map(parse_address_bytes).transpose()?,
+        token: params.token.as_deref().map(parse_address_bytes).transpose()?,
+    };
+    if filter.wallet.is_none() && filter.token.is_none() {
+        return Err(ApiError::BadRequest("Pass a wallet and/or token to subscribe to".to_string()));
+    }
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i32>().ok());
+
+    // Subscribe before replaying so nothing indexed during the replay is missed
+    let subscription = Subscription {
+        state,
+        filter,
+        receiver: subscribe(),
+        replay_from: params.from_block.or(last_event_id),
+        pending: VecDeque::new(),
+        last_balance_id: 0,
+        last_allowance_id: 0,
+        closed: false,
+    };
+
+    let events = stream::unfold(subscription, |subscription| subscription.next_event());
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(Duration::from_secs(15))))
+}
+
+struct Subscription {
+    state: ApiState,
+    filter: ChangeFilter,
+    receiver: Receiver<ChangeEvent>,
+    replay_from: Option<i32>,         // Next block to replay from; None once caught up
+    pending: VecDeque<ChangeEvent>,
+    last_balance_id: i32,             // Highest row ids sent, to drop live events already replayed
+    last_allowance_id: i32,
+    closed: bool,
+}
+
+impl Subscription {
+    async fn next_event(mut self) -> Option<(Result<Event, Infallible>, Self)> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                let sse_event = self.record_sse_event(event);
+                return Some((Ok(sse_event), self));
+            }
+
+            if self.closed {
+                return None;
+            }
+
+            if let Some(start_block) = self.replay_from {
+                let filter = self.filter.clone();
+                match with_conn(&self.state, move |conn| Ok(load_replay_window(conn, &filter, start_block)?)).await {
+                    Ok((events, next_block)) => {
+                        self.pending.extend(events);
+                        self.replay_from = next_block;
+                    }
+                    Err(e) => {
+                        error!("Error replaying changes from block {}: {:?}", start_block, e);
+                        self.closed = true;
+                        return Some((Ok(Event::default().event("error").data("Replay failed")), self));
+                    }
+                }
+                continue;
+            }
+
+            match self.receiver.recv().await {
+                Ok(event) if self.filter.matches(&event) && !self.already_sent(&event) => self.pending.push_back(event),
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    // The client fell behind the bus; it should reconnect and resume from its last event id
+                    self.closed = true;
+                    let message = format!("Subscriber lagged behind by {} changes; reconnect to resume", skipped);
+                    return Some((Ok(Event::default().event("lagged").data(message)), self));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    fn already_sent(&self, event: &ChangeEvent) -> bool {
+        match event {
+            ChangeEvent::Balance(balance) => balance.id <= self.last_balance_id,
+            ChangeEvent::Allowance(allowance) => allowance.id <= self.last_allowance_id,
+            ChangeEvent::Supply(_) | ChangeEvent::Transfer(_) => true,
+        }
+    }
+
+    /// Renders a change as an SSE event and remembers it as sent.
+    fn record_sse_event(&mut self, event: ChangeEvent) -> Event {
+        let block_number = event.block_number();
+        let (kind, data) = match event {
+            ChangeEvent::Balance(balance) => {
+                self.last_balance_id = self.last_balance_id.max(balance.id);
+                ("balance", serde_json::to_string(&BalanceView::from(balance)))
+            }
+            ChangeEvent::Allowance(allowance) => {
+                self.last_allowance_id = self.last_allowance_id.max(allowance.id);
+                ("allowance", serde_json::to_string(&AllowanceView::from(allowance)))
+            }
+            ChangeEvent::Supply(_) | ChangeEvent::Transfer(_) => unreachable!("filtered out by ChangeFilter::matches"),
+        };
+        Event::default()
+            .event(kind)
+            .id(block_number.to_string())
+            .data(data.unwrap_or_default())
+    }
+}
+
+/// Loads the next stored changes matching `filter` from `start_block` on, in block order.
+/// Returns the changes and the block to continue from, or `None` once every stored change was read.
+fn load_replay_window(conn: &mut PgPooledConnection, filter: &ChangeFilter, start_block: i32) -> QueryResult<(Vec<ChangeEvent>, Option<i32>)> {
+    let balance_rows = load_balance_changes(conn, filter, start_block, Some(REPLAY_CHUNK_SIZE))?;
+    let allowance_rows = load_allowance_changes(conn, filter, start_block, Some(REPLAY_CHUNK_SIZE))?;
+
+    // A full chunk may continue past its last block, so only blocks before the lowest such block are complete
+    let boundary = [
+        (balance_rows.len() as i64 == REPLAY_CHUNK_SIZE).then(|| balance_rows.last().map(|row| row.block_number)).flatten(),
+        (allowance_rows.len() as i64 == REPLAY_CHUNK_SIZE).then(|| allowance_rows.last().map(|row| row.block_number)).flatten(),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+
+    let mut events: Vec<ChangeEvent> = balance_rows
+        .into_iter()
+        .map(ChangeEvent::Balance)
+        .chain(allowance_rows.into_iter().map(ChangeEvent::Allowance))
+        .collect();
+
+    let Some(boundary) = boundary else {
+        events.sort_by_key(ChangeEvent::block_number);
+        return Ok((events, None));
+    };
+
+    events.retain(|event| event.block_number() < boundary);
+    if events.is_empty() {
+        // A single block holds more than a chunk of changes: read that block in full
+        let balance_rows = load_balance_changes(conn, filter, boundary, None)?;
+        let allowance_rows = load_allowance_changes(conn, filter, boundary, None)?;
+        events = balance_rows
+            .into_iter()
+            .map(ChangeEvent::Balance)
+            .chain(allowance_rows.into_iter().map(ChangeEvent::Allowance))
+            .collect();
+        return Ok((events, Some(boundary + 1)));
+    }
+
+    events.sort_by_key(ChangeEvent::block_number);
+    Ok((events, Some(boundary)))
+}
+
+fn load_balance_changes(conn: &mut PgPooledConnection, filter: &ChangeFilter, start_block: i32, limit: Option<i64>) -> QueryResult<Vec<Balance>> {
+    use crate::schema::balances::dsl::*;
+
+    let mut rows = balances
+        .filter(chain_id.eq(filter.chain_id))
+        .filter(block_number.ge(start_block))
+        .order_by((block_number.asc(), id.asc()))
+        .into_boxed();
+    if let Some(wallet) = filter.wallet.clone() {
+        rows = rows.filter(wallet_address.eq(wallet));
+    }
+    if let Some(token) = filter.token.clone() {
+        rows = rows.filter(token_address.eq(token));
+    }
+    match limit {
+        Some(limit) => rows.limit(limit).select(Balance::as_select()).load(conn),
+        // Only used to read a single block in full
+        None => rows.filter(block_number.eq(start_block)).select(Balance::as_select()).load(conn),
+    }
+}
+
+fn load_allowance_changes(conn: &mut PgPooledConnection, filter: &ChangeFilter, start_block: i32, limit: Option<i64>) -> QueryResult<Vec<Allowance>> {
+    use crate::schema::allowances::dsl::*;
+
+    let mut rows = allowances
+        .filter(chain_id.eq(filter.chain_id))
+        .filter(block_number.ge(start_block))
+        .order_by((block_number.asc(), id.asc()))
+        .into_boxed();
+    if let Some(wallet) = filter.wallet.clone() {
+        rows = rows.filter(owner_address.eq(wallet.clone()).or(spender_address.eq(wallet)));
+    }
+    if let Some(token) = filter.token.clone() {
+        rows = rows.filter(token_address.eq(token));
+    }
+    match limit {
+        Some(limit) => rows.limit(limit).select(Allowance::as_select()).load(conn),
+        None => rows.filter(block_number.eq(start_block)).select(Allowance::as_select()).load(conn),
+    }
+}
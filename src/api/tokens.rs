@@ -0,0 +1,260 @@
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Bytea, Integer, Nullable};
+use serde::Serialize;
+
+use crate::api::wallets::{load_holders, BalanceView};
+use crate::api::{
+    block_cursor, checksum, parse_address_bytes, parse_block_cursor, parse_id_cursor, parse_token_cursor, token_cursor, with_conn, ApiError,
+    ApiResult, ApiState, ChainParams, Page, PageParams, RowQuery,
+};
+use crate::models::token_supply::TokenSupply;
+use crate::models::{Token, TokenID};
+use crate::PgPooledConnection;
+
+#[derive(Serialize)]
+pub struct TokenView {
+    pub chain_id: i64,
+    pub address: String,
+    pub block_number: i32,
+    pub token_type: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<i16>,
+    pub granularity: Option<String>,
+}
+
+impl From<Token> for TokenView {
+    fn from(token: Token) -> Self {
+        TokenView {
+            chain_id: token.chain_id,
+            address: checksum(&token.token_address),
+            block_number: token.block_number,
+            token_type: token.token_type,
+            name: token.name,
+            symbol: token.symbol,
+            decimals: token.decimals,
+            granularity: token.granularity,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SupplyView {
+    pub chain_id: i64,
+    pub token_address: String,
+    pub total_supply: String,
+    pub block_number: i32,
+}
+
+impl From<TokenSupply> for SupplyView {
+    fn from(supply: TokenSupply) -> Self {
+        SupplyView {
+            chain_id: supply.chain_id,
+            token_address: checksum(&supply.token_address),
+            total_supply: supply.total_supply,
+            block_number: supply.block_number,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TokenIdView {
+    pub chain_id: i64,
+    pub contract_address: String,
+    pub token_id: i16,
+    pub token_uri: Option<String>,
+}
+
+impl From<TokenID> for TokenIdView {
+    fn from(row: TokenID) -> Self {
+        TokenIdView {
+            chain_id: row.chain_id,
+            contract_address: checksum(&row.contract_address),
+            token_id: row.token_id,
+            token_uri: row.token_uri,
+        }
+    }
+}
+
+/// `GET /v1/tokens` — all indexed tokens, ordered by address.
+pub async fn list_tokens(State(state): State<ApiState>, Query(params): Query<PageParams>) -> ApiResult<Page<TokenView>> {
+    let query = params.row_query(state.chain_id, parse_address_bytes)?;
+    let limit = query.limit;
+
+    let rows = with_conn(&state, move |conn| Ok(load_tokens(conn, query)?)).await?;
+
+    Ok(Json(Page::from_rows(rows, limit, |token| checksum(&token.token_address), |token| Some(token.into()))))
+}
+
+/// `GET /v1/tokens/:address` — metadata of a single token.
+pub async fn get_token(State(state): State<ApiState>, Path(address): Path<String>, Query(params): Query<ChainParams>) -> ApiResult<TokenView> {
+    let address_bytes = parse_address_bytes(&address)?;
+    let chain = params.chain_id(&state);
+    let token = with_conn(&state, move |conn| Ok(load_tokens_by_address(conn, chain, vec![address_bytes])?)).await?;
+
+    token
+        .into_iter()
+        .next()
+        .map(|token| Json(token.into()))
+        .ok_or_else(|| ApiError::NotFound(format!("Token {} not found", address)))
+}
+
+/// `GET /v1/tokens/:address/holders` — the latest non-zero balance per holder (and token ID) of a token,
+/// as of `at_block` when given.
+pub async fn token_holders(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    Query(params): Query<PageParams>,
+) -> ApiResult<Page<BalanceView>> {
+    let address_bytes = parse_address_bytes(&address)?;
+    let query = params.row_query(state.chain_id, parse_token_cursor)?;
+    let limit = query.limit;
+
+    let rows = with_conn(&state, move |conn| Ok(load_holders(conn, vec![address_bytes], query)?)).await?;
+
+    Ok(Json(Page::from_rows(
+        rows,
+        limit,
+        |row| token_cursor(&row.wallet_address, row.token_id),
+        |row| (row.balance != "0").then(|| row.into()),
+    )))
+}
+
+/// `GET /v1/tokens/:address/supply` — total supply history, in block order.
+pub async fn token_supply_history(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    Query(params): Query<PageParams>,
+) -> ApiResult<Page<SupplyView>> {
+    let address_bytes = parse_address_bytes(&address)?;
+    let query = params.row_query(state.chain_id, parse_block_cursor)?;
+    let limit = query.limit;
+
+    let rows = with_conn(&state, move |conn| Ok(load_supply_history(conn, vec![address_bytes], query)?)).await?;
+
+    Ok(Json(Page::from_rows(rows, limit, |supply| block_cursor(supply.block_number, supply.id), |supply| Some(supply.into()))))
+}
+
+/// `GET /v1/tokens/:address/token_ids` — ERC721/1155 token IDs and their URIs.
+pub async fn list_token_ids(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    Query(params): Query<PageParams>,
+) -> ApiResult<Page<TokenIdView>> {
+    let address_bytes = parse_address_bytes(&address)?;
+    let query = params.row_query(state.chain_id, parse_id_cursor)?;
+    let limit = query.limit;
+
+    let rows = with_conn(&state, move |conn| Ok(load_token_ids(conn, vec![address_bytes], query)?)).await?;
+
+    Ok(Json(Page::from_rows(rows, limit, |row| row.id.to_string(), |row| Some(row.into()))))
+}
+
+/// `GET /v1/tokens/:address/token_ids/:token_id` — URI of a single ERC721/1155 token ID.
+pub async fn get_token_id(
+    State(state): State<ApiState>,
+    Path((address, token_id_value)): Path<(String, i16)>,
+    Query(params): Query<ChainParams>,
+) -> ApiResult<TokenIdView> {
+    use crate::schema::token_ids::dsl::*;
+
+    let address_bytes = parse_address_bytes(&address)?;
+    let chain = params.chain_id(&state);
+    let row: Option<TokenID> = with_conn(&state, move |conn| {
+        Ok(token_ids
+            .filter(chain_id.eq(chain))
+            .filter(contract_address.eq(address_bytes))
+            .filter(token_id.eq(token_id_value))
+            .order_by(id.desc())
+            .select(TokenID::as_select())
+            .first::<TokenID>(conn)
+            .optional()?)
+    })
+    .await?;
+
+    row.map(|row| Json(row.into()))
+        .ok_or_else(|| ApiError::NotFound(format!("Token ID {} of {} not found", token_id_value, address)))
+}
+
+/// Loads tokens ordered by address.
+pub fn load_tokens(conn: &mut PgPooledConnection, query: RowQuery<Vec<u8>>) -> QueryResult<Vec<Token>> {
+    use crate::schema::tokens::dsl::*;
+
+    let mut rows = tokens
+        .filter(chain_id.eq(query.chain_id))
+        .order_by(token_address.asc())
+        .limit(query.limit)
+        .into_boxed();
+    if let Some(after) = query.after {
+        rows = rows.filter(token_address.gt(after));
+    }
+    if let Some(at_block) = query.at_block {
+        rows = rows.filter(block_number.le(at_block));
+    }
+    rows.load(conn)
+}
+
+/// Loads the tokens of a chain among `addresses` in a single query.
+pub fn load_tokens_by_address(conn: &mut PgPooledConnection, chain: i64, addresses: Vec<Vec<u8>>) -> QueryResult<Vec<Token>> {
+    use crate::schema::tokens::dsl::*;
+
+    tokens.filter(chain_id.eq(chain)).filter(token_address.eq_any(addresses)).load(conn)
+}
+
+/// Loads the total supply history of each of `tokens`, ordered by `(block_number, id)`, in a single
+/// query: the page is taken per token, so every token gets up to `limit` rows.
+pub fn load_supply_history(conn: &mut PgPooledConnection, tokens: Vec<Vec<u8>>, query: RowQuery<(i32, i32)>) -> QueryResult<Vec<TokenSupply>> {
+    let (after_block, after_id) = query.after.unzip();
+    diesel::sql_query(
+        "SELECT page.* FROM unnest($1) AS parent(address) CROSS JOIN LATERAL (
+             SELECT * FROM token_supplies
+             WHERE chain_id = $2 AND token_address = parent.address
+               AND ($3::int4 IS NULL OR block_number <= $3)
+               AND ($4::int4 IS NULL OR block_number > $4 OR (block_number = $4 AND id > $5::int4))
+             ORDER BY block_number, id
+             LIMIT $6
+         ) page
+         ORDER BY page.token_address, page.block_number, page.id",
+    )
+    .bind::<Array<Bytea>, _>(tokens)
+    .bind::<BigInt, _>(query.chain_id)
+    .bind::<Nullable<Integer>, _>(query.at_block)
+    .bind::<Nullable<Integer>, _>(after_block)
+    .bind::<Nullable<Integer>, _>(after_id)
+    .bind::<BigInt, _>(query.limit)
+    .load(conn)
+}
+
+/// Loads the latest total supply row of each token of a chain among `addresses` in a single query.
+pub fn load_latest_supplies(conn: &mut PgPooledConnection, chain: i64, addresses: Vec<Vec<u8>>) -> QueryResult<Vec<TokenSupply>> {
+    use crate::schema::token_supplies::dsl::*;
+
+    token_supplies
+        .filter(chain_id.eq(chain))
+        .filter(token_address.eq_any(addresses))
+        .distinct_on(token_address)
+        .order_by((token_address.asc(), block_number.desc(), id.desc()))
+        .load(conn)
+}
+
+/// Loads the token IDs of each of `contracts`, ordered by row id, in a single query; every contract
+/// gets up to `limit` rows.
+pub fn load_token_ids(conn: &mut PgPooledConnection, contracts: Vec<Vec<u8>>, query: RowQuery<i32>) -> QueryResult<Vec<TokenID>> {
+    diesel::sql_query(
+        "SELECT page.* FROM unnest($1) AS parent(address) CROSS JOIN LATERAL (
+             SELECT * FROM token_ids
+             WHERE chain_id = $2 AND contract_address = parent.address
+               AND ($3::int4 IS NULL OR id > $3)
+             ORDER BY id
+             LIMIT $4
+         ) page
+         ORDER BY page.contract_address, page.id",
+    )
+    .bind::<Array<Bytea>, _>(contracts)
+    .bind::<BigInt, _>(query.chain_id)
+    .bind::<Nullable<Integer>, _>(query.after)
+    .bind::<BigInt, _>(query.limit)
+    .load(conn)
+}
@@ -0,0 +1,151 @@
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Bytea, Integer, Nullable, SmallInt};
+use serde::Serialize;
+
+use crate::api::{block_cursor, checksum, parse_address_bytes, parse_block_cursor, parse_token_cursor, token_cursor, with_conn, ApiResult, ApiState, Page, PageParams, RowQuery};
+use crate::models::balance::Balance;
+use crate::PgPooledConnection;
+
+#[derive(Serialize)]
+pub struct BalanceView {
+    pub chain_id: i64,
+    pub wallet_address: String,
+    pub token_address: String,
+    pub token_id: Option<i16>,
+    pub token_type: String,
+    pub balance: String,
+    pub block_number: i32,
+}
+
+impl From<Balance> for BalanceView {
+    fn from(row: Balance) -> Self {
+        BalanceView {
+            chain_id: row.chain_id,
+            wallet_address: checksum(&row.wallet_address),
+            token_address: checksum(&row.token_address),
+            token_id: row.token_id,
+            token_type: row.token_type,
+            balance: row.balance,
+            block_number: row.block_number,
+        }
+    }
+}
+
+/// `GET /v1/wallets/:address/balances` — every historical balance row of a wallet, in block order.
+pub async fn wallet_balances(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    Query(params): Query<PageParams>,
+) -> ApiResult<Page<BalanceView>> {
+    let wallet = parse_address_bytes(&address)?;
+    let query = params.row_query(state.chain_id, parse_block_cursor)?;
+    let limit = query.limit;
+
+    let rows = with_conn(&state, move |conn| Ok(load_balance_history(conn, vec![wallet], query)?)).await?;
+
+    Ok(Json(Page::from_rows(rows, limit, |row| block_cursor(row.block_number, row.id), |row| Some(row.into()))))
+}
+
+/// `GET /v1/wallets/:address/holdings` — the latest non-zero balance per token (and token ID) of a wallet,
+/// as of `at_block` when given.
+pub async fn wallet_holdings(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    Query(params): Query<PageParams>,
+) -> ApiResult<Page<BalanceView>> {
+    let wallet = parse_address_bytes(&address)?;
+    let query = params.row_query(state.chain_id, parse_token_cursor)?;
+    let limit = query.limit;
+
+    let rows = with_conn(&state, move |conn| Ok(load_holdings(conn, vec![wallet], query)?)).await?;
+
+    Ok(Json(Page::from_rows(
+        rows,
+        limit,
+        |row| token_cursor(&row.token_address, row.token_id),
+        |row| (row.balance != "0").then(|| row.into()),
+    )))
+}
+
+/// Loads the balance history of each of `wallets`, ordered by `(block_number, id)`, in a single query;
+/// every wallet gets up to `limit` rows.
+pub fn load_balance_history(conn: &mut PgPooledConnection, wallets: Vec<Vec<u8>>, query: RowQuery<(i32, i32)>) -> QueryResult<Vec<Balance>> {
+    let (after_block, after_id) = query.after.unzip();
+    diesel::sql_query(
+        "SELECT page.* FROM unnest($1) AS parent(address) CROSS JOIN LATERAL (
+             SELECT * FROM balances
+             WHERE chain_id = $2 AND wallet_address = parent.address
+               AND ($3::bytea IS NULL OR token_address = $3)
+               AND ($4::int4 IS NULL OR block_number <= $4)
+               AND ($5::int4 IS NULL OR block_number > $5 OR (block_number = $5 AND id > $6::int4))
+             ORDER BY block_number, id
+             LIMIT $7
+         ) page
+         ORDER BY page.wallet_address, page.block_number, page.id",
+    )
+    .bind::<Array<Bytea>, _>(wallets)
+    .bind::<BigInt, _>(query.chain_id)
+    .bind::<Nullable<Bytea>, _>(query.token)
+    .bind::<Nullable<Integer>, _>(query.at_block)
+    .bind::<Nullable<Integer>, _>(after_block)
+    .bind::<Nullable<Integer>, _>(after_id)
+    .bind::<BigInt, _>(query.limit)
+    .load(conn)
+}
+
+/// Loads the latest balance row per `(token_address, token_id)` of each of `wallets` at or before
+/// `at_block`, ordered by `(token_address, token_id)`, in a single query; every wallet gets up to
+/// `limit` rows. Zero balances are included so the cursor stays stable.
+pub fn load_holdings(conn: &mut PgPooledConnection, wallets: Vec<Vec<u8>>, query: RowQuery<(Vec<u8>, Option<i16>)>) -> QueryResult<Vec<Balance>> {
+    let (after_token, after_id) = query.after.map_or((None, None), |(token, id)| (Some(token), id));
+    // Fungible rows (NULL token_id) sort last within a token, so a cursor without an ID exhausts the token
+    diesel::sql_query(
+        "SELECT page.* FROM unnest($1) AS parent(address) CROSS JOIN LATERAL (
+             SELECT DISTINCT ON (token_address, token_id) * FROM balances
+             WHERE chain_id = $2 AND wallet_address = parent.address
+               AND ($3::bytea IS NULL OR token_address = $3)
+               AND ($4::int4 IS NULL OR block_number <= $4)
+               AND ($5::bytea IS NULL OR token_address > $5
+                    OR (token_address = $5 AND $6::int2 IS NOT NULL AND (token_id > $6 OR token_id IS NULL)))
+             ORDER BY token_address, token_id, block_number DESC, id DESC
+             LIMIT $7
+         ) page
+         ORDER BY page.wallet_address, page.token_address, page.token_id",
+    )
+    .bind::<Array<Bytea>, _>(wallets)
+    .bind::<BigInt, _>(query.chain_id)
+    .bind::<Nullable<Bytea>, _>(query.token)
+    .bind::<Nullable<Integer>, _>(query.at_block)
+    .bind::<Nullable<Bytea>, _>(after_token)
+    .bind::<Nullable<SmallInt>, _>(after_id)
+    .bind::<BigInt, _>(query.limit)
+    .load(conn)
+}
+
+/// Loads the latest balance row per `(wallet_address, token_id)` of each of `tokens` at or before
+/// `at_block`, ordered by `(wallet_address, token_id)`, in a single query; every token gets up to
+/// `limit` rows. Zero balances are included so the cursor stays stable.
+pub fn load_holders(conn: &mut PgPooledConnection, tokens: Vec<Vec<u8>>, query: RowQuery<(Vec<u8>, Option<i16>)>) -> QueryResult<Vec<Balance>> {
+    let (after_wallet, after_id) = query.after.map_or((None, None), |(wallet, id)| (Some(wallet), id));
+    diesel::sql_query(
+        "SELECT page.* FROM unnest($1) AS parent(address) CROSS JOIN LATERAL (
+             SELECT DISTINCT ON (wallet_address, token_id) * FROM balances
+             WHERE chain_id = $2 AND token_address = parent.address
+               AND ($3::int4 IS NULL OR block_number <= $3)
+               AND ($4::bytea IS NULL OR wallet_address > $4
+                    OR (wallet_address = $4 AND $5::int2 IS NOT NULL AND (token_id > $5 OR token_id IS NULL)))
+             ORDER BY wallet_address, token_id, block_number DESC, id DESC
+             LIMIT $6
+         ) page
+         ORDER BY page.token_address, page.wallet_address, page.token_id",
+    )
+    .bind::<Array<Bytea>, _>(tokens)
+    .bind::<BigInt, _>(query.chain_id)
+    .bind::<Nullable<Integer>, _>(query.at_block)
+    .bind::<Nullable<Bytea>, _>(after_wallet)
+    .bind::<Nullable<SmallInt>, _>(after_id)
+    .bind::<BigInt, _>(query.limit)
+    .load(conn)
+}
@@ -0,0 +1,509 @@
+//! Parallel backfills. A backfill is split into items, block ranges kept in Postgres, which any
+//! number of scrapers claim with `SELECT ... FOR UPDATE SKIP LOCKED` and index side by side.
+//! Balances and supplies are running totals, so the workers stage the changes of their range (and
+//! the allowances, so those rows are written in block order too) instead of applying them; the merge
+//! applies the staged changes item by item in block order, each item in one transaction, once every
+//! item before it is merged.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Subcommand;
+use diesel::prelude::*;
+use ethers::types::Address;
+use log::{info, warn};
+use tokio_util::sync::CancellationToken;
+
+use crate::checkpoint::Stream;
+use crate::config::ProfileArgs;
+use crate::db::DbPool;
+use crate::events::{publish, AllowanceChange, BalanceChange, ChangeEvent, SupplyChange, TotalSupplyChange};
+use crate::indexer::IndexerBuilder;
+use crate::models::backfill::{
+    claim_item, delete_merged_items, finish_item, first_queued_block, insert_items, load_items, load_overlapping_items,
+    load_staged_changes, lock_checkpoints, lock_first_unmerged_item, mark_item_merged, retry_failed_items, touch_item, BackfillItem,
+    NewBackfillItem, StagedChange, DONE, FAILED, MERGED, PENDING, RUNNING,
+};
+use crate::sinks::staging::StagingSink;
+use crate::sinks::EventSinks;
+use crate::storage::{PgStorage, Storage};
+use crate::{PgPooledConnection, TokenType};
+
+pub type QueueResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Blocks per item unless set with `--item-blocks`.
+pub const DEFAULT_ITEM_BLOCKS: u64 = 100_000;
+
+// A running item whose worker reported no progress for this long is claimed again by another worker
+const STALE_AFTER: Duration = Duration::from_secs(15 * 60);
+
+// Attempts of an item before it is marked failed (and waits for `queue retry`)
+const MAX_ATTEMPTS: i32 = 3;
+
+// Wait of an idle worker for the items other workers are still on
+const IDLE_WAIT: Duration = Duration::from_secs(5);
+
+#[derive(Subcommand)]
+pub enum QueueCommand {
+    /// Split a range of blocks into items for the workers
+    Add {
+        #[arg(long)]
+        from_block: u64,
+
+        /// Last block queued (inclusive)
+        #[arg(long)]
+        to_block: u64,
+
+        /// Blocks per item
+        #[arg(long, default_value_t = DEFAULT_ITEM_BLOCKS, value_parser = clap::value_parser!(u64).range(1..))]
+        item_blocks: u64,
+    },
+
+    /// Claim and index items until every queued item is merged; run it on as many scrapers as wanted
+    Work {
+        #[command(flatten)]
+        profile: ProfileArgs,
+
+        /// Name of this worker in the queue (default: host name and process id)
+        #[arg(long, value_name = "NAME")]
+        worker: Option<String>,
+    },
+
+    /// Print the number of items by status, the running ones and the failed ones with their error
+    Status,
+
+    /// Put the failed items back in the queue, for one more attempt each
+    Retry,
+
+    /// Delete the merged items, so their blocks can be queued again
+    Clear,
+}
+
+/// Number of items of a chain by status.
+#[derive(Default)]
+pub struct QueueCounts {
+    pub pending: usize,
+    pub running: usize,
+    pub done: usize,
+    pub merged: usize,
+    pub failed: usize,
+}
+
+impl QueueCounts {
+    fn of(items: &[BackfillItem]) -> Self {
+        let count = |status: &str| items.iter().filter(|item| item.status == status).count();
+        QueueCounts { pending: count(PENDING), running: count(RUNNING), done: count(DONE), merged: count(MERGED), failed: count(FAILED) }
+    }
+}
+
+/// The backfill queue of a chain, as seen by one worker.
+pub struct WorkQueue {
+    pool: DbPool,
+    storage: PgStorage,
+    chain_id: i64,
+    worker: String,
+}
+
+impl WorkQueue {
+    pub fn new(pool: DbPool, chain_id: i64, worker: impl Into<String>) -> Self {
+        WorkQueue { storage: PgStorage::with_chain(pool.clone(), chain_id), pool, chain_id, worker: worker.into() }
+    }
+
+    /// Claims and indexes items until every queued item is merged, or the indexers' shutdown token
+    /// is cancelled. `builder` configures the indexer of the chain; the queue sets its range and
+    /// sinks for each item. Merges whatever it can after every item, and returns the counts left.
+    pub async fn work(&self, builder: impl Fn() -> IndexerBuilder, shutdown: &CancellationToken) -> QueueResult<QueueCounts> {
+        let streams = builder().build()?.streams().to_vec();
+        info!("Working on the backfill queue of chain {} as {}", self.chain_id, self.worker);
+
+        while !shutdown.is_cancelled() {
+            let claimed = claim_item(&mut self.pool.get()?, self.chain_id, &self.worker, STALE_AFTER.as_secs() as i64)?;
+            match claimed {
+                Some(item) => {
+                    self.index_item(&item, builder(), shutdown).await?;
+                    self.merge(&streams)?;
+                }
+                None => {
+                    self.merge(&streams)?;
+                    let counts = self.counts()?;
+                    if counts.pending + counts.running == 0 {
+                        return Ok(counts);
+                    }
+                    // Items stalled on another worker become claimable once stale
+                    tokio::select! {
+                        _ = tokio::time::sleep(IDLE_WAIT) => {}
+                        _ = shutdown.cancelled() => break,
+                    }
+                }
+            }
+        }
+        self.counts()
+    }
+
+    /// Applies the staged changes of the done items that follow the merged ones, in block order, and
+    /// moves the checkpoints of `streams` indexed up to an item past it. Returns the merged items.
+    pub fn merge(&self, streams: &[Stream]) -> QueueResult<Vec<BackfillItem>> {
+        let conn = &mut self.pool.get()?;
+        let mut merged = Vec::new();
+        loop {
+            let applied = conn.transaction::<_, Box<dyn std::error::Error + Send + Sync>, _>(|conn| {
+                // The lock keeps the other workers from merging at the same time
+                let Some(item) = lock_first_unmerged_item(conn, self.chain_id)? else {
+                    return Ok(None);
+                };
+                if item.status != DONE {
+                    return Ok(None);
+                }
+
+                let staged = load_staged_changes(conn, &item)?;
+                let mut rows = Vec::with_capacity(staged.len());
+                for change in &staged {
+                    rows.push(self.apply_staged(conn, change)?);
+                }
+                mark_item_merged(conn, &item)?;
+
+                let continuing = self.continuing_streams(conn, streams, &item)?;
+                self.storage.save_checkpoints_on(conn, &continuing, item.to_block)?;
+                Ok(Some((item, rows)))
+            })?;
+
+            let Some((item, rows)) = applied else {
+                break;
+            };
+            info!("Merged blocks {} to {} ({} changes)", item.from_block, item.to_block, rows.len());
+            // Notify in-process subscribers once the rows are committed, as the storage sink does
+            rows.into_iter().for_each(publish);
+            merged.push(item);
+        }
+        Ok(merged)
+    }
+
+    pub fn counts(&self) -> QueueResult<QueueCounts> {
+        Ok(QueueCounts::of(&load_items(&mut self.pool.get()?, self.chain_id, None)?))
+    }
+
+    // Indexes the range of a claimed item into the staging table, then records how it went
+    async fn index_item(&self, item: &BackfillItem, builder: IndexerBuilder, shutdown: &CancellationToken) -> QueueResult<()> {
+        info!("Claimed blocks {} to {} (attempt {})", item.from_block, item.to_block, item.attempts);
+        let storage: Arc<dyn Storage> = Arc::new(PgStorage::with_chain(self.pool.clone(), self.chain_id));
+        let sink = StagingSink::new(self.pool.clone(), item, storage);
+
+        let pool = self.pool.clone();
+        let claimed = item.clone();
+        let indexer = builder
+            .range(item.from_block as u64, Some(item.to_block as u64))
+            .sinks(EventSinks::new(vec![Box::new(sink)]))
+            .on_range_indexed(move |_, _| match pool.get().map_err(|e| e.to_string()).and_then(|mut conn| touch_item(&mut conn, &claimed).map_err(|e| e.to_string())) {
+                Ok(true) => {}
+                Ok(false) => warn!("Blocks {} to {} were claimed by another worker; this attempt is discarded", claimed.from_block, claimed.to_block),
+                Err(e) => warn!("Failed to report progress on blocks {} to {}: {}", claimed.from_block, claimed.to_block, e),
+            })
+            .build()?;
+
+        let failed = |error: String| {
+            let status = if item.attempts >= MAX_ATTEMPTS { FAILED } else { PENDING };
+            warn!("Blocks {} to {} failed (attempt {}): {}", item.from_block, item.to_block, item.attempts, error);
+            (status, Some(error))
+        };
+        let (status, error) = match indexer.run().await {
+            Ok(Some(last_block)) if last_block == item.to_block as u64 => (DONE, None),
+            // Left for another worker, or this one's next run
+            Ok(_) if shutdown.is_cancelled() => (PENDING, None),
+            Ok(_) => failed("The indexer stopped before the end of the range".to_string()),
+            Err(e) => failed(e.to_string()),
+        };
+
+        if !finish_item(&mut self.pool.get()?, item, status, error.as_deref())? {
+            warn!("Blocks {} to {} were claimed by another worker meanwhile; this attempt is discarded", item.from_block, item.to_block);
+        } else if status == DONE {
+            info!("Staged blocks {} to {}", item.from_block, item.to_block);
+        }
+        Ok(())
+    }
+
+    // Writes a staged change to its historical table, as the storage sink would have
+    fn apply_staged(&self, conn: &mut PgPooledConnection, change: &StagedChange) -> QueueResult<ChangeEvent> {
+        let address = |bytes: Option<&Vec<u8>>| bytes.map(|bytes| Address::from_slice(bytes)).ok_or("A staged change lacks an address");
+        let token_type = || -> QueueResult<&'static str> {
+            Ok(change.token_type.as_deref().ok_or("A staged change lacks its token type")?.parse::<TokenType>()?.as_str())
+        };
+        let chain_id = self.chain_id as u64;
+        let token_address = Address::from_slice(&change.token_address);
+
+        Ok(match change.kind.as_str() {
+            "balance" => ChangeEvent::Balance(self.storage.balance_change_on(conn, &BalanceChange {
+                wallet_address: address(change.wallet_address.as_ref())?,
+                token_address,
+                delta: change.amount.clone(),
+                token_id: change.token_id,
+                token_type: token_type()?,
+                block_number: change.block_number,
+                chain_id,
+            })?),
+            "allowance" => ChangeEvent::Allowance(self.storage.allowance_change_on(conn, &AllowanceChange {
+                owner_address: address(change.wallet_address.as_ref())?,
+                spender_address: address(change.spender_address.as_ref())?,
+                token_address,
+                allowance: change.amount.clone(),
+                token_id: change.token_id,
+                token_type: token_type()?,
+                block_number: change.block_number,
+                chain_id,
+            })?),
+            "supply" => {
+                let change = TotalSupplyChange { token_address, delta: change.amount.clone(), block_number: change.block_number, chain_id };
+                ChangeEvent::Supply(SupplyChange { supply: self.storage.supply_change_on(conn, &change)?, delta: change.delta })
+            }
+            other => return Err(format!("Unknown staged change kind {}", other).into()),
+        })
+    }
+
+    // The streams whose checkpoint the item continues: those indexed up to the block before it, and,
+    // when it is the first item queued on the chain, those not indexed at all yet. Read on the merge
+    // transaction, with the checkpoints locked until it commits
+    fn continuing_streams(&self, conn: &mut PgPooledConnection, streams: &[Stream], item: &BackfillItem) -> QueueResult<Vec<Stream>> {
+        let checkpoints = lock_checkpoints(conn, self.chain_id)?;
+        let is_first = first_queued_block(conn, self.chain_id)? == Some(item.from_block);
+        Ok(streams
+            .iter()
+            .filter(|stream| {
+                match checkpoints.iter().find(|checkpoint| checkpoint.handler == stream.handler && checkpoint.processor == stream.processor) {
+                    Some(checkpoint) => checkpoint.block_number + 1 == item.from_block,
+                    None => is_first,
+                }
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+/// Name of this process in the queue: the host name and process id.
+pub fn default_worker_name() -> String {
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "scraper".to_string());
+    format!("{}-{}", host, std::process::id())
+}
+
+/// Runs a `queue` subcommand that only works on the database, against the queue of `chain_id`.
+pub fn run_queue_command(pool: &DbPool, chain_id: i64, command: &QueueCommand) -> QueueResult<()> {
+    let conn = &mut pool.get()?;
+    match command {
+        QueueCommand::Add { from_block, to_block, item_blocks } => {
+            if from_block > to_block {
+                return Err(format!("--from-block {} is after --to-block {}", from_block, to_block).into());
+            }
+            // The merge adds the changes to the running totals: blocks indexed already would be
+            // counted twice, and rows stored after the range would be followed by earlier changes
+            let storage = PgStorage::with_chain(pool.clone(), chain_id);
+            let checkpoints = storage.checkpoints()?;
+            if let Some(checkpoint) = checkpoints.iter().find(|checkpoint| checkpoint.block_number >= *from_block as i64) {
+                return Err(format!(
+                    "{} {} is indexed up to block {}; only blocks after it can be queued",
+                    checkpoint.handler, checkpoint.processor, checkpoint.block_number
+                )
+                .into());
+            }
+            let rows = storage.count_rows_from(i32::try_from(*from_block)?)?;
+            if rows > 0 {
+                return Err(format!("{} balance, allowance and supply rows are stored from block {} on; only blocks after them can be queued", rows, from_block).into());
+            }
+            // Changes of a range queued twice would be applied twice
+            let overlapping = load_overlapping_items(conn, chain_id, *from_block as i64, *to_block as i64)?;
+            if let Some(item) = overlapping.first() {
+                return Err(format!(
+                    "Blocks {} to {} are already queued ({}); `queue clear` deletes the merged items",
+                    item.from_block, item.to_block, item.status
+                )
+                .into());
+            }
+
+            let new_items: Vec<NewBackfillItem> = (*from_block..=*to_block)
+                .step_by(*item_blocks as usize)
+                .map(|first| NewBackfillItem { chain_id, from_block: first as i64, to_block: (first + item_blocks - 1).min(*to_block) as i64 })
+                .collect();
+            insert_items(conn, &new_items)?;
+            println!("Queued blocks {} to {} of chain {} as {} items", from_block, to_block, chain_id, new_items.len());
+        }
+        QueueCommand::Status => {
+            let items = load_items(conn, chain_id, None)?;
+            let counts = QueueCounts::of(&items);
+            println!("Chain:    {}", chain_id);
+            println!("Pending:  {}", counts.pending);
+            println!("Running:  {}", counts.running);
+            println!("Done:     {} (waiting for the items before them to be merged)", counts.done);
+            println!("Merged:   {}", counts.merged);
+            println!("Failed:   {}", counts.failed);
+            for item in items.iter().filter(|item| item.status == RUNNING || item.status == FAILED) {
+                println!(
+                    "{}\t{} to {}\t{}\tattempts={}\t{}",
+                    item.status,
+                    item.from_block,
+                    item.to_block,
+                    item.worker.as_deref().unwrap_or("-"),
+                    item.attempts,
+                    item.error.as_deref().unwrap_or(""),
+                );
+            }
+        }
+        QueueCommand::Retry => println!("Put {} failed items back in the queue", retry_failed_items(conn, chain_id)?),
+        QueueCommand::Clear => println!("Deleted {} merged items", delete_merged_items(conn, chain_id)?),
+        QueueCommand::Work { .. } => return Err("`queue work` indexes blocks; run it with the scraper binary".into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::BalanceChange;
+    use crate::models::backfill::{insert_changes, NewStagedChange};
+    use crate::models::NewToken;
+    use crate::storage::DEFAULT_CHAIN_ID;
+    use crate::testing::TestDatabase;
+
+    use super::*;
+
+    fn add(database: &TestDatabase, from_block: u64, to_block: u64) -> QueueResult<()> {
+        run_queue_command(&database.pool, DEFAULT_CHAIN_ID, &QueueCommand::Add { from_block, to_block, item_blocks: 10 })
+    }
+
+    fn insert_token(database: &TestDatabase) {
+        let token_address = Address::from_low_u64_be(1);
+        let new_token = NewToken {
+            token_address: token_address.as_bytes(),
+            block_number: 0,
+            token_type: "ERC20",
+            name: None,
+            symbol: None,
+            decimals: Some(18),
+            granularity: None,
+        };
+        database.storage(DEFAULT_CHAIN_ID).insert_token(&new_token).unwrap();
+    }
+
+    // Stages a balance change of wallet 11 at `block_number` for the attempt holding `item`
+    fn stage(database: &TestDatabase, item: &BackfillItem, delta: &str, block_number: i32) {
+        let token_address = Address::from_low_u64_be(1);
+        let wallet_address = Address::from_low_u64_be(11);
+        let change = NewStagedChange {
+            item_id: item.id,
+            attempt: item.attempts,
+            seq: 0,
+            kind: "balance",
+            token_address: token_address.as_bytes(),
+            wallet_address: Some(wallet_address.as_bytes()),
+            spender_address: None,
+            token_id: None,
+            token_type: Some("ERC20"),
+            amount: delta,
+            block_number,
+        };
+        insert_changes(&mut database.pool.get().unwrap(), &[change]).unwrap();
+    }
+
+    fn claim(database: &TestDatabase, worker: &str) -> BackfillItem {
+        claim_item(&mut database.pool.get().unwrap(), DEFAULT_CHAIN_ID, worker, STALE_AFTER.as_secs() as i64).unwrap().unwrap()
+    }
+
+    fn statuses(queue: &WorkQueue) -> Vec<String> {
+        load_items(&mut queue.pool.get().unwrap(), DEFAULT_CHAIN_ID, None).unwrap().into_iter().map(|item| item.status).collect()
+    }
+
+    #[test]
+    fn queue_add_refuses_blocks_indexed_or_queued_already() {
+        let Some(database) = TestDatabase::create("queue_add") else { return };
+        let storage = database.storage(DEFAULT_CHAIN_ID);
+        storage.save_checkpoints(&[Stream::new("ERC20", "balances")], 99).unwrap();
+
+        let error = add(&database, 50, 149).unwrap_err().to_string();
+        assert!(error.contains("ERC20 balances is indexed up to block 99"), "{}", error);
+        add(&database, 100, 149).unwrap();
+        assert!(add(&database, 140, 159).unwrap_err().to_string().contains("already queued"));
+
+        insert_token(&database);
+        let change = BalanceChange {
+            wallet_address: Address::from_low_u64_be(11),
+            token_address: Address::from_low_u64_be(1),
+            delta: "10".to_string(),
+            token_id: None,
+            token_type: "ERC20",
+            block_number: 300,
+            chain_id: DEFAULT_CHAIN_ID as u64,
+        };
+        storage.apply_balance_change(&change).unwrap();
+        let error = add(&database, 200, 249).unwrap_err().to_string();
+        assert!(error.contains("1 balance, allowance and supply rows are stored from block 200 on"), "{}", error);
+        add(&database, 301, 349).unwrap();
+
+        // Another chain has its own checkpoints and rows
+        run_queue_command(&database.pool, 10, &QueueCommand::Add { from_block: 0, to_block: 9, item_blocks: 10 }).unwrap();
+    }
+
+    #[test]
+    fn items_done_out_of_order_are_merged_in_block_order() {
+        let Some(database) = TestDatabase::create("queue_merge") else { return };
+        insert_token(&database);
+        add(&database, 0, 29).unwrap();
+        let queue = WorkQueue::new(database.pool.clone(), DEFAULT_CHAIN_ID, "a");
+        let streams = [Stream::new("ERC20", "balances")];
+
+        let (first, second) = (claim(&database, "a"), claim(&database, "b"));
+        assert_eq!((first.from_block, second.from_block), (0, 10));
+        stage(&database, &second, "5", 15);
+        assert!(finish_item(&mut database.pool.get().unwrap(), &second, DONE, None).unwrap());
+
+        // The second item waits for the first
+        assert!(queue.merge(&streams).unwrap().is_empty());
+        assert_eq!(statuses(&queue), vec![RUNNING, DONE, PENDING]);
+        assert!(database.storage(DEFAULT_CHAIN_ID).checkpoints().unwrap().is_empty());
+
+        stage(&database, &first, "10", 5);
+        assert!(finish_item(&mut database.pool.get().unwrap(), &first, DONE, None).unwrap());
+        let merged: Vec<i64> = queue.merge(&streams).unwrap().iter().map(|item| item.from_block).collect();
+        assert_eq!(merged, vec![0, 10]);
+        assert_eq!(statuses(&queue), vec![MERGED, MERGED, PENDING]);
+
+        // Applied as running totals in block order, and checkpointed up to the last merged item
+        let storage = database.storage(DEFAULT_CHAIN_ID);
+        let balances = storage.latest_balances(Address::from_low_u64_be(1).as_bytes(), 10).unwrap();
+        assert_eq!(balances.iter().map(|row| (row.balance.as_str(), row.block_number)).collect::<Vec<_>>(), vec![("15", 15)]);
+        let checkpoints = storage.checkpoints().unwrap();
+        assert_eq!(checkpoints.iter().map(|checkpoint| checkpoint.block_number).collect::<Vec<_>>(), vec![19]);
+    }
+
+    #[test]
+    fn stale_items_are_claimed_again_and_fence_the_earlier_attempt() {
+        let Some(database) = TestDatabase::create("queue_claim") else { return };
+        insert_token(&database);
+        add(&database, 0, 9).unwrap();
+
+        let stalled = claim(&database, "a");
+        assert_eq!((stalled.worker.as_deref(), stalled.attempts), (Some("a"), 1));
+        stage(&database, &stalled, "10", 5);
+        assert!(claim_item(&mut database.pool.get().unwrap(), DEFAULT_CHAIN_ID, "b", STALE_AFTER.as_secs() as i64).unwrap().is_none());
+
+        // No progress reported for longer than STALE_AFTER
+        {
+            use diesel::dsl::{now, IntervalDsl};
+            use crate::schema::backfill_items::dsl::*;
+            diesel::update(backfill_items.filter(id.eq(stalled.id)))
+                .set(claimed_at.eq((now - (STALE_AFTER.as_secs() as i64 + 60).seconds()).nullable()))
+                .execute(&mut database.pool.get().unwrap())
+                .unwrap();
+        }
+        let reclaimed = claim(&database, "b");
+        assert_eq!((reclaimed.id, reclaimed.worker.as_deref(), reclaimed.attempts), (stalled.id, Some("b"), 2));
+        assert!(load_staged_changes(&mut database.pool.get().unwrap(), &stalled).unwrap().is_empty());
+
+        let conn = &mut database.pool.get().unwrap();
+        assert!(!touch_item(conn, &stalled).unwrap());
+        assert!(!finish_item(conn, &stalled, DONE, None).unwrap());
+        assert!(touch_item(conn, &reclaimed).unwrap());
+        assert!(finish_item(conn, &reclaimed, DONE, None).unwrap());
+        assert!(!finish_item(conn, &reclaimed, FAILED, Some("late")).unwrap());
+        assert_eq!(load_items(conn, DEFAULT_CHAIN_ID, Some(DONE)).unwrap().len(), 1);
+    }
+}
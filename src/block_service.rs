@@ -0,0 +1,73 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::Log;
+use futures::stream::{self, StreamExt};
+use log::{error, info};
+
+use crate::models::block::NewBlock;
+use crate::storage::Storage;
+
+// Maximum number of block headers requested from the provider at once
+const BLOCK_FETCH_CONCURRENCY: usize = 32;
+
+/// Collects the distinct block numbers carried by a batch of logs.
+pub fn blocks_in_logs(logs: &[Log]) -> BTreeSet<u64> {
+    logs.iter()
+        .filter_map(|log| log.block_number)
+        .map(|block_number| block_number.as_u64())
+        .collect()
+}
+
+/// Fetches the headers of `block_numbers` from the provider and stores them in the `blocks` table.
+pub async fn fetch_and_store_blocks(
+    storage: &dyn Storage,
+    provider: Arc<Provider<Http>>,
+    block_numbers: impl IntoIterator<Item = u64>,
+) {
+    // Bound the number of in-flight `eth_getBlockByNumber` requests
+    let stored = stream::iter(block_numbers)
+        .map(|block_number| {
+            let provider_clone = provider.clone();
+            async move {
+                match fetch_and_store_block(storage, provider_clone, block_number).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        error!("Error storing block {}: {:?}", block_number, e);
+                        false
+                    }
+                }
+            }
+        })
+        .buffer_unordered(BLOCK_FETCH_CONCURRENCY)
+        .filter(|stored| futures::future::ready(*stored))
+        .count()
+        .await;
+
+    info!("Stored headers for {} blocks", stored);
+}
+
+async fn fetch_and_store_block(
+    storage: &dyn Storage,
+    provider: Arc<Provider<Http>>,
+    block_number: u64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let block = provider
+        .get_block(block_number)
+        .await?
+        .ok_or_else(|| format!("Block {} not found", block_number))?;
+
+    let hash = block.hash.ok_or_else(|| format!("Block {} is still pending", block_number))?;
+
+    let new_block = NewBlock {
+        number: block_number as i32,
+        hash: hash.as_bytes(),
+        parent_hash: block.parent_hash.as_bytes(),
+        timestamp: block.timestamp.as_u64() as i64,
+    };
+
+    storage.insert_block(&new_block)?;
+
+    Ok(())
+}
@@ -0,0 +1,277 @@
+//! Checkpoints of the streams an indexer produces. A stream is what one handler's logs yield: the
+//! handler's own events, or the changes of one processor. Each stream records the last block it
+//! was indexed up to, so a handler or processor enabled later catches up on its own while the
+//! others continue at the head.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::indexer::Processors;
+
+/// Processor of a handler's own events: transfers, token rows and ABI-decoded events.
+pub const EVENTS: &str = "events";
+
+/// Handler of the streams that do not depend on a handler: the block header processors.
+pub const CHAIN_WIDE: &str = "*";
+
+/// A handler and one of the processors applied to its logs.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Stream {
+    pub handler: String,
+    pub processor: String,
+}
+
+impl Stream {
+    pub fn new(handler: &str, processor: &str) -> Self {
+        Stream { handler: handler.to_string(), processor: processor.to_string() }
+    }
+}
+
+impl std::fmt::Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.handler, self.processor)
+    }
+}
+
+/// The streams produced by `handlers` (in registration order) with `processors` enabled.
+pub fn streams<'a>(handlers: impl IntoIterator<Item = &'a str>, processors: &Processors) -> Vec<Stream> {
+    let handler_processors = [
+        (processors.balances, "balances"),
+        (processors.allowances, "allowances"),
+        (processors.total_supplies, "total_supplies"),
+        (processors.token_uri, "token_uri"),
+    ];
+    let mut streams = Vec::new();
+    for handler in handlers {
+        streams.push(Stream::new(handler, EVENTS));
+        for (_, processor) in handler_processors.iter().filter(|(enabled, _)| *enabled) {
+            streams.push(Stream::new(handler, processor));
+        }
+    }
+    if processors.all_blocks {
+        streams.push(Stream::new(CHAIN_WIDE, "all_blocks"));
+    } else if processors.blocks {
+        streams.push(Stream::new(CHAIN_WIDE, "blocks"));
+    }
+    streams
+}
+
+/// The streams indexed together over one block range.
+#[derive(Clone, Debug)]
+pub struct Pass {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub streams: BTreeSet<Stream>,
+}
+
+impl Pass {
+    /// Whether the pass includes the handler's own events.
+    pub fn has_events(&self, handler: &str) -> bool {
+        self.streams.contains(&Stream::new(handler, EVENTS))
+    }
+
+    /// Whether the pass includes any stream of the handler.
+    pub fn has_handler(&self, handler: &str) -> bool {
+        self.streams.iter().any(|stream| stream.handler == handler)
+    }
+
+    /// The processors to apply to the logs of `handler` in this pass.
+    pub fn processors(&self, handler: &str) -> Processors {
+        let has = |processor: &str| self.streams.contains(&Stream::new(handler, processor));
+        Processors {
+            balances: has("balances"),
+            allowances: has("allowances"),
+            total_supplies: has("total_supplies"),
+            token_uri: has("token_uri"),
+            blocks: self.streams.contains(&Stream::new(CHAIN_WIDE, "blocks")),
+            all_blocks: self.streams.contains(&Stream::new(CHAIN_WIDE, "all_blocks")),
+        }
+    }
+}
+
+/// What the head of the indexer is to do next.
+pub enum HeadClaim {
+    Pass(Pass),
+    CaughtUp,                             // Every stream at the head is indexed up to the end block
+    Busy,                                 // The streams at the head are still being caught up
+}
+
+#[derive(Clone, Copy)]
+struct Position {
+    next_block: u64,                      // First block the stream still lacks
+    busy: bool,                           // In a pass that is not done yet
+}
+
+/// Where every stream stands. Passes claim their streams and blocks up front, so the head and the
+/// catch-up of lagging streams never index the same stream at once.
+pub struct Progress {
+    positions: BTreeMap<Stream, Position>,
+}
+
+impl Progress {
+    /// Starts every stream at its first missing block.
+    pub fn new(next_blocks: impl IntoIterator<Item = (Stream, u64)>) -> Self {
+        let positions = next_blocks.into_iter().map(|(stream, next_block)| (stream, Position { next_block, busy: false })).collect();
+        Progress { positions }
+    }
+
+    /// The first block the furthest streams lack.
+    pub fn head(&self) -> Option<u64> {
+        self.positions.values().map(|position| position.next_block).max()
+    }
+
+    /// Whether some streams are behind the others.
+    pub fn is_lagging(&self) -> bool {
+        let mut next_blocks = self.positions.values().map(|position| position.next_block);
+        next_blocks.next().is_some_and(|first| next_blocks.any(|next_block| next_block != first))
+    }
+
+    /// Claims the next range of the streams at the head, up to `end_block`.
+    pub fn claim_head(&mut self, end_block: u64, block_range: u64) -> HeadClaim {
+        let Some(head) = self.head() else {
+            return HeadClaim::CaughtUp;
+        };
+        if head > end_block {
+            return HeadClaim::CaughtUp;
+        }
+        let streams: BTreeSet<Stream> = self
+            .positions
+            .iter()
+            .filter(|(_, position)| position.next_block == head && !position.busy)
+            .map(|(stream, _)| stream.clone())
+            .collect();
+        if streams.is_empty() {
+            return HeadClaim::Busy;
+        }
+        HeadClaim::Pass(self.claim(head, end_block.min(head + block_range - 1), streams))
+    }
+
+    /// Claims the next range of the streams furthest behind, up to the block the next streams stand
+    /// at, so they join them. None once no idle stream is behind.
+    pub fn claim_lagging(&mut self, block_range: u64) -> Option<Pass> {
+        let head = self.head()?;
+        let from_block = self
+            .positions
+            .values()
+            .filter(|position| position.next_block < head && !position.busy)
+            .map(|position| position.next_block)
+            .min()?;
+        let next_position = self.positions.values().map(|position| position.next_block).filter(|next_block| *next_block > from_block).min()?;
+        let streams: BTreeSet<Stream> = self
+            .positions
+            .iter()
+            .filter(|(_, position)| position.next_block == from_block && !position.busy)
+            .map(|(stream, _)| stream.clone())
+            .collect();
+        let to_block = (next_position - 1).min(from_block + block_range - 1);
+        Some(self.claim(from_block, to_block, streams))
+    }
+
+    /// Marks the streams of a pass as done with its range.
+    pub fn release(&mut self, pass: &Pass) {
+        for stream in &pass.streams {
+            if let Some(position) = self.positions.get_mut(stream) {
+                position.busy = false;
+            }
+        }
+    }
+
+    fn claim(&mut self, from_block: u64, to_block: u64, streams: BTreeSet<Stream>) -> Pass {
+        for stream in &streams {
+            if let Some(position) = self.positions.get_mut(stream) {
+                position.next_block = to_block + 1;
+                position.busy = true;
+            }
+        }
+        Pass { from_block, to_block, streams }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(next_blocks: &[(&str, u64)]) -> Progress {
+        Progress::new(next_blocks.iter().map(|(handler, next_block)| (Stream::new(handler, EVENTS), *next_block)))
+    }
+
+    fn handlers(pass: &Pass) -> Vec<&str> {
+        pass.streams.iter().map(|stream| stream.handler.as_str()).collect()
+    }
+
+    fn head_pass(progress: &mut Progress, end_block: u64, block_range: u64) -> Pass {
+        match progress.claim_head(end_block, block_range) {
+            HeadClaim::Pass(pass) => pass,
+            HeadClaim::CaughtUp => panic!("caught up"),
+            HeadClaim::Busy => panic!("busy"),
+        }
+    }
+
+    #[test]
+    fn claim_head_covers_the_streams_at_the_head_up_to_the_end_block() {
+        let mut progress = progress(&[("a", 10), ("b", 10)]);
+        let pass = head_pass(&mut progress, 100, 5);
+        assert_eq!((pass.from_block, pass.to_block, handlers(&pass)), (10, 14, vec!["a", "b"]));
+
+        progress.release(&pass);
+        let pass = head_pass(&mut progress, 17, 5);
+        assert_eq!((pass.from_block, pass.to_block), (15, 17));
+
+        progress.release(&pass);
+        assert!(matches!(progress.claim_head(17, 5), HeadClaim::CaughtUp));
+    }
+
+    #[test]
+    fn claim_head_is_busy_until_the_pass_is_released() {
+        let mut progress = progress(&[("a", 10)]);
+        let pass = head_pass(&mut progress, 100, 5);
+        assert!(matches!(progress.claim_head(100, 5), HeadClaim::Busy));
+
+        progress.release(&pass);
+        assert_eq!(head_pass(&mut progress, 100, 5).from_block, 15);
+    }
+
+    #[test]
+    fn claim_head_leaves_lagging_streams_out() {
+        let mut progress = progress(&[("a", 10), ("b", 50)]);
+        assert!(progress.is_lagging());
+        let pass = head_pass(&mut progress, 100, 5);
+        assert_eq!((pass.from_block, pass.to_block, handlers(&pass)), (50, 54, vec!["b"]));
+    }
+
+    #[test]
+    fn claim_lagging_stops_where_the_next_streams_stand() {
+        let mut progress = progress(&[("a", 10), ("b", 30), ("c", 50)]);
+        let pass = progress.claim_lagging(100).unwrap();
+        assert_eq!((pass.from_block, pass.to_block, handlers(&pass)), (10, 29, vec!["a"]));
+
+        progress.release(&pass);
+        let pass = progress.claim_lagging(100).unwrap();
+        assert_eq!((pass.from_block, pass.to_block, handlers(&pass)), (30, 49, vec!["a", "b"]));
+
+        progress.release(&pass);
+        assert!(!progress.is_lagging());
+        assert!(progress.claim_lagging(100).is_none());
+    }
+
+    #[test]
+    fn claim_lagging_is_limited_by_the_block_range() {
+        let mut progress = progress(&[("a", 10), ("b", 50)]);
+        let pass = progress.claim_lagging(15).unwrap();
+        assert_eq!((pass.from_block, pass.to_block), (10, 24));
+    }
+
+    #[test]
+    fn lagging_and_head_passes_never_share_a_stream() {
+        let mut progress = progress(&[("a", 10), ("b", 50)]);
+        let head = head_pass(&mut progress, 100, 5);
+        let lagging = progress.claim_lagging(100).unwrap();
+        assert!(head.streams.is_disjoint(&lagging.streams));
+        // The head claimed 50..=54 up front, so a catches up to 55
+        assert_eq!((lagging.from_block, lagging.to_block), (10, 54));
+
+        progress.release(&lagging);
+        assert!(progress.claim_lagging(100).is_none());
+        let pass = head_pass(&mut progress, 100, 5);
+        assert_eq!((pass.from_block, pass.to_block, handlers(&pass)), (55, 59, vec!["a"]));
+    }
+}
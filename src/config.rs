@@ -0,0 +1,555 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Args, Subcommand, ValueEnum};
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::address_filter::{AddressFilter, AddressFilterConfig};
+use crate::db::PoolConfig;
+use crate::handlers::{load_contracts, AbiHandler, ContractConfig, EventHandler, ScriptHandler, ScriptLimits, SystemHandler};
+use crate::indexer::{Finality, Processors};
+use crate::presets::preset;
+use crate::sinks::SinkSpec;
+use crate::TokenType;
+
+pub type ConfigResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Read when no config file is given, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "scraper.toml";
+
+/// Checkpoint file used when a profile names none.
+pub const DEFAULT_CHECKPOINT_FILE: &str = "lastProcessedBlock.txt";
+
+/// A configuration file: named `[profile.<name>]` tables, one of which is used per run.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>,  // Used when no profile is selected
+    #[serde(default, rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Everything a run is configured with. Unset values fall back to the environment and built-in
+/// defaults; command-line flags override them.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip)]
+    pub name: Option<String>,             // Name of the profile, None without a config file
+    #[serde(skip)]
+    pub base_dir: PathBuf,                // Directory relative paths are resolved against
+    pub chain: Option<String>,            // Label of the indexed chain, used in log messages
+    pub chain_id: Option<u64>,            // Chain served by `rpc_url`, detected from it when unset
+    pub rpc_url: Option<String>,
+    pub chains: Vec<ChainConfig>,         // Chains indexed side by side instead of the one of `rpc_url`
+    pub database_url: Option<String>,     // Postgres URL or SQLite path
+    pub standards: BTreeSet<TokenType>,   // e.g. ["erc20", "erc721"]
+    pub processors: Processors,
+    pub contracts: Vec<ContractConfig>,   // Contracts decoded with their ABI
+    pub contracts_file: Option<PathBuf>,  // JSON list of more such contracts
+    pub scripts: Vec<PathBuf>,            // Rhai handlers
+    pub script_limits: ScriptLimitsConfig,
+    pub sinks: Vec<SinkSpec>,             // `db` when empty
+    pub block_range: Option<u64>,
+    pub log_limit: Option<usize>,         // Logs per `eth_getLogs` response the provider allows
+    pub address_limit: Option<usize>,     // Addresses per `eth_getLogs` request the provider allows
+    pub addresses: Option<AddressFilterConfig>,  // Allowlist and denylist of contracts
+    pub finality: Option<Finality>,
+    pub poll_interval: Option<u64>,       // Seconds between two polls of the chain head by `follow`
+    pub system_handlers: Option<Vec<SystemHandler>>,
+    pub concurrency: Option<usize>,       // Logs handled at once, unlimited when unset
+    pub checkpoint_file: Option<String>,  // Imported into the database when it has no checkpoint yet
+    pub shutdown_timeout: Option<u64>,    // Seconds the ranges in flight get on SIGINT/SIGTERM
+    pub leader_election: bool,            // `follow` only ingests while holding the chain's leader lock
+    pub pool: PoolConfig,
+    pub migrations: MigrationMode,
+}
+
+/// A chain indexed by a profile listing several, with its own RPC endpoint, checkpoint and blocks.
+/// Unset values fall back to the profile's, then to the preset of the chain.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainConfig {
+    pub chain_id: Option<u64>,            // Required in `chains`; detected from the RPC for a single chain
+    pub name: Option<String>,             // Label used in log messages
+    pub rpc_url: Option<String>,
+    pub block_range: Option<u64>,
+    pub log_limit: Option<usize>,
+    pub address_limit: Option<usize>,
+    pub addresses: Option<AddressFilterConfig>,  // Replaces the profile's lists, addresses being per chain
+    pub finality: Option<Finality>,
+    pub poll_interval: Option<u64>,
+    pub system_handlers: Option<Vec<SystemHandler>>,  // An empty list disables those of the preset
+    pub concurrency: Option<usize>,
+    pub checkpoint_file: Option<String>,  // lastProcessedBlock.<chain_id>.txt when several chains run
+    pub start_block: Option<u64>,         // First block indexed when there is no checkpoint
+    pub end_block: Option<u64>,           // Last block indexed, instead of following the chain head
+}
+
+impl ChainConfig {
+    /// Name of the chain in log messages.
+    pub fn label(&self) -> String {
+        match (&self.name, self.chain_id) {
+            (Some(name), _) => name.clone(),
+            (None, Some(chain_id)) => format!("chain {}", chain_id),
+            (None, None) => "chain".to_string(),
+        }
+    }
+
+    pub fn checkpoint_file(&self) -> &str {
+        self.checkpoint_file.as_deref().unwrap_or(DEFAULT_CHECKPOINT_FILE)
+    }
+
+    /// The allowlist and denylist of the chain, their files read once already.
+    pub fn address_filter(&self) -> ConfigResult<AddressFilter> {
+        match &self.addresses {
+            Some(addresses) => AddressFilter::from_config(addresses),
+            None => Ok(AddressFilter::new()),
+        }
+    }
+
+    /// This chain as `chain_id`, with the settings it leaves unset taken from the chain's preset.
+    pub fn with_preset(self, chain_id: u64) -> ChainConfig {
+        let Some(preset) = preset(chain_id) else {
+            return ChainConfig { chain_id: Some(chain_id), ..self };
+        };
+        ChainConfig {
+            chain_id: Some(chain_id),
+            name: self.name.or_else(|| Some(preset.name.to_string())),
+            block_range: self.block_range.or(Some(preset.block_range)),
+            log_limit: self.log_limit.or(Some(preset.log_limit)),
+            finality: self.finality.or(Some(preset.finality)),
+            poll_interval: self.poll_interval.or(Some(preset.block_time)),
+            system_handlers: self.system_handlers.or_else(|| Some(preset.system_handlers.to_vec())),
+            ..self
+        }
+    }
+}
+
+/// What is done with the migrations a database lacks when the scraper starts.
+#[derive(Copy, Clone, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationMode {
+    #[default]
+    Apply,                                // Apply them
+    Verify,                               // Refuse to start until they are applied with `migrate`
+}
+
+/// Per-log limits of the scripts, in config file units.
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScriptLimitsConfig {
+    pub max_operations: Option<u64>,
+    pub timeout_ms: Option<u64>,
+}
+
+impl ScriptLimitsConfig {
+    pub fn limits(&self) -> ScriptLimits {
+        let defaults = ScriptLimits::default();
+        ScriptLimits {
+            max_operations: self.max_operations.unwrap_or(defaults.max_operations),
+            timeout: self.timeout_ms.map(Duration::from_millis).unwrap_or(defaults.timeout),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(contents: &str) -> ConfigResult<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+}
+
+/// Loads the profile `name` (or the default one) of the config file at `path`, or of
+/// `scraper.toml` when it exists. Without a config file, an empty profile is returned.
+pub fn load_profile(path: Option<&Path>, name: Option<&str>) -> ConfigResult<Profile> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => PathBuf::from(DEFAULT_CONFIG_FILE),
+        None => {
+            return match name {
+                Some(name) => Err(format!("Profile `{}` selected, but there is no {}", name, DEFAULT_CONFIG_FILE).into()),
+                None => Ok(Profile::default()),
+            };
+        }
+    };
+
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Cannot read config file {}: {}", path.display(), e))?;
+    let mut config = Config::parse(&contents).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+
+    let available = config.profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+    let name = match name.map(str::to_string).or_else(|| config.default_profile.clone()) {
+        Some(name) => name,
+        None if config.profiles.len() == 1 => config.profiles.keys().next().cloned().unwrap_or_default(),
+        None if config.profiles.is_empty() => return Ok(Profile::default()),
+        None => return Err(format!("{} has several profiles ({}); select one or set `default_profile`", path.display(), available).into()),
+    };
+    let mut profile = match config.profiles.remove(&name) {
+        Some(profile) => profile,
+        None => return Err(format!("No profile `{}` in {} (available: {})", name, path.display(), available).into()),
+    };
+
+    profile.name = Some(name);
+    profile.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    profile.contracts_file = profile.contracts_file.map(|file| profile.base_dir.join(file));
+    profile.scripts = profile.scripts.iter().map(|script| profile.base_dir.join(script)).collect();
+    profile.addresses = profile.addresses.map(|addresses| addresses.relative_to(&profile.base_dir));
+    for chain in &mut profile.chains {
+        chain.addresses = chain.addresses.take().map(|addresses| addresses.relative_to(&profile.base_dir));
+    }
+    Ok(profile)
+}
+
+impl Profile {
+    pub fn block_range(&self) -> u64 {
+        self.block_range.unwrap_or(crate::indexer::DEFAULT_BLOCK_RANGE)
+    }
+
+    pub fn checkpoint_file(&self) -> &str {
+        self.checkpoint_file.as_deref().unwrap_or(DEFAULT_CHECKPOINT_FILE)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout.map(Duration::from_secs).unwrap_or(crate::indexer::DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    /// The chains to index: those listed in `chains`, completed with the profile's settings, or the
+    /// single chain of `rpc_url`.
+    pub fn chains(&self) -> Vec<ChainConfig> {
+        if self.chains.is_empty() {
+            return vec![ChainConfig {
+                chain_id: self.chain_id,
+                name: self.chain.clone(),
+                rpc_url: self.rpc_url.clone(),
+                block_range: self.block_range,
+                log_limit: self.log_limit,
+                address_limit: self.address_limit,
+                addresses: self.addresses.clone(),
+                finality: self.finality,
+                poll_interval: self.poll_interval,
+                system_handlers: self.system_handlers.clone(),
+                concurrency: self.concurrency,
+                checkpoint_file: Some(self.checkpoint_file().to_string()),
+                start_block: None,
+                end_block: None,
+            }];
+        }
+
+        // Chains sharing a checkpoint file would overwrite each other's progress
+        let single = self.chains.len() == 1;
+        self.chains
+            .iter()
+            .map(|chain| {
+                let default_checkpoint = match (&self.checkpoint_file, chain.chain_id) {
+                    (Some(file), _) if single => file.clone(),
+                    (_, Some(chain_id)) => format!("lastProcessedBlock.{}.txt", chain_id),
+                    (_, None) => DEFAULT_CHECKPOINT_FILE.to_string(),
+                };
+                ChainConfig {
+                    block_range: chain.block_range.or(self.block_range),
+                    log_limit: chain.log_limit.or(self.log_limit),
+                    address_limit: chain.address_limit.or(self.address_limit),
+                    addresses: chain.addresses.clone().or_else(|| self.addresses.clone()),
+                    finality: chain.finality.or(self.finality),
+                    poll_interval: chain.poll_interval.or(self.poll_interval),
+                    system_handlers: chain.system_handlers.clone().or_else(|| self.system_handlers.clone()),
+                    concurrency: chain.concurrency.or(self.concurrency),
+                    checkpoint_file: Some(chain.checkpoint_file.clone().unwrap_or(default_checkpoint)),
+                    ..chain.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// Restricts the profile to the chain `chain_id`: the one listed in `chains` with that id, or
+    /// the chain of `rpc_url` when none are listed.
+    pub fn select_chain(&mut self, chain_id: u64) -> ConfigResult<()> {
+        if self.chains.is_empty() {
+            self.chain_id = Some(chain_id);
+            return Ok(());
+        }
+        let listed: Vec<String> = self.chains.iter().filter_map(|chain| chain.chain_id).map(|id| id.to_string()).collect();
+        self.chains.retain(|chain| chain.chain_id == Some(chain_id));
+        if self.chains.is_empty() {
+            return Err(format!("Chain {} is not configured (configured: {})", chain_id, listed.join(", ")).into());
+        }
+        Ok(())
+    }
+
+    pub fn sinks(&self) -> Vec<SinkSpec> {
+        if self.sinks.is_empty() {
+            return vec![SinkSpec::Storage];
+        }
+        self.sinks.clone()
+    }
+
+    /// Every problem preventing an indexing run with this profile, without reading any file.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.chains.is_empty() {
+            match self.rpc_url.as_deref().map(Url::parse) {
+                None => errors.push("No RPC URL: set `rpc_url`, RPC_URL or --rpc-url".to_string()),
+                Some(Err(e)) => errors.push(format!("Invalid RPC URL: {}", e)),
+                Some(Ok(_)) => {}
+            }
+        }
+        errors.extend(self.validate_chains());
+        if self.database_url.is_none() {
+            errors.push("No database: set `database_url`, DATABASE_URL or --database-url".to_string());
+        }
+        if self.standards.is_empty() && self.contracts.is_empty() && self.contracts_file.is_none() && self.scripts.is_empty() {
+            errors.push("Nothing to index: enable a standard (`standards`, --erc20, ...), a contract or a script".to_string());
+        }
+        if self.block_range == Some(0) {
+            errors.push("`block_range` must be at least 1".to_string());
+        }
+        if self.concurrency == Some(0) {
+            errors.push("`concurrency` must be at least 1".to_string());
+        }
+        if self.log_limit == Some(0) {
+            errors.push("`log_limit` must be at least 1".to_string());
+        }
+        if self.address_limit == Some(0) {
+            errors.push("`address_limit` must be at least 1".to_string());
+        }
+        if self.poll_interval == Some(0) {
+            errors.push("`poll_interval` must be at least 1".to_string());
+        }
+        if self.pool.max_size == 0 {
+            errors.push("`pool.max_size` must be at least 1".to_string());
+        }
+        if self.pool.min_idle > self.pool.max_size {
+            errors.push(format!("`pool.min_idle` ({}) exceeds `pool.max_size` ({})", self.pool.min_idle, self.pool.max_size));
+        }
+        for contract in &self.contracts {
+            if contract.addresses.is_empty() {
+                errors.push(format!("Contract {} has no addresses", contract.name));
+            }
+        }
+
+        errors
+    }
+
+    // Problems with the `chains` list; the profile's own settings are checked by `validate`
+    fn validate_chains(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.chains.is_empty() {
+            return errors;
+        }
+
+        let mut chain_ids = BTreeSet::new();
+        let mut checkpoint_files = BTreeSet::new();
+        for chain in self.chains() {
+            let label = chain.label();
+            match chain.chain_id {
+                None => errors.push(format!("A chain of `chains` ({}) has no `chain_id`", label)),
+                Some(chain_id) if !chain_ids.insert(chain_id) => errors.push(format!("Chain {} is listed twice", chain_id)),
+                Some(_) => {}
+            }
+            match chain.rpc_url.as_deref().map(Url::parse) {
+                None => errors.push(format!("No RPC URL for {}: set its `rpc_url`", label)),
+                Some(Err(e)) => errors.push(format!("Invalid RPC URL for {}: {}", label, e)),
+                Some(Ok(_)) => {}
+            }
+            if chain.block_range == Some(0) {
+                errors.push(format!("`block_range` of {} must be at least 1", label));
+            }
+            if chain.concurrency == Some(0) {
+                errors.push(format!("`concurrency` of {} must be at least 1", label));
+            }
+            if chain.log_limit == Some(0) {
+                errors.push(format!("`log_limit` of {} must be at least 1", label));
+            }
+            if chain.address_limit == Some(0) {
+                errors.push(format!("`address_limit` of {} must be at least 1", label));
+            }
+            if chain.poll_interval == Some(0) {
+                errors.push(format!("`poll_interval` of {} must be at least 1", label));
+            }
+            if let (Some(start_block), Some(end_block)) = (chain.start_block, chain.end_block) {
+                if start_block > end_block {
+                    errors.push(format!("`start_block` {} of {} is after its `end_block` {}", start_block, label, end_block));
+                }
+            }
+            if !checkpoint_files.insert(chain.checkpoint_file().to_string()) {
+                errors.push(format!("{} shares its checkpoint file {} with another chain", label, chain.checkpoint_file()));
+            }
+        }
+
+        errors
+    }
+
+    /// Applies command-line overrides. Standards and processors enabled by flags add to those of the
+    /// profile, scripts are appended and sinks replace the profile's.
+    pub fn apply_args(&mut self, args: &ProfileArgs) {
+        if args.checkpoint_file.is_some() {
+            self.checkpoint_file.clone_from(&args.checkpoint_file);
+        }
+        self.block_range = args.block_range.or(self.block_range);
+        self.concurrency = args.concurrency.or(self.concurrency);
+        self.shutdown_timeout = args.shutdown_timeout.or(self.shutdown_timeout);
+        self.contracts_file = args.contracts.clone().or(self.contracts_file.take());
+        self.scripts.extend(args.scripts.iter().cloned());
+        self.script_limits.max_operations = args.script_max_operations.or(self.script_limits.max_operations);
+        self.script_limits.timeout_ms = args.script_timeout_ms.or(self.script_limits.timeout_ms);
+        if !args.sinks.is_empty() {
+            self.sinks = args.sinks.clone();
+        }
+
+        let standards = [
+            (args.erc20, TokenType::ERC20),
+            (args.erc721, TokenType::ERC721),
+            (args.erc1155, TokenType::ERC1155),
+            (args.erc777, TokenType::ERC777),
+        ];
+        self.standards.extend(standards.into_iter().filter(|(enabled, _)| *enabled).map(|(_, standard)| standard));
+
+        let processors = &mut self.processors;
+        processors.balances |= args.process_balances;
+        processors.allowances |= args.process_allowances;
+        processors.total_supplies |= args.process_total_supplies;
+        processors.token_uri |= args.process_token_uri;
+        processors.blocks |= args.process_blocks;
+        processors.all_blocks |= args.process_all_blocks;
+    }
+
+    /// Loads the ABI and script handlers of the profile.
+    pub fn custom_handlers(&self) -> ConfigResult<Vec<Arc<dyn EventHandler>>> {
+        let mut handlers: Vec<Arc<dyn EventHandler>> = Vec::new();
+        for contract in &self.contracts {
+            handlers.push(Arc::new(AbiHandler::from_config(contract, &self.base_dir)?));
+        }
+        if let Some(file) = &self.contracts_file {
+            for contract in load_contracts(&file.to_string_lossy())? {
+                handlers.push(Arc::new(contract));
+            }
+        }
+        for script in &self.scripts {
+            handlers.push(Arc::new(ScriptHandler::load(script, self.script_limits.limits())?));
+        }
+        Ok(handlers)
+    }
+}
+
+/// Every problem preventing an indexing run with this profile, including unreadable ABIs and
+/// scripts that do not compile.
+pub fn check(profile: &Profile) -> Vec<String> {
+    let mut errors = profile.validate();
+    if let Err(e) = profile.custom_handlers() {
+        errors.push(e.to_string());
+    }
+    for chain in profile.chains() {
+        if let Err(e) = chain.address_filter() {
+            errors.push(e.to_string());
+        }
+    }
+    errors
+}
+
+/// Inspects the configuration file.
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the selected profile with the environment and command-line overrides applied, and print it
+    Check(ProfileArgs),
+}
+
+/// Command-line overrides of the indexing settings of a profile.
+#[derive(Args, Clone, Default)]
+pub struct ProfileArgs {
+    /// Blocks requested from the RPC at once (default 10000)
+    #[arg(long, env = "BLOCK_RANGE")]
+    pub block_range: Option<u64>,
+
+    /// Maximum number of logs handled at once (default: unlimited)
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+
+    /// Seconds the ranges in flight get to be decoded on SIGINT/SIGTERM before they are dropped (default 30)
+    #[arg(long, value_name = "SECONDS")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// File the last indexed block was recorded in before checkpoints were stored in the database;
+    /// imported when the database has none yet (default lastProcessedBlock.txt)
+    #[arg(long, value_name = "PATH")]
+    pub checkpoint_file: Option<String>,
+
+    /// Include ERC20 events
+    #[arg(long)]
+    pub erc20: bool,
+
+    /// Include ERC721 events
+    #[arg(long)]
+    pub erc721: bool,
+
+    /// Include ERC1155 events
+    #[arg(long)]
+    pub erc1155: bool,
+
+    /// Include ERC777 events
+    #[arg(long)]
+    pub erc777: bool,
+
+    /// JSON file listing contracts (name, ABI file, addresses) whose events are decoded with their ABI into
+    /// the `contract_events` table
+    #[arg(long, env = "CONTRACTS_FILE", value_name = "PATH")]
+    pub contracts: Option<PathBuf>,
+
+    /// Rhai script handling events at runtime (see README); repeat to load several scripts
+    #[arg(long = "script", value_name = "PATH")]
+    pub scripts: Vec<PathBuf>,
+
+    /// Operations a script may run per log before it is aborted (default 100000)
+    #[arg(long)]
+    pub script_max_operations: Option<u64>,
+
+    /// Milliseconds a script may run per log before it is aborted (default 100)
+    #[arg(long)]
+    pub script_timeout_ms: Option<u64>,
+
+    /// Process balances
+    #[arg(long)]
+    pub process_balances: bool,
+
+    /// Process allowances
+    #[arg(long)]
+    pub process_allowances: bool,
+
+    /// Process total supplies
+    #[arg(long)]
+    pub process_total_supplies: bool,
+
+    #[arg(long)]
+    pub process_token_uri: bool,
+
+    /// Store block headers (number, hash, parent hash, timestamp) for blocks containing processed logs
+    #[arg(long)]
+    pub process_blocks: bool,
+
+    /// Store block headers for every block in the scraped range, not only those containing logs
+    #[arg(long)]
+    pub process_all_blocks: bool,
+
+    /// Where decoded events are written: `db` (the DATABASE_URL storage, the default), `jsonl` (stdout),
+    /// `jsonl:<path>` or `nats://host:port[/subject]`; repeat to write to several sinks
+    #[arg(long = "sink", value_name = "SINK")]
+    pub sinks: Vec<SinkSpec>,
+}
+
+/// `url` without its password, or its path and query for RPC endpoints (which often carry an API key).
+pub fn redact_url(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    if parsed.password().is_some() {
+        let _ = parsed.set_password(Some("***"));
+    }
+    if matches!(parsed.scheme(), "http" | "https" | "ws" | "wss") && (parsed.path() != "/" || parsed.query().is_some()) {
+        parsed.set_path("/***");
+        parsed.set_query(None);
+    }
+    parsed.to_string()
+}
@@ -65,6 +65,38 @@ pub static ERC_APPROVAL_FOR_ALL_SIGNATURE: Lazy<H256> = Lazy::new(|| {
     ))
 });
 
+// Native token movements logged by Polygon PoS system contracts
+pub static POLYGON_LOG_TRANSFER_SIGNATURE: Lazy<H256> = Lazy::new(|| {
+    H256::from_slice(&hex_literal::hex!(
+        "e6497e3ee548a3372136af2fcb0696db31fc6cf20260707645068bd3fe97f3c4"
+    ))
+});
+
+pub static POLYGON_LOG_FEE_TRANSFER_SIGNATURE: Lazy<H256> = Lazy::new(|| {
+    H256::from_slice(&hex_literal::hex!(
+        "4dfe1bbbcf077ddc3e01291eea2d5c70c2b422b415d95645b9adcfd678cb1d63"
+    ))
+});
+
+// Mints and burns of the zkSync Era base token, which are not logged as `Transfer`
+pub static ZKSYNC_MINT_SIGNATURE: Lazy<H256> = Lazy::new(|| {
+    H256::from_slice(&hex_literal::hex!(
+        "0f6798a560793a54c3bcfe86a93cde1e73087d944c0ea20544137d4121396885"
+    ))
+});
+
+pub static ZKSYNC_WITHDRAWAL_SIGNATURE: Lazy<H256> = Lazy::new(|| {
+    H256::from_slice(&hex_literal::hex!(
+        "2717ead6b9200dd235aad468c9809ea400fe33ac69b5bfaa6d3e90fc922b6398"
+    ))
+});
+
+pub static ZKSYNC_WITHDRAWAL_WITH_MESSAGE_SIGNATURE: Lazy<H256> = Lazy::new(|| {
+    H256::from_slice(&hex_literal::hex!(
+        "c405fe8958410bbaf0c73b7a0c3e20859e86ca168a4c9b0def9c54d2555a306b"
+    ))
+});
+
 // Use `Abigen` to generate the contract bindings at compile time
 abigen!(
     ERC20,
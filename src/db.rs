@@ -1,19 +1,45 @@
 use diesel::pg::PgConnection;
 use dotenv::dotenv;
 use std::env;
+use std::sync::Arc;
 use diesel::r2d2::{self, ConnectionManager};
 use r2d2::Pool;
+use serde::Deserialize;
+
+use crate::storage::{open_storage, Storage};
 
 // Create a type alias for the connection pool
 pub type DbPool = Pool<ConnectionManager<PgConnection>>;
 
-pub fn establish_connection_pool() -> DbPool {
+/// Opens the Postgres database or SQLite file named by `DATABASE_URL`.
+pub fn establish_storage() -> Arc<dyn Storage> {
     dotenv().ok();
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    open_storage(&database_url).expect("Failed to open storage.")
+}
+
+/// Size of the Postgres connection pool; the SQLite storage keeps its own small pool.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    pub min_idle: u32,                    // Connections kept open while idle
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig { max_size: 100, min_idle: 32 }
+    }
+}
+
+pub fn establish_connection_pool(database_url: &str) -> DbPool {
+    build_connection_pool(database_url, PoolConfig::default()).expect("Failed to create pool.")
+}
+
+pub fn build_connection_pool(database_url: &str, config: PoolConfig) -> Result<DbPool, r2d2::PoolError> {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     Pool::builder()
-        .max_size(100)
-        .min_idle(Some(32))
+        .max_size(config.max_size)
+        .min_idle(Some(config.min_idle))
         .build(manager)
-        .expect("Failed to create pool.")
-}
\ No newline at end of file
+}
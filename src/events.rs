@@ -0,0 +1,261 @@
+use once_cell::sync::Lazy;
+use serde::{Serialize, Serializer};
+use tokio::sync::broadcast;
+
+use ethers::types::{Address, Log, H256};
+use ethers::utils::to_checksum;
+
+use crate::models::allowance::Allowance;
+use crate::models::balance::Balance;
+use crate::models::token_supply::TokenSupply;
+
+// Events buffered per subscriber before a slow subscriber starts missing them
+const CHANNEL_CAPACITY: usize = 10_000;
+
+/// A change applied by the scraper: rows as they were written, plus the transfers that caused them.
+#[derive(Clone)]
+pub enum ChangeEvent {
+    Balance(Balance),
+    Allowance(Allowance),
+    Supply(SupplyChange),
+    Transfer(Transfer),
+}
+
+/// A new total supply row and the signed amount it changed by.
+#[derive(Clone)]
+pub struct SupplyChange {
+    pub supply: TokenSupply,
+    pub delta: String,                    // Decimal string, prefixed with '-' for burns
+}
+
+/// A normalized event decoded from a log by the handlers and written by every configured sink.
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IndexedEvent {
+    Transfer(Transfer),
+    BalanceChange(BalanceChange),
+    AllowanceChange(AllowanceChange),
+    SupplyChange(TotalSupplyChange),
+    ContractEvent(ContractEvent),
+}
+
+/// A token movement decoded from a Transfer/TransferSingle/TransferBatch/Sent/Minted/Burned log.
+#[derive(Clone, Serialize)]
+pub struct Transfer {
+    #[serde(serialize_with = "checksummed")]
+    pub token_address: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub from: Address,                    // Zero address for mints
+    #[serde(serialize_with = "checksummed")]
+    pub to: Address,                      // Zero address for burns
+    pub token_id: Option<i16>,            // Token ID for ERC721/1155, None for ERC20/ERC777
+    pub value: String,                    // Decimal amount (1 for ERC721)
+    pub token_type: &'static str,
+    pub block_number: i32,
+    pub transaction_hash: Option<String>,
+    pub log_index: Option<u64>,
+    pub chain_id: u64,                    // Set by the indexer once the handlers decoded the log
+}
+
+impl Transfer {
+    /// Builds a transfer carrying the block and transaction coordinates of `log`.
+    pub fn from_log(log: &Log, from: Address, to: Address, token_id: Option<i16>, value: String, token_type: &'static str) -> Self {
+        Transfer {
+            token_address: log.address,
+            from,
+            to,
+            token_id,
+            value,
+            token_type,
+            block_number: log_block_number(log),
+            transaction_hash: log.transaction_hash.map(|hash| format!("{:?}", hash)),
+            log_index: log.log_index.map(|index| index.as_u64()),
+            chain_id: 0,
+        }
+    }
+}
+
+/// An amount added to (or, when prefixed with '-', subtracted from) a wallet's balance.
+#[derive(Clone, Serialize)]
+pub struct BalanceChange {
+    #[serde(serialize_with = "checksummed")]
+    pub wallet_address: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub token_address: Address,
+    pub delta: String,                    // Decimal string, prefixed with '-' for outgoing amounts
+    pub token_id: Option<i16>,            // Token ID for ERC721/1155, None for ERC20/ERC777
+    pub token_type: &'static str,
+    pub block_number: i32,
+    pub chain_id: u64,                    // Set by the indexer once the handlers decoded the log
+}
+
+/// An allowance or operator approval as set by an Approval/ApprovalForAll/AuthorizedOperator/RevokedOperator log.
+#[derive(Clone, Serialize)]
+pub struct AllowanceChange {
+    #[serde(serialize_with = "checksummed")]
+    pub owner_address: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub spender_address: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub token_address: Address,
+    pub allowance: String,                // Decimal amount, or 1/0 for approved/revoked operators
+    pub token_id: Option<i16>,            // Token ID for single-token ERC721 approvals
+    pub token_type: &'static str,
+    pub block_number: i32,
+    pub chain_id: u64,                    // Set by the indexer once the handlers decoded the log
+}
+
+/// An amount minted (or, when prefixed with '-', burned) from a token's total supply.
+#[derive(Clone, Serialize)]
+pub struct TotalSupplyChange {
+    #[serde(serialize_with = "checksummed")]
+    pub token_address: Address,
+    pub delta: String,                    // Decimal string, prefixed with '-' for burns
+    pub block_number: i32,
+    pub chain_id: u64,                    // Set by the indexer once the handlers decoded the log
+}
+
+/// An event of a contract configured with an ABI, with its parameters decoded into JSON.
+#[derive(Clone, Serialize)]
+pub struct ContractEvent {
+    #[serde(serialize_with = "checksummed")]
+    pub contract_address: Address,
+    pub contract_name: String,            // Name given to the contract in the contracts file
+    pub event_name: String,
+    pub event_signature: String,          // Canonical signature, e.g. "Deposit(address,uint256)"
+    pub params: serde_json::Value,        // Parameters by name; integers as decimal strings, bytes as 0x-hex
+    pub block_number: i32,
+    pub transaction_hash: H256,
+    pub transaction_index: u64,
+    pub log_index: u64,
+    pub chain_id: u64,                    // Set by the indexer once the handlers decoded the log
+}
+
+impl IndexedEvent {
+    /// Whether the event is a change derived by a processor (balances, allowances or total
+    /// supplies), rather than one of the handler's own events.
+    pub fn is_processor_change(&self) -> bool {
+        matches!(self, IndexedEvent::BalanceChange(_) | IndexedEvent::AllowanceChange(_) | IndexedEvent::SupplyChange(_))
+    }
+
+    pub fn transfer(log: &Log, from: Address, to: Address, token_id: Option<i16>, value: String, token_type: &'static str) -> Self {
+        IndexedEvent::Transfer(Transfer::from_log(log, from, to, token_id, value, token_type))
+    }
+
+    /// A balance change of `wallet` in the token that emitted `log`.
+    pub fn balance_change(log: &Log, wallet: Address, delta: String, token_id: Option<i16>, token_type: &'static str) -> Self {
+        IndexedEvent::BalanceChange(BalanceChange {
+            wallet_address: wallet,
+            token_address: log.address,
+            delta,
+            token_id,
+            token_type,
+            block_number: log_block_number(log),
+            chain_id: 0,
+        })
+    }
+
+    /// An allowance granted by `owner` to `spender` in the token that emitted `log`.
+    pub fn allowance_change(log: &Log, owner: Address, spender: Address, allowance: String, token_id: Option<i16>, token_type: &'static str) -> Self {
+        IndexedEvent::AllowanceChange(AllowanceChange {
+            owner_address: owner,
+            spender_address: spender,
+            token_address: log.address,
+            allowance,
+            token_id,
+            token_type,
+            block_number: log_block_number(log),
+            chain_id: 0,
+        })
+    }
+
+    /// A total supply change of the token that emitted `log`.
+    pub fn supply_change(log: &Log, delta: String) -> Self {
+        IndexedEvent::SupplyChange(TotalSupplyChange {
+            token_address: log.address,
+            delta,
+            block_number: log_block_number(log),
+            chain_id: 0,
+        })
+    }
+
+    /// An event decoded from `log` with the ABI of the contract named `contract_name`.
+    pub fn contract_event(log: &Log, contract_name: &str, event_name: &str, event_signature: String, params: serde_json::Value) -> Self {
+        IndexedEvent::ContractEvent(ContractEvent {
+            contract_address: log.address,
+            contract_name: contract_name.to_string(),
+            event_name: event_name.to_string(),
+            event_signature,
+            params,
+            block_number: log_block_number(log),
+            transaction_hash: log.transaction_hash.unwrap_or_default(),
+            transaction_index: log.transaction_index.map(|index| index.as_u64()).unwrap_or_default(),
+            log_index: log.log_index.map(|index| index.as_u64()).unwrap_or_default(),
+            chain_id: 0,
+        })
+    }
+
+    /// Records the chain the event was read from.
+    pub fn set_chain_id(&mut self, chain_id: u64) {
+        match self {
+            IndexedEvent::Transfer(event) => event.chain_id = chain_id,
+            IndexedEvent::BalanceChange(event) => event.chain_id = chain_id,
+            IndexedEvent::AllowanceChange(event) => event.chain_id = chain_id,
+            IndexedEvent::SupplyChange(event) => event.chain_id = chain_id,
+            IndexedEvent::ContractEvent(event) => event.chain_id = chain_id,
+        }
+    }
+
+    /// Short name of the event kind, as used in the `kind` field of its JSON form.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IndexedEvent::Transfer(_) => "transfer",
+            IndexedEvent::BalanceChange(_) => "balance_change",
+            IndexedEvent::AllowanceChange(_) => "allowance_change",
+            IndexedEvent::SupplyChange(_) => "supply_change",
+            IndexedEvent::ContractEvent(_) => "contract_event",
+        }
+    }
+}
+
+fn log_block_number(log: &Log) -> i32 {
+    log.block_number.map(|number| number.as_u32() as i32).unwrap_or_default()
+}
+
+fn checksummed<S: Serializer>(address: &Address, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_checksum(address, None))
+}
+
+impl ChangeEvent {
+    pub fn chain_id(&self) -> i64 {
+        match self {
+            ChangeEvent::Balance(balance) => balance.chain_id,
+            ChangeEvent::Allowance(allowance) => allowance.chain_id,
+            ChangeEvent::Supply(change) => change.supply.chain_id,
+            ChangeEvent::Transfer(transfer) => transfer.chain_id as i64,
+        }
+    }
+
+    pub fn block_number(&self) -> i32 {
+        match self {
+            ChangeEvent::Balance(balance) => balance.block_number,
+            ChangeEvent::Allowance(allowance) => allowance.block_number,
+            ChangeEvent::Supply(change) => change.supply.block_number,
+            ChangeEvent::Transfer(transfer) => transfer.block_number,
+        }
+    }
+}
+
+// In-process bus fed by the storage sink as it writes each row
+static CHANGE_BUS: Lazy<broadcast::Sender<ChangeEvent>> = Lazy::new(|| broadcast::channel(CHANNEL_CAPACITY).0);
+
+/// Publishes an applied change to every current subscriber.
+pub fn publish(event: ChangeEvent) {
+    // Sending only fails when nobody is subscribed, which is the normal case for a plain scrape
+    let _ = CHANGE_BUS.send(event);
+}
+
+/// Subscribes to changes published from now on.
+pub fn subscribe() -> broadcast::Receiver<ChangeEvent> {
+    CHANGE_BUS.subscribe()
+}
@@ -0,0 +1,464 @@
+pub mod writer;
+
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use diesel::prelude::*;
+use ethers::types::Address;
+use ethers::utils::hex;
+
+use crate::export::writer::{Cell, Column, ColumnType, PartitionedWriter};
+use crate::models::allowance::Allowance;
+use crate::models::balance::Balance;
+use crate::models::token_supply::TokenSupply;
+use crate::models::Token;
+use crate::PgPooledConnection;
+
+pub type ExportResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Rows read from the database per query
+const EXPORT_BATCH_SIZE: i64 = 50_000;
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum ExportFormat {
+    Parquet,
+    Csv,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// How amounts are written to Parquet files; CSV files always hold decimal strings.
+#[derive(Copy, Clone, ValueEnum)]
+pub enum AmountEncoding {
+    String,
+    Decimal128,                           // Decimal128(38, 0); larger amounts are written as null
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ExportTable {
+    Balances,
+    Allowances,
+    TokenSupplies,
+    Tokens,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Output file format
+    #[arg(long, value_enum, default_value = "parquet")]
+    format: ExportFormat,
+
+    /// Directory receiving one sub-directory per table
+    #[arg(long, default_value = "export")]
+    out_dir: PathBuf,
+
+    /// Tables to export, comma separated (default: all)
+    #[arg(long = "tables", value_enum, value_delimiter = ',')]
+    tables: Vec<ExportTable>,
+
+    /// Only rows written at or after this block
+    #[arg(long)]
+    from_block: Option<i32>,
+
+    /// Only rows written at or before this block
+    #[arg(long)]
+    to_block: Option<i32>,
+
+    /// Only rows of this token
+    #[arg(long)]
+    token: Option<Address>,
+
+    /// Only balances of, and allowances granted by or to, this wallet
+    #[arg(long)]
+    wallet: Option<Address>,
+
+    /// Blocks per partition directory (`block_start=<n>`)
+    #[arg(long, default_value_t = 100_000, value_parser = clap::value_parser!(i32).range(1..))]
+    partition_blocks: i32,
+
+    /// Parquet encoding of amounts
+    #[arg(long, value_enum, default_value = "string")]
+    amounts: AmountEncoding,
+}
+
+static BALANCE_COLUMNS: [Column; 7] = [
+    Column::new("id", ColumnType::Int32, false),
+    Column::new("wallet_address", ColumnType::Text, false),
+    Column::new("token_address", ColumnType::Text, false),
+    Column::new("balance", ColumnType::Amount, false),
+    Column::new("token_id", ColumnType::Int16, true),
+    Column::new("block_number", ColumnType::Int32, false),
+    Column::new("token_type", ColumnType::Text, false),
+];
+
+static ALLOWANCE_COLUMNS: [Column; 8] = [
+    Column::new("id", ColumnType::Int32, false),
+    Column::new("owner_address", ColumnType::Text, false),
+    Column::new("spender_address", ColumnType::Text, false),
+    Column::new("token_address", ColumnType::Text, false),
+    Column::new("allowance", ColumnType::Amount, true),
+    Column::new("block_number", ColumnType::Int32, false),
+    Column::new("token_id", ColumnType::Int16, true),
+    Column::new("token_type", ColumnType::Text, false),
+];
+
+static SUPPLY_COLUMNS: [Column; 4] = [
+    Column::new("id", ColumnType::Int32, false),
+    Column::new("token_address", ColumnType::Text, false),
+    Column::new("total_supply", ColumnType::Amount, false),
+    Column::new("block_number", ColumnType::Int32, false),
+];
+
+static TOKEN_COLUMNS: [Column; 7] = [
+    Column::new("token_address", ColumnType::Text, false),
+    Column::new("block_number", ColumnType::Int32, false),
+    Column::new("token_type", ColumnType::Text, false),
+    Column::new("name", ColumnType::Text, true),
+    Column::new("symbol", ColumnType::Text, true),
+    Column::new("decimals", ColumnType::Int16, true),
+    Column::new("granularity", ColumnType::Amount, true),
+];
+
+/// Exports the selected tables of the chain `chain` to `<out_dir>/<table>/`.
+pub fn run_export(conn: &mut PgPooledConnection, args: &ExportArgs, chain: i64) -> ExportResult<()> {
+    let tables = if args.tables.is_empty() {
+        vec![ExportTable::Balances, ExportTable::Allowances, ExportTable::TokenSupplies, ExportTable::Tokens]
+    } else {
+        args.tables.clone()
+    };
+
+    for table in tables {
+        match table {
+            ExportTable::Balances => export_rows(
+                conn,
+                args,
+                "balances",
+                &BALANCE_COLUMNS,
+                true,
+                |conn, after| load_balances(conn, args, chain, after),
+                |row: &Balance| (row.block_number, row.id),
+                |row| row.block_number,
+                |row| {
+                    vec![
+                        Cell::Int32(Some(row.id)),
+                        Cell::Text(Some(hex_address(&row.wallet_address))),
+                        Cell::Text(Some(hex_address(&row.token_address))),
+                        Cell::Amount(Some(row.balance)),
+                        Cell::Int16(row.token_id),
+                        Cell::Int32(Some(row.block_number)),
+                        Cell::Text(Some(row.token_type)),
+                    ]
+                },
+            )?,
+            ExportTable::Allowances => export_rows(
+                conn,
+                args,
+                "allowances",
+                &ALLOWANCE_COLUMNS,
+                true,
+                |conn, after| load_allowances(conn, args, chain, after),
+                |row: &Allowance| (row.block_number, row.id),
+                |row| row.block_number,
+                |row| {
+                    vec![
+                        Cell::Int32(Some(row.id)),
+                        Cell::Text(Some(hex_address(&row.owner_address))),
+                        Cell::Text(Some(hex_address(&row.spender_address))),
+                        Cell::Text(Some(hex_address(&row.token_address))),
+                        Cell::Amount(row.allowance),
+                        Cell::Int32(Some(row.block_number)),
+                        Cell::Int16(row.token_id),
+                        Cell::Text(Some(row.token_type)),
+                    ]
+                },
+            )?,
+            ExportTable::TokenSupplies => export_rows(
+                conn,
+                args,
+                "token_supplies",
+                &SUPPLY_COLUMNS,
+                true,
+                |conn, after| load_supplies(conn, args, chain, after),
+                |row: &TokenSupply| (row.block_number, row.id),
+                |row| row.block_number,
+                |row| {
+                    vec![
+                        Cell::Int32(Some(row.id)),
+                        Cell::Text(Some(hex_address(&row.token_address))),
+                        Cell::Amount(Some(row.total_supply)),
+                        Cell::Int32(Some(row.block_number)),
+                    ]
+                },
+            )?,
+            // Tokens are few and keyed by address, so they are written to a single file
+            ExportTable::Tokens => export_rows(
+                conn,
+                args,
+                "tokens",
+                &TOKEN_COLUMNS,
+                false,
+                |conn, after| load_tokens(conn, args, chain, after),
+                |row: &Token| row.token_address.clone(),
+                |row| row.block_number,
+                |row| {
+                    vec![
+                        Cell::Text(Some(hex_address(&row.token_address))),
+                        Cell::Int32(Some(row.block_number)),
+                        Cell::Text(Some(row.token_type)),
+                        Cell::Text(row.name),
+                        Cell::Text(row.symbol),
+                        Cell::Int16(row.decimals),
+                        Cell::Amount(row.granularity),
+                    ]
+                },
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Streams a table in keyset batches into a partitioned writer.
+#[allow(clippy::too_many_arguments)]
+fn export_rows<R, K>(
+    conn: &mut PgPooledConnection,
+    args: &ExportArgs,
+    table_name: &str,
+    columns: &'static [Column],
+    partitioned: bool,
+    load: impl Fn(&mut PgPooledConnection, Option<K>) -> QueryResult<Vec<R>>,
+    key_of: impl Fn(&R) -> K,
+    block_of: impl Fn(&R) -> i32,
+    cells: impl Fn(R) -> Vec<Cell>,
+) -> ExportResult<()> {
+    let mut writer = PartitionedWriter::new(
+        args.out_dir.join(table_name),
+        args.format,
+        args.amounts,
+        columns,
+        partitioned.then_some(args.partition_blocks),
+    );
+
+    let mut after: Option<K> = None;
+    loop {
+        let rows = load(conn, after.take())?;
+        let exhausted = (rows.len() as i64) < EXPORT_BATCH_SIZE;
+        after = rows.last().map(&key_of);
+
+        for row in rows {
+            writer.write(block_of(&row), cells(row))?;
+        }
+        writer.flush()?;
+
+        if exhausted {
+            break;
+        }
+    }
+
+    let writer = writer.finish()?;
+    println!("{}: {} rows in {} files", table_name, writer.rows_written, writer.files_written);
+    if writer.amounts_nulled > 0 {
+        println!("{}: {} amounts exceeded 38 digits and were written as null", table_name, writer.amounts_nulled);
+    }
+    Ok(())
+}
+
+fn load_balances(conn: &mut PgPooledConnection, args: &ExportArgs, chain: i64, after: Option<(i32, i32)>) -> QueryResult<Vec<Balance>> {
+    use crate::schema::balances::dsl::*;
+
+    let mut rows = balances
+        .filter(chain_id.eq(chain))
+        .order_by((block_number.asc(), id.asc()))
+        .limit(EXPORT_BATCH_SIZE)
+        .into_boxed();
+    if let Some(from_block) = args.from_block {
+        rows = rows.filter(block_number.ge(from_block));
+    }
+    if let Some(to_block) = args.to_block {
+        rows = rows.filter(block_number.le(to_block));
+    }
+    if let Some(token) = args.token {
+        rows = rows.filter(token_address.eq(token.as_bytes().to_vec()));
+    }
+    if let Some(wallet) = args.wallet {
+        rows = rows.filter(wallet_address.eq(wallet.as_bytes().to_vec()));
+    }
+    if let Some((after_block, after_id)) = after {
+        rows = rows.filter(block_number.gt(after_block).or(block_number.eq(after_block).and(id.gt(after_id))));
+    }
+    rows.select(Balance::as_select()).load(conn)
+}
+
+fn load_allowances(conn: &mut PgPooledConnection, args: &ExportArgs, chain: i64, after: Option<(i32, i32)>) -> QueryResult<Vec<Allowance>> {
+    use crate::schema::allowances::dsl::*;
+
+    let mut rows = allowances
+        .filter(chain_id.eq(chain))
+        .order_by((block_number.asc(), id.asc()))
+        .limit(EXPORT_BATCH_SIZE)
+        .into_boxed();
+    if let Some(from_block) = args.from_block {
+        rows = rows.filter(block_number.ge(from_block));
+    }
+    if let Some(to_block) = args.to_block {
+        rows = rows.filter(block_number.le(to_block));
+    }
+    if let Some(token) = args.token {
+        rows = rows.filter(token_address.eq(token.as_bytes().to_vec()));
+    }
+    if let Some(wallet) = args.wallet {
+        let wallet = wallet.as_bytes().to_vec();
+        rows = rows.filter(owner_address.eq(wallet.clone()).or(spender_address.eq(wallet)));
+    }
+    if let Some((after_block, after_id)) = after {
+        rows = rows.filter(block_number.gt(after_block).or(block_number.eq(after_block).and(id.gt(after_id))));
+    }
+    rows.select(Allowance::as_select()).load(conn)
+}
+
+fn load_supplies(conn: &mut PgPooledConnection, args: &ExportArgs, chain: i64, after: Option<(i32, i32)>) -> QueryResult<Vec<TokenSupply>> {
+    use crate::schema::token_supplies::dsl::*;
+
+    let mut rows = token_supplies
+        .filter(chain_id.eq(chain))
+        .order_by((block_number.asc(), id.asc()))
+        .limit(EXPORT_BATCH_SIZE)
+        .into_boxed();
+    if let Some(from_block) = args.from_block {
+        rows = rows.filter(block_number.ge(from_block));
+    }
+    if let Some(to_block) = args.to_block {
+        rows = rows.filter(block_number.le(to_block));
+    }
+    if let Some(token) = args.token {
+        rows = rows.filter(token_address.eq(token.as_bytes().to_vec()));
+    }
+    if let Some((after_block, after_id)) = after {
+        rows = rows.filter(block_number.gt(after_block).or(block_number.eq(after_block).and(id.gt(after_id))));
+    }
+    rows.select(TokenSupply::as_select()).load(conn)
+}
+
+/// Tokens first seen within the block range, ordered by address.
+fn load_tokens(conn: &mut PgPooledConnection, args: &ExportArgs, chain: i64, after: Option<Vec<u8>>) -> QueryResult<Vec<Token>> {
+    use crate::schema::tokens::dsl::*;
+
+    let mut rows = tokens
+        .filter(chain_id.eq(chain))
+        .order_by(token_address.asc())
+        .limit(EXPORT_BATCH_SIZE)
+        .into_boxed();
+    if let Some(from_block) = args.from_block {
+        rows = rows.filter(block_number.ge(from_block));
+    }
+    if let Some(to_block) = args.to_block {
+        rows = rows.filter(block_number.le(to_block));
+    }
+    if let Some(token) = args.token {
+        rows = rows.filter(token_address.eq(token.as_bytes().to_vec()));
+    }
+    if let Some(after_address) = after {
+        rows = rows.filter(token_address.gt(after_address));
+    }
+    rows.load::<Token>(conn)
+}
+
+/// Renders a stored address as lowercase `0x` hex, so exported columns join as plain strings.
+fn hex_address(address_bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(address_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+    use crate::events::BalanceChange;
+    use crate::models::NewToken;
+    use crate::storage::{Storage, DEFAULT_CHAIN_ID};
+    use crate::testing::{TempPath, TestDatabase};
+
+    const TOKEN: &str = "0xdAC17F958D2ee523a2206206994597C13D831ec7";
+    const WALLET: &str = "0xAb5801a7D398351b8bE11C439e05C5B3259aeC9B";
+
+    fn export_args(format: ExportFormat, out_dir: &TempPath) -> ExportArgs {
+        ExportArgs {
+            format,
+            out_dir: out_dir.path().to_path_buf(),
+            tables: vec![ExportTable::Balances],
+            from_block: None,
+            to_block: None,
+            token: None,
+            wallet: None,
+            partition_blocks: 100,
+            amounts: AmountEncoding::String,
+        }
+    }
+
+    // Balance rows of WALLET at blocks 5, 99, 100 and 150, on both sides of the boundary at block 100
+    fn seed(database: &TestDatabase) {
+        let storage = database.storage(DEFAULT_CHAIN_ID);
+        let token_address: Address = TOKEN.parse().unwrap();
+        let new_token = NewToken {
+            token_address: token_address.as_bytes(),
+            block_number: 1,
+            token_type: "ERC20",
+            name: None,
+            symbol: None,
+            decimals: Some(6),
+            granularity: None,
+        };
+        storage.insert_token(&new_token).unwrap();
+        for block_number in [5, 99, 100, 150] {
+            let change = BalanceChange {
+                wallet_address: WALLET.parse().unwrap(),
+                token_address,
+                delta: "10".to_string(),
+                token_id: None,
+                token_type: "ERC20",
+                block_number,
+                chain_id: DEFAULT_CHAIN_ID as u64,
+            };
+            storage.apply_balance_change(&change).unwrap();
+        }
+    }
+
+    #[test]
+    fn balances_round_trip_through_partitioned_files() {
+        let Some(database) = TestDatabase::create("export_balances") else { return };
+        seed(&database);
+        let conn = &mut database.pool.get().unwrap();
+
+        let csv_dir = TempPath::new("export-balances-csv");
+        run_export(conn, &export_args(ExportFormat::Csv, &csv_dir), DEFAULT_CHAIN_ID).unwrap();
+        let read_csv = |partition: i32| {
+            let path = csv_dir.path().join(format!("balances/block_start={}/part-0.csv", partition));
+            let mut reader = csv::Reader::from_path(path).unwrap();
+            reader.records().map(|record| record.unwrap()).map(|record| (record[1].to_string(), record[2].to_string(), record[3].to_string(), record[5].to_string())).collect::<Vec<_>>()
+        };
+        let (wallet, token) = (WALLET.to_lowercase(), TOKEN.to_lowercase());
+        let row = |balance: &str, block_number: &str| (wallet.clone(), token.clone(), balance.to_string(), block_number.to_string());
+        assert_eq!(read_csv(0), vec![row("10", "5"), row("20", "99")]);
+        assert_eq!(read_csv(100), vec![row("30", "100"), row("40", "150")]);
+
+        let parquet_dir = TempPath::new("export-balances-parquet");
+        run_export(conn, &export_args(ExportFormat::Parquet, &parquet_dir), DEFAULT_CHAIN_ID).unwrap();
+        let read_parquet = |partition: i32| {
+            let path = parquet_dir.path().join(format!("balances/block_start={}/part-0.parquet", partition));
+            let batch = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(path).unwrap()).unwrap().build().unwrap().next().unwrap().unwrap();
+            let strings = |index: usize| batch.column(index).as_any().downcast_ref::<StringArray>().unwrap().iter().map(|value| value.unwrap().to_string()).collect::<Vec<_>>();
+            (strings(1), strings(2), strings(3))
+        };
+        assert_eq!(read_parquet(0), (vec![wallet.clone(); 2], vec![token.clone(); 2], vec!["10".to_string(), "20".to_string()]));
+        assert_eq!(read_parquet(100), (vec![wallet.clone(); 2], vec![token.clone(); 2], vec!["30".to_string(), "40".to_string()]));
+        assert!(!parquet_dir.path().join("balances/block_start=200").exists());
+    }
+}
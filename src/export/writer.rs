@@ -0,0 +1,344 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::builder::{Decimal128Builder, Int16Builder, Int32Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::export::{AmountEncoding, ExportFormat, ExportResult};
+
+// Largest number of digits a Decimal128 column can hold
+const DECIMAL128_PRECISION: u8 = 38;
+
+/// Physical type of an exported column.
+#[derive(Copy, Clone)]
+pub enum ColumnType {
+    Int16,
+    Int32,
+    Text,
+    Amount,                               // Decimal string, or Decimal128(38, 0) when requested
+}
+
+pub struct Column {
+    pub name: &'static str,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+}
+
+impl Column {
+    pub const fn new(name: &'static str, column_type: ColumnType, nullable: bool) -> Self {
+        Column { name, column_type, nullable }
+    }
+}
+
+/// A single exported value; its variant matches the column type.
+pub enum Cell {
+    Int16(Option<i16>),
+    Int32(Option<i32>),
+    Text(Option<String>),
+    Amount(Option<String>),
+}
+
+/// Writes the rows of one file.
+trait FileWriter {
+    fn write_rows(&mut self, rows: Vec<Vec<Cell>>) -> ExportResult<()>;
+    fn close(self: Box<Self>) -> ExportResult<()>;
+}
+
+/// Writes a table as one file per `partition_blocks` blocks, under `<dir>/block_start=<n>/`.
+/// Rows must arrive in block order.
+pub struct PartitionedWriter {
+    dir: PathBuf,
+    format: ExportFormat,
+    amounts: AmountEncoding,
+    columns: &'static [Column],
+    partition_blocks: Option<i32>,        // None writes the whole table to a single file
+    current: Option<(i32, Box<dyn FileWriter>)>,
+    pending: Vec<Vec<Cell>>,
+    pub files_written: usize,
+    pub rows_written: usize,
+    pub amounts_nulled: usize,            // Amounts too large for Decimal128, written as null
+}
+
+impl PartitionedWriter {
+    pub fn new(dir: PathBuf, format: ExportFormat, amounts: AmountEncoding, columns: &'static [Column], partition_blocks: Option<i32>) -> Self {
+        PartitionedWriter {
+            dir,
+            format,
+            amounts,
+            columns,
+            partition_blocks,
+            current: None,
+            pending: Vec::new(),
+            files_written: 0,
+            rows_written: 0,
+            amounts_nulled: 0,
+        }
+    }
+
+    /// Buffers a row of `block_number`, switching to the next partition's file when needed.
+    pub fn write(&mut self, block_number: i32, row: Vec<Cell>) -> ExportResult<()> {
+        let partition = match self.partition_blocks {
+            Some(size) => block_number.div_euclid(size) * size,
+            None => 0,
+        };
+        if self.current.as_ref().is_none_or(|(current, _)| *current != partition) {
+            self.close_current()?;
+            let path = self.partition_path(partition);
+            fs::create_dir_all(path.parent().expect("partition files live in a directory"))?;
+            let writer: Box<dyn FileWriter> = match self.format {
+                ExportFormat::Csv => Box::new(CsvFileWriter::create(&path, self.columns)?),
+                ExportFormat::Parquet => Box::new(ParquetFileWriter::create(&path, self.columns, self.amounts)?),
+            };
+            self.current = Some((partition, writer));
+            self.files_written += 1;
+        }
+        self.pending.push(row);
+        self.rows_written += 1;
+        Ok(())
+    }
+
+    /// Hands the buffered rows to the current file; called once per loaded batch.
+    pub fn flush(&mut self) -> ExportResult<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.pending);
+        self.amounts_nulled += self.count_oversized_amounts(&rows);
+        if let Some((_, writer)) = self.current.as_mut() {
+            writer.write_rows(rows)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> ExportResult<Self> {
+        self.close_current()?;
+        Ok(self)
+    }
+
+    fn close_current(&mut self) -> ExportResult<()> {
+        self.flush()?;
+        if let Some((_, writer)) = self.current.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+
+    fn partition_path(&self, partition: i32) -> PathBuf {
+        let file_name = format!("part-0.{}", self.format.extension());
+        match self.partition_blocks {
+            Some(_) => self.dir.join(format!("block_start={}", partition)).join(file_name),
+            None => self.dir.join(file_name),
+        }
+    }
+
+    fn count_oversized_amounts(&self, rows: &[Vec<Cell>]) -> usize {
+        if !matches!((self.format, self.amounts), (ExportFormat::Parquet, AmountEncoding::Decimal128)) {
+            return 0;
+        }
+        rows.iter()
+            .flatten()
+            .filter(|cell| matches!(cell, Cell::Amount(Some(value)) if parse_decimal128(value).is_none()))
+            .count()
+    }
+}
+
+struct CsvFileWriter {
+    writer: csv::Writer<File>,
+}
+
+impl CsvFileWriter {
+    fn create(path: &Path, columns: &[Column]) -> ExportResult<Self> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(columns.iter().map(|column| column.name))?;
+        Ok(CsvFileWriter { writer })
+    }
+}
+
+impl FileWriter for CsvFileWriter {
+    fn write_rows(&mut self, rows: Vec<Vec<Cell>>) -> ExportResult<()> {
+        for row in rows {
+            // Nulls are written as empty fields
+            let record: Vec<String> = row
+                .into_iter()
+                .map(|cell| match cell {
+                    Cell::Int16(value) => value.map(|value| value.to_string()).unwrap_or_default(),
+                    Cell::Int32(value) => value.map(|value| value.to_string()).unwrap_or_default(),
+                    Cell::Text(value) | Cell::Amount(value) => value.unwrap_or_default(),
+                })
+                .collect();
+            self.writer.write_record(&record)?;
+        }
+        Ok(())
+    }
+
+    fn close(mut self: Box<Self>) -> ExportResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+struct ParquetFileWriter {
+    writer: ArrowWriter<File>,
+    schema: Arc<Schema>,
+    columns: &'static [Column],
+    amounts: AmountEncoding,
+}
+
+impl ParquetFileWriter {
+    fn create(path: &Path, columns: &'static [Column], amounts: AmountEncoding) -> ExportResult<Self> {
+        let fields: Vec<Field> = columns
+            .iter()
+            .map(|column| {
+                let data_type = match column.column_type {
+                    ColumnType::Int16 => DataType::Int16,
+                    ColumnType::Int32 => DataType::Int32,
+                    ColumnType::Text => DataType::Utf8,
+                    ColumnType::Amount => match amounts {
+                        AmountEncoding::String => DataType::Utf8,
+                        AmountEncoding::Decimal128 => DataType::Decimal128(DECIMAL128_PRECISION, 0),
+                    },
+                };
+                // Oversized decimals are written as null, so decimal amount columns are always nullable
+                let nullable = column.nullable || matches!((column.column_type, amounts), (ColumnType::Amount, AmountEncoding::Decimal128));
+                Field::new(column.name, data_type, nullable)
+            })
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(properties))?;
+        Ok(ParquetFileWriter { writer, schema, columns, amounts })
+    }
+}
+
+impl FileWriter for ParquetFileWriter {
+    fn write_rows(&mut self, rows: Vec<Vec<Cell>>) -> ExportResult<()> {
+        let arrays: Vec<ArrayRef> = (0..self.columns.len()).map(|index| self.build_column(&rows, index)).collect::<ExportResult<_>>()?;
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        self.writer.write(&batch)?;
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) -> ExportResult<()> {
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+impl ParquetFileWriter {
+    fn build_column(&self, rows: &[Vec<Cell>], index: usize) -> ExportResult<ArrayRef> {
+        let cells = rows.iter().map(|row| &row[index]);
+        let array: ArrayRef = match (self.columns[index].column_type, self.amounts) {
+            (ColumnType::Int16, _) => {
+                let mut builder = Int16Builder::with_capacity(rows.len());
+                cells.for_each(|cell| builder.append_option(if let Cell::Int16(value) = cell { *value } else { None }));
+                Arc::new(builder.finish())
+            }
+            (ColumnType::Int32, _) => {
+                let mut builder = Int32Builder::with_capacity(rows.len());
+                cells.for_each(|cell| builder.append_option(if let Cell::Int32(value) = cell { *value } else { None }));
+                Arc::new(builder.finish())
+            }
+            (ColumnType::Amount, AmountEncoding::Decimal128) => {
+                let mut builder = Decimal128Builder::with_capacity(rows.len());
+                cells.for_each(|cell| {
+                    builder.append_option(if let Cell::Amount(Some(value)) = cell { parse_decimal128(value) } else { None })
+                });
+                Arc::new(builder.finish().with_precision_and_scale(DECIMAL128_PRECISION, 0)?)
+            }
+            (ColumnType::Text, _) | (ColumnType::Amount, AmountEncoding::String) => {
+                let mut builder = StringBuilder::new();
+                cells.for_each(|cell| match cell {
+                    Cell::Text(value) | Cell::Amount(value) => builder.append_option(value.as_deref()),
+                    _ => builder.append_null(),
+                });
+                Arc::new(builder.finish())
+            }
+        };
+        Ok(array)
+    }
+}
+
+/// Parses a decimal amount into a Decimal128(38, 0) value, or None if it has more than 38 digits.
+fn parse_decimal128(value: &str) -> Option<i128> {
+    if value.len() > DECIMAL128_PRECISION as usize {
+        return None;
+    }
+    value.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Array, Decimal128Array, Int32Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+    use crate::testing::TempPath;
+
+    static COLUMNS: [Column; 3] = [
+        Column::new("block_number", ColumnType::Int32, false),
+        Column::new("address", ColumnType::Text, false),
+        Column::new("amount", ColumnType::Amount, false),
+    ];
+
+    // Four rows across the partitions starting at blocks 0 and 100, the second amount one digit too long for Decimal128
+    fn write_rows(dir: &Path, format: ExportFormat, amounts: AmountEncoding) -> PartitionedWriter {
+        let mut writer = PartitionedWriter::new(dir.to_path_buf(), format, amounts, &COLUMNS, Some(100));
+        for (block_number, amount) in [(5, "1".to_string()), (99, "9".repeat(39)), (100, "3".to_string()), (150, "9".repeat(38))] {
+            let row = vec![Cell::Int32(Some(block_number)), Cell::Text(Some(format!("0x{:040x}", block_number))), Cell::Amount(Some(amount))];
+            writer.write(block_number, row).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn decimal128_holds_up_to_38_digits() {
+        assert_eq!(parse_decimal128("0"), Some(0));
+        assert_eq!(parse_decimal128(&"9".repeat(38)), Some(10_i128.pow(38) - 1));
+        assert_eq!(parse_decimal128(&"9".repeat(39)), None);
+        assert_eq!(parse_decimal128("-1"), Some(-1));
+    }
+
+    #[test]
+    fn parquet_partitions_hold_their_blocks_and_null_oversized_decimals() {
+        let dir = TempPath::new("export-parquet");
+        let writer = write_rows(dir.path(), ExportFormat::Parquet, AmountEncoding::Decimal128);
+        assert_eq!((writer.files_written, writer.rows_written, writer.amounts_nulled), (2, 4, 1));
+
+        let read = |partition: i32| {
+            let file = File::open(dir.path().join(format!("block_start={}/part-0.parquet", partition))).unwrap();
+            let mut batches = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+            let batch = batches.next().unwrap().unwrap();
+            assert!(batches.next().is_none());
+            let blocks = batch.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+            let amounts = batch.column(2).as_any().downcast_ref::<Decimal128Array>().unwrap();
+            assert_eq!(batch.column(1).as_any().downcast_ref::<StringArray>().unwrap().value(0), format!("0x{:040x}", blocks.value(0)));
+            (blocks.values().to_vec(), (0..amounts.len()).map(|index| amounts.is_valid(index).then(|| amounts.value(index))).collect::<Vec<_>>())
+        };
+        assert_eq!(read(0), (vec![5, 99], vec![Some(1), None]));
+        assert_eq!(read(100), (vec![100, 150], vec![Some(3), Some(10_i128.pow(38) - 1)]));
+    }
+
+    #[test]
+    fn csv_partitions_hold_their_blocks_and_amounts_as_written() {
+        let dir = TempPath::new("export-csv");
+        let writer = write_rows(dir.path(), ExportFormat::Csv, AmountEncoding::Decimal128);
+        assert_eq!((writer.files_written, writer.rows_written, writer.amounts_nulled), (2, 4, 0));
+
+        let read = |partition: i32| {
+            let mut reader = csv::Reader::from_path(dir.path().join(format!("block_start={}/part-0.csv", partition))).unwrap();
+            assert_eq!(reader.headers().unwrap(), vec!["block_number", "address", "amount"]);
+            reader.records().map(|record| record.unwrap().iter().map(str::to_string).collect::<Vec<_>>()).collect::<Vec<_>>()
+        };
+        let partition = read(0);
+        assert_eq!(partition.iter().map(|row| row[0].as_str()).collect::<Vec<_>>(), vec!["5", "99"]);
+        assert_eq!(partition[1][2], "9".repeat(39));
+        let partition = read(100);
+        assert_eq!(partition.iter().map(|row| row[0].as_str()).collect::<Vec<_>>(), vec!["100", "150"]);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::abi::{Abi, Event, RawLog, Token};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, Log, H256, I256};
+use ethers::utils::{hex, to_checksum};
+use log::info;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::events::IndexedEvent;
+use crate::handlers::{EventHandler, HandlerContext, HandlerResult};
+
+/// A contract indexed generically, as listed in the contracts file or a config profile.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContractConfig {
+    pub name: String,                     // Stored with each event and used in log messages
+    pub abi: PathBuf,                     // ABI JSON file, or a build artifact with an `abi` field
+    pub addresses: Vec<Address>,          // Deployments sharing the ABI
+}
+
+/// Every event declared by a contract ABI, decoded into `contract_event`s with JSON parameters.
+/// Only logs emitted by the configured addresses are claimed; anonymous events have no topic to
+/// request and are skipped.
+pub struct AbiHandler {
+    name: String,
+    addresses: HashSet<Address>,
+    events: HashMap<H256, Event>,         // Keyed by signature (topic0)
+}
+
+impl AbiHandler {
+    pub fn new(name: impl Into<String>, abi: &Abi, addresses: impl IntoIterator<Item = Address>) -> Self {
+        let events = abi
+            .events()
+            .filter(|event| !event.anonymous)
+            .map(|event| (event.signature(), event.clone()))
+            .collect();
+
+        AbiHandler {
+            name: name.into(),
+            addresses: addresses.into_iter().collect(),
+            events,
+        }
+    }
+
+    /// Loads the ABI of `config`, resolving a relative path against `base_dir`.
+    pub fn from_config(config: &ContractConfig, base_dir: &Path) -> HandlerResult<Self> {
+        let abi_path = base_dir.join(&config.abi);
+        let abi = read_abi(&abi_path).map_err(|e| format!("Invalid ABI file {}: {}", abi_path.display(), e))?;
+        Ok(AbiHandler::new(config.name.clone(), &abi, config.addresses.iter().copied()))
+    }
+}
+
+/// Loads the handlers of the contracts listed in a JSON file of `ContractConfig`s:
+/// `[{"name": "vault", "abi": "abis/Vault.json", "addresses": ["0x..."]}]`.
+pub fn load_contracts(path: &str) -> HandlerResult<Vec<AbiHandler>> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Cannot read contracts file {}: {}", path, e))?;
+    let configs: Vec<ContractConfig> =
+        serde_json::from_str(&contents).map_err(|e| format!("Invalid contracts file {}: {}", path, e))?;
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+
+    configs
+        .iter()
+        .map(|config| {
+            let handler = AbiHandler::from_config(config, base_dir)?;
+            info!("Indexing {} events of {} at {} addresses", handler.events.len(), handler.name, handler.addresses.len());
+            Ok(handler)
+        })
+        .collect()
+}
+
+fn read_abi(path: &Path) -> HandlerResult<Abi> {
+    let json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+    // Hardhat and Foundry artifacts wrap the ABI with the bytecode and metadata
+    let abi = match json {
+        Value::Object(mut artifact) if artifact.contains_key("abi") => artifact.remove("abi").unwrap_or_default(),
+        abi => abi,
+    };
+    Ok(serde_json::from_value(abi)?)
+}
+
+#[async_trait]
+impl EventHandler for AbiHandler {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        self.events.keys().copied().collect()
+    }
+
+    async fn matches_contract(&self, _provider: Arc<Provider<Http>>, address: Address) -> bool {
+        self.addresses.contains(&address)
+    }
+
+    async fn handle(&self, log: &Log, _context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()> {
+        let Some(event) = log.topics.first().and_then(|topic| self.events.get(topic)) else {
+            return Ok(());
+        };
+
+        let decoded = event.parse_log(RawLog { topics: log.topics.clone(), data: log.data.to_vec() })?;
+
+        // Unnamed parameters are keyed by their position
+        let params: Map<String, Value> = decoded
+            .params
+            .into_iter()
+            .enumerate()
+            .map(|(position, param)| {
+                let key = if param.name.is_empty() { position.to_string() } else { param.name };
+                (key, token_to_json(&param.value))
+            })
+            .collect();
+
+        events.push(IndexedEvent::contract_event(log, &self.name, &event.name, event_signature(event), Value::Object(params)));
+        Ok(())
+    }
+}
+
+/// Canonical signature of an event, e.g. `Transfer(address,address,uint256)`.
+fn event_signature(event: &Event) -> String {
+    let types: Vec<String> = event.inputs.iter().map(|input| input.kind.to_string()).collect();
+    format!("{}({})", event.name, types.join(","))
+}
+
+/// Converts a decoded value to JSON: addresses checksummed, integers as decimal strings (they may
+/// exceed JSON numbers), bytes as 0x-prefixed hex, arrays and tuples as arrays. Indexed parameters
+/// of dynamic types are only available as the hash carried by their topic.
+fn token_to_json(token: &Token) -> Value {
+    match token {
+        Token::Address(address) => Value::String(to_checksum(address, None)),
+        Token::FixedBytes(bytes) | Token::Bytes(bytes) => Value::String(format!("0x{}", hex::encode(bytes))),
+        Token::Int(value) => Value::String(I256::from_raw(*value).to_string()),
+        Token::Uint(value) => Value::String(value.to_string()),
+        Token::Bool(value) => Value::Bool(*value),
+        Token::String(value) => Value::String(value.clone()),
+        Token::FixedArray(tokens) | Token::Array(tokens) | Token::Tuple(tokens) => {
+            Value::Array(tokens.iter().map(token_to_json).collect())
+        }
+    }
+}
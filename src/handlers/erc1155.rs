@@ -1,14 +1,46 @@
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use ethers::providers::{Http, Provider};
 use ethers::types::{Address, Log, H256, U256};
+use log::warn;
 
-use crate::models::balance::update_historical_balance;
-use crate::models::allowance::update_historical_allowance;
+use crate::events::IndexedEvent;
+use crate::handlers::{EventHandler, HandlerContext, HandlerResult};
+use crate::storage::Storage;
 use crate::token_service::{check_and_insert_token, fetch_and_store_token_uri};
-use crate::{Cli, PgPooledConnection, TokenType, ERC1155_BATCH_TRANSFER_SIGNATURE, ERC1155_SINGLE_TRANSFER_SIGNATURE, ERC_APPROVAL_FOR_ALL_SIGNATURE};
+use crate::constants::{ERC1155_BATCH_TRANSFER_SIGNATURE, ERC1155_SINGLE_TRANSFER_SIGNATURE, ERC_APPROVAL_FOR_ALL_SIGNATURE};
+use crate::indexer::Processors;
+use crate::utils::is_erc1155;
+use crate::TokenType;
+
+/// `TransferSingle`, `TransferBatch` and `ApprovalForAll` of ERC1155 tokens, recognized by `supportsInterface(0xd9b67a26)`.
+pub struct Erc1155Handler;
+
+#[async_trait]
+impl EventHandler for Erc1155Handler {
+    fn name(&self) -> &str {
+        "ERC1155"
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        vec![
+            *ERC1155_SINGLE_TRANSFER_SIGNATURE,
+            *ERC1155_BATCH_TRANSFER_SIGNATURE,
+            *ERC_APPROVAL_FOR_ALL_SIGNATURE,
+        ]
+    }
+
+    async fn matches_contract(&self, provider: Arc<Provider<Http>>, address: Address) -> bool {
+        is_erc1155(provider, address).await
+    }
+
+    async fn handle(&self, log: &Log, context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()> {
+        handle_erc1155_event(log, context.storage, context.provider.clone(), context.processors, events).await
+    }
+}
 
-pub async fn handle_erc1155_event(log: &Log, conn: &mut PgPooledConnection, provider: Arc<Provider<Http>>, cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub async fn handle_erc1155_event(log: &Log, storage: &dyn Storage, provider: Arc<Provider<Http>>, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let event_signature: H256 = log.topics[0];
 
     // ERC1155 Event Signatures
@@ -17,16 +49,16 @@ pub async fn handle_erc1155_event(log: &Log, conn: &mut PgPooledConnection, prov
     let approval_for_all_event_signature = *ERC_APPROVAL_FOR_ALL_SIGNATURE;  // ERC1155 ApprovalForAll event signature
 
     match event_signature {
-        sig if sig == transfer_single_event_signature => handle_erc1155_transfer_single(log, conn, provider, cli).await?,
-        sig if sig == transfer_batch_event_signature => handle_erc1155_transfer_batch(log, conn, provider, cli).await?,
-        sig if sig == approval_for_all_event_signature => handle_erc1155_approval_for_all(log, conn, cli).await?,
-        _ => println!("Unknown ERC1155 event at address: {:?}", log.address),
+        sig if sig == transfer_single_event_signature => handle_erc1155_transfer_single(log, storage, provider, processors, events).await?,
+        sig if sig == transfer_batch_event_signature => handle_erc1155_transfer_batch(log, storage, provider, processors, events).await?,
+        sig if sig == approval_for_all_event_signature => handle_erc1155_approval_for_all(log, processors, events).await?,
+        _ => warn!("Unknown ERC1155 event at address: {:?}", log.address),
     }
 
     Ok(())
 }
 
-async fn handle_erc1155_transfer_single(log: &Log, conn: &mut PgPooledConnection, provider: Arc<Provider<Http>>, cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn handle_erc1155_transfer_single(log: &Log, storage: &dyn Storage, provider: Arc<Provider<Http>>, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Parse TransferSingle event
     let _operator = Address::from(log.topics[1]);
     let from = Address::from(log.topics[2]);
@@ -35,24 +67,25 @@ async fn handle_erc1155_transfer_single(log: &Log, conn: &mut PgPooledConnection
     let block_number = log.block_number.unwrap().as_u32() as i32;
     
     // Pass the token type (ERC1155 in this case) to check_and_insert_token
-    check_and_insert_token(conn, provider.clone(), log.address.as_bytes(), block_number, TokenType::ERC1155).await?;
-    if cli.process_token_uri {
-        fetch_and_store_token_uri(conn, log.address.as_bytes(), token_id, TokenType::ERC721, provider.clone()).await?;
+    check_and_insert_token(storage, provider.clone(), log.address.as_bytes(), block_number, TokenType::ERC1155).await?;
+    events.push(IndexedEvent::transfer(log, from, to, Some(token_id), value.to_string(), "ERC1155"));
+    if processors.token_uri {
+        fetch_and_store_token_uri(storage, log.address.as_bytes(), token_id, TokenType::ERC721, provider.clone()).await?;
     }
 
 
-    if cli.process_balances {
+    if processors.balances {
         // Update the balance for the sender (subtract)
-        update_historical_balance(conn, from.as_bytes(), log.address.as_bytes(), format!("-{}", value), Some(token_id as i16), "ERC1155", log.block_number.unwrap().as_u32() as i32).unwrap();
+        events.push(IndexedEvent::balance_change(log, from, format!("-{}", value), Some(token_id), "ERC1155"));
 
         // Update the balance for the recipient (add)
-        update_historical_balance(conn, to.as_bytes(), log.address.as_bytes(), format!("{}", value), Some(token_id as i16), "ERC1155", log.block_number.unwrap().as_u32() as i32).unwrap();
+        events.push(IndexedEvent::balance_change(log, to, format!("{}", value), Some(token_id), "ERC1155"));
     }
 
     Ok(())
 }
 
-async fn handle_erc1155_transfer_batch(log: &Log, conn: &mut PgPooledConnection, provider: Arc<Provider<Http>>, cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn handle_erc1155_transfer_batch(log: &Log, storage: &dyn Storage, provider: Arc<Provider<Http>>, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Parse TransferBatch event
     let _operator = Address::from(log.topics[1]);
     let from = Address::from(log.topics[2]);
@@ -64,17 +97,18 @@ async fn handle_erc1155_transfer_batch(log: &Log, conn: &mut PgPooledConnection,
         // Update the balance for each token_id in the batch
         for (token_id, value) in token_ids.iter().zip(values.iter()) {
             // Pass the token type (ERC1155 in this case) to check_and_insert_token
-            check_and_insert_token(conn, provider.clone(), log.address.as_bytes(), block_number, TokenType::ERC1155).await?;
+            check_and_insert_token(storage, provider.clone(), log.address.as_bytes(), block_number, TokenType::ERC1155).await?;
+            events.push(IndexedEvent::transfer(log, from, to, Some(*token_id), value.to_string(), "ERC1155"));
     
-            if cli.process_token_uri {
-                fetch_and_store_token_uri(conn, log.address.as_bytes(), *token_id, TokenType::ERC721, provider.clone()).await?;
+            if processors.token_uri {
+                fetch_and_store_token_uri(storage, log.address.as_bytes(), *token_id, TokenType::ERC721, provider.clone()).await?;
             }
-            if cli.process_balances {
+            if processors.balances {
                 // Update the balance for the sender (subtract)
-                update_historical_balance(conn, from.as_bytes(), log.address.as_bytes(), format!("-{}", value), Some(*token_id as i16), "ERC1155", log.block_number.unwrap().as_u32() as i32).unwrap();
+                events.push(IndexedEvent::balance_change(log, from, format!("-{}", value), Some(*token_id), "ERC1155"));
 
                 // Update the balance for the recipient (add)
-                update_historical_balance(conn, to.as_bytes(), log.address.as_bytes(), format!("{}", value), Some(*token_id as i16), "ERC1155", log.block_number.unwrap().as_u32() as i32).unwrap();
+                events.push(IndexedEvent::balance_change(log, to, format!("{}", value), Some(*token_id), "ERC1155"));
             }
         }
 
@@ -82,8 +116,8 @@ async fn handle_erc1155_transfer_batch(log: &Log, conn: &mut PgPooledConnection,
     Ok(())
 }
 
-async fn handle_erc1155_approval_for_all(log: &Log, conn: &mut PgPooledConnection, cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if !cli.process_allowances { return Ok(()) }
+async fn handle_erc1155_approval_for_all(log: &Log, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !processors.allowances { return Ok(()) }
     // Parse ApprovalForAll event
     let owner = Address::from(log.topics[1]);
     let operator = Address::from(log.topics[2]);
@@ -92,7 +126,7 @@ async fn handle_erc1155_approval_for_all(log: &Log, conn: &mut PgPooledConnectio
     let value = if approved { 1 } else { 0 };  // Set allowance to 1 for approval, 0 for revocation
 
     // Update operator allowance for all tokens owned by the user (without token_id)
-    update_historical_allowance(conn, owner.as_bytes(), operator.as_bytes(), log.address.as_bytes(), value.to_string(), None, "ERC1155", log.block_number.unwrap().as_u32() as i32).unwrap();
+    events.push(IndexedEvent::allowance_change(log, owner, operator, value.to_string(), None, "ERC1155"));
 
     Ok(())
 }
@@ -1,20 +1,49 @@
 use std::sync::Arc;
+use async_trait::async_trait;
 use ethers::providers::{Http, Provider};
 use ethers::types::{Address, Log, H256, U256};
-use log::info;
+use log::{info, warn};
 
 use crate::token_service::check_and_insert_token;
-use crate::{Cli, PgPooledConnection, TokenType, ERC_APPROVAL_SIGNATURE, ERC_TRANSFER_SIGNATURE};
+use crate::events::IndexedEvent;
+use crate::handlers::{EventHandler, HandlerContext, HandlerResult};
+use crate::storage::Storage;
+use crate::constants::{ERC_APPROVAL_SIGNATURE, ERC_TRANSFER_SIGNATURE};
+use crate::indexer::Processors;
+use crate::utils::is_erc20;
+use crate::TokenType;
 
-use crate::models::allowance::update_historical_allowance;
-use crate::models::balance::update_historical_balance;
-use crate::models::token_supply::update_total_supply;
+/// `Transfer` and `Approval` of ERC20 tokens, recognized by `decimals()`.
+pub struct Erc20Handler;
+
+#[async_trait]
+impl EventHandler for Erc20Handler {
+    fn name(&self) -> &str {
+        "ERC20"
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        vec![
+            *ERC_TRANSFER_SIGNATURE,
+            *ERC_APPROVAL_SIGNATURE,
+        ]
+    }
+
+    async fn matches_contract(&self, provider: Arc<Provider<Http>>, address: Address) -> bool {
+        is_erc20(provider, address).await
+    }
+
+    async fn handle(&self, log: &Log, context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()> {
+        handle_erc20_event(log, context.storage, context.provider.clone(), context.processors, events).await
+    }
+}
 
 pub async fn handle_erc20_event(
     log: &Log,
-    conn: &mut PgPooledConnection,
+    storage: &dyn Storage,
     provider: Arc<Provider<Http>>,
-    cli: &Arc<Cli>,
+    processors: &Processors,
+    events: &mut Vec<IndexedEvent>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let event_signature: H256 = log.topics[0];
 
@@ -23,9 +52,9 @@ pub async fn handle_erc20_event(
     let approval_event_signature: H256 = *ERC_APPROVAL_SIGNATURE; // ERC20 Approval event signature
 
     match event_signature {
-        sig if sig == transfer_event_signature => handle_erc20_log(log, conn, provider, cli).await?,
-        sig if sig == approval_event_signature => handle_erc20_allowance(log, conn, cli).await?,
-        _ => println!("Unknown ERC20 event at address: {:?}", log.address),
+        sig if sig == transfer_event_signature => handle_erc20_log(log, storage, provider, processors, events).await?,
+        sig if sig == approval_event_signature => handle_erc20_allowance(log, processors, events).await?,
+        _ => warn!("Unknown ERC20 event at address: {:?}", log.address),
     }
 
     Ok(())
@@ -33,9 +62,10 @@ pub async fn handle_erc20_event(
 
 async fn handle_erc20_log(
     log: &Log,
-    conn: &mut PgPooledConnection,
+    storage: &dyn Storage,
     provider: Arc<Provider<Http>>,
-    cli: &Arc<Cli>,
+    processors: &Processors,
+    events: &mut Vec<IndexedEvent>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     info!(
         "Handling ERC20 transfer log for token address: {:?}",
@@ -45,51 +75,51 @@ async fn handle_erc20_log(
     let from = Address::from(log.topics[1]);
     let to = Address::from(log.topics[2]);
     let value = U256::from_big_endian(&log.data.0); // Use the full U256 value
+
+    record_erc20_transfer(log, from, to, value, storage, provider, processors, events).await
+}
+
+/// Records a movement of `value` of the ERC20 token emitting `log`, with the balance and supply
+/// changes it implies. Also used by the handlers of system contracts holding native tokens.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn record_erc20_transfer(
+    log: &Log,
+    from: Address,
+    to: Address,
+    value: U256,
+    storage: &dyn Storage,
+    provider: Arc<Provider<Http>>,
+    processors: &Processors,
+    events: &mut Vec<IndexedEvent>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let block_number = log.block_number.unwrap().as_u32() as i32;
 
     // Pass the token type (ERC20 in this case) to check_and_insert_token
-    check_and_insert_token(conn, provider, log.address.as_bytes(), block_number, TokenType::ERC20).await?;
+    check_and_insert_token(storage, provider, log.address.as_bytes(), block_number, TokenType::ERC20).await?;
+    events.push(IndexedEvent::transfer(log, from, to, None, value.to_string(), "ERC20"));
 
-    if cli.process_balances {
+    if processors.balances {
         info!(
             "Updating balance for sender: {:?}, recipient: {:?}",
             from, to
         );
 
-        // Convert U256 to string for database storage
+        // Convert U256 to string for storage
         let value_str = value.to_string();
-        update_historical_balance(
-            conn,
-            from.as_bytes(),
-            log.address.as_bytes(),
-            format!("-{}", value_str), // Store as negative string for subtraction
-            None,
-            "ERC20",
-            block_number,
-        )
-        .unwrap();
-        update_historical_balance(
-            conn,
-            to.as_bytes(),
-            log.address.as_bytes(),
-            value_str, // Store as positive string
-            None,
-            "ERC20",
-            block_number,
-        )
-        .unwrap();
+        events.push(IndexedEvent::balance_change(log, from, format!("-{}", value_str), None, "ERC20")); // Negative string for subtraction
+        events.push(IndexedEvent::balance_change(log, to, value_str, None, "ERC20"));
     }
     
-    if cli.process_total_supplies {
+    if processors.total_supplies {
         // Handle minting or burning
         let zero_address = Address::zero();
         let value_str = value.to_string();
         if from == zero_address {
             info!("Minting detected for token address: {:?}", log.address);
-            update_total_supply(conn, log.address.as_bytes(), value_str, block_number)?;
+            events.push(IndexedEvent::supply_change(log, value_str));
         } else if to == zero_address {
             info!("Burning detected for token address: {:?}", log.address);
-            update_total_supply(conn, log.address.as_bytes(), format!("-{}", value_str), block_number)?;
+            events.push(IndexedEvent::supply_change(log, format!("-{}", value_str)));
         }
     }
 
@@ -98,27 +128,18 @@ async fn handle_erc20_log(
 
 async fn handle_erc20_allowance(
     log: &Log,
-    conn: &mut PgPooledConnection,
-    cli: &Arc<Cli>,
+    processors: &Processors,
+    events: &mut Vec<IndexedEvent>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if cli.process_allowances {
+    if processors.allowances {
         // Parse Approval event
         let owner = Address::from(log.topics[1]);
         let spender = Address::from(log.topics[2]);
         let value = U256::from_big_endian(&log.data.0); // Use the full U256 value
-        let value_str = value.to_string(); // Convert to string for database storage
-
-        // Update the allowance with historical tracking (adding or subtracting the value)
-        update_historical_allowance(
-            conn,
-            owner.as_bytes(),
-            spender.as_bytes(),
-            log.address.as_bytes(),
-            value_str,
-            None,
-            "ERC20",
-            log.block_number.unwrap().as_u32() as i32,
-        )?;
+        let value_str = value.to_string(); // Convert to string for storage
+
+        // Record the allowance as set by the approval
+        events.push(IndexedEvent::allowance_change(log, owner, spender, value_str, None, "ERC20"));
     }
 
     Ok(())
@@ -1,15 +1,46 @@
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use ethers::providers::{Http, Provider};
 use ethers::types::{Address, Log, H256, U256};
+use log::warn;
 
-use crate::models::balance::update_historical_balance;
-use crate::models::allowance::update_historical_allowance;
+use crate::events::IndexedEvent;
+use crate::handlers::{EventHandler, HandlerContext, HandlerResult};
+use crate::storage::Storage;
 use crate::token_service::{check_and_insert_token, fetch_and_store_token_uri};
-use crate::{Cli, PgPooledConnection, TokenType, ERC_APPROVAL_FOR_ALL_SIGNATURE, ERC_APPROVAL_SIGNATURE, ERC_TRANSFER_SIGNATURE};
+use crate::constants::{ERC_APPROVAL_FOR_ALL_SIGNATURE, ERC_APPROVAL_SIGNATURE, ERC_TRANSFER_SIGNATURE};
+use crate::indexer::Processors;
+use crate::utils::is_erc721;
+use crate::TokenType;
+
+/// `Transfer`, `Approval` and `ApprovalForAll` of ERC721 tokens, recognized by `supportsInterface(0x80ac58cd)`.
+pub struct Erc721Handler;
+
+#[async_trait]
+impl EventHandler for Erc721Handler {
+    fn name(&self) -> &str {
+        "ERC721"
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        vec![
+            *ERC_TRANSFER_SIGNATURE,
+            *ERC_APPROVAL_SIGNATURE,
+            *ERC_APPROVAL_FOR_ALL_SIGNATURE,
+        ]
+    }
 
+    async fn matches_contract(&self, provider: Arc<Provider<Http>>, address: Address) -> bool {
+        is_erc721(provider, address).await
+    }
+
+    async fn handle(&self, log: &Log, context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()> {
+        handle_erc721_event(log, context.storage, context.provider.clone(), context.processors, events).await
+    }
+}
 
-pub async fn handle_erc721_event(log: &Log, conn: &mut PgPooledConnection, provider: Arc<Provider<Http>>, cli: &Arc<Cli>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>  {
+pub async fn handle_erc721_event(log: &Log, storage: &dyn Storage, provider: Arc<Provider<Http>>, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>  {
     let event_signature: H256 = log.topics[0];
 
     // ERC721 Event Signatures
@@ -18,17 +49,17 @@ pub async fn handle_erc721_event(log: &Log, conn: &mut PgPooledConnection, provi
     let approval_for_all_event_signature: H256 = *ERC_APPROVAL_FOR_ALL_SIGNATURE;  // ERC721 ApprovalForAll event signature
 
     match event_signature {
-        sig if sig == transfer_event_signature => handle_erc721_log(log, conn, provider, cli).await?,
-        sig if sig == approval_event_signature => handle_erc721_allowance(log, conn, cli).await?,
-        sig if sig == approval_for_all_event_signature => handle_erc721_approval_for_all(log, conn, cli).await?,
-        _ => println!("Unknown ERC721 event at address: {:?}", log.address),
+        sig if sig == transfer_event_signature => handle_erc721_log(log, storage, provider, processors, events).await?,
+        sig if sig == approval_event_signature => handle_erc721_allowance(log, processors, events).await?,
+        sig if sig == approval_for_all_event_signature => handle_erc721_approval_for_all(log, processors, events).await?,
+        _ => warn!("Unknown ERC721 event at address: {:?}", log.address),
     }
 
     Ok(())
 }
 
 
-async fn handle_erc721_log(log: &Log, conn: &mut PgPooledConnection, provider: Arc<Provider<Http>>, cli: &Arc<Cli>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn handle_erc721_log(log: &Log, storage: &dyn Storage, provider: Arc<Provider<Http>>, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let block_number = log.block_number.unwrap().as_u32() as i32;
 
     let from = Address::from(log.topics[1]);
@@ -36,41 +67,42 @@ async fn handle_erc721_log(log: &Log, conn: &mut PgPooledConnection, provider: A
     let token_id: i16 = U256::from(log.topics[3].0).as_u64() as i16;
 
     // Pass the token type (ERC20 in this case) to check_and_insert_token
-    check_and_insert_token(conn, provider.clone(), log.address.as_bytes(), block_number, TokenType::ERC721).await?;
+    check_and_insert_token(storage, provider.clone(), log.address.as_bytes(), block_number, TokenType::ERC721).await?;
+    events.push(IndexedEvent::transfer(log, from, to, Some(token_id), "1".to_string(), "ERC721"));
     
-    if cli.process_token_uri {
-        fetch_and_store_token_uri(conn, log.address.as_bytes(), token_id, TokenType::ERC721, provider.clone()).await?;
+    if processors.token_uri {
+        fetch_and_store_token_uri(storage, log.address.as_bytes(), token_id, TokenType::ERC721, provider.clone()).await?;
     }
 
-    if cli.process_balances {
+    if processors.balances {
         // Parse Transfer event
 
         // Update the balance for the sender (subtract ownership) with historical tracking
-        update_historical_balance(conn, from.as_bytes(), log.address.as_bytes(), "-1".to_string(), Some(token_id), "ERC721", log.block_number.unwrap().as_u64() as i32)?;
+        events.push(IndexedEvent::balance_change(log, from, "-1".to_string(), Some(token_id), "ERC721"));
 
         // Update the balance for the recipient (add ownership) with historical tracking
-        update_historical_balance(conn, to.as_bytes(), log.address.as_bytes(), "1".to_string(), Some(token_id), "ERC721", log.block_number.unwrap().as_u64() as i32)?;
+        events.push(IndexedEvent::balance_change(log, to, "1".to_string(), Some(token_id), "ERC721"));
     }
 
     Ok(())
 }
 
-async fn handle_erc721_allowance(log: &Log, conn: &mut PgPooledConnection, cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if cli.process_allowances {
+async fn handle_erc721_allowance(log: &Log, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if processors.allowances {
         // Parse Approval event
         let owner = Address::from(log.topics[1]);
         let approved = Address::from(log.topics[2]);
         let token_id = U256::from(log.topics[3].0).as_u32() as i16;
 
         // Insert approval for a specific token_id with historical tracking
-        update_historical_allowance(conn, owner.as_bytes(), approved.as_bytes(), log.address.as_bytes(), "1".to_string(), Some(token_id), "ERC721", log.block_number.unwrap().as_u64() as i32).unwrap();
+        events.push(IndexedEvent::allowance_change(log, owner, approved, "1".to_string(), Some(token_id), "ERC721"));
     }
 
     Ok(())
 }
 
-async fn handle_erc721_approval_for_all(log: &Log, conn: &mut PgPooledConnection, cli: &Arc<Cli>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>{
-    if cli.process_allowances {
+async fn handle_erc721_approval_for_all(log: &Log, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>{
+    if processors.allowances {
         // Parse ApprovalForAll event
         let owner = Address::from(log.topics[1]);
         let operator = Address::from(log.topics[2]);
@@ -79,7 +111,7 @@ async fn handle_erc721_approval_for_all(log: &Log, conn: &mut PgPooledConnection
         let value = if approved { 1 } else { 0 };  // Set allowance to 1 for approval, 0 for revocation
 
         // Update operator allowance for all tokens owned by the user (without token_id)
-        update_historical_allowance(conn, owner.as_bytes(), operator.as_bytes(), log.address.as_bytes(), value.to_string(), None, "ERC721", log.block_number.unwrap().as_u64() as i32).unwrap();
+        events.push(IndexedEvent::allowance_change(log, owner, operator, value.to_string(), None, "ERC721"));
     }
 
     Ok(())
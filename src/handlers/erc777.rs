@@ -1,16 +1,48 @@
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use ethers::providers::{Http, Provider};
 use ethers::types::{Address, Log, H256, U256};
+use log::warn;
 
-use crate::models::balance::update_historical_balance;
-use crate::models::allowance::update_historical_allowance;
-use crate::models::token_supply::update_total_supply;
+use crate::events::IndexedEvent;
+use crate::handlers::{EventHandler, HandlerContext, HandlerResult};
+use crate::storage::Storage;
 use crate::token_service::check_and_insert_token;
-use crate::{Cli, PgPooledConnection, TokenType, ERC777_AUTHORIZED_OPERATOR_SIGNATURE, ERC777_BURNED_SIGNATURE, ERC777_MINTED_SIGNATURE, ERC777_REVOKED_OPERATOR_SIGNATURE, ERC777_SENT_SIGNATURE};
+use crate::constants::{ERC777_AUTHORIZED_OPERATOR_SIGNATURE, ERC777_BURNED_SIGNATURE, ERC777_MINTED_SIGNATURE, ERC777_REVOKED_OPERATOR_SIGNATURE, ERC777_SENT_SIGNATURE};
+use crate::indexer::Processors;
+use crate::utils::is_erc777;
+use crate::TokenType;
+
+/// `Sent`, `Minted`, `Burned` and operator events of ERC777 tokens, recognized by `granularity()`.
+pub struct Erc777Handler;
+
+#[async_trait]
+impl EventHandler for Erc777Handler {
+    fn name(&self) -> &str {
+        "ERC777"
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        vec![
+            *ERC777_SENT_SIGNATURE,
+            *ERC777_MINTED_SIGNATURE,
+            *ERC777_BURNED_SIGNATURE,
+            *ERC777_AUTHORIZED_OPERATOR_SIGNATURE,
+            *ERC777_REVOKED_OPERATOR_SIGNATURE,
+        ]
+    }
 
+    async fn matches_contract(&self, provider: Arc<Provider<Http>>, address: Address) -> bool {
+        is_erc777(provider, address).await
+    }
+
+    async fn handle(&self, log: &Log, context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()> {
+        handle_erc777_event(log, context.storage, context.provider.clone(), context.processors, events).await
+    }
+}
 
-pub async fn handle_erc777_event(log: &Log, conn: &mut PgPooledConnection, provider: Arc<Provider<Http>>, cli: &Arc<Cli>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub async fn handle_erc777_event(log: &Log, storage: &dyn Storage, provider: Arc<Provider<Http>>, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let event_signature: H256 = log.topics[0];
 
     // ERC777 Event Signatures
@@ -21,18 +53,18 @@ pub async fn handle_erc777_event(log: &Log, conn: &mut PgPooledConnection, provi
     let revoked_operator_event_signature: H256 = *ERC777_REVOKED_OPERATOR_SIGNATURE;  // ERC777 RevokedOperator
 
     match event_signature {
-        sig if sig == sent_event_signature => handle_erc777_sent(log, conn, provider, cli).await?,
-        sig if sig == minted_event_signature => handle_erc777_minted(log, conn, cli).await?,
-        sig if sig == burned_event_signature => handle_erc777_burned(log, conn, cli).await?,
-        sig if sig == authorized_operator_event_signature => handle_erc777_authorized_operator(log, conn, cli).await?,
-        sig if sig == revoked_operator_event_signature => handle_erc777_revoked_operator(log, conn, cli).await?,
-        _ => println!("Unknown ERC777 event at address: {:?}", log.address),
+        sig if sig == sent_event_signature => handle_erc777_sent(log, storage, provider, processors, events).await?,
+        sig if sig == minted_event_signature => handle_erc777_minted(log, processors, events).await?,
+        sig if sig == burned_event_signature => handle_erc777_burned(log, processors, events).await?,
+        sig if sig == authorized_operator_event_signature => handle_erc777_authorized_operator(log, processors, events).await?,
+        sig if sig == revoked_operator_event_signature => handle_erc777_revoked_operator(log, processors, events).await?,
+        _ => warn!("Unknown ERC777 event at address: {:?}", log.address),
     }
 
     Ok(())
 }
 
-async fn handle_erc777_sent(log: &Log, conn: &mut  PgPooledConnection, provider: Arc<Provider<Http>>, cli: &Arc<Cli>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn handle_erc777_sent(log: &Log, storage: &dyn Storage, provider: Arc<Provider<Http>>, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Parse Sent event
     let _operator = Address::from(log.topics[1]);
     let from = Address::from(log.topics[2]);
@@ -41,77 +73,80 @@ async fn handle_erc777_sent(log: &Log, conn: &mut  PgPooledConnection, provider:
     let block_number = log.block_number.unwrap().as_u32() as i32;
 
     // Pass the token type (ERC20 in this case) to check_and_insert_token
-    check_and_insert_token(conn, provider, log.address.as_bytes(), block_number, TokenType::ERC1155).await?;
+    check_and_insert_token(storage, provider, log.address.as_bytes(), block_number, TokenType::ERC1155).await?;
+    events.push(IndexedEvent::transfer(log, from, to, None, value.to_string(), "ERC777"));
 
-    if cli.process_balances {
+    if processors.balances {
         // Update the balance for the sender (subtract)
-        update_historical_balance(conn, from.as_bytes(), log.address.as_bytes(), format!("-{}", value), None, "ERC777", log.block_number.unwrap().as_u64() as i32).unwrap();
+        events.push(IndexedEvent::balance_change(log, from, format!("-{}", value), None, "ERC777"));
 
         // Update the balance for the recipient (add)
-        update_historical_balance(conn, to.as_bytes(), log.address.as_bytes(), format!("{}", value), None, "ERC777", log.block_number.unwrap().as_u64() as i32).unwrap();
+        events.push(IndexedEvent::balance_change(log, to, format!("{}", value), None, "ERC777"));
 
     }
 
     Ok(())
 }
 
-async fn handle_erc777_minted(log: &Log, conn: &mut PgPooledConnection, cli: &Arc<Cli>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn handle_erc777_minted(log: &Log, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Parse Minted event
     let _operator = Address::from(log.topics[1]);
     let to = Address::from(log.topics[2]);
     let value = U256::from_big_endian(&log.data[0..32]);
+    events.push(IndexedEvent::transfer(log, Address::zero(), to, None, value.to_string(), "ERC777"));
 
-    if cli.process_balances {
+    if processors.balances {
         // Update the balance for the recipient (add)
-        update_historical_balance(conn, to.as_bytes(), log.address.as_bytes(), format!("{}", value), None, "ERC777", log.block_number.unwrap().as_u32() as i32).unwrap();
+        events.push(IndexedEvent::balance_change(log, to, format!("{}", value), None, "ERC777"));
     }
-    if cli.process_total_supplies {
+    if processors.total_supplies {
         // Increase the total supply
-        update_total_supply(conn, log.address.as_bytes(), format!("{}", value), log.block_number.unwrap().as_u32() as i32)?;
+        events.push(IndexedEvent::supply_change(log, format!("{}", value)));
     }
 
     Ok(())
 }
 
-async fn handle_erc777_burned(log: &Log, conn: &mut PgPooledConnection, cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn handle_erc777_burned(log: &Log, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Parse Burned event
     let _operator = Address::from(log.topics[1]);
     let from = Address::from(log.topics[2]);
     let value = U256::from_big_endian(&log.data[0..32]);
+    events.push(IndexedEvent::transfer(log, from, Address::zero(), None, value.to_string(), "ERC777"));
 
-    if cli.process_balances {
+    if processors.balances {
         // Update the balance for the sender (subtract)
-        update_historical_balance(conn, from.as_bytes(), log.address.as_bytes(), format!("-{}", value), None, "ERC777", log.block_number.unwrap().as_u32() as i32).unwrap();
+        events.push(IndexedEvent::balance_change(log, from, format!("-{}", value), None, "ERC777"));
     }
-    if cli.process_total_supplies {
+    if processors.total_supplies {
         // Decrease the total supply
-        update_total_supply(conn, log.address.as_bytes(), format!("-{}", value), log.block_number.unwrap().as_u32() as i32).unwrap();
+        events.push(IndexedEvent::supply_change(log, format!("-{}", value)));
     }
 
     Ok(())
 }
 
-async fn handle_erc777_authorized_operator(log: &Log, conn: &mut PgPooledConnection, cli: &Arc<Cli>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if !cli.process_allowances { return Ok(()) } ;
+async fn handle_erc777_authorized_operator(log: &Log, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !processors.allowances { return Ok(()) } ;
 
     // Parse AuthorizedOperator event
     let holder = Address::from(log.topics[1]);
     let operator = Address::from(log.topics[2]);
 
     // Update allowance for the operator (1 means authorized)
-    update_historical_allowance(conn, holder.as_bytes(), operator.as_bytes(), log.address.as_bytes(), "1".to_string(), None, "ERC777", log.block_number.unwrap().as_u32() as i32).unwrap();
+    events.push(IndexedEvent::allowance_change(log, holder, operator, "1".to_string(), None, "ERC777"));
 
     Ok(())
 }
 
-async fn handle_erc777_revoked_operator(log: &Log, conn: &mut PgPooledConnection, cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if !cli.process_allowances { return Ok(())} ;
+async fn handle_erc777_revoked_operator(log: &Log, processors: &Processors, events: &mut Vec<IndexedEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !processors.allowances { return Ok(())} ;
     // Parse RevokedOperator event
     let holder = Address::from(log.topics[1]);
     let operator = Address::from(log.topics[2]);
 
     // Update allowance for the operator (0 means revoked)
-    update_historical_allowance(conn, holder.as_bytes(), operator.as_bytes(), log.address.as_bytes(), "0".to_string(), None, "ERC777", log.block_number.unwrap().as_u32() as i32).unwrap();
+    events.push(IndexedEvent::allowance_change(log, holder, operator, "0".to_string(), None, "ERC777"));
 
     Ok(())
 }
@@ -1,9 +1,120 @@
+pub mod abi;
 pub mod erc20;
 pub mod erc721;
 pub mod erc777;
 pub mod erc1155;
+pub mod script;
+pub mod system;
 
+pub use abi::{load_contracts, AbiHandler, ContractConfig};
 pub use erc20::*;
 pub use erc721::*;
 pub use erc777::*;
 pub use erc1155::*;
+pub use script::{ScriptHandler, ScriptLimits};
+pub use system::SystemHandler;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, Log, H256};
+
+use crate::events::IndexedEvent;
+use crate::indexer::Processors;
+use crate::storage::Storage;
+use crate::TokenType;
+
+pub type HandlerResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// What a handler may use while decoding a log.
+pub struct HandlerContext<'a> {
+    pub storage: &'a dyn Storage,
+    pub provider: Arc<Provider<Http>>,
+    pub processors: &'a Processors,
+}
+
+/// Decodes the logs of one kind of contract (a token standard, or an in-house protocol) into events.
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    /// Name used in log messages, e.g. "ERC20".
+    fn name(&self) -> &str;
+
+    /// Event signatures (topic0) of the logs this handler decodes; they are requested from the provider.
+    fn topics(&self) -> Vec<H256>;
+
+    /// Whether the contract emitting a log with one of `topics` is one this handler decodes.
+    /// Handlers sharing a topic (e.g. ERC20 and ERC721 `Transfer`) are asked in registration order.
+    async fn matches_contract(&self, _provider: Arc<Provider<Http>>, _address: Address) -> bool {
+        true
+    }
+
+    /// Appends the transfers and state changes carried by `log` to `events`.
+    async fn handle(&self, log: &Log, context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()>;
+}
+
+/// The handlers a log is dispatched to.
+#[derive(Clone, Default)]
+pub struct HandlerRegistry {
+    handlers: Vec<Arc<dyn EventHandler>>,
+}
+
+impl HandlerRegistry {
+    pub fn new() -> Self {
+        HandlerRegistry::default()
+    }
+
+    /// Adds a handler, asked after the ones already registered.
+    pub fn register(&mut self, handler: Arc<dyn EventHandler>) {
+        self.handlers.push(handler);
+    }
+
+    /// Registers the built-in handler of a token standard.
+    pub fn register_standard(&mut self, standard: TokenType) {
+        let handler: Arc<dyn EventHandler> = match standard {
+            TokenType::ERC20 => Arc::new(Erc20Handler),
+            TokenType::ERC721 => Arc::new(Erc721Handler),
+            TokenType::ERC1155 => Arc::new(Erc1155Handler),
+            TokenType::ERC777 => Arc::new(Erc777Handler),
+        };
+        self.register(handler);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Names of the registered handlers, in registration order.
+    pub fn names(&self) -> Vec<&str> {
+        self.handlers.iter().map(|handler| handler.name()).collect()
+    }
+
+    /// Every topic wanted by a registered handler, without duplicates.
+    pub fn topics(&self) -> Vec<H256> {
+        self.topics_of(|_| true)
+    }
+
+    /// Every topic wanted by the registered handlers whose name `wanted` accepts, without duplicates.
+    pub fn topics_of(&self, wanted: impl Fn(&str) -> bool) -> Vec<H256> {
+        let mut topics: Vec<H256> = self
+            .handlers
+            .iter()
+            .filter(|handler| wanted(handler.name()))
+            .flat_map(|handler| handler.topics())
+            .collect();
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// The first handler that wants the log's topic and recognizes the contract that emitted it.
+    pub async fn classify(&self, provider: Arc<Provider<Http>>, log: &Log) -> Option<&Arc<dyn EventHandler>> {
+        let topic = *log.topics.first()?;
+        for handler in &self.handlers {
+            if handler.topics().contains(&topic) && handler.matches_contract(provider.clone(), log.address).await {
+                return Some(handler);
+            }
+        }
+        None
+    }
+}
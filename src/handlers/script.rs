@@ -0,0 +1,359 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, Log, H256, I256, U256};
+use ethers::utils::{hex, keccak256, to_checksum};
+use log::{debug, info};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use crate::events::IndexedEvent;
+use crate::handlers::{EventHandler, HandlerContext, HandlerResult};
+use crate::token_service::check_and_insert_token;
+use crate::TokenType;
+
+// Operations between two checks of the time limit
+const DEADLINE_CHECK_INTERVAL: u64 = 1_024;
+
+thread_local! {
+    // When the script call running on this thread must stop; calls run synchronously, so one per thread
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Resources a script may use for each log.
+#[derive(Copy, Clone, Debug)]
+pub struct ScriptLimits {
+    pub max_operations: u64,              // Rhai operations per call, the equivalent of WASM fuel
+    pub timeout: Duration,                // Wall-clock time per call
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        ScriptLimits {
+            max_operations: 100_000,
+            timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+/// A handler written in Rhai and loaded at runtime. The script defines:
+///
+/// - `fn topics()`: the event signatures (e.g. `"Rebase(uint256,uint256)"`) or topic0 hashes it handles;
+/// - `fn matches_contract(address)` (optional): whether it handles logs of the checksummed `address`;
+/// - `fn handle(log)`: the mutations carried by `log`, as an array of maps with a `kind` of
+///   `transfer`, `balance`, `allowance`, `supply` or `custom`.
+///
+/// Scripts cannot import modules or reach the file system or network; they only see the log and the
+/// word-decoding and 256-bit arithmetic helpers registered by `sandboxed_engine`.
+pub struct ScriptHandler {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    topics: HashMap<H256, String>,        // Topic0 to the signature given by the script
+    matches_contract: bool,               // Whether the script defines `matches_contract`
+    limits: ScriptLimits,
+}
+
+impl ScriptHandler {
+    /// Compiles the script at `path`; the handler is named after the file stem.
+    pub fn load(path: &Path, limits: ScriptLimits) -> HandlerResult<Self> {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "script".to_string());
+        let engine = sandboxed_engine(&name, limits);
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| format!("Invalid script {}: {}", path.display(), e))?;
+
+        let defines = |function: &str, arity: usize| ast.iter_functions().any(|f| f.name == function && f.params.len() == arity);
+        if !defines("topics", 0) || !defines("handle", 1) {
+            return Err(format!("Script {} must define `topics()` and `handle(log)`", path.display()).into());
+        }
+        let matches_contract = defines("matches_contract", 1);
+
+        let mut handler = ScriptHandler { name, engine, ast, topics: HashMap::new(), matches_contract, limits };
+        let signatures: Array = handler.call("topics", ())?;
+        for signature in signatures {
+            let signature = signature.into_string().map_err(|kind| format!("`topics()` returned a {} instead of a string", kind))?;
+            handler.topics.insert(parse_topic(&signature)?, signature);
+        }
+
+        info!("Loaded script {} handling {} events", handler.name, handler.topics.len());
+        Ok(handler)
+    }
+
+    /// Calls a script function with the limits applied.
+    fn call<T: Clone + Send + Sync + 'static>(&self, function: &str, args: impl rhai::FuncArgs) -> HandlerResult<T> {
+        DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + self.limits.timeout)));
+        let result = self.engine.call_fn::<T>(&mut Scope::new(), &self.ast, function, args);
+        DEADLINE.with(|deadline| deadline.set(None));
+
+        result.map_err(|e| match *e {
+            EvalAltResult::ErrorTerminated(..) => {
+                format!("Script {} exceeded {:?} in `{}`", self.name, self.limits.timeout, function).into()
+            }
+            e => format!("Script {} failed in `{}`: {}", self.name, function, e).into(),
+        })
+    }
+
+    /// Converts a mutation returned by `handle` into an event of `log`; mutations of a table whose
+    /// processor is disabled are dropped, as with the built-in handlers.
+    async fn mutation_event(&self, log: &Log, mutation: Map, context: &HandlerContext<'_>) -> HandlerResult<Option<IndexedEvent>> {
+        let kind = string_field(&mutation, "kind")?;
+        let processors = context.processors;
+
+        if kind == "custom" {
+            let params = mutation.get("params").cloned().unwrap_or_else(|| Dynamic::from_map(Map::new()));
+            let signature = log.topics.first().and_then(|topic| self.topics.get(topic)).cloned().unwrap_or_default();
+            return Ok(Some(IndexedEvent::contract_event(log, &self.name, &string_field(&mutation, "name")?, signature, serde_json::to_value(&params)?)));
+        }
+        if kind == "balance" && !processors.balances
+            || kind == "allowance" && !processors.allowances
+            || kind == "supply" && !processors.total_supplies
+        {
+            return Ok(None);
+        }
+
+        // Token mutations reference the emitting contract, which is stored as a token first
+        let token_type = match mutation.get("token_type") {
+            Some(_) => string_field(&mutation, "token_type")?.parse::<TokenType>()?,
+            None => TokenType::ERC20,
+        };
+        let block_number = log.block_number.map(|number| number.as_u32() as i32).unwrap_or_default();
+        check_and_insert_token(context.storage, context.provider.clone(), log.address.as_bytes(), block_number, token_type).await?;
+
+        let token_type_name = token_type.as_str();
+        let token_id = match mutation.get("token_id") {
+            Some(id) => Some(id.as_int().map_err(|kind| format!("`token_id` is a {} instead of an integer", kind))? as i16),
+            None => None,
+        };
+
+        let event = match kind.as_str() {
+            "transfer" => IndexedEvent::transfer(
+                log,
+                address_field(&mutation, "from")?,
+                address_field(&mutation, "to")?,
+                token_id,
+                amount_field(&mutation, "value")?,
+                token_type_name,
+            ),
+            "balance" => IndexedEvent::balance_change(log, address_field(&mutation, "wallet")?, amount_field(&mutation, "delta")?, token_id, token_type_name),
+            "allowance" => IndexedEvent::allowance_change(
+                log,
+                address_field(&mutation, "owner")?,
+                address_field(&mutation, "spender")?,
+                unsigned_amount_field(&mutation, "allowance")?,
+                token_id,
+                token_type_name,
+            ),
+            "supply" => IndexedEvent::supply_change(log, amount_field(&mutation, "delta")?),
+            other => return Err(format!("Unknown mutation kind `{}`", other).into()),
+        };
+        Ok(Some(event))
+    }
+}
+
+#[async_trait]
+impl EventHandler for ScriptHandler {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        self.topics.keys().copied().collect()
+    }
+
+    async fn matches_contract(&self, _provider: Arc<Provider<Http>>, address: Address) -> bool {
+        if !self.matches_contract {
+            return true;
+        }
+        self.call::<bool>("matches_contract", (to_checksum(&address, None),)).unwrap_or_else(|e| {
+            debug!("{}", e);
+            false
+        })
+    }
+
+    async fn handle(&self, log: &Log, context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()> {
+        let result: Dynamic = self.call("handle", (log_map(log, self.topics.get(&log.topics[0])),))?;
+        if result.is_unit() {
+            return Ok(());
+        }
+
+        let mutations = result.into_array().map_err(|kind| format!("`handle` returned a {} instead of an array", kind))?;
+        for mutation in mutations {
+            let mutation = mutation.try_cast::<Map>().ok_or("`handle` returned a mutation that is not a map")?;
+            if let Some(event) = self.mutation_event(log, mutation, context).await? {
+                events.push(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An engine with the sandbox limits applied and the host helpers registered.
+fn sandboxed_engine(name: &str, limits: ScriptLimits) -> Engine {
+    let mut engine = Engine::new();
+
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(limits.max_operations)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(64 * 1024)
+        .set_max_array_size(10_000)
+        .set_max_map_size(1_000)
+        .disable_symbol("eval");
+
+    engine.on_progress(|operations| {
+        if operations % DEADLINE_CHECK_INTERVAL != 0 {
+            return None;
+        }
+        let expired = DEADLINE.with(|deadline| deadline.get()).is_some_and(|deadline| Instant::now() > deadline);
+        expired.then_some(Dynamic::UNIT)
+    });
+
+    let script_name = name.to_string();
+    engine.on_print(move |text| info!("[{}] {}", script_name, text));
+    engine.on_debug(|text, _, _| debug!("{}", text));
+
+    // Decoding of the 32-byte words of topics and data
+    engine.register_fn("word", |data: &str, index: i64| -> Result<String, Box<EvalAltResult>> {
+        let bytes = decode_hex(data)?;
+        let missing = || format!("no word {} in {} bytes of data", index, bytes.len());
+        // Checked, as the index comes from the script
+        let start = usize::try_from(index).map_err(|_| "negative word index")?.checked_mul(32).ok_or_else(missing)?;
+        let end = start.checked_add(32).ok_or_else(missing)?;
+        let word = bytes.get(start..end).ok_or_else(missing)?;
+        Ok(format!("0x{}", hex::encode(word)))
+    });
+    engine.register_fn("to_address", |word: &str| -> Result<String, Box<EvalAltResult>> {
+        Ok(to_checksum(&Address::from(parse_word(word)?), None))
+    });
+    engine.register_fn("to_uint", |word: &str| -> Result<String, Box<EvalAltResult>> {
+        Ok(U256::from_big_endian(parse_word(word)?.as_bytes()).to_string())
+    });
+    engine.register_fn("to_int", |word: &str| -> Result<String, Box<EvalAltResult>> {
+        Ok(I256::from_raw(U256::from_big_endian(parse_word(word)?.as_bytes())).to_string())
+    });
+    engine.register_fn("event_topic", |signature: &str| format!("{:?}", H256::from(keccak256(signature))));
+
+    // 256-bit unsigned arithmetic on decimal strings
+    engine.register_fn("uint_add", |a: &str, b: &str| uint_op(a, b, U256::checked_add));
+    engine.register_fn("uint_sub", |a: &str, b: &str| uint_op(a, b, U256::checked_sub));
+    engine.register_fn("uint_mul", |a: &str, b: &str| uint_op(a, b, U256::checked_mul));
+    engine.register_fn("uint_div", |a: &str, b: &str| uint_op(a, b, U256::checked_div));
+
+    engine
+}
+
+/// The log as passed to `handle`: hex strings, a checksummed address and integer coordinates.
+fn log_map(log: &Log, signature: Option<&String>) -> Map {
+    let mut map = Map::new();
+    map.insert("address".into(), to_checksum(&log.address, None).into());
+    map.insert("topics".into(), log.topics.iter().map(|topic| Dynamic::from(format!("{:?}", topic))).collect::<Array>().into());
+    map.insert("data".into(), format!("0x{}", hex::encode(&log.data)).into());
+    map.insert("signature".into(), signature.cloned().unwrap_or_default().into());
+    map.insert("block_number".into(), log.block_number.map(|number| number.as_u64() as i64).unwrap_or_default().into());
+    map.insert("transaction_hash".into(), log.transaction_hash.map(|hash| format!("{:?}", hash)).unwrap_or_default().into());
+    map.insert("log_index".into(), log.log_index.map(|index| index.as_u64() as i64).unwrap_or_default().into());
+    map
+}
+
+/// A topic given as a 0x-prefixed hash, or an event signature to hash.
+fn parse_topic(topic: &str) -> HandlerResult<H256> {
+    if topic.starts_with("0x") {
+        return Ok(topic.parse()?);
+    }
+    Ok(H256::from(keccak256(topic)))
+}
+
+fn string_field(mutation: &Map, field: &str) -> HandlerResult<String> {
+    let value = mutation.get(field).ok_or_else(|| format!("Mutation without `{}`", field))?;
+    Ok(value.clone().into_string().map_err(|kind| format!("`{}` is a {} instead of a string", field, kind))?)
+}
+
+fn address_field(mutation: &Map, field: &str) -> HandlerResult<Address> {
+    Ok(string_field(mutation, field)?.parse()?)
+}
+
+/// A decimal amount, optionally prefixed with '-', given as a string or an integer.
+fn amount_field(mutation: &Map, field: &str) -> HandlerResult<String> {
+    let amount = match mutation.get(field) {
+        Some(value) if value.is_int() => value.as_int().unwrap_or_default().to_string(),
+        _ => string_field(mutation, field)?,
+    };
+    U256::from_dec_str(amount.strip_prefix('-').unwrap_or(&amount)).map_err(|_| format!("`{}` is not a decimal amount: {}", field, amount))?;
+    Ok(amount)
+}
+
+// Amounts that replace a value rather than add to it, such as allowances, cannot be negative
+fn unsigned_amount_field(mutation: &Map, field: &str) -> HandlerResult<String> {
+    let amount = amount_field(mutation, field)?;
+    if amount.starts_with('-') {
+        return Err(format!("`{}` cannot be negative: {}", field, amount).into());
+    }
+    Ok(amount)
+}
+
+fn decode_hex(data: &str) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    hex::decode(data.trim_start_matches("0x")).map_err(|e| format!("invalid hex: {}", e).into())
+}
+
+fn parse_word(word: &str) -> Result<H256, Box<EvalAltResult>> {
+    let bytes = decode_hex(word)?;
+    if bytes.len() != 32 {
+        return Err(format!("a word is 32 bytes, got {}", bytes.len()).into());
+    }
+    Ok(H256::from_slice(&bytes))
+}
+
+fn uint_op(a: &str, b: &str, op: fn(U256, U256) -> Option<U256>) -> Result<String, Box<EvalAltResult>> {
+    let parse = |value: &str| U256::from_dec_str(value).map_err(|_| format!("not an unsigned decimal: {}", value));
+    let result = op(parse(a)?, parse(b)?).ok_or_else(|| format!("overflow, underflow or division by zero with {} and {}", a, b))?;
+    Ok(result.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(script: &str) -> Result<String, Box<EvalAltResult>> {
+        sandboxed_engine("test", ScriptLimits::default()).eval::<String>(script)
+    }
+
+    #[test]
+    fn word_reads_the_words_of_data() {
+        let data = format!("0x{}{}", "00".repeat(31) + "01", "00".repeat(31) + "02");
+        assert_eq!(eval(&format!("word(\"{}\", 1)", data)).unwrap(), format!("0x{}02", "00".repeat(31)));
+        assert!(eval(&format!("word(\"{}\", 2)", data)).is_err());
+        assert!(eval(&format!("word(\"{}\", -1)", data)).is_err());
+    }
+
+    #[test]
+    fn word_rejects_indexes_overflowing_the_offset() {
+        assert!(eval(&format!("word(\"0x{}\", {})", "00".repeat(32), i64::MAX)).is_err());
+        assert!(eval(&format!("word(\"0x{}\", {})", "00".repeat(32), (usize::MAX / 32) as i64)).is_err());
+    }
+
+    #[test]
+    fn allowances_cannot_be_negative() {
+        let mut mutation = Map::new();
+        mutation.insert("delta".into(), Dynamic::from("-5".to_string()));
+        mutation.insert("allowance".into(), Dynamic::from("-5".to_string()));
+        assert_eq!(amount_field(&mutation, "delta").unwrap(), "-5");
+        assert!(unsigned_amount_field(&mutation, "allowance").is_err());
+        mutation.insert("allowance".into(), Dynamic::from(5_i64));
+        assert_eq!(unsigned_amount_field(&mutation, "allowance").unwrap(), "5");
+    }
+
+    #[test]
+    fn parse_topic_reads_hashes_and_hashes_signatures() {
+        let transfer = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+        assert_eq!(parse_topic(transfer).unwrap(), transfer.parse().unwrap());
+        assert_eq!(parse_topic("Transfer(address,address,uint256)").unwrap(), transfer.parse().unwrap());
+        assert!(parse_topic("0x1234").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, Log, H160, H256, U256};
+use log::info;
+use serde::Deserialize;
+
+use crate::constants::{
+    ERC_APPROVAL_SIGNATURE, ERC_TRANSFER_SIGNATURE, POLYGON_LOG_FEE_TRANSFER_SIGNATURE, POLYGON_LOG_TRANSFER_SIGNATURE,
+    ZKSYNC_MINT_SIGNATURE, ZKSYNC_WITHDRAWAL_SIGNATURE, ZKSYNC_WITHDRAWAL_WITH_MESSAGE_SIGNATURE,
+};
+use crate::events::IndexedEvent;
+use crate::handlers::erc20::record_erc20_transfer;
+use crate::handlers::{EventHandler, HandlerContext, HandlerResult};
+use crate::TokenType;
+
+/// MRC20 contract whose logs record native POL (formerly MATIC) movements on Polygon PoS.
+pub const POLYGON_NATIVE_TOKEN: Address = H160(hex_literal::hex!("0000000000000000000000000000000000001010"));
+
+/// L2BaseToken system contract holding the ETH balances of zkSync Era.
+pub const ZKSYNC_BASE_TOKEN: Address = H160(hex_literal::hex!("000000000000000000000000000000000000800a"));
+
+/// Handlers of chain-specific system contracts, enabled by the chain presets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemHandler {
+    PolygonLogTransfer,                   // Native POL movements of Polygon PoS
+    ZksyncBaseToken,                      // ETH mints and withdrawals of zkSync Era
+}
+
+impl SystemHandler {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SystemHandler::PolygonLogTransfer => "polygon_log_transfer",
+            SystemHandler::ZksyncBaseToken => "zksync_base_token",
+        }
+    }
+
+    /// Standard the native token is indexed as; the handler only runs when it is enabled.
+    pub fn standard(&self) -> TokenType {
+        TokenType::ERC20
+    }
+
+    pub fn handler(&self) -> Arc<dyn EventHandler> {
+        match self {
+            SystemHandler::PolygonLogTransfer => Arc::new(PolygonLogTransferHandler),
+            SystemHandler::ZksyncBaseToken => Arc::new(ZksyncBaseTokenHandler),
+        }
+    }
+}
+
+/// `LogTransfer` and `LogFeeTransfer` of the Polygon PoS native token, which the node logs for every
+/// value transfer and gas fee payment. The contract's own `Transfer` and `Approval` logs repeat a
+/// `LogTransfer` and are skipped.
+pub struct PolygonLogTransferHandler;
+
+#[async_trait]
+impl EventHandler for PolygonLogTransferHandler {
+    fn name(&self) -> &str {
+        "Polygon LogTransfer"
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        vec![
+            *POLYGON_LOG_TRANSFER_SIGNATURE,
+            *POLYGON_LOG_FEE_TRANSFER_SIGNATURE,
+            *ERC_TRANSFER_SIGNATURE,
+            *ERC_APPROVAL_SIGNATURE,
+        ]
+    }
+
+    async fn matches_contract(&self, _provider: Arc<Provider<Http>>, address: Address) -> bool {
+        address == POLYGON_NATIVE_TOKEN
+    }
+
+    async fn handle(&self, log: &Log, context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()> {
+        let event_signature = log.topics[0];
+        if event_signature != *POLYGON_LOG_TRANSFER_SIGNATURE && event_signature != *POLYGON_LOG_FEE_TRANSFER_SIGNATURE {
+            return Ok(());
+        }
+
+        // LogTransfer(address indexed token, address indexed from, address indexed to, uint256 amount, ...)
+        let from = Address::from(log.topics[2]);
+        let to = Address::from(log.topics[3]);
+        let amount = first_word(log)?;
+        info!("Handling Polygon native transfer from {:?} to {:?}", from, to);
+
+        record_erc20_transfer(log, from, to, amount, context.storage, context.provider.clone(), context.processors, events).await
+    }
+}
+
+/// ETH movements of zkSync Era's base token: its `Transfer` logs, plus the `Mint` of deposits and the
+/// `Withdrawal` burns, which are not logged as transfers.
+pub struct ZksyncBaseTokenHandler;
+
+#[async_trait]
+impl EventHandler for ZksyncBaseTokenHandler {
+    fn name(&self) -> &str {
+        "zkSync base token"
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        vec![
+            *ERC_TRANSFER_SIGNATURE,
+            *ZKSYNC_MINT_SIGNATURE,
+            *ZKSYNC_WITHDRAWAL_SIGNATURE,
+            *ZKSYNC_WITHDRAWAL_WITH_MESSAGE_SIGNATURE,
+        ]
+    }
+
+    async fn matches_contract(&self, _provider: Arc<Provider<Http>>, address: Address) -> bool {
+        address == ZKSYNC_BASE_TOKEN
+    }
+
+    async fn handle(&self, log: &Log, context: &HandlerContext<'_>, events: &mut Vec<IndexedEvent>) -> HandlerResult<()> {
+        let event_signature = log.topics[0];
+        let (from, to) = if event_signature == *ERC_TRANSFER_SIGNATURE {
+            (Address::from(log.topics[1]), Address::from(log.topics[2]))
+        } else if event_signature == *ZKSYNC_MINT_SIGNATURE {
+            // Mint(address indexed account, uint256 amount)
+            (Address::zero(), Address::from(log.topics[1]))
+        } else {
+            // The withdrawn value is sent to the contract, then burnt from its balance
+            (ZKSYNC_BASE_TOKEN, Address::zero())
+        };
+        let amount = first_word(log)?;
+
+        record_erc20_transfer(log, from, to, amount, context.storage, context.provider.clone(), context.processors, events).await
+    }
+}
+
+// The amount these events carry as their first non-indexed parameter
+fn first_word(log: &Log) -> HandlerResult<U256> {
+    if log.data.len() < 32 {
+        return Err(format!("Log of {:?} carries no amount", log.address).into());
+    }
+    Ok(U256::from_big_endian(&log.data[..32]))
+}
@@ -0,0 +1,913 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::providers::ProviderError;
+use ethers::types::{Address, BlockNumber, Filter, Log, H256};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::address_filter::{AddressFilter, DEFAULT_ADDRESS_LIMIT};
+use crate::block_service::{blocks_in_logs, fetch_and_store_blocks};
+use crate::checkpoint::{self, HeadClaim, Pass, Progress, Stream, CHAIN_WIDE};
+use crate::constants::{
+    ERC777_AUTHORIZED_OPERATOR_SIGNATURE, ERC777_BURNED_SIGNATURE, ERC777_MINTED_SIGNATURE, ERC777_REVOKED_OPERATOR_SIGNATURE,
+    ERC_APPROVAL_FOR_ALL_SIGNATURE, ERC_APPROVAL_SIGNATURE, ERC_TRANSFER_SIGNATURE, ZKSYNC_MINT_SIGNATURE, ZKSYNC_WITHDRAWAL_SIGNATURE,
+    ZKSYNC_WITHDRAWAL_WITH_MESSAGE_SIGNATURE,
+};
+use crate::events::IndexedEvent;
+use crate::handlers::{EventHandler, HandlerRegistry, SystemHandler};
+use crate::parser::parse_log_in_pass;
+use crate::sinks::storage::StorageSink;
+use crate::sinks::{EventSink, EventSinks};
+use crate::storage::Storage;
+use crate::utils::{read_last_processed_block, write_last_processed_block};
+use crate::TokenType;
+
+pub type IndexerResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Blocks covered by each `eth_getLogs` request unless set with `block_range`.
+pub const DEFAULT_BLOCK_RANGE: u64 = 10_000;
+
+/// Time the ranges in flight get to be decoded once a shutdown is requested, unless set with
+/// `shutdown_timeout`.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Wait of the head for the streams a catch-up pass still holds
+const BUSY_WAIT: Duration = Duration::from_secs(1);
+
+// Topics of the standard events whose indexed addresses are all in topic1 and topic2; the watched
+// wallets are also looked for in topic3 for the other events
+static TWO_ADDRESS_TOPICS: Lazy<[H256; 10]> = Lazy::new(|| {
+    [
+        *ERC_TRANSFER_SIGNATURE,
+        *ERC_APPROVAL_SIGNATURE,
+        *ERC_APPROVAL_FOR_ALL_SIGNATURE,
+        *ERC777_MINTED_SIGNATURE,
+        *ERC777_BURNED_SIGNATURE,
+        *ERC777_AUTHORIZED_OPERATOR_SIGNATURE,
+        *ERC777_REVOKED_OPERATOR_SIGNATURE,
+        *ZKSYNC_MINT_SIGNATURE,
+        *ZKSYNC_WITHDRAWAL_SIGNATURE,
+        *ZKSYNC_WITHDRAWAL_WITH_MESSAGE_SIGNATURE,
+    ]
+});
+
+type EventCallback = Arc<dyn Fn(&IndexedEvent) + Send + Sync>;
+type RangeCallback = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// What is derived from the indexed logs, besides the transfers themselves.
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Processors {
+    pub balances: bool,
+    pub allowances: bool,
+    pub total_supplies: bool,
+    pub token_uri: bool,                  // Fetch and store ERC721/1155 token URIs
+    pub blocks: bool,                     // Store headers of the blocks carrying indexed logs
+    pub all_blocks: bool,                 // Store headers of every block in the range
+}
+
+/// Which blocks are indexed when following the chain head, so logs that may still be reorganized
+/// away are left for later.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Finality {
+    #[default]
+    Latest,                               // Up to the head
+    Confirmations(u64),                   // Up to this many blocks behind the head
+    Finalized,                            // Up to the block the node reports as `finalized`
+}
+
+impl std::fmt::Display for Finality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Finality::Latest => write!(f, "latest"),
+            Finality::Confirmations(confirmations) => write!(f, "{} confirmations", confirmations),
+            Finality::Finalized => write!(f, "finalized"),
+        }
+    }
+}
+
+/// Configures an [`Indexer`]; only the provider, the storage and at least one standard or handler are required.
+#[derive(Clone)]
+pub struct IndexerBuilder {
+    provider: Option<Arc<Provider<Http>>>,
+    storage: Option<Arc<dyn Storage>>,
+    standards: BTreeSet<TokenType>,
+    handlers: Vec<Arc<dyn EventHandler>>,
+    system_handlers: Vec<SystemHandler>,
+    processors: Processors,
+    from_block: Option<u64>,
+    first_block: u64,
+    to_block: Option<u64>,
+    block_range: u64,
+    log_limit: Option<usize>,
+    address_filter: Arc<AddressFilter>,
+    address_limit: usize,
+    finality: Finality,
+    concurrency: Option<usize>,
+    checkpoint_file: Option<String>,
+    stored_checkpoints: bool,
+    shutdown: CancellationToken,
+    shutdown_timeout: Duration,
+    abort: CancellationToken,
+    sinks: Option<EventSinks>,
+    event_callbacks: Vec<EventCallback>,
+    range_callbacks: Vec<RangeCallback>,
+}
+
+impl IndexerBuilder {
+    pub fn provider(mut self, provider: Arc<Provider<Http>>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Enables the built-in handler of a token standard. Built-in handlers are asked to recognize
+    /// contracts before custom ones, in ERC20, ERC721, ERC1155, ERC777 order.
+    pub fn standard(mut self, standard: TokenType) -> Self {
+        self.standards.insert(standard);
+        self
+    }
+
+    pub fn standards(mut self, standards: impl IntoIterator<Item = TokenType>) -> Self {
+        self.standards.extend(standards);
+        self
+    }
+
+    /// Adds a custom handler, asked to recognize contracts after the built-in ones and those added before it.
+    pub fn handler(mut self, handler: impl EventHandler + 'static) -> Self {
+        self.handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Adds custom handlers, in order.
+    pub fn handlers(mut self, handlers: impl IntoIterator<Item = Arc<dyn EventHandler>>) -> Self {
+        self.handlers.extend(handlers);
+        self
+    }
+
+    /// Adds handlers of chain-specific system contracts. They are asked before the standards, so they
+    /// claim the logs of their contracts, and only registered when the standard they extend is.
+    pub fn system_handlers(mut self, handlers: impl IntoIterator<Item = SystemHandler>) -> Self {
+        self.system_handlers.extend(handlers);
+        self
+    }
+
+    pub fn processors(mut self, processors: Processors) -> Self {
+        self.processors = processors;
+        self
+    }
+
+    /// Indexes from `from_block` up to `to_block` (inclusive), or up to the chain head at the time
+    /// each range is fetched when `to_block` is None.
+    pub fn range(mut self, from_block: u64, to_block: Option<u64>) -> Self {
+        self.from_block = Some(from_block);
+        self.to_block = to_block;
+        self
+    }
+
+    /// Starts at `first_block` instead of the genesis block when the checkpoint records none (and
+    /// no start block is given with `range`), e.g. at the deployment of the indexed contracts.
+    pub fn first_block(mut self, first_block: u64) -> Self {
+        self.first_block = first_block;
+        self
+    }
+
+    /// Stops after `to_block` (inclusive) instead of at the chain head, starting after the checkpoint
+    /// unless a start block is given with `range`.
+    pub fn to_block(mut self, to_block: u64) -> Self {
+        self.to_block = Some(to_block);
+        self
+    }
+
+    /// Number of blocks requested from the provider at once.
+    pub fn block_range(mut self, block_range: u64) -> Self {
+        self.block_range = block_range;
+        self
+    }
+
+    /// Number of logs the provider returns at most per `eth_getLogs` request. A response that
+    /// reaches it may be truncated, so its range is requested again in halves.
+    pub fn log_limit(mut self, log_limit: usize) -> Self {
+        self.log_limit = Some(log_limit);
+        self
+    }
+
+    /// Only indexes the logs of the contracts `filter` lets through: an allowlist is requested from
+    /// the provider, a denylist is applied to the logs it returns.
+    pub fn address_filter(mut self, filter: AddressFilter) -> Self {
+        self.address_filter = Arc::new(filter);
+        self
+    }
+
+    /// Number of addresses of the allowlist requested at once; longer allowlists are requested in
+    /// several `eth_getLogs` calls.
+    pub fn address_limit(mut self, address_limit: usize) -> Self {
+        self.address_limit = address_limit;
+        self
+    }
+
+    /// Which blocks count as the chain head; the latest block by default.
+    pub fn finality(mut self, finality: Finality) -> Self {
+        self.finality = finality;
+        self
+    }
+
+    /// Maximum number of logs handled at once; unlimited by default.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
+    /// Records the last indexed block in `path` after every range, and resumes after it when no
+    /// start block is given with `range`.
+    pub fn checkpoint_file(mut self, path: impl Into<String>) -> Self {
+        self.checkpoint_file = Some(path.into());
+        self
+    }
+
+    /// Records the last indexed block of every stream (a handler's events, or one processor applied
+    /// to its logs) in the storage instead, so a handler or processor enabled later catches up on
+    /// its own while the others continue at the head. A checkpoint file is then only imported, when
+    /// the storage has no checkpoint of the chain yet.
+    pub fn stored_checkpoints(mut self) -> Self {
+        self.stored_checkpoints = true;
+        self
+    }
+
+    /// Stops indexing once `token` is cancelled: no range is started anymore, and those in flight
+    /// are written and checkpointed before `run` and `follow` return.
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// Time the ranges in flight get to be decoded once the shutdown is requested; past it, they are
+    /// dropped before anything of them is written, and indexed again by the next run.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Stops indexing at once when `token` is cancelled: like a shutdown, except that the ranges not
+    /// being written yet are dropped right away instead of after the shutdown timeout.
+    pub fn abort(mut self, token: CancellationToken) -> Self {
+        self.abort = token;
+        self
+    }
+
+    /// Replaces the sinks the decoded events are written to. Without sinks, events are applied
+    /// to the storage.
+    pub fn sinks(mut self, sinks: EventSinks) -> Self {
+        self.sinks = Some(sinks);
+        self
+    }
+
+    /// Adds a sink the decoded events are written to.
+    pub fn sink(mut self, sink: impl EventSink + 'static) -> Self {
+        self.sinks.get_or_insert_with(|| EventSinks::new(Vec::new())).push(Box::new(sink));
+        self
+    }
+
+    /// Calls `callback` with every event decoded, after it was written to the sinks.
+    pub fn on_event(mut self, callback: impl Fn(&IndexedEvent) + Send + Sync + 'static) -> Self {
+        self.event_callbacks.push(Arc::new(callback));
+        self
+    }
+
+    /// Calls `callback` with the first and last block of every range once it is fully indexed.
+    pub fn on_range_indexed(mut self, callback: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.range_callbacks.push(Arc::new(callback));
+        self
+    }
+
+    pub fn build(self) -> IndexerResult<Indexer> {
+        let provider = self.provider.ok_or("An indexer needs a provider")?;
+        let storage = self.storage.ok_or("An indexer needs a storage")?;
+        let mut handlers = HandlerRegistry::new();
+        for system_handler in self.system_handlers.iter().filter(|handler| self.standards.contains(&handler.standard())) {
+            handlers.register(system_handler.handler());
+        }
+        self.standards.into_iter().for_each(|standard| handlers.register_standard(standard));
+        self.handlers.into_iter().for_each(|handler| handlers.register(handler));
+        if handlers.is_empty() {
+            return Err("An indexer needs at least one token standard or handler".into());
+        }
+        if self.block_range == 0 {
+            return Err("The block range must be at least 1".into());
+        }
+        if self.concurrency == Some(0) {
+            return Err("The concurrency must be at least 1".into());
+        }
+        if self.log_limit == Some(0) {
+            return Err("The log limit must be at least 1".into());
+        }
+        if self.address_limit == 0 {
+            return Err("The address limit must be at least 1".into());
+        }
+
+        let sinks = self
+            .sinks
+            .unwrap_or_else(|| EventSinks::new(vec![Box::new(StorageSink::new(storage.clone()))]));
+        let streams = checkpoint::streams(handlers.names(), &self.processors);
+
+        Ok(Indexer {
+            provider,
+            storage,
+            handlers: Arc::new(handlers),
+            from_block: self.from_block,
+            first_block: self.first_block,
+            to_block: self.to_block,
+            block_range: self.block_range,
+            log_limit: self.log_limit,
+            address_filter: self.address_filter,
+            address_limit: self.address_limit,
+            finality: self.finality,
+            concurrency: self.concurrency.map(|permits| Arc::new(Semaphore::new(permits))),
+            checkpoint_file: self.checkpoint_file,
+            stored_checkpoints: self.stored_checkpoints,
+            streams,
+            shutdown: self.shutdown,
+            shutdown_timeout: self.shutdown_timeout,
+            abort: self.abort,
+            sinks: Arc::new(sinks),
+            event_callbacks: Arc::new(self.event_callbacks),
+            range_callbacks: self.range_callbacks,
+        })
+    }
+}
+
+/// Fetches the logs wanted by the registered handlers range by range, decodes them and writes the
+/// resulting events to the sinks.
+pub struct Indexer {
+    provider: Arc<Provider<Http>>,
+    storage: Arc<dyn Storage>,
+    handlers: Arc<HandlerRegistry>,
+    from_block: Option<u64>,
+    first_block: u64,                     // Start when neither a start block nor a checkpoint is given
+    to_block: Option<u64>,
+    block_range: u64,
+    log_limit: Option<usize>,
+    address_filter: Arc<AddressFilter>,
+    address_limit: usize,                 // Addresses per `eth_getLogs` request
+    finality: Finality,
+    concurrency: Option<Arc<Semaphore>>,  // Permits for the log tasks, when limited
+    checkpoint_file: Option<String>,
+    stored_checkpoints: bool,
+    streams: Vec<Stream>,                 // Every stream the handlers and processors produce
+    shutdown: CancellationToken,
+    shutdown_timeout: Duration,
+    abort: CancellationToken,
+    sinks: Arc<EventSinks>,
+    event_callbacks: Arc<Vec<EventCallback>>,
+    range_callbacks: Vec<RangeCallback>,
+}
+
+impl Indexer {
+    pub fn builder() -> IndexerBuilder {
+        IndexerBuilder {
+            provider: None,
+            storage: None,
+            standards: BTreeSet::new(),
+            handlers: Vec::new(),
+            system_handlers: Vec::new(),
+            processors: Processors::default(),
+            from_block: None,
+            first_block: 0,
+            to_block: None,
+            block_range: DEFAULT_BLOCK_RANGE,
+            log_limit: None,
+            address_filter: Arc::new(AddressFilter::new()),
+            address_limit: DEFAULT_ADDRESS_LIMIT,
+            finality: Finality::default(),
+            concurrency: None,
+            checkpoint_file: None,
+            stored_checkpoints: false,
+            shutdown: CancellationToken::new(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            abort: CancellationToken::new(),
+            sinks: None,
+            event_callbacks: Vec::new(),
+            range_callbacks: Vec::new(),
+        }
+    }
+
+    /// Indexes every range up to the end block (or the chain head) and returns the last indexed
+    /// block, if any range was indexed. Streams behind the others catch up alongside. Returns early,
+    /// with every indexed range checkpointed, once the shutdown is requested.
+    pub async fn run(&self) -> IndexerResult<Option<u64>> {
+        info!("Starting the block processing loop");
+        let progress = Mutex::new(self.load_progress()?);
+        let stop = self.shutdown.child_token();
+        let ((last_indexed_block, caught_up), ()) = tokio::join!(
+            async {
+                let results = tokio::join!(
+                    stop_on_error(&stop, self.index_head(&progress, &stop)),
+                    stop_on_error(&stop, self.catch_up(&progress, &stop)),
+                );
+                stop.cancel();
+                results
+            },
+            self.stop_on_abort(&stop),
+        );
+        caught_up?;
+        last_indexed_block
+    }
+
+    /// Indexes up to the chain head, then polls it every `poll_interval` and indexes the new blocks
+    /// as they are produced. Returns only once the end block given with `range` is indexed, or the
+    /// shutdown is requested.
+    pub async fn follow(&self, poll_interval: Duration) -> IndexerResult<()> {
+        let progress = Mutex::new(self.load_progress()?);
+        let stop = self.shutdown.child_token();
+        info!("Following the chain from block {}", progress.lock().unwrap().head().unwrap_or(self.first_block));
+
+        let follow_head = async {
+            loop {
+                self.index_head(&progress, &stop).await?;
+                let head = progress.lock().unwrap().head();
+                if self.to_block.is_some_and(|to_block| head.is_some_and(|head| head > to_block)) {
+                    return Ok(());
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(poll_interval) => {}
+                    _ = stop.cancelled() => return Ok(()),
+                }
+            }
+        };
+        let ((followed, caught_up), ()) = tokio::join!(
+            async {
+                let results = tokio::join!(stop_on_error(&stop, follow_head), stop_on_error(&stop, self.catch_up(&progress, &stop)));
+                stop.cancel();
+                results
+            },
+            self.stop_on_abort(&stop),
+        );
+        followed?;
+        caught_up
+    }
+
+    // Stops the loops of a run when the abort token is cancelled; returns once they are stopped
+    async fn stop_on_abort(&self, stop: &CancellationToken) {
+        tokio::select! {
+            _ = self.abort.cancelled() => stop.cancel(),
+            _ = stop.cancelled() => {}
+        }
+    }
+
+    /// Every stream the indexer produces: each handler's events and processors, and the block headers.
+    pub fn streams(&self) -> &[Stream] {
+        &self.streams
+    }
+
+    // Where every stream starts: at the block given with `range`, or after its checkpoint, or at the
+    // first block
+    fn load_progress(&self) -> IndexerResult<Progress> {
+        let start = |last_processed_block: u64| match last_processed_block {
+            0 => self.first_block,
+            last_processed_block => last_processed_block + 1,
+        };
+        let everywhere = |next_block: u64| Progress::new(self.streams.iter().map(|stream| (stream.clone(), next_block)));
+
+        if let Some(from_block) = self.from_block {
+            return Ok(everywhere(from_block));
+        }
+        let file_block = self.checkpoint_file.as_deref().map(read_last_processed_block).unwrap_or(0);
+        if !self.stored_checkpoints {
+            return Ok(everywhere(start(file_block)));
+        }
+
+        let stored: HashMap<Stream, u64> = self
+            .storage
+            .checkpoints()?
+            .into_iter()
+            .map(|checkpoint| (Stream { handler: checkpoint.handler, processor: checkpoint.processor }, checkpoint.block_number as u64))
+            .collect();
+        if stored.is_empty() && file_block > 0 {
+            info!("Importing the checkpoint at block {} of {}", file_block, self.checkpoint_file.as_deref().unwrap_or_default());
+            return Ok(everywhere(start(file_block)));
+        }
+
+        let progress = Progress::new(self.streams.iter().map(|stream| (stream.clone(), start(stored.get(stream).copied().unwrap_or(0)))));
+        if progress.is_lagging() {
+            for stream in &self.streams {
+                match stored.get(stream) {
+                    Some(block_number) => info!("{} is indexed up to block {}", stream, block_number),
+                    None => info!("{} has no checkpoint yet", stream),
+                }
+            }
+        }
+        Ok(progress)
+    }
+
+    // Indexes the streams at the head up to the end block (or the chain head); returns the last
+    // block indexed, if any
+    async fn index_head(&self, progress: &Mutex<Progress>, stop: &CancellationToken) -> IndexerResult<Option<u64>> {
+        let mut last_indexed_block = None;
+
+        while !stop.is_cancelled() {
+            let end_block = match self.to_block {
+                Some(to_block) => to_block,
+                None => tokio::select! {
+                    head_block = self.head_block() => head_block?,
+                    _ = stop.cancelled() => break,
+                },
+            };
+            let claim = progress.lock().unwrap().claim_head(end_block, self.block_range);
+            let pass = match claim {
+                HeadClaim::Pass(pass) => pass,
+                HeadClaim::CaughtUp => break,
+                HeadClaim::Busy => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(BUSY_WAIT) => continue,
+                        _ = stop.cancelled() => break,
+                    }
+                }
+            };
+
+            info!("Processing blocks from {} to {}", pass.from_block, pass.to_block);
+            self.index_pass(&pass).await?;
+            self.save_checkpoint(&pass)?;
+            progress.lock().unwrap().release(&pass);
+            for callback in &self.range_callbacks {
+                callback(pass.from_block, pass.to_block);
+            }
+
+            last_indexed_block = Some(pass.to_block);
+        }
+
+        Ok(last_indexed_block)
+    }
+
+    // Indexes the streams behind the others until they all reach the head
+    async fn catch_up(&self, progress: &Mutex<Progress>, stop: &CancellationToken) -> IndexerResult<()> {
+        while !stop.is_cancelled() {
+            let Some(pass) = progress.lock().unwrap().claim_lagging(self.block_range) else {
+                return Ok(());
+            };
+
+            let streams: Vec<String> = pass.streams.iter().map(Stream::to_string).collect();
+            info!("Catching up blocks from {} to {} for {}", pass.from_block, pass.to_block, streams.join(", "));
+            self.index_pass(&pass).await?;
+            self.save_checkpoint(&pass)?;
+            progress.lock().unwrap().release(&pass);
+        }
+        Ok(())
+    }
+
+    fn save_checkpoint(&self, pass: &Pass) -> IndexerResult<()> {
+        if self.stored_checkpoints {
+            let streams: Vec<Stream> = pass.streams.iter().cloned().collect();
+            self.storage.save_checkpoints(&streams, pass.to_block as i64)?;
+        } else if let Some(path) = &self.checkpoint_file {
+            write_last_processed_block(path, pass.to_block)?;
+        }
+        Ok(())
+    }
+
+    /// Indexes the logs of `from_block..=to_block` for every stream, without touching the checkpoint.
+    pub async fn index_range(&self, from_block: u64, to_block: u64) -> IndexerResult<()> {
+        info!("Processing blocks from {} to {}", from_block, to_block);
+        let pass = Pass { from_block, to_block, streams: self.streams.iter().cloned().collect() };
+        self.index_pass(&pass).await
+    }
+
+    // Indexes the logs of a pass's range for its streams. The logs are decoded first, and their
+    // events only written once all of them are, so a pass dropped at shutdown while decoding writes
+    // no event (decoding may still have stored the metadata of new tokens). The writes themselves
+    // are not transactional: a sink failing part-way leaves the events written before it, and the
+    // range, not checkpointed, is written again by the next run.
+    async fn index_pass(&self, pass: &Pass) -> IndexerResult<()> {
+        let decoded = tokio::select! {
+            decoded = self.decode_pass(pass) => decoded?,
+            _ = self.shutdown_deadline() => {
+                let reason = if self.abort.is_cancelled() { "Indexing was aborted" } else { "The shutdown timed out" };
+                return Err(format!(
+                    "{}; blocks {} to {} were dropped before being written and are indexed again on the next run",
+                    reason, pass.from_block, pass.to_block
+                )
+                .into());
+            }
+        };
+
+        // Written in the order of the logs, so running totals are applied as on chain
+        let chain_id = self.storage.chain_id() as u64;
+        for mut events in decoded {
+            events.iter_mut().for_each(|event| event.set_chain_id(chain_id));
+            self.sinks.write(&events).await?;
+            for callback in self.event_callbacks.iter() {
+                events.iter().for_each(|event| callback(event));
+            }
+        }
+        self.sinks.flush().await?;
+
+        info!("Finished processing blocks from {} to {}", pass.from_block, pass.to_block);
+        Ok(())
+    }
+
+    // The events of every log of the pass's range, in log order, each log decoded by its own task
+    async fn decode_pass(&self, pass: &Pass) -> IndexerResult<Vec<Vec<IndexedEvent>>> {
+        let (from_block, to_block) = (pass.from_block, pass.to_block);
+        let processors = pass.processors(CHAIN_WIDE);
+
+        // Block headers of the blocks carrying logs need every log; those of every block need none
+        let topics = if processors.blocks {
+            self.handlers.topics()
+        } else {
+            self.handlers.topics_of(|handler| pass.has_handler(handler))
+        };
+        let lists = self.address_filter.lists()?;
+        let mut logs = match (&lists.allowed, &lists.watched) {
+            _ if topics.is_empty() => Vec::new(),
+            (Some(allowed), _) if allowed.is_empty() => Vec::new(),
+            (_, Some(watched)) if watched.is_empty() => Vec::new(),
+            (allowed, watched) => self.fetch_logs(from_block, to_block, &topics, allowed.as_deref(), watched.as_deref()).await?,
+        };
+        // Denied contracts are skipped before any of their logs is parsed
+        if !lists.denied.is_empty() {
+            logs.retain(|log| !lists.denied.contains(&log.address));
+        }
+        let block_numbers = blocks_in_logs(&logs);
+
+        // Dropping the set, when the shutdown times out, aborts the tasks
+        let mut tasks = JoinSet::new();
+        for (position, log) in logs.into_iter().enumerate() {
+            let handlers = Arc::clone(&self.handlers);
+            let pass = pass.clone();
+            let storage = Arc::clone(&self.storage);
+            let provider = Arc::clone(&self.provider);
+            let concurrency = self.concurrency.clone();
+
+            tasks.spawn(async move {
+                // Held until the log is decoded
+                let _permit = match concurrency {
+                    Some(semaphore) => semaphore.acquire_owned().await.ok(),
+                    None => None,
+                };
+                let mut events = Vec::new();
+                if let Err(e) = parse_log_in_pass(&log, &handlers, &pass, storage.as_ref(), provider, &mut events).await {
+                    error!("Error parsing log: {:?}", e);
+                }
+                (position, events)
+            });
+        }
+
+        let mut decoded = Vec::with_capacity(tasks.len());
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok((position, mut events)) => {
+                    events.retain(|event| lists.keeps(event));
+                    decoded.push((position, events));
+                }
+                Err(e) => error!("Error decoding log: {}", e),
+            }
+        }
+        decoded.sort_by_key(|(position, _)| *position);
+
+        // Record block headers so balances can be resolved by time; storing them again is a no-op
+        if processors.all_blocks {
+            fetch_and_store_blocks(self.storage.as_ref(), self.provider.clone(), from_block..=to_block).await;
+        } else if processors.blocks {
+            fetch_and_store_blocks(self.storage.as_ref(), self.provider.clone(), block_numbers).await;
+        }
+
+        Ok(decoded.into_iter().map(|(_, events)| events).collect())
+    }
+
+    // Resolves once the shutdown timeout has passed since the shutdown was requested, or at once
+    // when indexing is aborted
+    async fn shutdown_deadline(&self) {
+        let timed_out = async {
+            self.shutdown.cancelled().await;
+            tokio::time::sleep(self.shutdown_timeout).await;
+        };
+        tokio::select! {
+            _ = timed_out => {}
+            _ = self.abort.cancelled() => {}
+        }
+    }
+
+    // The last block considered final, which indexing does not go past
+    async fn head_block(&self) -> IndexerResult<u64> {
+        match self.finality {
+            Finality::Latest => Ok(self.provider.get_block_number().await?.as_u64()),
+            Finality::Confirmations(confirmations) => Ok(self.provider.get_block_number().await?.as_u64().saturating_sub(confirmations)),
+            Finality::Finalized => {
+                let block = self.provider.get_block(BlockNumber::Finalized).await?;
+                Ok(block.and_then(|block| block.number).ok_or("The provider reports no finalized block")?.as_u64())
+            }
+        }
+    }
+
+    // The logs of the range with one of `topics`, emitted by one of `contracts` and involving one of
+    // `wallets` when given, in log order
+    async fn fetch_logs(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        topics: &[H256],
+        contracts: Option<&BTreeSet<Address>>,
+        wallets: Option<&BTreeSet<Address>>,
+    ) -> IndexerResult<Vec<Log>> {
+        info!("Fetching logs with topics: {:?}", topics);
+
+        // A watched wallet is looked for in each topic that may hold an address
+        let wallets: Vec<H256> = wallets.into_iter().flatten().map(|wallet| H256::from(*wallet)).collect();
+        let third_topics: Vec<H256> = topics.iter().filter(|topic| !TWO_ADDRESS_TOPICS.contains(topic)).copied().collect();
+        let mut topic_filters = Vec::new();
+        if wallets.is_empty() {
+            topic_filters.push(Filter::new().topic0(topics.to_vec()));
+        }
+        for chunk in wallets.chunks(self.address_limit) {
+            topic_filters.push(Filter::new().topic0(topics.to_vec()).topic1(chunk.to_vec()));
+            topic_filters.push(Filter::new().topic0(topics.to_vec()).topic2(chunk.to_vec()));
+            if !third_topics.is_empty() {
+                topic_filters.push(Filter::new().topic0(third_topics.clone()).topic3(chunk.to_vec()));
+            }
+        }
+
+        let contracts: Vec<Address> = contracts.into_iter().flatten().copied().collect();
+        let filters: Vec<Filter> = if contracts.is_empty() {
+            topic_filters
+        } else {
+            let chunks = contracts.chunks(self.address_limit);
+            topic_filters.iter().flat_map(|filter| chunks.clone().map(|chunk| filter.clone().address(chunk.to_vec()))).collect()
+        };
+
+        let mut logs = Vec::new();
+        for filter in &filters {
+            logs.extend(self.fetch_logs_of(from_block, to_block, filter).await?);
+        }
+        // Each request returns its logs in order, but not those of the other requests, and a log
+        // involving two watched wallets is returned twice
+        if filters.len() > 1 {
+            logs.sort_by_key(|log| (log.block_number, log.log_index));
+            logs.dedup_by_key(|log| (log.block_number, log.log_index));
+        }
+
+        info!("Fetched {} logs", logs.len());
+        Ok(logs)
+    }
+
+    // The logs `filter` selects in the range, which is split when the provider refuses or truncates it
+    async fn fetch_logs_of(&self, from_block: u64, to_block: u64, filter: &Filter) -> IndexerResult<Vec<Log>> {
+        // Ranges the provider refuses or truncates are split in halves, first half on top
+        let mut logs = Vec::new();
+        let mut ranges = vec![(from_block, to_block)];
+        while let Some((from_block, to_block)) = ranges.pop() {
+            let filter = filter.clone().from_block(BlockNumber::Number(from_block.into())).to_block(BlockNumber::Number(to_block.into()));
+
+            let split = match self.provider.get_logs(&filter).await {
+                Ok(range_logs) if self.log_limit.is_some_and(|limit| range_logs.len() >= limit) && from_block < to_block => true,
+                Ok(range_logs) => {
+                    logs.extend(range_logs);
+                    false
+                }
+                Err(e) if is_log_limit_error(&e) && from_block < to_block => true,
+                Err(e) => return Err(e.into()),
+            };
+            if split {
+                let middle = from_block + (to_block - from_block) / 2;
+                info!("Splitting blocks {} to {} at {} to stay within the log limit", from_block, to_block, middle);
+                ranges.push((middle + 1, to_block));
+                ranges.push((from_block, middle));
+            }
+        }
+        Ok(logs)
+    }
+}
+
+// Whether a provider refused an `eth_getLogs` request for covering too many blocks or logs, with
+// the messages of the common providers
+fn is_log_limit_error(error: &ProviderError) -> bool {
+    const LIMIT_MESSAGES: [&str; 5] = [
+        "query returned more than",
+        "response size exceeded",
+        "exceed maximum block range",
+        "block range is too large",
+        "too many logs",
+    ];
+    let message = error.to_string().to_lowercase();
+    LIMIT_MESSAGES.iter().any(|limit_message| message.contains(limit_message))
+}
+
+// Stops the other loops of an indexer when one of them fails
+async fn stop_on_error<T>(stop: &CancellationToken, work: impl std::future::Future<Output = IndexerResult<T>>) -> IndexerResult<T> {
+    let result = work.await;
+    if result.is_err() {
+        stop.cancel();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::extract::State;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::{json, Value};
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::storage::SqliteStorage;
+    use crate::testing::TempPath;
+
+    // A JSON-RPC endpoint answering every `eth_getLogs` request without logs, holding the second
+    // request until `release` is notified
+    #[derive(Default)]
+    struct FakeRpc {
+        requests: AtomicUsize,
+        second_request: Notify,
+        release: Notify,
+    }
+
+    async fn answer(State(rpc): State<Arc<FakeRpc>>, Json(request): Json<Value>) -> Json<Value> {
+        assert_eq!(request["method"], "eth_getLogs");
+        if rpc.requests.fetch_add(1, Ordering::SeqCst) == 1 {
+            rpc.second_request.notify_one();
+            rpc.release.notified().await;
+        }
+        Json(json!({ "jsonrpc": "2.0", "id": request["id"], "result": [] }))
+    }
+
+    async fn serve_rpc() -> (Arc<FakeRpc>, Arc<Provider<Http>>) {
+        let rpc = Arc::new(FakeRpc::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let router = Router::new().route("/", post(answer)).with_state(rpc.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (rpc, Arc::new(Provider::<Http>::try_from(url).unwrap()))
+    }
+
+    // Indexes the ERC20 events and balances of blocks 0 to 29, 10 blocks per range
+    fn indexer(provider: Arc<Provider<Http>>, storage: Arc<dyn Storage>, shutdown: &CancellationToken, shutdown_timeout: Duration) -> Indexer {
+        Indexer::builder()
+            .provider(provider)
+            .storage(storage)
+            .standard(TokenType::ERC20)
+            .processors(Processors { balances: true, ..Processors::default() })
+            .range(0, Some(29))
+            .block_range(10)
+            .stored_checkpoints()
+            .shutdown(shutdown.clone())
+            .shutdown_timeout(shutdown_timeout)
+            .build()
+            .unwrap()
+    }
+
+    fn checkpoints(storage: &dyn Storage) -> Vec<(String, i64)> {
+        storage.checkpoints().unwrap().into_iter().map(|checkpoint| (format!("{} {}", checkpoint.handler, checkpoint.processor), checkpoint.block_number)).collect()
+    }
+
+    #[tokio::test]
+    async fn a_shutdown_timing_out_mid_pass_keeps_the_checkpoint_of_the_last_written_range() {
+        let (rpc, provider) = serve_rpc().await;
+        let path = TempPath::new("shutdown-timeout.db");
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::open(path.as_str()).unwrap());
+        let shutdown = CancellationToken::new();
+        let indexer = indexer(provider, storage.clone(), &shutdown, Duration::from_millis(100));
+
+        // Cancelled while blocks 10 to 19 are fetched, which never completes
+        let (result, ()) = tokio::join!(indexer.run(), async {
+            rpc.second_request.notified().await;
+            shutdown.cancel();
+        });
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("blocks 10 to 19 were dropped"), "{}", error);
+        assert_eq!(checkpoints(storage.as_ref()), vec![("ERC20 balances".to_string(), 9), ("ERC20 events".to_string(), 9)]);
+        assert_eq!(rpc.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn a_shutdown_lets_the_pass_in_flight_finish_and_starts_no_other() {
+        let (rpc, provider) = serve_rpc().await;
+        let path = TempPath::new("shutdown.db");
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::open(path.as_str()).unwrap());
+        let shutdown = CancellationToken::new();
+        let indexer = indexer(provider, storage.clone(), &shutdown, Duration::from_secs(30));
+
+        let (result, ()) = tokio::join!(indexer.run(), async {
+            rpc.second_request.notified().await;
+            shutdown.cancel();
+            rpc.release.notify_one();
+        });
+
+        assert_eq!(result.unwrap(), Some(19));
+        assert_eq!(checkpoints(storage.as_ref()), vec![("ERC20 balances".to_string(), 19), ("ERC20 events".to_string(), 19)]);
+        assert_eq!(rpc.requests.load(Ordering::SeqCst), 2);
+    }
+}
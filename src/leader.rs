@@ -0,0 +1,179 @@
+//! Leader election among scrapers following the same chain, so a standby can take over without both
+//! writing the same changes. The leader holds a Postgres advisory lock on a connection of its own for
+//! as long as it ingests; the lock lapses when that session ends, and a standby polling for it
+//! resumes from the stored checkpoints.
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use diesel::PgConnection;
+use log::{info, warn};
+use tokio_util::sync::CancellationToken;
+
+use crate::indexer::IndexerBuilder;
+
+pub type LeaderResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Time between two attempts of a standby to take the lock, and two checks by the leader that it
+/// still holds it.
+pub const LEADER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// High half of the lock keys ("hist"), so they do not collide with the advisory locks of other
+// applications sharing the database
+const LOCK_NAMESPACE: i64 = 0x6869_7374;
+
+diesel::define_sql_function!(fn pg_try_advisory_lock(key: BigInt) -> Bool);
+
+/// Key of the advisory lock the leader of `chain_id` holds.
+pub fn lock_key(chain_id: i64) -> i64 {
+    (LOCK_NAMESPACE << 32) | (chain_id & 0xffff_ffff)
+}
+
+/// The leader lock of a chain, held for as long as the value lives: dropping it closes its
+/// connection, which releases the lock.
+pub struct LeaderLock {
+    conn: Arc<Mutex<PgConnection>>,       // Session holding the lock, outside of any pool
+}
+
+impl LeaderLock {
+    /// Waits until this process holds the leader lock of `chain_id`, trying every `interval`.
+    /// Returns None when `shutdown` is cancelled first.
+    pub async fn acquire(database_url: &str, chain_id: i64, interval: Duration, shutdown: &CancellationToken) -> Option<LeaderLock> {
+        let key = lock_key(chain_id);
+        let mut conn: Option<PgConnection> = None;
+        let mut standing_by = false;
+        loop {
+            let url = database_url.to_string();
+            let attempt = tokio::task::spawn_blocking(move || try_lock(conn, &url, key)).await;
+            match attempt {
+                Ok((Ok(true), Some(locked))) => return Some(LeaderLock { conn: Arc::new(Mutex::new(locked)) }),
+                Ok((Ok(_), kept)) => {
+                    conn = kept;
+                    if !standing_by {
+                        info!("Standing by: another scraper leads chain {}", chain_id);
+                        standing_by = true;
+                    }
+                }
+                Ok((Err(e), _)) => {
+                    conn = None;
+                    warn!("Failed to try the leader lock of chain {}: {}", chain_id, e);
+                }
+                Err(e) => {
+                    conn = None;
+                    warn!("Failed to try the leader lock of chain {}: {}", chain_id, e);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = shutdown.cancelled() => return None,
+            }
+        }
+    }
+
+    /// Takes the leader lock of `chain_id` unless another scraper holds it, without waiting.
+    pub async fn try_acquire(database_url: &str, chain_id: i64) -> LeaderResult<Option<LeaderLock>> {
+        let url = database_url.to_string();
+        match tokio::task::spawn_blocking(move || try_lock(None, &url, lock_key(chain_id))).await? {
+            (Ok(true), Some(conn)) => Ok(Some(LeaderLock { conn: Arc::new(Mutex::new(conn)) })),
+            (Ok(_), _) => Ok(None),
+            (Err(e), _) => Err(e),
+        }
+    }
+
+    /// Checks the lock's session every `interval`, and returns why the lock may be lost once it
+    /// fails or does not answer within `interval`.
+    pub async fn lost(&self, interval: Duration) -> String {
+        loop {
+            tokio::time::sleep(interval).await;
+            let conn = self.conn.clone();
+            let check = tokio::task::spawn_blocking(move || conn.lock().unwrap().batch_execute("SELECT 1"));
+            match tokio::time::timeout(interval, check).await {
+                Ok(Ok(Ok(()))) => {}
+                Ok(Ok(Err(e))) => return format!("its connection failed: {}", e),
+                Ok(Err(e)) => return format!("its check failed: {}", e),
+                Err(_) => return format!("its connection did not answer within {}s", interval.as_secs()),
+            }
+        }
+    }
+}
+
+// Tries the lock on `conn`, connecting first when there is none; returns whether the lock was
+// taken and the connection to try again with, if it still works
+fn try_lock(conn: Option<PgConnection>, database_url: &str, key: i64) -> (LeaderResult<bool>, Option<PgConnection>) {
+    let mut conn = match conn {
+        Some(conn) => conn,
+        None => match PgConnection::establish(database_url) {
+            Ok(conn) => conn,
+            Err(e) => return (Err(e.into()), None),
+        },
+    };
+    match diesel::select(pg_try_advisory_lock(key)).get_result::<bool>(&mut conn) {
+        Ok(locked) => (Ok(locked), Some(conn)),
+        Err(e) => (Err(e.into()), None),
+    }
+}
+
+/// Follows the chain with indexers built from `builder` while this process holds the chain's leader
+/// lock, and stands by while another scraper does. Each time it takes the lock over, it waits for
+/// the previous leader to notice it lost it, then resumes from the stored checkpoints; the ranges
+/// not written yet when the lock is lost are dropped. Returns once `shutdown` is cancelled, or with
+/// the first error that is not a lost lock.
+pub async fn follow_as_leader(
+    builder: IndexerBuilder,
+    database_url: &str,
+    chain_id: i64,
+    poll_interval: Duration,
+    shutdown: &CancellationToken,
+) -> LeaderResult<()> {
+    loop {
+        let Some(lock) = LeaderLock::acquire(database_url, chain_id, LEADER_CHECK_INTERVAL, shutdown).await else {
+            return Ok(());
+        };
+        info!("Leading chain {}; following it in {}s", chain_id, (2 * LEADER_CHECK_INTERVAL).as_secs());
+
+        // The previous leader notices within two checks, and stops before writing anything else
+        tokio::select! {
+            _ = tokio::time::sleep(2 * LEADER_CHECK_INTERVAL) => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+
+        let lost = CancellationToken::new();
+        let indexer = builder.clone().abort(lost.clone()).build()?;
+        let follow = indexer.follow(poll_interval);
+        tokio::pin!(follow);
+        let followed = tokio::select! {
+            followed = &mut follow => followed,
+            reason = lock.lost(LEADER_CHECK_INTERVAL) => {
+                warn!("Lost the leader lock of chain {}: {}; stopping until it is held again", chain_id, reason);
+                lost.cancel();
+                follow.await
+            }
+        };
+        drop(lock);
+
+        if !lost.is_cancelled() {
+            return followed;
+        }
+        if let Err(e) = followed {
+            info!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_key_keeps_chains_apart_in_the_namespace() {
+        assert_eq!(lock_key(1), 0x6869_7374_0000_0001);
+        assert_eq!(lock_key(137) >> 32, LOCK_NAMESPACE);
+        assert_ne!(lock_key(1), lock_key(10));
+        // Only the low 32 bits of the chain id are kept
+        assert_eq!(lock_key(0x1_0000_0001), lock_key(1));
+    }
+}
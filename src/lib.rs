@@ -0,0 +1,99 @@
+//! Indexes ERC20, ERC721, ERC1155 and ERC777 logs into historical balances, allowances, total supplies
+//! and token metadata, and the events of any contract given its ABI.
+//!
+//! Embed it with the [`Indexer`] builder:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! use std::sync::Arc;
+//! use ethers::providers::{Http, Provider};
+//! use histori_evm_scraper::{open_storage, Indexer, Processors, TokenType};
+//!
+//! let provider = Arc::new(Provider::<Http>::try_from("https://rpc.example")?);
+//! let indexer = Indexer::builder()
+//!     .provider(provider)
+//!     .storage(open_storage("sqlite://tokens.db")?)
+//!     .standards([TokenType::ERC20, TokenType::ERC721])
+//!     .processors(Processors { balances: true, ..Processors::default() })
+//!     .range(18_000_000, Some(18_100_000))
+//!     .on_event(|event| println!("{}", event.kind()))
+//!     .build()?;
+//! indexer.run().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod address_filter;
+pub mod api;
+pub mod backfill_queue;
+pub mod block_service;
+pub mod checkpoint;
+pub mod config;
+pub mod constants;
+pub mod db;
+pub mod events;
+pub mod export;
+pub mod handlers;
+pub mod indexer;
+pub mod leader;
+pub mod models;
+pub mod parser;
+pub mod presets;
+pub mod schema;
+pub mod sinks;
+pub mod snapshot;
+pub mod storage;
+pub mod token_service;
+pub mod utils;
+pub mod verify;
+pub mod webhook_service;
+
+#[cfg(test)]
+mod testing;
+
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+
+pub use events::{AllowanceChange, BalanceChange, ChangeEvent, ContractEvent, IndexedEvent, TotalSupplyChange, Transfer};
+pub use handlers::{AbiHandler, EventHandler, HandlerContext, HandlerRegistry, HandlerResult};
+pub use indexer::{Indexer, IndexerBuilder, IndexerResult, Processors};
+pub use sinks::{EventSink, EventSinks};
+pub use storage::{open_storage, Storage};
+
+pub type PgPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+/// Token standards the scraper understands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    ERC20,
+    ERC721,
+    ERC1155,
+    ERC777,
+}
+
+impl TokenType {
+    /// Name stored in the `token_type` columns, e.g. "ERC20".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenType::ERC20 => "ERC20",
+            TokenType::ERC721 => "ERC721",
+            TokenType::ERC1155 => "ERC1155",
+            TokenType::ERC777 => "ERC777",
+        }
+    }
+}
+
+impl std::str::FromStr for TokenType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "ERC20" => Ok(TokenType::ERC20),
+            "ERC721" => Ok(TokenType::ERC721),
+            "ERC1155" => Ok(TokenType::ERC1155),
+            "ERC777" => Ok(TokenType::ERC777),
+            other => Err(format!("Unknown token type `{}`", other)),
+        }
+    }
+}
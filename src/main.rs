@@ -1,76 +1,270 @@
-mod db;
-mod parser;
-mod schema;
-mod utils;
-mod token_service;
-mod models;
-mod handlers;
-mod constants;  // Import the constants module
-
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::collections::HashSet; 
-use clap::Parser;
-use diesel::r2d2::ConnectionManager;
-use diesel::PgConnection;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
 use dotenv::dotenv;
 use ethers::providers::{Http, Middleware, Provider};
-use ethers::types::{BlockNumber, Filter, Log, H256};
-use futures::future::join_all;
-use log::{info, error};
-use parser::parse_log;
-use r2d2::PooledConnection;
-use utils::{read_last_processed_block, write_last_processed_block};
-use std::env;
-use tokio::task::JoinHandle;
-use crate::db::establish_connection_pool;
-use crate::constants::*;  // Import all constants
-
-pub type PgPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
-
-// Define supported token types as an enum for CLI options
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum TokenType {
-    ERC20,
-    ERC721,
-    ERC1155,
-    ERC777,
-}
+use ethers::types::Address;
+use log::{error, info, warn};
+use tokio_util::sync::CancellationToken;
+
+use histori_evm_scraper::address_filter::{AddressFilter, AddressFilterConfig, DEFAULT_ADDRESS_LIMIT};
+use histori_evm_scraper::api;
+use histori_evm_scraper::backfill_queue::{default_worker_name, run_queue_command, QueueCommand, WorkQueue};
+use histori_evm_scraper::config::{
+    self, load_profile, redact_url, ChainConfig, ConfigCommand, ConfigResult, MigrationMode, Profile, ProfileArgs,
+};
+use histori_evm_scraper::db::DbPool;
+use histori_evm_scraper::export::{run_export, ExportArgs};
+use histori_evm_scraper::leader::{follow_as_leader, LeaderLock};
+use histori_evm_scraper::sinks::{EventSinks, SinkSpec};
+use histori_evm_scraper::snapshot;
+use histori_evm_scraper::presets::preset;
+use histori_evm_scraper::storage::{open_storage_with_pool, StorageResult, DEFAULT_CHAIN_ID};
+use histori_evm_scraper::utils::read_last_processed_block;
+use histori_evm_scraper::verify::verify_erc20;
+use histori_evm_scraper::webhook_service::{run_webhook_command, WebhookCommand, WebhookDispatcher};
+use histori_evm_scraper::{EventHandler, Indexer, IndexerBuilder, IndexerResult, PgPooledConnection, Storage};
+
+// Seconds between two polls of the chain head by `follow`, for chains without a preset
+const DEFAULT_POLL_INTERVAL: u64 = 12;
 
 // Define CLI arguments
 #[derive(Parser)]
 #[command(name = "Token Scraper CLI")]
 #[command(about = "Scrape logs for selected token types", long_about = None)]
 pub struct Cli {
-    /// Include ERC20 events
-    #[arg(long)]
-    erc20: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Include ERC721 events
-    #[arg(long)]
-    erc721: bool,
+    /// TOML file of named profiles (default: scraper.toml, when present)
+    #[arg(long, global = true, env = "SCRAPER_CONFIG", value_name = "PATH")]
+    config: Option<PathBuf>,
 
-    /// Include ERC1155 events
-    #[arg(long)]
-    erc1155: bool,
+    /// Profile of the config file to run with (default: its `default_profile`, or its only profile)
+    #[arg(long, global = true, env = "SCRAPER_PROFILE", value_name = "NAME")]
+    profile: Option<String>,
 
-    /// Include ERC777 events
-    #[arg(long)]
-    erc777: bool,
+    /// JSON-RPC endpoint, overriding the profile's `rpc_url`
+    #[arg(long, global = true, env = "RPC_URL", hide_env_values = true, value_name = "URL")]
+    rpc_url: Option<String>,
 
-    /// Process balances
-    #[arg(long)]
-    process_balances: bool,
+    /// Postgres URL or SQLite path, overriding the profile's `database_url`
+    #[arg(long, global = true, env = "DATABASE_URL", hide_env_values = true, value_name = "URL")]
+    database_url: Option<String>,
 
-    /// Process allowances
-    #[arg(long)]
-    process_allowances: bool,
+    /// Only work on this chain of the profile's `chains` (or the chain `rpc_url` serves)
+    #[arg(long, global = true, env = "CHAIN_ID", value_name = "ID")]
+    chain_id: Option<u64>,
 
-    /// Process total supplies
-    #[arg(long)]
-    process_total_supplies: bool,
+    /// Apply the migrations the database lacks at startup, or only verify there are none
+    #[arg(long, global = true, env = "SCRAPER_MIGRATIONS", value_enum)]
+    migrations: Option<MigrationMode>,
+
+    /// Without a command, the scraper runs as with `scrape`; before one, these flags apply to it
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Resolve a Unix timestamp to the last stored block at or before it, print it and exit
+    #[arg(long, value_name = "UNIX_TIMESTAMP")]
+    block_at_timestamp: Option<i64>,
+}
+
+/// Settings of the commands that index logs.
+#[derive(Args, Clone)]
+pub struct RunArgs {
+    #[command(flatten)]
+    profile: ProfileArgs,
+
+    /// Also serve the API while scraping, so `/v1/subscribe` clients receive changes as they are indexed
+    #[arg(long, value_name = "ADDR")]
+    serve_addr: Option<SocketAddr>,
 
+    /// Deliver transfers, approvals and supply changes indexed by this run to the registered webhooks
     #[arg(long)]
-    process_token_uri: bool,
+    webhooks: bool,
+}
+
+impl RunArgs {
+    // These flags, completed with those given before the command
+    fn or(&self, outer: &RunArgs) -> RunArgs {
+        RunArgs {
+            profile: self.profile.clone(),
+            serve_addr: self.serve_addr.or(outer.serve_addr),
+            webhooks: self.webhooks || outer.webhooks,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Index from the block after the checkpoint up to the chain head, recording the checkpoint as it goes
+    Scrape {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Start at this block instead of after the checkpoint
+        #[arg(long)]
+        from_block: Option<u64>,
+
+        /// Stop after this block instead of at the chain head
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+
+    /// Index an explicit range of blocks, leaving the checkpoint alone
+    Backfill {
+        #[command(flatten)]
+        run: RunArgs,
+
+        #[arg(long)]
+        from_block: u64,
+
+        /// Last block indexed (inclusive)
+        #[arg(long)]
+        to_block: u64,
+    },
+
+    /// Index the earlier logs of tokens added to the allowlist, up to the checkpoint, leaving it alone
+    BackfillToken {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Contract address of the token; repeat to backfill several
+        #[arg(required = true, value_name = "ADDRESS")]
+        addresses: Vec<Address>,
+
+        /// First block indexed (default: the chain's `start_block`, or 0)
+        #[arg(long)]
+        from_block: Option<u64>,
+
+        /// Last block indexed (inclusive; default: the checkpoint of the chain)
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+
+    /// Delete a token's rows and index its logs again, up to the checkpoint, leaving it alone
+    ReindexToken {
+        #[command(flatten)]
+        run: RunArgs,
+
+        address: Address,
+
+        /// Only delete and index again the token's rows from this block on, keeping the earlier ones
+        /// (default: the whole token, from the chain's `start_block`, or 0)
+        #[arg(long)]
+        from_block: Option<u64>,
+    },
+
+    /// Index up to the chain head, then keep indexing new blocks as they are produced
+    Follow {
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// Start at this block instead of after the checkpoint
+        #[arg(long)]
+        from_block: Option<u64>,
+
+        /// Seconds between two polls of the chain head (default: the chain's `poll_interval`, or its
+        /// block time, or 12)
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+        poll_interval: Option<u64>,
+
+        /// Only ingest while holding the chain's leader lock in Postgres, standing by while another
+        /// scraper does (also the profile's `leader_election`)
+        #[arg(long)]
+        leader_election: bool,
+    },
+
+    /// Compare stored ERC20 balances and total supplies with `balanceOf` and `totalSupply` on chain
+    Verify {
+        /// Token to check; repeat to check several (default: every stored ERC20 token)
+        #[arg(long = "token", value_name = "ADDRESS")]
+        tokens: Vec<Address>,
+
+        /// Holders checked per token
+        #[arg(long, default_value_t = 20)]
+        wallets: i64,
+
+        /// Block to compare at (default: the checkpoint of the ERC20 balances and total supplies)
+        #[arg(long)]
+        block: Option<u64>,
+
+        /// Checkpoint file of the chain, when the database has no checkpoint yet (default lastProcessedBlock.txt)
+        #[arg(long, value_name = "PATH")]
+        checkpoint_file: Option<String>,
+    },
+
+    /// Print the checkpoints, the chain head and the number of stored rows
+    Status {
+        /// Checkpoint file of the chain, when the database has no checkpoint yet (default lastProcessedBlock.txt)
+        #[arg(long, value_name = "PATH")]
+        checkpoint_file: Option<String>,
+    },
+
+    /// Delete a token with its balances, allowances, supplies and token IDs, so it is indexed anew
+    ResetToken {
+        address: Address,
+    },
+
+    /// Store the balances and total supplies of tokens at a block, from a CSV or JSON file, to index
+    /// them from the next block on
+    ImportSnapshot {
+        /// `.csv` or `.json` file of `token_address`, `wallet_address` (empty for the total supply) and `amount`
+        #[arg(value_name = "PATH")]
+        file: PathBuf,
+
+        /// Block the amounts are those of
+        #[arg(long)]
+        block: u64,
+
+        /// Read the amounts with `balanceOf` and `totalSupply` at the block, for the wallets the file lists
+        #[arg(long)]
+        on_chain: bool,
+    },
+
+    /// Apply the pending database migrations
+    Migrate,
+
+    /// Serve the indexed data over a JSON REST API instead of scraping
+    Serve {
+        /// Address to listen on
+        #[arg(long, env = "API_ADDR", default_value = "0.0.0.0:3000")]
+        addr: SocketAddr,
+    },
+
+    /// Export indexed tables to partitioned Parquet or CSV files
+    Export(ExportArgs),
+
+    /// Split a backfill into block ranges that several scrapers index side by side (Postgres only)
+    Queue {
+        #[command(subcommand)]
+        action: QueueCommand,
+    },
+
+    /// Manage webhooks notified of indexed changes (delivered while scraping with `--webhooks`)
+    Webhook {
+        #[command(subcommand)]
+        action: WebhookCommand,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+/// The blocks an indexing command covers.
+#[derive(Clone)]
+enum IndexMode {
+    Scrape { from_block: Option<u64>, to_block: Option<u64> },
+    Backfill { from_block: u64, to_block: u64 },
+    BackfillToken { addresses: Vec<Address>, from_block: Option<u64>, to_block: Option<u64>, reindex: bool },
+    Follow { from_block: Option<u64>, poll_interval: Option<Duration>, leader_election: bool },
 }
 
 #[tokio::main]
@@ -81,104 +275,882 @@ async fn main() {
 
     info!("Starting the Token Scraper CLI");
 
-    // Set up connection pool
-    let pool: r2d2::Pool<ConnectionManager<PgConnection>> = establish_connection_pool();
+    let cli = Cli::parse();
+
+    // The profile, with the environment and command-line overrides applied
+    let profile = match resolve_profile(&cli) {
+        Ok(profile) => profile,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(Command::Config { action: ConfigCommand::Check(_) }) = &cli.command {
+        std::process::exit(check_config(&profile));
+    }
+
+    // Open the Postgres database or SQLite file
+    let Some(database_url) = profile.database_url.as_deref() else {
+        error!("No database: set `database_url`, DATABASE_URL or --database-url");
+        std::process::exit(1);
+    };
+    let storage: Arc<dyn Storage> = match open_storage_with_pool(database_url, profile.pool) {
+        Ok(storage) => storage,
+        Err(e) => {
+            error!("Failed to open storage {}: {}", redact_url(database_url), e);
+            std::process::exit(1);
+        }
+    };
+
+    // `migrate` reports the migrations itself, and commands that only read leave the schema alone
+    if !matches!(cli.command, Some(Command::Migrate)) {
+        let mode = if writes(&cli) { profile.migrations } else { MigrationMode::Verify };
+        if let Err(e) = prepare_schema(storage.as_ref(), mode) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
 
-    let rpc_url = env::var("RPC_URL").expect("RPC_URL must be set");
-    let provider: Arc<Provider<Http>> = Arc::new(Provider::<Http>::try_from(rpc_url).expect("Invalid RPC URL"));
+    let (run, mode) = match &cli.command {
+        Some(Command::Scrape { run, from_block, to_block }) => {
+            (run.or(&cli.run), IndexMode::Scrape { from_block: *from_block, to_block: *to_block })
+        }
+        Some(Command::Backfill { run, from_block, to_block }) => {
+            if from_block > to_block {
+                error!("--from-block {} is after --to-block {}", from_block, to_block);
+                std::process::exit(1);
+            }
+            (run.or(&cli.run), IndexMode::Backfill { from_block: *from_block, to_block: *to_block })
+        }
+        Some(Command::BackfillToken { run, addresses, from_block, to_block }) => {
+            // Token addresses belong to a single chain
+            single_chain(&profile);
+            let mode = IndexMode::BackfillToken { addresses: addresses.clone(), from_block: *from_block, to_block: *to_block, reindex: false };
+            (run.or(&cli.run), mode)
+        }
+        Some(Command::ReindexToken { run, address, from_block }) => {
+            single_chain(&profile);
+            let mode = IndexMode::BackfillToken { addresses: vec![*address], from_block: *from_block, to_block: None, reindex: true };
+            (run.or(&cli.run), mode)
+        }
+        Some(Command::Follow { run, from_block, poll_interval, leader_election }) => {
+            let mode = IndexMode::Follow {
+                from_block: *from_block,
+                poll_interval: poll_interval.map(Duration::from_secs),
+                leader_election: *leader_election || profile.leader_election,
+            };
+            (run.or(&cli.run), mode)
+        }
+        Some(Command::Verify { tokens, wallets, block, .. }) => {
+            let chain = single_chain(&profile);
+            let storage = storage.for_chain(chain_id_of(&chain).await);
+            std::process::exit(verify(&chain, storage.as_ref(), tokens, *wallets, *block).await);
+        }
+        Some(Command::Status { .. }) => {
+            std::process::exit(status(&profile, storage.as_ref()).await);
+        }
+        Some(Command::ResetToken { address }) => {
+            let storage = storage.for_chain(chain_id_of(&single_chain(&profile)).await);
+            match storage.delete_token(address.as_bytes()) {
+                Ok(None) => println!("Token {:?} is not stored", address),
+                Ok(Some(rows)) => println!("Deleted token {:?} and {} rows of it", address, rows),
+                Err(e) => {
+                    error!("Failed to reset token {:?}: {}", address, e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::ImportSnapshot { file, block, on_chain }) => {
+            let chain = single_chain(&profile);
+            let storage = storage.for_chain(chain_id_of(&chain).await);
+            std::process::exit(import_snapshot(&chain, storage.as_ref(), file, *block, *on_chain).await);
+        }
+        Some(Command::Migrate) => {
+            match storage.migrate() {
+                Ok(applied) if applied.is_empty() => println!("The {} schema is up to date", storage.name()),
+                Ok(applied) => applied.iter().for_each(|migration| println!("Applied migration {}", migration)),
+                Err(e) => {
+                    error!("Migration failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Serve { addr }) => {
+            let pool = require_postgres(storage.as_ref(), "serve");
+            // Requests pass `chain_id` for the other chains
+            let chain_id = chain_id_of(&profile.chains()[0]).await;
+            if let Err(e) = api::serve(pool, chain_id, *addr).await {
+                error!("API server failed: {:?}", e);
+            }
+            return;
+        }
+        Some(Command::Export(args)) => {
+            let pool = require_postgres(storage.as_ref(), "export");
+            let chain_id = chain_id_of(&single_chain(&profile)).await;
+            let conn: &mut PgPooledConnection = &mut pool.get().expect("Failed to get connection from pool");
+            if let Err(e) = run_export(conn, args, chain_id) {
+                error!("Export failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Queue { action: QueueCommand::Work { worker, .. } }) => {
+            match work_queue(&profile, storage, worker.clone()).await {
+                Ok(None) => {}
+                Ok(Some(signal)) => {
+                    info!("Stopped by {}; the blocks in progress are back in the queue", signal.name);
+                    std::process::exit(signal.exit_code);
+                }
+                Err(e) => {
+                    error!("Working on the backfill queue failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Queue { action }) => {
+            let pool = require_postgres(storage.as_ref(), "queue");
+            let chain_id = chain_id_of(&single_chain(&profile)).await;
+            if let Err(e) = run_queue_command(&pool, chain_id, action) {
+                error!("Queue command failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Webhook { action }) => {
+            let pool = require_postgres(storage.as_ref(), "webhook");
+            let conn: &mut PgPooledConnection = &mut pool.get().expect("Failed to get connection from pool");
+            if let Err(e) = run_webhook_command(conn, action) {
+                error!("Webhook command failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Config { .. }) => return,
+        None => {
+            if let Some(target_timestamp) = cli.block_at_timestamp {
+                let storage = storage.for_chain(chain_id_of(&single_chain(&profile)).await);
+                match storage.find_block_number_by_timestamp(target_timestamp) {
+                    Ok(Some(block_number)) => println!("{}", block_number),
+                    Ok(None) => println!("No stored block at or before timestamp {}", target_timestamp),
+                    Err(e) => error!("Error resolving timestamp {}: {:?}", target_timestamp, e),
+                }
+                return;
+            }
+            (cli.run.clone(), IndexMode::Scrape { from_block: None, to_block: None })
+        }
+    };
 
-    let block_range: u64 = env::var("BLOCK_RANGE").unwrap_or_else(|_| "10000".to_string()).parse().expect("Invalid BLOCK_RANGE");
+    match index(&profile, storage, &run, mode).await {
+        Ok(None) => {}
+        Ok(Some(signal)) => {
+            info!("Stopped by {}; every indexed range is checkpointed", signal.name);
+            std::process::exit(signal.exit_code);
+        }
+        Err(e) => {
+            error!("Indexing failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    // Parse CLI arguments and wrap in Arc for thread-safe sharing
-    let cli: Arc<Cli> = Arc::new(Cli::parse());
+/// A signal asking the scraper to stop.
+#[derive(Copy, Clone)]
+struct StopSignal {
+    name: &'static str,
+    exit_code: i32,                       // 128 plus the signal number, as a shell reports it
+}
 
-    let mut from_block: u64 = read_last_processed_block("lastProcessedBlock.txt");
+/// Waits for SIGINT (Ctrl-C) or SIGTERM (a container stop).
+async fn stop_signal() -> StopSignal {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => StopSignal { name: "SIGINT", exit_code: 130 },
+            _ = terminate.recv() => StopSignal { name: "SIGTERM", exit_code: 143 },
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        StopSignal { name: "Ctrl-C", exit_code: 130 }
+    }
+}
 
+/// Runs the indexer of every configured chain over the blocks selected by `mode`, side by side.
+/// Returns the signal that stopped them early, if any.
+async fn index(profile: &Profile, storage: Arc<dyn Storage>, run: &RunArgs, mode: IndexMode) -> IndexerResult<Option<StopSignal>> {
+    let errors = profile.validate();
+    if !errors.is_empty() {
+        return Err(errors.join("; ").into());
+    }
 
-   let mut latest_finalized_block = provider.get_block_number().await.unwrap().0[0];
+    // Every chain's endpoint is checked before any of them starts
+    let mut chains = Vec::new();
+    for chain in profile.chains() {
+        let provider: Arc<Provider<Http>> = Arc::new(Provider::<Http>::try_from(chain.rpc_url.as_deref().unwrap_or_default())?);
+        let chain_id = detect_chain_id(&chain, &provider).await?;
+        let chain = chain.with_preset(chain_id as u64);
+        info!("Indexing {} (chain id {})", chain.label(), chain_id);
+        chains.push((chain, provider, storage.for_chain(chain_id)));
+    }
+
+    let handlers = profile.custom_handlers().map_err(|e| format!("Failed to load handlers: {}", e))?;
+
+    // A standby taking over resumes from the stored checkpoints, wherever the first leader started
+    let leader_election = matches!(mode, IndexMode::Follow { leader_election: true, .. });
+    if leader_election {
+        if matches!(mode, IndexMode::Follow { from_block: Some(_), .. }) {
+            return Err("--from-block cannot be used with leader election: every leader resumes from the stored checkpoints".into());
+        }
+        require_postgres(storage.as_ref(), "--leader-election");
+    }
+    let database_url = profile.database_url.as_deref().unwrap_or_default();
+
+    if let Some(addr) = run.serve_addr {
+        let api_pool = require_postgres(storage.as_ref(), "--serve-addr");
+        let chain_id = chains[0].2.chain_id();
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(api_pool, chain_id, addr).await {
+                error!("API server failed: {:?}", e);
+            }
+        });
+    }
 
-   info!("Starting the block processing loop");
+    let storages: Vec<Arc<dyn Storage>> = chains.iter().map(|(_, _, storage)| storage.clone()).collect();
+    let sinks = EventSinks::connect_chains(&profile.sinks(), &storages).await.map_err(|e| format!("Failed to open sinks: {}", e))?;
 
-    // Continuously process blocks
-    while from_block < latest_finalized_block {
-        // Calculate the block range for the current iteration
-        let to_block = if from_block > latest_finalized_block - block_range { latest_finalized_block } else { from_block + block_range};  // Ensure from_block doesn't exceed current_block
-        
-        info!("Processing blocks from {} to {}", from_block, to_block);
+    let webhook_dispatcher = run.webhooks.then(|| WebhookDispatcher::spawn(require_postgres(storage.as_ref(), "--webhooks")));
 
-        // Fetch logs based on the selected token types
-        let logs = fetch_logs(&provider, from_block, to_block, &cli).await;
+    // The first signal stops every chain once the ranges in flight are written
+    let shutdown = CancellationToken::new();
+    let signal = spawn_stop_signal(shutdown.clone(), profile.shutdown_timeout());
 
-        // Dispatch each log to its own task using tokio::spawn
-        let tasks: Vec<JoinHandle<()>> = logs
-            .into_iter()
-            .map(|log| {
-                let pool_clone = pool.clone();   // Clone the pool
-                let provider_clone = provider.clone();   // Clone the Provider instance
-                let cli_clone = Arc::clone(&cli); // Clone the Arc for `Cli`
+    // Held until every chain is done
+    let mut leader_locks = Vec::new();
+    let mut runs = Vec::new();
+    for ((chain, provider, storage), sinks) in chains.into_iter().zip(sinks) {
+        let chain_id = storage.chain_id();
+        let address_filter = chain.address_filter()?;
+        if profile.processors.total_supplies && address_filter.lists()?.watched.is_some() {
+            warn!("{} watches wallets, so its total supplies are not indexed", chain.label());
+        }
+        let mut builder = indexer_builder(profile, &chain, provider, storage.clone(), &handlers, &shutdown).sinks(sinks);
+        let (from_block, to_block) = match &mode {
+            IndexMode::Scrape { from_block, to_block } => (*from_block, to_block.or(chain.end_block)),
+            IndexMode::Backfill { from_block, to_block } => {
+                if profile.sinks().iter().any(|sink| matches!(sink, SinkSpec::Storage)) {
+                    check_backfill_range(&builder, storage.as_ref(), *from_block)?;
+                }
+                (Some(*from_block), Some(*to_block))
+            }
+            IndexMode::BackfillToken { addresses, from_block, to_block, reindex } => {
+                let lists = address_filter.lists()?;
+                for address in addresses.iter().filter(|address| !lists.accepts(address)) {
+                    warn!("{:?} is not allowed by the address lists of {}; its later logs are not indexed", address, chain.label());
+                }
+                // Only the tokens, but never the denied contracts
+                let config = AddressFilterConfig { allowlist: Some(addresses.clone()), allowlist_file: None, ..chain.addresses.clone().unwrap_or_default() };
+                builder = builder.address_filter(AddressFilter::from_config(&config)?);
+                let first_block = from_block.unwrap_or(chain.start_block.unwrap_or(0));
+                let to_block = match to_block {
+                    Some(to_block) => *to_block,
+                    None => indexed_up_to(&builder, storage.as_ref())?,
+                };
+                if first_block > to_block {
+                    return Err(format!("Nothing to backfill from block {} to block {}", first_block, to_block).into());
+                }
+                // The rows are only deleted once the range to index them again is known
+                if *reindex {
+                    // The leader of the chain would write the token's new logs in between
+                    if storage.postgres_pool().is_some() {
+                        match LeaderLock::try_acquire(database_url, chain_id).await? {
+                            Some(lock) => leader_locks.push(lock),
+                            None => return Err(format!("Another scraper leads chain {}; stop it before reindexing", chain_id).into()),
+                        }
+                    }
+                    for address in addresses {
+                        match from_block {
+                            // Later changes are applied on top of the totals kept before `from_block`
+                            Some(from_block) => {
+                                let rows = storage.delete_token_from(address.as_bytes(), i32::try_from(*from_block)?)?;
+                                info!("Deleted {} rows of token {:?} from block {}", rows, address, from_block);
+                            }
+                            None => match storage.delete_token(address.as_bytes())? {
+                                None => info!("Token {:?} is not stored yet", address),
+                                Some(rows) => info!("Deleted token {:?} and {} rows of it", address, rows),
+                            },
+                        }
+                    }
+                } else {
+                    // Balances are running totals, which the backfilled changes would be added to
+                    for address in addresses {
+                        let rows = storage.count_token_rows(address.as_bytes())?;
+                        if rows > 0 {
+                            return Err(format!("Token {:?} already has {} balance, allowance and supply rows; rebuild it with reindex-token instead", address, rows).into());
+                        }
+                    }
+                }
+                (Some(first_block), Some(to_block))
+            }
+            IndexMode::Follow { from_block, .. } => (*from_block, chain.end_block),
+        };
+        if !matches!(mode, IndexMode::BackfillToken { .. }) {
+            builder = builder.address_filter(address_filter);
+        }
+        // A backfill runs alongside the scrape owning the checkpoints
+        if !matches!(mode, IndexMode::Backfill { .. } | IndexMode::BackfillToken { .. }) {
+            builder = builder.checkpoint_file(chain.checkpoint_file()).stored_checkpoints();
+        }
+        if let Some(from_block) = from_block {
+            builder = builder.range(from_block, to_block);
+        } else if let Some(to_block) = to_block {
+            builder = builder.to_block(to_block);
+        }
+        let indexer = builder.clone().build()?;
+        let shutdown = &shutdown;
+        let follow = match mode {
+            IndexMode::Follow { poll_interval, leader_election, .. } => Some((poll_interval, leader_election)),
+            IndexMode::Scrape { .. } | IndexMode::Backfill { .. } | IndexMode::BackfillToken { .. } => None,
+        };
 
-                tokio::spawn(async move {
-                    let conn: &mut PgPooledConnection = &mut pool_clone.get().expect("Failed to get connection from pool");  // Get a connection from the pool
-                    if let Err(e) = parse_log(&log, conn, provider_clone, &cli_clone).await {
-                        error!("Error parsing log: {:?}", e);
+        runs.push(async move {
+            let result = match follow {
+                Some((poll_interval, leader_election)) => {
+                    let poll_interval = poll_interval.unwrap_or(Duration::from_secs(chain.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL)));
+                    if leader_election {
+                        follow_as_leader(builder, database_url, chain_id, poll_interval, shutdown).await
+                    } else {
+                        indexer.follow(poll_interval).await
                     }
-                })
-            })
-            .collect();
+                }
+                None => indexer.run().await.map(|_| ()),
+            };
+            (chain.label(), result)
+        });
+    }
+    let mut results = futures::future::join_all(runs).await;
 
-        // Wait for all the spawned threads to finish
-        join_all(tasks).await;
+    if let Some(dispatcher) = webhook_dispatcher {
+        dispatcher.finish().await;
+    }
+    let stopped_by = if shutdown.is_cancelled() {
+        signal.await.ok()
+    } else {
+        signal.abort();
+        None
+    };
 
-        info!("Finished processing blocks from {} to {}", from_block, to_block);
-        let _ = write_last_processed_block("lastProcessedBlock.txt", to_block);
+    if results.len() == 1 {
+        return results.remove(0).1.map(|_| stopped_by);
+    }
+    // One chain failing does not stop the others; each failure is reported once they are all done
+    let failed: Vec<String> = results
+        .into_iter()
+        .filter_map(|(label, result)| {
+            let e = result.err()?;
+            error!("Indexing {} failed: {}", label, e);
+            Some(label)
+        })
+        .collect();
+    if !failed.is_empty() {
+        return Err(format!("{} of the chains failed ({})", failed.len(), failed.join(", ")).into());
+    }
+    Ok(stopped_by)
+}
 
-        // Move to the next block range
-        from_block = to_block + block_range;
-        latest_finalized_block = provider.get_block_number().await.unwrap().0[0];
+/// The block every stream of the indexer `builder` configures is indexed up to: the lowest of their
+/// checkpoints.
+fn indexed_up_to(builder: &IndexerBuilder, storage: &dyn Storage) -> IndexerResult<u64> {
+    let checkpoints: HashMap<(String, String), i64> =
+        storage.checkpoints()?.into_iter().map(|checkpoint| ((checkpoint.handler, checkpoint.processor), checkpoint.block_number)).collect();
+    let mut lowest = u64::MAX;
+    for stream in builder.clone().build()?.streams() {
+        match checkpoints.get(&(stream.handler.clone(), stream.processor.clone())) {
+            Some(block_number) => lowest = lowest.min(*block_number as u64),
+            None => return Err(format!("{} has no checkpoint yet, so the next run indexes the tokens with the others", stream).into()),
+        }
     }
+    Ok(lowest)
 }
 
-async fn fetch_logs(provider: &Provider<Http>, from_block: u64, to_block: u64, cli: &Arc<Cli>) -> Vec<Log> {
-    let mut topics: HashSet<H256> = HashSet::new();  // Use HashSet to store unique topics
+/// Refuses to backfill the database from `from_block` when a stream of the indexer `builder` is
+/// indexed at or past it, or rows of the chain are stored from it on: balances and supplies are
+/// running totals, which the backfilled changes would be added to out of order.
+fn check_backfill_range(builder: &IndexerBuilder, storage: &dyn Storage, from_block: u64) -> IndexerResult<()> {
+    let checkpoints: HashMap<(String, String), i64> =
+        storage.checkpoints()?.into_iter().map(|checkpoint| ((checkpoint.handler, checkpoint.processor), checkpoint.block_number)).collect();
+    for stream in builder.clone().build()?.streams() {
+        if let Some(block_number) = checkpoints.get(&(stream.handler.clone(), stream.processor.clone())).filter(|block_number| **block_number as u64 >= from_block) {
+            return Err(format!("{} is indexed up to block {}; only backfill the database after it, or into other sinks", stream, block_number).into());
+        }
+    }
+    let rows = storage.count_rows_from(i32::try_from(from_block)?)?;
+    if rows > 0 {
+        return Err(format!("{} balance, allowance and supply rows are stored from block {} on; only backfill the database after them, or into other sinks", rows, from_block).into());
+    }
+    Ok(())
+}
 
-    // Add event signatures based on the CLI token type options
-    if cli.erc20 || cli.erc721 {
-        topics.insert(*ERC_TRANSFER_SIGNATURE);
-        topics.insert(*ERC_APPROVAL_SIGNATURE);
+/// Cancels `shutdown` on the first SIGINT or SIGTERM, and returns that signal.
+fn spawn_stop_signal(shutdown: CancellationToken, shutdown_timeout: Duration) -> tokio::task::JoinHandle<StopSignal> {
+    tokio::spawn(async move {
+        let signal = stop_signal().await;
+        warn!("Received {}; stopping once the blocks in progress are written (dropping them after {}s)", signal.name, shutdown_timeout.as_secs());
+        shutdown.cancel();
+        tokio::spawn(async move {
+            loop {
+                let again = stop_signal().await;
+                warn!("Received {} while stopping; the blocks in progress are dropped after the shutdown timeout", again.name);
+            }
+        });
+        signal
+    })
+}
+
+/// An indexer of `chain` configured by the profile, before the blocks, checkpoints and sinks of a
+/// command are set.
+fn indexer_builder(
+    profile: &Profile,
+    chain: &ChainConfig,
+    provider: Arc<Provider<Http>>,
+    storage: Arc<dyn Storage>,
+    handlers: &[Arc<dyn EventHandler>],
+    shutdown: &CancellationToken,
+) -> IndexerBuilder {
+    let mut builder = Indexer::builder()
+        .provider(provider)
+        .storage(storage)
+        .standards(profile.standards.iter().copied())
+        .handlers(handlers.iter().cloned())
+        .processors(profile.processors)
+        .system_handlers(chain.system_handlers.clone().unwrap_or_default())
+        .block_range(chain.block_range.unwrap_or(profile.block_range()))
+        .finality(chain.finality.unwrap_or_default())
+        .first_block(chain.start_block.unwrap_or(0))
+        .shutdown(shutdown.clone())
+        .shutdown_timeout(profile.shutdown_timeout());
+    if let Some(log_limit) = chain.log_limit {
+        builder = builder.log_limit(log_limit);
+    }
+    if let Some(address_limit) = chain.address_limit {
+        builder = builder.address_limit(address_limit);
     }
+    if let Some(concurrency) = chain.concurrency {
+        builder = builder.concurrency(concurrency);
+    }
+    builder
+}
 
-    if cli.erc1155 {
-        topics.insert(*ERC1155_BATCH_TRANSFER_SIGNATURE);
-        topics.insert(*ERC1155_SINGLE_TRANSFER_SIGNATURE);
-        topics.insert(*ERC_APPROVAL_FOR_ALL_SIGNATURE);  // ERC1155 shares ApprovalForAll with ERC721
+/// Claims and indexes items of the backfill queue of the profile's chain until every queued item is
+/// merged. Returns the signal that stopped it early, if any.
+async fn work_queue(profile: &Profile, storage: Arc<dyn Storage>, worker: Option<String>) -> IndexerResult<Option<StopSignal>> {
+    let errors = profile.validate();
+    if !errors.is_empty() {
+        return Err(errors.join("; ").into());
+    }
+    let pool = require_postgres(storage.as_ref(), "queue work");
+    let chain = single_chain(profile);
+    let provider: Arc<Provider<Http>> = Arc::new(Provider::<Http>::try_from(chain.rpc_url.as_deref().unwrap_or_default())?);
+    let chain_id = detect_chain_id(&chain, &provider).await?;
+    let chain = chain.with_preset(chain_id as u64);
+    let storage = storage.for_chain(chain_id);
+    let handlers = profile.custom_handlers().map_err(|e| format!("Failed to load handlers: {}", e))?;
+    if profile.sinks().iter().any(|sink| !matches!(sink, SinkSpec::Storage)) {
+        warn!("Queue workers only write to the database; the other sinks are left out");
     }
 
-    if cli.erc777 {
-        topics.insert(*ERC777_SENT_SIGNATURE);
-        topics.insert(*ERC777_MINTED_SIGNATURE);
-        topics.insert(*ERC777_BURNED_SIGNATURE);
-        topics.insert(*ERC777_AUTHORIZED_OPERATOR_SIGNATURE);
-        topics.insert(*ERC777_REVOKED_OPERATOR_SIGNATURE);
+    let shutdown = CancellationToken::new();
+    let signal = spawn_stop_signal(shutdown.clone(), profile.shutdown_timeout());
+    let builder = indexer_builder(profile, &chain, provider, storage, &handlers, &shutdown).address_filter(chain.address_filter()?);
+    let queue = WorkQueue::new(pool, chain_id, worker.unwrap_or_else(default_worker_name));
+    let counts = queue.work(|| builder.clone(), &shutdown).await;
+
+    let stopped_by = if shutdown.is_cancelled() {
+        signal.await.ok()
+    } else {
+        signal.abort();
+        None
+    };
+    let counts = counts?;
+    if stopped_by.is_some() {
+        return Ok(stopped_by);
+    }
+    if counts.failed > 0 {
+        return Err(format!("{} items failed; see `queue status`, and put them back with `queue retry`", counts.failed).into());
     }
+    info!("Every queued item of {} is merged", chain.label());
+    Ok(None)
+}
 
-    // Convert HashSet back to Vec<H256> for the FilterBuilder
-    let topics_vec: Vec<H256> = topics.into_iter().collect();
-    info!("Fetching logs with topics: {:?}", topics_vec);
-    // Build the log filter
-    let filter = Filter::new()
-        .from_block(BlockNumber::Number(from_block.into()))
-        .to_block(BlockNumber::Number(to_block.into()))
-        .topic0(topics_vec);
+/// The id of the chain `provider` serves, which must be the one configured for `chain`, if any.
+async fn detect_chain_id(chain: &ChainConfig, provider: &Provider<Http>) -> IndexerResult<i64> {
+    let served = provider
+        .get_chainid()
+        .await
+        .map_err(|e| format!("Failed to read the chain id of {}: {}", chain.label(), e))?
+        .as_u64();
+    match chain.chain_id {
+        Some(configured) if configured != served => {
+            Err(format!("The RPC endpoint of {} serves chain {}, not the configured chain {}", chain.label(), served, configured).into())
+        }
+        _ => Ok(served as i64),
+    }
+}
 
-    let logs = match provider.get_logs(&filter).await {
-        Ok(logs) => {
-            info!("Fetched {} logs", logs.len());
-            logs
-        },
+/// The id of `chain` for commands that only read the database: the configured one, else the one its
+/// RPC endpoint serves, else `DEFAULT_CHAIN_ID`.
+async fn chain_id_of(chain: &ChainConfig) -> i64 {
+    if let Some(chain_id) = chain.chain_id {
+        return chain_id as i64;
+    }
+    let Some(Ok(provider)) = chain.rpc_url.as_deref().map(Provider::<Http>::try_from) else {
+        return DEFAULT_CHAIN_ID;
+    };
+    match provider.get_chainid().await {
+        Ok(chain_id) => chain_id.as_u64() as i64,
         Err(e) => {
-            error!("Error fetching logs: {:?}", e);
-            Vec::new()
-        },
+            error!("Failed to read the chain id of {}, assuming chain {}: {}", chain.label(), DEFAULT_CHAIN_ID, e);
+            DEFAULT_CHAIN_ID
+        }
+    }
+}
+
+/// The only chain of the profile, for commands working on one; exits when several are configured.
+fn single_chain(profile: &Profile) -> ChainConfig {
+    let mut chains = profile.chains();
+    if chains.len() > 1 {
+        error!("Several chains are configured; select one with --chain-id");
+        std::process::exit(1);
+    }
+    chains.remove(0)
+}
+
+/// Loads the selected profile and applies the command-line flags (and through them, the environment) to it.
+fn resolve_profile(cli: &Cli) -> ConfigResult<Profile> {
+    let mut profile = load_profile(cli.config.as_deref(), cli.profile.as_deref())?;
+
+    if cli.rpc_url.is_some() {
+        profile.rpc_url.clone_from(&cli.rpc_url);
+    }
+    if cli.database_url.is_some() {
+        profile.database_url.clone_from(&cli.database_url);
+    }
+    profile.migrations = cli.migrations.unwrap_or(profile.migrations);
+    if let Some(chain_id) = cli.chain_id {
+        profile.select_chain(chain_id)?;
+    }
+
+    // Flags given before the command come first
+    profile.apply_args(&cli.run.profile);
+    match &cli.command {
+        Some(
+            Command::Scrape { run, .. }
+            | Command::Backfill { run, .. }
+            | Command::BackfillToken { run, .. }
+            | Command::ReindexToken { run, .. }
+            | Command::Follow { run, .. },
+        ) => profile.apply_args(&run.profile),
+        Some(Command::Config { action: ConfigCommand::Check(args) }) => profile.apply_args(args),
+        Some(Command::Queue { action: QueueCommand::Work { profile: args, .. } }) => profile.apply_args(args),
+        Some(Command::Verify { checkpoint_file, .. } | Command::Status { checkpoint_file }) => {
+            profile.apply_args(&ProfileArgs { checkpoint_file: checkpoint_file.clone(), ..ProfileArgs::default() })
+        }
+        Some(_) | None => {}
+    }
+
+    Ok(profile)
+}
+
+/// Whether the command stores, changes or deletes rows, so may bring the schema up to date first.
+fn writes(cli: &Cli) -> bool {
+    match &cli.command {
+        Some(Command::Verify { .. } | Command::Status { .. } | Command::Serve { .. } | Command::Export(_) | Command::Config { .. }) => false,
+        Some(Command::Queue { action }) => !matches!(action, QueueCommand::Status),
+        Some(Command::Webhook { action }) => !matches!(action, WebhookCommand::List),
+        Some(_) => true,
+        None => cli.block_at_timestamp.is_none(),
+    }
+}
+
+/// Applies the migrations the database lacks, or fails when there are any in `verify` mode.
+fn prepare_schema(storage: &dyn Storage, mode: MigrationMode) -> StorageResult<()> {
+    match mode {
+        MigrationMode::Apply => {
+            for migration in storage.migrate()? {
+                info!("Applied migration {}", migration);
+            }
+        }
+        MigrationMode::Verify => {
+            let pending = storage.pending_migrations()?;
+            if !pending.is_empty() {
+                return Err(format!("The database lacks {} migrations ({}); apply them with `migrate`", pending.len(), pending.join(", ")).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compares stored ERC20 amounts with the chain and prints the differences; returns the exit code.
+async fn verify(chain: &ChainConfig, storage: &dyn Storage, tokens: &[Address], wallets: i64, block: Option<u64>) -> i32 {
+    let Some(rpc_url) = chain.rpc_url.as_deref() else {
+        error!("No RPC URL: set `rpc_url`, RPC_URL or --rpc-url");
+        return 1;
     };
-    logs
+    let provider = match Provider::<Http>::try_from(rpc_url) {
+        Ok(provider) => Arc::new(provider),
+        Err(e) => {
+            error!("Invalid RPC URL: {}", e);
+            return 1;
+        }
+    };
+    let block = match block.map(Ok).unwrap_or_else(|| erc20_checkpoint(chain, storage)) {
+        Ok(0) => {
+            error!("No checkpoint of the ERC20 balances; give the block to compare at with --block");
+            return 1;
+        }
+        Ok(block) => block,
+        Err(e) => {
+            error!("Failed to read the checkpoints: {}", e);
+            return 1;
+        }
+    };
+
+    let report = match verify_erc20(storage, provider, tokens, wallets, block).await {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Verification failed: {}", e);
+            return 1;
+        }
+    };
+
+    for mismatch in &report.mismatches {
+        let holder = match mismatch.wallet_address {
+            Some(wallet) => format!("balance of {:?}", wallet),
+            None => "total supply".to_string(),
+        };
+        println!("{:?} {}: stored {}, on chain {}", mismatch.token_address, holder, mismatch.stored, mismatch.on_chain);
+    }
+    println!(
+        "Checked {} amounts of {} tokens at block {}: {} mismatches",
+        report.checked,
+        report.tokens,
+        block,
+        report.mismatches.len()
+    );
+    if report.mismatches.is_empty() { 0 } else { 1 }
+}
+
+/// Imports the snapshot of `file` at `block`, read on chain when `on_chain`; returns the exit code.
+async fn import_snapshot(chain: &ChainConfig, storage: &dyn Storage, file: &Path, block: u64, on_chain: bool) -> i32 {
+    let Some(rpc_url) = chain.rpc_url.as_deref() else {
+        error!("No RPC URL: set `rpc_url`, RPC_URL or --rpc-url");
+        return 1;
+    };
+    let provider = match Provider::<Http>::try_from(rpc_url) {
+        Ok(provider) => Arc::new(provider),
+        Err(e) => {
+            error!("Invalid RPC URL: {}", e);
+            return 1;
+        }
+    };
+
+    // Indexing resumes after the checkpoint, so logs between it and the snapshot would be counted twice
+    let indexed = match erc20_checkpoint(chain, storage) {
+        Ok(indexed) => indexed,
+        Err(e) => {
+            error!("Failed to read the checkpoints: {}", e);
+            return 1;
+        }
+    };
+    if indexed != 0 && indexed < block {
+        error!("The ERC20 balances are indexed up to block {}; take the snapshot at that block or an earlier one", indexed);
+        return 1;
+    }
+
+    let imported = async {
+        let mut entries = snapshot::read_snapshot(file)?;
+        if on_chain {
+            entries = snapshot::read_on_chain(&entries, provider.clone(), block).await?;
+        }
+        snapshot::import_snapshot(storage, provider, &entries, block).await
+    };
+    let report = match imported.await {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Failed to import {}: {}", file.display(), e);
+            return 1;
+        }
+    };
+    println!("Imported {} balances and {} total supplies of {} tokens at block {}", report.balances, report.supplies, report.tokens, block);
+
+    // The logs of the snapshot's block are already counted in its amounts
+    if indexed == 0 {
+        println!("Index from block {} on, e.g. with `scrape --from-block {}`", block + 1, block + 1);
+    } else if indexed > block {
+        // backfill-token refuses tokens with rows, which the snapshot just stored
+        println!(
+            "The chain is indexed up to block {}; index each token's later logs with `reindex-token <ADDRESS> --from-block {}`",
+            indexed,
+            block + 1
+        );
+    }
+    0
+}
+
+/// The block the stored ERC20 balances and total supplies are both indexed up to, else the one
+/// recorded in the checkpoint file; 0 when there is none.
+fn erc20_checkpoint(chain: &ChainConfig, storage: &dyn Storage) -> StorageResult<u64> {
+    let stored = storage
+        .checkpoints()?
+        .into_iter()
+        .filter(|checkpoint| checkpoint.handler == "ERC20" && ["balances", "total_supplies"].contains(&checkpoint.processor.as_str()))
+        .map(|checkpoint| checkpoint.block_number as u64)
+        .min();
+    Ok(stored.unwrap_or_else(|| read_last_processed_block(chain.checkpoint_file())))
+}
+
+/// Prints where indexing stands on every configured chain; returns the exit code.
+async fn status(profile: &Profile, storage: &dyn Storage) -> i32 {
+    println!("Profile:         {}", profile.name.as_deref().unwrap_or("(no config file)"));
+    println!("Storage:         {}", storage.name());
+
+    let mut exit_code = 0;
+    for chain in profile.chains() {
+        let chain_id = chain_id_of(&chain).await;
+        let storage = storage.for_chain(chain_id);
+        let checkpoints = match storage.checkpoints() {
+            Ok(checkpoints) => checkpoints,
+            Err(e) => {
+                error!("Failed to read the checkpoints of {}: {}", chain.label(), e);
+                exit_code = 1;
+                Vec::new()
+            }
+        };
+        // Until the first run imports it, the checkpoint file stands for every stream
+        let checkpoint = match checkpoints.iter().map(|checkpoint| checkpoint.block_number as u64).min() {
+            Some(lowest) => lowest,
+            None => read_last_processed_block(chain.checkpoint_file()),
+        };
+        let head = match chain.rpc_url.as_deref().map(Provider::<Http>::try_from) {
+            Some(Ok(provider)) => provider.get_block_number().await.map(|head| head.as_u64()).map_err(|e| e.to_string()),
+            Some(Err(e)) => Err(e.to_string()),
+            None => Err("no RPC URL".to_string()),
+        };
+
+        println!();
+        println!("Chain:           {} (chain id {})", chain.label(), chain_id);
+        match (checkpoints.is_empty(), checkpoint) {
+            (true, 0) => println!("Checkpoint:      none"),
+            (true, checkpoint) => println!("Checkpoint:      {} ({}, imported on the next run)", checkpoint, chain.checkpoint_file()),
+            (false, _) => {
+                println!("Checkpoints:");
+                for stored in &checkpoints {
+                    println!("  {:<26}{}", format!("{} {}:", stored.handler, stored.processor), stored.block_number);
+                }
+            }
+        }
+        match head {
+            Ok(head) => println!("Chain head:      {} ({} blocks behind)", head, head.saturating_sub(checkpoint)),
+            Err(e) => println!("Chain head:      unavailable: {}", e),
+        }
+
+        match storage.count_rows() {
+            Ok(counts) => {
+                for (table, count) in counts {
+                    println!("{:<17}{}", format!("{}:", table), count);
+                }
+            }
+            Err(e) => {
+                error!("Failed to count rows of {}: {}", chain.label(), e);
+                exit_code = 1;
+            }
+        }
+    }
+    exit_code
+}
+
+/// Prints the resolved profile and every problem found with it; returns the exit code.
+fn check_config(profile: &Profile) -> i32 {
+    let list = |items: Vec<String>| if items.is_empty() { "none".to_string() } else { items.join(", ") };
+    let processors = profile.processors;
+    let enabled_processors = [
+        (processors.balances, "balances"),
+        (processors.allowances, "allowances"),
+        (processors.total_supplies, "total_supplies"),
+        (processors.token_uri, "token_uri"),
+        (processors.blocks, "blocks"),
+        (processors.all_blocks, "all_blocks"),
+    ];
+
+    println!("Profile:         {}", profile.name.as_deref().unwrap_or("(no config file)"));
+    for chain in profile.chains() {
+        // The preset of a chain whose id is only detected at startup is applied then
+        let (chain, chain_id) = match chain.chain_id {
+            Some(chain_id) => (chain.with_preset(chain_id), chain_id.to_string()),
+            None => (chain, "detected".to_string()),
+        };
+        let preset = chain.chain_id.and_then(preset).map(|preset| preset.name).unwrap_or("none");
+        let system_handlers = chain.system_handlers.iter().flatten().map(|handler| handler.as_str().to_string()).collect();
+        println!("Chain:           {} (chain id {}, preset {})", chain.label(), chain_id, preset);
+        println!("  RPC URL:       {}", chain.rpc_url.as_deref().map(redact_url).unwrap_or_else(|| "-".to_string()));
+        println!("  Block range:   {}", chain.block_range.unwrap_or(profile.block_range()));
+        println!("  Log limit:     {}", chain.log_limit.map(|log_limit| log_limit.to_string()).unwrap_or_else(|| "none".to_string()));
+        if let Some(addresses) = &chain.addresses {
+            let file = |file: &Option<PathBuf>| file.as_ref().map(|file| format!(" and those of {}", file.display())).unwrap_or_default();
+            let allowlist = match (&addresses.allowlist, &addresses.allowlist_file) {
+                (None, None) => "none".to_string(),
+                (allowlist, allowlist_file) => format!("{} addresses{}", allowlist.iter().flatten().count(), file(allowlist_file)),
+            };
+            println!("  Allowlist:     {}, {} per request", allowlist, chain.address_limit.unwrap_or(DEFAULT_ADDRESS_LIMIT));
+            println!("  Denylist:      {} addresses{}", addresses.denylist.len(), file(&addresses.denylist_file));
+            if addresses.watchlist.is_some() || addresses.watchlist_file.is_some() {
+                println!("  Watchlist:     {} wallets{}", addresses.watchlist.iter().flatten().count(), file(&addresses.watchlist_file));
+            }
+        }
+        println!("  Finality:      {}", chain.finality.unwrap_or_default());
+        println!("  Poll interval: {}s", chain.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL));
+        println!("  Handlers:      {}", list(system_handlers));
+        println!("  Concurrency:   {}", chain.concurrency.map(|concurrency| concurrency.to_string()).unwrap_or_else(|| "unlimited".to_string()));
+        println!("  Checkpoints:   database (imports {})", chain.checkpoint_file());
+        if chain.start_block.is_some() || chain.end_block.is_some() {
+            let bound = |block: Option<u64>| block.map(|block| block.to_string()).unwrap_or_else(|| "-".to_string());
+            println!("  Blocks:        {} to {}", bound(chain.start_block), bound(chain.end_block));
+        }
+    }
+    println!("Database:        {}", profile.database_url.as_deref().map(redact_url).unwrap_or_else(|| "-".to_string()));
+    println!("Standards:       {}", list(profile.standards.iter().map(|standard| standard.as_str().to_string()).collect()));
+    println!("Processors:      {}", list(enabled_processors.iter().filter(|(enabled, _)| *enabled).map(|(_, name)| name.to_string()).collect()));
+    println!("Contracts:       {}", list(profile.contracts.iter().map(|contract| format!("{} ({} addresses)", contract.name, contract.addresses.len())).collect()));
+    println!("Contracts file:  {}", profile.contracts_file.as_ref().map(|file| file.display().to_string()).unwrap_or_else(|| "-".to_string()));
+    println!("Scripts:         {}", list(profile.scripts.iter().map(|script| script.display().to_string()).collect()));
+    println!("Sinks:           {}", list(profile.sinks().iter().map(SinkSpec::to_string).collect()));
+    println!("Shutdown:        {}s for the blocks in progress", profile.shutdown_timeout().as_secs());
+    println!("Postgres pool:   {} connections, {} idle", profile.pool.max_size, profile.pool.min_idle);
+    println!("Migrations:      {:?}", profile.migrations);
+
+    let errors = config::check(profile);
+    if errors.is_empty() {
+        println!("Configuration is valid");
+        return 0;
+    }
+    for e in &errors {
+        println!("error: {}", e);
+    }
+    1
+}
+
+/// Returns the Postgres pool for a feature that needs one, exiting with an error when running on SQLite.
+fn require_postgres(storage: &dyn Storage, feature: &str) -> DbPool {
+    match storage.postgres_pool() {
+        Some(pool) => pool.clone(),
+        None => {
+            error!("{} requires a Postgres DATABASE_URL, but the {} storage is configured", feature, storage.name());
+            std::process::exit(1);
+        }
+    }
 }
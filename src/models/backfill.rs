@@ -0,0 +1,234 @@
+use diesel::prelude::*;
+use crate::models::checkpoint::Checkpoint;
+use crate::PgPooledConnection;
+
+/// Status of an item waiting to be claimed.
+pub const PENDING: &str = "pending";
+/// Status of an item a worker is indexing.
+pub const RUNNING: &str = "running";
+/// Status of an item whose changes are staged, waiting for the items before it to be merged.
+pub const DONE: &str = "done";
+/// Status of an item whose changes are applied to the historical tables.
+pub const MERGED: &str = "merged";
+/// Status of an item given up on after its last attempt failed.
+pub const FAILED: &str = "failed";
+
+/// A block range of a backfill and where its processing stands.
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::schema::backfill_items)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BackfillItem {
+    pub id: i32,                            // Unique ID for the item, ordering nothing
+    pub chain_id: i64,                      // Chain the range is indexed from
+    pub from_block: i64,                    // First block of the range
+    pub to_block: i64,                      // Last block of the range (inclusive)
+    pub status: String,                     // "pending", "running", "done", "merged" or "failed"
+    pub worker: Option<String>,             // Scraper that claimed the range last
+    pub attempts: i32,                      // Claims so far; the last one tags the staged changes
+    pub error: Option<String>,              // Error of the last failed attempt
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::backfill_items)]
+pub struct NewBackfillItem {
+    pub chain_id: i64,
+    pub from_block: i64,
+    pub to_block: i64,
+}
+
+/// A balance, allowance or supply change decoded from an item's range, waiting for the merge.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::backfill_changes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct StagedChange {
+    pub kind: String,                       // "balance", "allowance" or "supply"
+    pub token_address: Vec<u8>,
+    pub wallet_address: Option<Vec<u8>>,    // Holder of a balance, owner of an allowance
+    pub spender_address: Option<Vec<u8>>,   // Spender of an allowance
+    pub token_id: Option<i16>,
+    pub token_type: Option<String>,         // "ERC20", "ERC721", ... for balances and allowances
+    pub amount: String,                     // Signed delta, or the allowance
+    pub block_number: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::backfill_changes)]
+pub struct NewStagedChange<'a> {
+    pub item_id: i32,
+    pub attempt: i32,
+    pub seq: i32,                           // Position of the change in the range
+    pub kind: &'a str,
+    pub token_address: &'a [u8],
+    pub wallet_address: Option<&'a [u8]>,
+    pub spender_address: Option<&'a [u8]>,
+    pub token_id: Option<i16>,
+    pub token_type: Option<&'a str>,
+    pub amount: &'a str,
+    pub block_number: i32,
+}
+
+pub fn insert_items(conn: &mut PgPooledConnection, new_items: &[NewBackfillItem]) -> QueryResult<usize> {
+    use crate::schema::backfill_items::dsl::*;
+
+    diesel::insert_into(backfill_items).values(new_items).execute(conn)
+}
+
+/// The items of a chain whose range shares a block with `from_block..=to_block`.
+pub fn load_overlapping_items(conn: &mut PgPooledConnection, chain_id_value: i64, from_block_value: i64, to_block_value: i64) -> QueryResult<Vec<BackfillItem>> {
+    use crate::schema::backfill_items::dsl::*;
+
+    backfill_items
+        .filter(chain_id.eq(chain_id_value))
+        .filter(from_block.le(to_block_value))
+        .filter(to_block.ge(from_block_value))
+        .order_by(from_block.asc())
+        .select(BackfillItem::as_select())
+        .load(conn)
+}
+
+/// The items of a chain, optionally only those with a status, in block order.
+pub fn load_items(conn: &mut PgPooledConnection, chain_id_value: i64, status_value: Option<&str>) -> QueryResult<Vec<BackfillItem>> {
+    use crate::schema::backfill_items::dsl::*;
+
+    let mut query = backfill_items.filter(chain_id.eq(chain_id_value)).order_by(from_block.asc()).into_boxed();
+    if let Some(status_value) = status_value {
+        query = query.filter(status.eq(status_value));
+    }
+    query.select(BackfillItem::as_select()).load(conn)
+}
+
+/// Claims the first pending item of a chain, or one whose worker has not reported progress for
+/// `stale_after_secs`, skipping the items other workers are claiming at the same time.
+pub fn claim_item(conn: &mut PgPooledConnection, chain_id_value: i64, worker_value: &str, stale_after_secs: i64) -> QueryResult<Option<BackfillItem>> {
+    use diesel::dsl::{now, IntervalDsl};
+    use crate::schema::backfill_items::dsl::*;
+
+    conn.transaction(|conn| {
+        let stale = status.eq(RUNNING).and(claimed_at.lt((now - stale_after_secs.seconds()).nullable()));
+        let claimable: Option<i32> = backfill_items
+            .filter(chain_id.eq(chain_id_value))
+            .filter(status.eq(PENDING).or(stale))
+            .order_by(from_block.asc())
+            .select(id)
+            .for_update()
+            .skip_locked()
+            .first(conn)
+            .optional()?;
+        let Some(item_id) = claimable else {
+            return Ok(None);
+        };
+
+        // Changes staged by an earlier claim are left behind by a worker that stopped or stalled
+        diesel::delete(crate::schema::backfill_changes::table.filter(crate::schema::backfill_changes::item_id.eq(item_id))).execute(conn)?;
+        diesel::update(backfill_items.filter(id.eq(item_id)))
+            .set((status.eq(RUNNING), worker.eq(worker_value), attempts.eq(attempts + 1), claimed_at.eq(now.nullable())))
+            .returning(BackfillItem::as_returning())
+            .get_result(conn)
+            .map(Some)
+    })
+}
+
+/// Records that the worker of an item is still on it. Returns false once another worker claimed it.
+pub fn touch_item(conn: &mut PgPooledConnection, item: &BackfillItem) -> QueryResult<bool> {
+    use diesel::dsl::now;
+    use crate::schema::backfill_items::dsl::*;
+
+    // Only while the attempt that claimed the item holds it
+    let target = backfill_items.filter(id.eq(item.id)).filter(status.eq(RUNNING)).filter(attempts.eq(item.attempts));
+    let updated = diesel::update(target).set(claimed_at.eq(now.nullable())).execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Ends the attempt of an item with `status_value` (and the error that ended it). Returns false
+/// when another worker claimed the item since, and the attempt counts for nothing.
+pub fn finish_item(conn: &mut PgPooledConnection, item: &BackfillItem, status_value: &str, error_value: Option<&str>) -> QueryResult<bool> {
+    use crate::schema::backfill_items::dsl::*;
+
+    let target = backfill_items.filter(id.eq(item.id)).filter(status.eq(RUNNING)).filter(attempts.eq(item.attempts));
+    let updated = diesel::update(target).set((status.eq(status_value), error.eq(error_value))).execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Puts the failed items of a chain back in the queue; returns how many there were.
+pub fn retry_failed_items(conn: &mut PgPooledConnection, chain_id_value: i64) -> QueryResult<usize> {
+    use crate::schema::backfill_items::dsl::*;
+
+    diesel::update(backfill_items.filter(chain_id.eq(chain_id_value)).filter(status.eq(FAILED)))
+        .set(status.eq(PENDING))
+        .execute(conn)
+}
+
+/// Deletes the merged items of a chain; returns how many there were.
+pub fn delete_merged_items(conn: &mut PgPooledConnection, chain_id_value: i64) -> QueryResult<usize> {
+    use crate::schema::backfill_items::dsl::*;
+
+    diesel::delete(backfill_items.filter(chain_id.eq(chain_id_value)).filter(status.eq(MERGED))).execute(conn)
+}
+
+pub fn insert_changes(conn: &mut PgPooledConnection, new_changes: &[NewStagedChange]) -> QueryResult<usize> {
+    use crate::schema::backfill_changes::dsl::*;
+
+    diesel::insert_into(backfill_changes).values(new_changes).execute(conn)
+}
+
+/// The first item of a chain not merged yet, locked until the end of the transaction; None when
+/// every item is merged, or when another worker holds the first one (e.g. to merge it).
+pub fn lock_first_unmerged_item(conn: &mut PgPooledConnection, chain_id_value: i64) -> QueryResult<Option<BackfillItem>> {
+    use crate::schema::backfill_items::dsl::*;
+
+    let first: Option<i32> = backfill_items
+        .filter(chain_id.eq(chain_id_value))
+        .filter(status.ne(MERGED))
+        .order_by(from_block.asc())
+        .select(id)
+        .first(conn)
+        .optional()?;
+    let Some(first) = first else {
+        return Ok(None);
+    };
+    backfill_items
+        .filter(id.eq(first))
+        .for_update()
+        .skip_locked()
+        .select(BackfillItem::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// The checkpoints of a chain, locked until the end of the transaction so that the indexer does
+/// not move them while an item is merged.
+pub fn lock_checkpoints(conn: &mut PgPooledConnection, chain_id_value: i64) -> QueryResult<Vec<Checkpoint>> {
+    use crate::schema::checkpoints::dsl::*;
+
+    checkpoints.filter(chain_id.eq(chain_id_value)).for_update().select(Checkpoint::as_select()).load(conn)
+}
+
+/// The first block queued on a chain, merged or not.
+pub fn first_queued_block(conn: &mut PgPooledConnection, chain_id_value: i64) -> QueryResult<Option<i64>> {
+    use crate::schema::backfill_items::dsl::*;
+
+    backfill_items.filter(chain_id.eq(chain_id_value)).select(diesel::dsl::min(from_block)).first(conn)
+}
+
+/// The changes staged by the attempt of an item that completed it, in order.
+pub fn load_staged_changes(conn: &mut PgPooledConnection, item: &BackfillItem) -> QueryResult<Vec<StagedChange>> {
+    use crate::schema::backfill_changes::dsl::*;
+
+    backfill_changes
+        .filter(item_id.eq(item.id))
+        .filter(attempt.eq(item.attempts))
+        .order_by(seq.asc())
+        .select(StagedChange::as_select())
+        .load(conn)
+}
+
+/// Marks an item as merged and deletes its staged changes, those of earlier attempts included.
+pub fn mark_item_merged(conn: &mut PgPooledConnection, item: &BackfillItem) -> QueryResult<()> {
+    use crate::schema::{backfill_changes, backfill_items};
+
+    diesel::delete(backfill_changes::table.filter(backfill_changes::item_id.eq(item.id))).execute(conn)?;
+    diesel::update(backfill_items::table.filter(backfill_items::id.eq(item.id)))
+        .set((backfill_items::status.eq(MERGED), backfill_items::error.eq(None::<String>)))
+        .execute(conn)?;
+    Ok(())
+}
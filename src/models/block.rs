@@ -0,0 +1,23 @@
+use diesel::prelude::*;
+
+/// Struct to represent a block header stored for time-based queries.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::blocks)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[allow(dead_code)]
+pub struct Block {
+    pub number: i32,              // Block number
+    pub hash: Vec<u8>,            // 32-byte block hash
+    pub parent_hash: Vec<u8>,     // 32-byte parent block hash
+    pub timestamp: i64,           // Unix timestamp (seconds)
+    pub chain_id: i64,            // Chain the block belongs to
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::blocks)]
+pub struct NewBlock<'a> {
+    pub number: i32,              // Block number
+    pub hash: &'a [u8],           // 32-byte block hash
+    pub parent_hash: &'a [u8],    // 32-byte parent block hash
+    pub timestamp: i64,           // Unix timestamp (seconds)
+}
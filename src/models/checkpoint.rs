@@ -0,0 +1,12 @@
+use diesel::prelude::*;
+
+/// Struct to represent the last block a stream of a chain is indexed up to.
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::schema::checkpoints)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct Checkpoint {
+    pub chain_id: i64,            // Chain the stream is indexed from
+    pub handler: String,          // Handler name, or "*" for the block header processors
+    pub processor: String,        // "events", or the processor applied to the handler's logs
+    pub block_number: i64,        // Last indexed block
+}
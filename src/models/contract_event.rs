@@ -0,0 +1,34 @@
+use diesel::prelude::*;
+
+/// Struct to represent an event of a contract configured with an ABI, decoded generically.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::contract_events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ContractEvent {
+    pub id: i32,                          // Unique ID for the event
+    pub contract_address: Vec<u8>,        // 20-byte address of the emitting contract
+    pub contract_name: String,            // Name given to the contract in the contracts file
+    pub event_name: String,               // e.g. "Deposit"
+    pub event_signature: String,          // e.g. "Deposit(address,uint256)"
+    pub params: serde_json::Value,        // Decoded parameters by name
+    pub block_number: i32,
+    pub transaction_hash: Vec<u8>,        // 32-byte transaction hash
+    pub transaction_index: i32,
+    pub log_index: i32,                   // Position of the log in its block
+    pub chain_id: i64,                    // Chain the contract is deployed on
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::contract_events)]
+pub struct NewContractEvent<'a> {
+    pub contract_address: &'a [u8],
+    pub contract_name: &'a str,
+    pub event_name: &'a str,
+    pub event_signature: &'a str,
+    pub params: &'a serde_json::Value,
+    pub block_number: i32,
+    pub transaction_hash: &'a [u8],
+    pub transaction_index: i32,
+    pub log_index: i32,
+    pub chain_id: i64,
+}
@@ -3,6 +3,11 @@ pub mod token_id;
 pub mod token_supply;
 pub mod balance;
 pub mod allowance;
+pub mod block;
+pub mod backfill;
+pub mod checkpoint;
+pub mod contract_event;
+pub mod webhook;
 
 // Re-export models so they can be used with `use models::*;`
 pub use token::*;
@@ -2,7 +2,8 @@ use diesel::prelude::*;
 
 /// Struct to represent a token in the database, including metadata for ERC20 and ERC721.
 #[derive(Queryable)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[allow(dead_code)]
 pub struct Token {
     pub token_address: Vec<u8>,            // 20-byte token address
     pub block_number: i32,                 // Integer for block number
@@ -16,12 +17,13 @@ pub struct Token {
     // ERC777-specific metadata
     pub granularity: Option<String>,             // Granularity (optional for ERC777)
 
+    pub chain_id: i64,                     // Chain the token is deployed on
 }
 
 /// Struct to represent new token data to be inserted into the database.
 #[derive(Insertable)]
 #[diesel(table_name = crate::schema::tokens)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
 pub struct NewToken<'a> {
     pub token_address: &'a [u8],           // Token address as bytes
     pub block_number: i32,                 // Block number
@@ -1,12 +1,15 @@
 use diesel::prelude::*;
 
-#[derive(Debug, Queryable, Selectable)]
+#[derive(Debug, Queryable, QueryableByName, Selectable, Clone)]
 #[diesel(table_name = crate::schema::token_ids)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[allow(dead_code)]
 pub struct TokenID {
     pub id: i32,
     pub contract_address: Vec<u8>,
     pub token_id: i16,
     pub token_uri: Option<String>,
+    pub chain_id: i64,
 }
 
 #[derive(Insertable)]
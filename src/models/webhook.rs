@@ -0,0 +1,173 @@
+use diesel::prelude::*;
+use crate::PgPooledConnection;
+
+/// A registered webhook endpoint and the filter selecting which changes it receives.
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::schema::webhooks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Webhook {
+    pub id: i32,                            // Unique ID for the webhook
+    pub url: String,                        // Endpoint receiving POSTed JSON payloads
+    pub secret: String,                     // HMAC-SHA256 key used to sign payloads
+    pub wallet_address: Option<Vec<u8>>,    // Only changes involving this wallet, NULL for any
+    pub token_address: Option<Vec<u8>>,     // Only changes of this token, NULL for any
+    pub event_kind: Option<String>,         // "transfer", "approval" or "supply", NULL for all
+    pub min_amount: Option<String>,         // Minimum amount as a decimal string, NULL for no minimum
+    pub active: bool,                       // Inactive webhooks are kept but not delivered to
+    pub chain_id: Option<i64>,              // Only changes on this chain, NULL for any
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::webhooks)]
+pub struct NewWebhook<'a> {
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub wallet_address: Option<&'a [u8]>,
+    pub token_address: Option<&'a [u8]>,
+    pub event_kind: Option<&'a str>,
+    pub min_amount: Option<&'a str>,
+    pub chain_id: Option<i64>,
+}
+
+/// A payload sent (or being sent) to a webhook, with the outcome of its last attempt.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookDelivery {
+    pub id: i32,                            // Unique ID for the delivery, sent as a header
+    pub webhook_id: i32,                    // Webhook the payload was sent to
+    pub event_kind: String,                 // "transfer", "approval" or "supply"
+    pub block_number: i32,                  // Block of the change that triggered the delivery
+    pub status: String,                     // "pending", "delivered" or "failed"
+    pub attempts: i32,                      // Attempts made so far
+    pub response_status: Option<i32>,       // HTTP status of the last attempt, if any
+    pub error: Option<String>,              // Error of the last failed attempt
+}
+
+/// A pending delivery claimed to be resumed, with the webhook it goes to.
+pub struct PendingDelivery {
+    pub id: i32,
+    pub event_kind: String,
+    pub payload: serde_json::Value,         // Body as first sent
+    pub attempts: i32,                      // Attempts made before it was left pending
+    pub webhook: Webhook,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+pub struct NewWebhookDelivery<'a> {
+    pub webhook_id: i32,
+    pub event_kind: &'a str,
+    pub block_number: i32,
+    pub payload: &'a serde_json::Value,     // Body as sent
+    pub status: &'a str,
+}
+
+pub fn insert_webhook(conn: &mut PgPooledConnection, new_webhook: &NewWebhook) -> QueryResult<i32> {
+    use crate::schema::webhooks::dsl::*;
+
+    diesel::insert_into(webhooks)
+        .values(new_webhook)
+        .returning(id)
+        .get_result(conn)
+}
+
+pub fn load_webhooks(conn: &mut PgPooledConnection, only_active: bool) -> QueryResult<Vec<Webhook>> {
+    use crate::schema::webhooks::dsl::*;
+
+    let mut query = webhooks.order_by(id.asc()).into_boxed();
+    if only_active {
+        query = query.filter(active.eq(true));
+    }
+    query.select(Webhook::as_select()).load(conn)
+}
+
+pub fn delete_webhook(conn: &mut PgPooledConnection, webhook_id_value: i32) -> QueryResult<usize> {
+    use crate::schema::webhooks::dsl::*;
+
+    // Deliveries are removed with it (ON DELETE CASCADE)
+    diesel::delete(webhooks.filter(id.eq(webhook_id_value))).execute(conn)
+}
+
+pub fn insert_delivery(conn: &mut PgPooledConnection, new_delivery: &NewWebhookDelivery) -> QueryResult<i32> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    diesel::insert_into(webhook_deliveries)
+        .values(new_delivery)
+        .returning(id)
+        .get_result(conn)
+}
+
+/// Records the outcome of an attempt; `finished` marks the delivery as completed (delivered or given up on).
+pub fn record_delivery_attempt(
+    conn: &mut PgPooledConnection,
+    delivery_id: i32,
+    status_value: &str,
+    attempts_value: i32,
+    response_status_value: Option<i32>,
+    error_value: Option<&str>,
+    finished: bool,
+) -> QueryResult<usize> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    let target = webhook_deliveries.filter(id.eq(delivery_id));
+    let changes = (
+        status.eq(status_value),
+        attempts.eq(attempts_value),
+        response_status.eq(response_status_value),
+        error.eq(error_value),
+        attempted_at.eq(diesel::dsl::now),
+    );
+    if finished {
+        diesel::update(target).set((changes, completed_at.eq(diesel::dsl::now.nullable()))).execute(conn)
+    } else {
+        diesel::update(target).set(changes).execute(conn)
+    }
+}
+
+/// Loads the most recent deliveries, optionally of a single webhook, newest first.
+pub fn load_recent_deliveries(conn: &mut PgPooledConnection, webhook_id_value: Option<i32>, limit: i64) -> QueryResult<Vec<WebhookDelivery>> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    let mut query = webhook_deliveries.order_by(id.desc()).limit(limit).into_boxed();
+    if let Some(webhook_id_value) = webhook_id_value {
+        query = query.filter(webhook_id.eq(webhook_id_value));
+    }
+    query.select(WebhookDelivery::as_select()).load(conn)
+}
+
+/// Claims the pending deliveries to active webhooks not attempted for `stale_after_secs`: those left
+/// behind by a scraper that stopped, rather than those another one is retrying. Claiming marks them
+/// attempted now, so two scrapers starting together do not both resume a delivery.
+pub fn claim_stale_deliveries(conn: &mut PgPooledConnection, stale_after_secs: i32) -> QueryResult<Vec<PendingDelivery>> {
+    use crate::schema::{webhook_deliveries, webhooks};
+    use diesel::dsl::{now, IntervalDsl};
+
+    let active_webhooks = webhooks::table.filter(webhooks::active.eq(true)).select(webhooks::id);
+    let claimed: Vec<i32> = diesel::update(
+        webhook_deliveries::table
+            .filter(webhook_deliveries::status.eq("pending"))
+            .filter(webhook_deliveries::attempted_at.lt(now - stale_after_secs.seconds()))
+            .filter(webhook_deliveries::webhook_id.eq_any(active_webhooks)),
+    )
+    .set(webhook_deliveries::attempted_at.eq(now))
+    .returning(webhook_deliveries::id)
+    .get_results(conn)?;
+
+    let rows: Vec<(i32, String, serde_json::Value, i32, Webhook)> = webhook_deliveries::table
+        .inner_join(webhooks::table)
+        .filter(webhook_deliveries::id.eq_any(claimed))
+        .order_by(webhook_deliveries::id.asc())
+        .select((
+            webhook_deliveries::id,
+            webhook_deliveries::event_kind,
+            webhook_deliveries::payload,
+            webhook_deliveries::attempts,
+            Webhook::as_select(),
+        ))
+        .load(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, event_kind, payload, attempts, webhook)| PendingDelivery { id, event_kind, payload, attempts, webhook })
+        .collect())
+}
@@ -1,32 +1,62 @@
 use std::sync::Arc;
 
 use ethers::providers::{Http, Provider};
-use ethers::types::{Log, H160};
-use crate::utils::determine_token_type;
-use crate::handlers::{handle_erc20_event, handle_erc721_event, handle_erc1155_event, handle_erc777_event};
-use crate::{Cli, PgPooledConnection};
+use ethers::types::Log;
+use log::warn;
+use crate::checkpoint::Pass;
+use crate::events::IndexedEvent;
+use crate::storage::Storage;
+use crate::handlers::{HandlerContext, HandlerRegistry};
+use crate::indexer::Processors;
 
-/// Function to parse ERC20, ERC721, ERC1155, and ERC777 log
-/// This function checks the token type and dispatches the appropriate handler.
+/// Function to parse a log with the registered handlers
+/// This function finds the handler that recognizes the emitting contract and dispatches the log to it,
+/// which appends the events decoded from the log to `events`.
 pub async fn parse_log(
-    log: &Log, 
-    conn: &mut PgPooledConnection, 
-    provider: Arc<Provider<Http>>, 
-    cli: &Arc<Cli>
+    log: &Log,
+    handlers: &HandlerRegistry,
+    storage: &dyn Storage,
+    provider: Arc<Provider<Http>>,
+    processors: &Processors,
+    events: &mut Vec<IndexedEvent>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let token_address: H160 = log.address;
+    // Determine the handler based on the event signature and the contract
+    let Some(handler) = handlers.classify(provider.clone(), log).await else {
+        warn!("No handler recognizes the contract at address: {:?}", log.address);
+        return Ok(());
+    };
 
-    // Determine the token type based on the address
-    let token_type = determine_token_type(provider.clone(), token_address).await;
+    let context = HandlerContext { storage, provider, processors };
+    handler.handle(log, &context, events).await
+}
 
-    // Dispatch the log to the appropriate handler based on token type
-    match token_type.as_str() {
-        "ERC20" => handle_erc20_event(log, conn, provider, cli).await?,
-        "ERC721" => handle_erc721_event(log, conn, provider, cli).await?,
-        "ERC1155" => handle_erc1155_event(log, conn, provider, cli).await?,
-        "ERC777" => handle_erc777_event(log, conn, provider, cli).await?,
-        _ => println!("Unknown token type at address: {:?}", token_address),
+/// Like `parse_log`, for a pass over only some of the streams: the log is left alone when the pass
+/// lacks every stream of the handler recognizing its contract, and only the changes of the pass's
+/// processors are kept when it lacks the handler's own events.
+pub async fn parse_log_in_pass(
+    log: &Log,
+    handlers: &HandlerRegistry,
+    pass: &Pass,
+    storage: &dyn Storage,
+    provider: Arc<Provider<Http>>,
+    events: &mut Vec<IndexedEvent>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Classified among every handler, so a handler left out of the pass still claims its contracts
+    let Some(handler) = handlers.classify(provider.clone(), log).await else {
+        warn!("No handler recognizes the contract at address: {:?}", log.address);
+        return Ok(());
+    };
+    if !pass.has_handler(handler.name()) {
+        return Ok(());
     }
 
+    let processors = pass.processors(handler.name());
+    let context = HandlerContext { storage, provider, processors: &processors };
+    let mut decoded = Vec::new();
+    handler.handle(log, &context, &mut decoded).await?;
+    if !pass.has_events(handler.name()) {
+        decoded.retain(IndexedEvent::is_processor_change);
+    }
+    events.extend(decoded);
     Ok(())
 }
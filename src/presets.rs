@@ -0,0 +1,107 @@
+//! Built-in defaults of the chains whose block times, `eth_getLogs` limits, finality or system
+//! contracts differ from Ethereum's. A profile's own settings override them.
+
+use crate::handlers::SystemHandler;
+use crate::indexer::Finality;
+
+/// Defaults applied to a chain selected by its id.
+#[derive(Clone, Debug)]
+pub struct ChainPreset {
+    pub chain_id: u64,
+    pub name: &'static str,
+    pub block_time: u64,                  // Seconds, the poll interval of `follow`
+    pub block_range: u64,                 // Blocks per `eth_getLogs` request
+    pub log_limit: usize,                 // Logs per `eth_getLogs` response the usual providers allow
+    pub finality: Finality,
+    pub system_handlers: &'static [SystemHandler],
+}
+
+static PRESETS: [ChainPreset; 9] = [
+    ChainPreset {
+        chain_id: 1,
+        name: "ethereum",
+        block_time: 12,
+        block_range: 10_000,
+        log_limit: 10_000,
+        finality: Finality::Confirmations(12),
+        system_handlers: &[],
+    },
+    ChainPreset {
+        chain_id: 11155111,
+        name: "sepolia",
+        block_time: 12,
+        block_range: 10_000,
+        log_limit: 10_000,
+        finality: Finality::Confirmations(12),
+        system_handlers: &[],
+    },
+    ChainPreset {
+        chain_id: 10,
+        name: "optimism",
+        block_time: 2,
+        block_range: 10_000,
+        log_limit: 10_000,
+        finality: Finality::Confirmations(10),
+        system_handlers: &[],
+    },
+    ChainPreset {
+        chain_id: 8453,
+        name: "base",
+        block_time: 2,
+        block_range: 10_000,
+        log_limit: 10_000,
+        finality: Finality::Confirmations(10),
+        system_handlers: &[],
+    },
+    ChainPreset {
+        chain_id: 42161,
+        name: "arbitrum",
+        block_time: 1,
+        block_range: 10_000,
+        log_limit: 10_000,
+        finality: Finality::Confirmations(20),
+        system_handlers: &[],
+    },
+    ChainPreset {
+        chain_id: 56,
+        name: "bsc",
+        block_time: 3,
+        block_range: 5_000,
+        log_limit: 10_000,
+        finality: Finality::Finalized,
+        system_handlers: &[],
+    },
+    ChainPreset {
+        chain_id: 137,
+        name: "polygon",
+        block_time: 2,
+        block_range: 2_000,
+        log_limit: 10_000,
+        finality: Finality::Finalized,
+        system_handlers: &[SystemHandler::PolygonLogTransfer],
+    },
+    ChainPreset {
+        chain_id: 80002,
+        name: "polygon-amoy",
+        block_time: 2,
+        block_range: 2_000,
+        log_limit: 10_000,
+        finality: Finality::Finalized,
+        system_handlers: &[SystemHandler::PolygonLogTransfer],
+    },
+    ChainPreset {
+        chain_id: 324,
+        name: "zksync",
+        block_time: 1,
+        block_range: 10_000,
+        log_limit: 10_000,
+        // Batches are only final once proven on L1, hours later; sealed blocks are not reorganized
+        finality: Finality::Latest,
+        system_handlers: &[SystemHandler::ZksyncBaseToken],
+    },
+];
+
+/// The preset of `chain_id`, if there is one.
+pub fn preset(chain_id: u64) -> Option<&'static ChainPreset> {
+    PRESETS.iter().find(|preset| preset.chain_id == chain_id)
+}
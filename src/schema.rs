@@ -10,6 +10,41 @@ diesel::table! {
         block_number -> Int4,
         token_id -> Nullable<Int2>,
         token_type -> Text,
+        chain_id -> Int8,
+    }
+}
+
+diesel::table! {
+    backfill_changes (item_id, attempt, seq) {
+        item_id -> Int4,
+        attempt -> Int4,
+        seq -> Int4,
+        #[max_length = 16]
+        kind -> Varchar,
+        token_address -> Bytea,
+        wallet_address -> Nullable<Bytea>,
+        spender_address -> Nullable<Bytea>,
+        token_id -> Nullable<Int2>,
+        #[max_length = 16]
+        token_type -> Nullable<Varchar>,
+        amount -> Text,
+        block_number -> Int4,
+    }
+}
+
+diesel::table! {
+    backfill_items (id) {
+        id -> Int4,
+        chain_id -> Int8,
+        from_block -> Int8,
+        to_block -> Int8,
+        #[max_length = 16]
+        status -> Varchar,
+        worker -> Nullable<Text>,
+        attempts -> Int4,
+        error -> Nullable<Text>,
+        claimed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
     }
 }
 
@@ -22,6 +57,42 @@ diesel::table! {
         token_id -> Nullable<Int2>,
         token_type -> Text,
         block_number -> Int4,
+        chain_id -> Int8,
+    }
+}
+
+diesel::table! {
+    blocks (chain_id, number) {
+        number -> Int4,
+        hash -> Bytea,
+        parent_hash -> Bytea,
+        timestamp -> Int8,
+        chain_id -> Int8,
+    }
+}
+
+diesel::table! {
+    checkpoints (chain_id, handler, processor) {
+        chain_id -> Int8,
+        handler -> Text,
+        processor -> Text,
+        block_number -> Int8,
+    }
+}
+
+diesel::table! {
+    contract_events (id) {
+        id -> Int4,
+        contract_address -> Bytea,
+        contract_name -> Text,
+        event_name -> Text,
+        event_signature -> Text,
+        params -> Jsonb,
+        block_number -> Int4,
+        transaction_hash -> Bytea,
+        transaction_index -> Int4,
+        log_index -> Int4,
+        chain_id -> Int8,
     }
 }
 
@@ -31,6 +102,7 @@ diesel::table! {
         contract_address -> Bytea,
         token_id -> Int2,
         token_uri -> Nullable<Text>,
+        chain_id -> Int8,
     }
 }
 
@@ -40,11 +112,12 @@ diesel::table! {
         token_address -> Bytea,
         total_supply -> Text,
         block_number -> Int4,
+        chain_id -> Int8,
     }
 }
 
 diesel::table! {
-    tokens (token_address) {
+    tokens (chain_id, token_address) {
         token_address -> Bytea,
         block_number -> Int4,
         token_type -> Varchar,
@@ -52,18 +125,59 @@ diesel::table! {
         symbol -> Nullable<Text>,
         decimals -> Nullable<Int2>,
         granularity -> Nullable<Text>,
+        chain_id -> Int8,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        #[max_length = 16]
+        event_kind -> Varchar,
+        block_number -> Int4,
+        payload -> Jsonb,
+        #[max_length = 16]
+        status -> Varchar,
+        attempts -> Int4,
+        response_status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        completed_at -> Nullable<Timestamp>,
+        attempted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Int4,
+        url -> Text,
+        secret -> Text,
+        wallet_address -> Nullable<Bytea>,
+        token_address -> Nullable<Bytea>,
+        #[max_length = 16]
+        event_kind -> Nullable<Varchar>,
+        min_amount -> Nullable<Text>,
+        active -> Bool,
+        created_at -> Timestamp,
+        chain_id -> Nullable<Int8>,
     }
 }
 
-diesel::joinable!(allowances -> tokens (token_address));
-diesel::joinable!(balances -> tokens (token_address));
-diesel::joinable!(token_ids -> tokens (contract_address));
-diesel::joinable!(token_supplies -> tokens (token_address));
+diesel::joinable!(backfill_changes -> backfill_items (item_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     allowances,
+    backfill_changes,
+    backfill_items,
     balances,
+    blocks,
+    checkpoints,
+    contract_events,
     token_ids,
     token_supplies,
     tokens,
+    webhook_deliveries,
+    webhooks,
 );
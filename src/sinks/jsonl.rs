@@ -0,0 +1,96 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::events::IndexedEvent;
+use crate::sinks::{EventSink, SinkResult};
+
+/// Writes one JSON object per event and line, to stdout or appended to a file.
+pub struct JsonlSink {
+    name: String,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonlSink {
+    pub fn stdout() -> Self {
+        JsonlSink {
+            name: "jsonl:stdout".to_string(),
+            writer: Mutex::new(Box::new(BufWriter::new(io::stdout()))),
+        }
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonlSink {
+            name: format!("jsonl:{}", path.display()),
+            writer: Mutex::new(Box::new(BufWriter::new(file))),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for JsonlSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&self, events: &[IndexedEvent]) -> SinkResult<()> {
+        let mut writer = self.writer.lock().map_err(|_| "JSONL writer poisoned")?;
+        for event in events {
+            serde_json::to_writer(&mut *writer, event)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> SinkResult<()> {
+        self.writer.lock().map_err(|_| "JSONL writer poisoned")?.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Address;
+
+    use super::*;
+    use crate::events::TotalSupplyChange;
+
+    fn supply_change(block_number: i32) -> IndexedEvent {
+        IndexedEvent::SupplyChange(TotalSupplyChange {
+            token_address: Address::from_low_u64_be(1),
+            delta: "5".to_string(),
+            block_number,
+            chain_id: 1,
+        })
+    }
+
+    fn read_lines(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn events_are_appended_one_per_line() {
+        let path = std::env::temp_dir().join(format!("jsonl-sink-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let sink = JsonlSink::open(&path).unwrap();
+        sink.write(&[supply_change(1), supply_change(2)]).await.unwrap();
+        sink.flush().await.unwrap();
+        drop(sink);
+        // Reopening appends rather than truncating
+        let sink = JsonlSink::open(&path).unwrap();
+        sink.write(&[supply_change(3)]).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let lines = read_lines(&path);
+        std::fs::remove_file(&path).unwrap();
+        let blocks: Vec<i64> = lines.iter().map(|line| line["block_number"].as_i64().unwrap()).collect();
+        assert_eq!(blocks, vec![1, 2, 3]);
+        assert!(lines.iter().all(|line| line["kind"] == "supply_change"));
+        assert_eq!(lines[0]["token_address"], "0x0000000000000000000000000000000000000001");
+    }
+}
@@ -0,0 +1,154 @@
+pub mod storage;
+pub mod jsonl;
+pub mod nats;
+pub mod staging;
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::events::IndexedEvent;
+use crate::storage::Storage;
+
+pub type SinkResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Subject prefix used when a NATS sink is given without one
+const DEFAULT_NATS_SUBJECT: &str = "histori.events";
+
+/// A destination for the events decoded by the handlers.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Name used in log messages.
+    fn name(&self) -> &str;
+
+    /// Writes the events decoded from a single log, in order.
+    async fn write(&self, events: &[IndexedEvent]) -> SinkResult<()>;
+
+    /// Makes everything written so far durable; called before a block range is checkpointed.
+    async fn flush(&self) -> SinkResult<()> {
+        Ok(())
+    }
+}
+
+/// A sink selected on the command line with `--sink`, or in the `sinks` of a config profile.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SinkSpec {
+    Storage,                                    // `db` (or `postgres`): historical tables of the DATABASE_URL storage (the default)
+    Jsonl(Option<PathBuf>),                     // `jsonl` for stdout, or `jsonl:<path>` to append to a file
+    Nats { addr: String, subject: String },     // `nats://host:port[/subject]`
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "db" || value == "postgres" {
+            return Ok(SinkSpec::Storage);
+        }
+        if value == "jsonl" || value == "jsonl:-" {
+            return Ok(SinkSpec::Jsonl(None));
+        }
+        if let Some(path) = value.strip_prefix("jsonl:") {
+            return Ok(SinkSpec::Jsonl(Some(PathBuf::from(path))));
+        }
+        if let Some(rest) = value.strip_prefix("nats://") {
+            let (addr, subject) = match rest.split_once('/') {
+                Some((addr, subject)) if !subject.is_empty() => (addr, subject),
+                Some((addr, _)) => (addr, DEFAULT_NATS_SUBJECT),
+                None => (rest, DEFAULT_NATS_SUBJECT),
+            };
+            if addr.is_empty() {
+                return Err(format!("Missing host in sink {}", value));
+            }
+            let addr = if addr.contains(':') { addr.to_string() } else { format!("{}:4222", addr) };
+            return Ok(SinkSpec::Nats { addr, subject: subject.to_string() });
+        }
+        Err(format!("Unknown sink {}: expected db, jsonl, jsonl:<path> or nats://host:port[/subject]", value))
+    }
+}
+
+impl std::fmt::Display for SinkSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SinkSpec::Storage => write!(f, "db"),
+            SinkSpec::Jsonl(None) => write!(f, "jsonl"),
+            SinkSpec::Jsonl(Some(path)) => write!(f, "jsonl:{}", path.display()),
+            SinkSpec::Nats { addr, subject } => write!(f, "nats://{}/{}", addr, subject),
+        }
+    }
+}
+
+impl TryFrom<String> for SinkSpec {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Every configured sink; events are written to each in turn.
+#[derive(Clone)]
+pub struct EventSinks {
+    sinks: Vec<Arc<dyn EventSink>>,       // Shared with the sinks of other chains, except the storage sink
+}
+
+impl EventSinks {
+    pub fn new(sinks: Vec<Box<dyn EventSink>>) -> Self {
+        EventSinks { sinks: sinks.into_iter().map(Arc::from).collect() }
+    }
+
+    pub fn push(&mut self, sink: Box<dyn EventSink>) {
+        self.sinks.push(Arc::from(sink));
+    }
+
+    /// Opens the sinks selected by `specs`, failing fast if a file or broker is unreachable.
+    pub async fn connect(specs: &[SinkSpec], storage: &Arc<dyn Storage>) -> SinkResult<Self> {
+        let mut sinks = Self::connect_chains(specs, std::slice::from_ref(storage)).await?;
+        Ok(sinks.remove(0))
+    }
+
+    /// Opens the sinks selected by `specs` once for every chain of `storages`, returning the sinks of
+    /// each chain in the same order. Files and brokers are shared by the chains, while each chain's
+    /// events are applied to its own storage.
+    pub async fn connect_chains(specs: &[SinkSpec], storages: &[Arc<dyn Storage>]) -> SinkResult<Vec<Self>> {
+        let mut chains: Vec<EventSinks> = storages.iter().map(|_| EventSinks { sinks: Vec::new() }).collect();
+        for spec in specs {
+            let shared: Arc<dyn EventSink> = match spec {
+                SinkSpec::Storage => {
+                    for (sinks, storage) in chains.iter_mut().zip(storages) {
+                        sinks.sinks.push(Arc::new(storage::StorageSink::new(storage.clone())));
+                    }
+                    continue;
+                }
+                SinkSpec::Jsonl(None) => Arc::new(jsonl::JsonlSink::stdout()),
+                SinkSpec::Jsonl(Some(path)) => Arc::new(jsonl::JsonlSink::open(path)?),
+                SinkSpec::Nats { addr, subject } => Arc::new(nats::NatsSink::connect(addr, subject).await?),
+            };
+            chains.iter_mut().for_each(|sinks| sinks.sinks.push(shared.clone()));
+        }
+        Ok(chains)
+    }
+
+    /// Writes the events of a log to every sink in turn, failing with the first sink that fails, so
+    /// the range is not checkpointed.
+    pub async fn write(&self, events: &[IndexedEvent]) -> SinkResult<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        for sink in &self.sinks {
+            sink.write(events).await.map_err(|e| format!("Error writing {} events to the {} sink: {}", events.len(), sink.name(), e))?;
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&self) -> SinkResult<()> {
+        for sink in &self.sinks {
+            sink.flush().await.map_err(|e| format!("Error flushing the {} sink: {}", sink.name(), e))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::{error, warn};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::events::IndexedEvent;
+use crate::sinks::{EventSink, SinkResult};
+
+// Sent after the server's INFO greeting; no acknowledgements, so publishing never waits on the server
+const CONNECT_COMMAND: &[u8] = b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"histori_evm_scraper\",\"lang\":\"rust\"}\r\n";
+
+type SharedWriter = Arc<Mutex<BufWriter<OwnedWriteHalf>>>;
+
+/// Publishes each event as JSON to `<subject>.<kind>` over the NATS client protocol
+/// (e.g. `histori.events.transfer`), reconnecting once per batch if the connection dropped.
+/// Delivery is at-most-once, like any core NATS publish.
+pub struct NatsSink {
+    name: String,
+    addr: String,
+    subject: String,
+    connection: Mutex<Option<NatsConnection>>,
+}
+
+struct NatsConnection {
+    writer: SharedWriter,
+    reader: JoinHandle<()>,               // Answers server PINGs so the server keeps the connection open
+}
+
+impl Drop for NatsConnection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl NatsSink {
+    pub async fn connect(addr: &str, subject: &str) -> io::Result<Self> {
+        let connection = NatsConnection::open(addr).await?;
+        Ok(NatsSink {
+            name: format!("nats://{}/{}", addr, subject),
+            addr: addr.to_string(),
+            subject: subject.to_string(),
+            connection: Mutex::new(Some(connection)),
+        })
+    }
+
+    async fn send(&self, frames: &[u8]) -> io::Result<()> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            *connection = Some(NatsConnection::open(&self.addr).await?);
+        }
+        let writer = connection.as_ref().map(|connection| connection.writer.clone()).expect("connection was just opened");
+
+        let mut writer = writer.lock().await;
+        let result = async {
+            writer.write_all(frames).await?;
+            writer.flush().await
+        }
+        .await;
+        if result.is_err() {
+            // Drop the broken connection so the next send reconnects
+            *connection = None;
+        }
+        result
+    }
+}
+
+impl NatsConnection {
+    async fn open(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let (read_half, write_half) = stream.into_split();
+        let mut reader = BufReader::new(read_half);
+
+        let mut greeting = String::new();
+        reader.read_line(&mut greeting).await?;
+        if !greeting.starts_with("INFO") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected NATS greeting: {}", greeting.trim())));
+        }
+
+        let writer: SharedWriter = Arc::new(Mutex::new(BufWriter::new(write_half)));
+        {
+            let mut writer = writer.lock().await;
+            writer.write_all(CONNECT_COMMAND).await?;
+            writer.flush().await?;
+        }
+
+        let pong_writer = writer.clone();
+        let reader = tokio::spawn(async move {
+            let mut line = String::new();
+            loop {
+                line.clear();
+                match reader.read_line(&mut line).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                if line.starts_with("PING") {
+                    let mut writer = pong_writer.lock().await;
+                    if writer.write_all(b"PONG\r\n").await.is_err() || writer.flush().await.is_err() {
+                        break;
+                    }
+                } else if line.starts_with("-ERR") {
+                    error!("NATS server error: {}", line.trim());
+                }
+            }
+        });
+
+        Ok(NatsConnection { writer, reader })
+    }
+}
+
+#[async_trait]
+impl EventSink for NatsSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&self, events: &[IndexedEvent]) -> SinkResult<()> {
+        let mut frames = Vec::new();
+        for event in events {
+            let payload = serde_json::to_vec(event)?;
+            frames.extend_from_slice(format!("PUB {}.{} {}\r\n", self.subject, event.kind(), payload.len()).as_bytes());
+            frames.extend_from_slice(&payload);
+            frames.extend_from_slice(b"\r\n");
+        }
+
+        if let Err(e) = self.send(&frames).await {
+            warn!("Publishing to {} failed ({}); reconnecting", self.name, e);
+            self.send(&frames).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ethers::types::Address;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::events::{TotalSupplyChange, Transfer};
+
+    fn supply_change(block_number: i32) -> IndexedEvent {
+        IndexedEvent::SupplyChange(TotalSupplyChange {
+            token_address: Address::from_low_u64_be(1),
+            delta: "-5".to_string(),
+            block_number,
+            chain_id: 1,
+        })
+    }
+
+    fn transfer(block_number: i32) -> IndexedEvent {
+        IndexedEvent::Transfer(Transfer {
+            token_address: Address::from_low_u64_be(1),
+            from: Address::from_low_u64_be(2),
+            to: Address::from_low_u64_be(3),
+            token_id: None,
+            value: "5".to_string(),
+            token_type: "ERC20",
+            block_number,
+            transaction_hash: None,
+            log_index: Some(0),
+            chain_id: 1,
+        })
+    }
+
+    // Plays the server side of a connection: greets the client and returns the connection and the
+    // CONNECT line the client answered with
+    async fn accept(listener: &TcpListener) -> (BufReader<TcpStream>, String) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.write_all(b"INFO {\"server_id\":\"fake\"}\r\n").await.unwrap();
+        let mut connection = BufReader::new(stream);
+        let mut connect = String::new();
+        connection.read_line(&mut connect).await.unwrap();
+        (connection, connect)
+    }
+
+    // Reads a PUB frame, checking its declared size, and returns its subject and JSON payload
+    async fn read_pub(connection: &mut BufReader<TcpStream>) -> (String, serde_json::Value) {
+        let mut line = String::new();
+        connection.read_line(&mut line).await.unwrap();
+        let parts: Vec<&str> = line.trim_end().split(' ').collect();
+        assert_eq!(parts.len(), 3, "not a PUB frame: {:?}", line);
+        assert_eq!(parts[0], "PUB");
+        let size: usize = parts[2].parse().unwrap();
+        let mut payload = vec![0; size + 2];
+        connection.read_exact(&mut payload).await.unwrap();
+        assert_eq!(&payload[size..], b"\r\n");
+        (parts[1].to_string(), serde_json::from_slice(&payload[..size]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn events_are_published_to_the_subject_of_their_kind() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (sink, (mut connection, connect)) = tokio::join!(NatsSink::connect(&addr, "histori.events"), accept(&listener));
+        let sink = sink.unwrap();
+        assert_eq!(connect.as_bytes(), CONNECT_COMMAND);
+        assert_eq!(sink.name(), format!("nats://{}/histori.events", addr));
+
+        sink.write(&[transfer(7), supply_change(8)]).await.unwrap();
+
+        let (subject, payload) = read_pub(&mut connection).await;
+        assert_eq!(subject, "histori.events.transfer");
+        assert_eq!((payload["kind"].as_str(), payload["block_number"].as_i64()), (Some("transfer"), Some(7)));
+        let (subject, payload) = read_pub(&mut connection).await;
+        assert_eq!(subject, "histori.events.supply_change");
+        assert_eq!((payload["delta"].as_str(), payload["block_number"].as_i64()), (Some("-5"), Some(8)));
+    }
+
+    #[tokio::test]
+    async fn batches_are_republished_on_a_new_connection_when_the_server_drops_it() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (sink, (connection, _)) = tokio::join!(NatsSink::connect(&addr, "histori.events"), accept(&listener));
+        let sink = sink.unwrap();
+        drop(connection);
+
+        // The first batch may still be taken by the dead socket, and is then lost (at-most-once); once the
+        // reset came back, writing fails and the batch goes out on a new connection
+        let publish = async {
+            let _ = sink.write(&[supply_change(1)]).await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sink.write(&[supply_change(2)]).await
+        };
+        let (result, (mut connection, connect)) = tokio::join!(publish, accept(&listener));
+        result.unwrap();
+        assert_eq!(connect.as_bytes(), CONNECT_COMMAND);
+
+        let (_, mut payload) = read_pub(&mut connection).await;
+        if payload["block_number"] == 1 {
+            payload = read_pub(&mut connection).await.1;
+        }
+        assert_eq!(payload["block_number"], 2);
+    }
+
+    #[tokio::test]
+    async fn a_failed_write_is_returned_when_the_server_cannot_be_reached_again() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (sink, (connection, _)) = tokio::join!(NatsSink::connect(&addr, "histori.events"), accept(&listener));
+        let sink = sink.unwrap();
+        drop(connection);
+        drop(listener);
+
+        let _ = sink.write(&[supply_change(1)]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(sink.write(&[supply_change(2)]).await.is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::db::DbPool;
+use crate::events::IndexedEvent;
+use crate::models::backfill::{insert_changes, BackfillItem, NewStagedChange};
+use crate::sinks::storage::StorageSink;
+use crate::sinks::{EventSink, SinkResult};
+use crate::storage::Storage;
+
+/// Stages the balance, allowance and supply changes of a backfill item in `backfill_changes`, in
+/// the order they are written, for the merge to apply once the items before it are. The other
+/// events do not depend on earlier blocks and go to the storage right away.
+pub struct StagingSink {
+    pool: DbPool,
+    item_id: i32,
+    attempt: i32,
+    next_seq: AtomicI32,
+    events: StorageSink,
+}
+
+impl StagingSink {
+    pub fn new(pool: DbPool, item: &BackfillItem, storage: Arc<dyn Storage>) -> Self {
+        StagingSink {
+            pool,
+            item_id: item.id,
+            attempt: item.attempts,
+            next_seq: AtomicI32::new(0),
+            events: StorageSink::new(storage),
+        }
+    }
+
+    fn stage(&self, changes: &[&IndexedEvent]) -> SinkResult<()> {
+        let first_seq = self.next_seq.fetch_add(changes.len() as i32, Ordering::SeqCst);
+        let rows: Vec<NewStagedChange> = changes
+            .iter()
+            .zip(first_seq..)
+            .filter_map(|(event, seq)| {
+                let row = NewStagedChange {
+                    item_id: self.item_id,
+                    attempt: self.attempt,
+                    seq,
+                    kind: "balance",
+                    token_address: &[],
+                    wallet_address: None,
+                    spender_address: None,
+                    token_id: None,
+                    token_type: None,
+                    amount: "",
+                    block_number: 0,
+                };
+                match event {
+                    IndexedEvent::BalanceChange(change) => Some(NewStagedChange {
+                        token_address: change.token_address.as_bytes(),
+                        wallet_address: Some(change.wallet_address.as_bytes()),
+                        token_id: change.token_id,
+                        token_type: Some(change.token_type),
+                        amount: &change.delta,
+                        block_number: change.block_number,
+                        ..row
+                    }),
+                    IndexedEvent::AllowanceChange(change) => Some(NewStagedChange {
+                        kind: "allowance",
+                        token_address: change.token_address.as_bytes(),
+                        wallet_address: Some(change.owner_address.as_bytes()),
+                        spender_address: Some(change.spender_address.as_bytes()),
+                        token_id: change.token_id,
+                        token_type: Some(change.token_type),
+                        amount: &change.allowance,
+                        block_number: change.block_number,
+                        ..row
+                    }),
+                    IndexedEvent::SupplyChange(change) => Some(NewStagedChange {
+                        kind: "supply",
+                        token_address: change.token_address.as_bytes(),
+                        amount: &change.delta,
+                        block_number: change.block_number,
+                        ..row
+                    }),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let conn = &mut self.pool.get()?;
+        insert_changes(conn, &rows)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for StagingSink {
+    fn name(&self) -> &str {
+        "backfill staging"
+    }
+
+    async fn write(&self, events: &[IndexedEvent]) -> SinkResult<()> {
+        let (changes, others): (Vec<&IndexedEvent>, Vec<&IndexedEvent>) = events.iter().partition(|event| event.is_processor_change());
+        if !changes.is_empty() {
+            self.stage(&changes)?;
+        }
+        if !others.is_empty() {
+            let others: Vec<IndexedEvent> = others.into_iter().cloned().collect();
+            self.events.write(&others).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::events::{publish, ChangeEvent, IndexedEvent, SupplyChange};
+use crate::sinks::{EventSink, SinkResult};
+use crate::storage::Storage;
+
+/// Applies events to the historical `balances`, `allowances` and `token_supplies` tables of the
+/// configured storage, publishing every written row to in-process subscribers, and stores events
+/// decoded with a contract ABI in `contract_events`.
+pub struct StorageSink {
+    storage: Arc<dyn Storage>,
+}
+
+impl StorageSink {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        StorageSink { storage }
+    }
+}
+
+#[async_trait]
+impl EventSink for StorageSink {
+    fn name(&self) -> &str {
+        self.storage.name()
+    }
+
+    async fn write(&self, events: &[IndexedEvent]) -> SinkResult<()> {
+        for event in events {
+            let change = match event {
+                // Transfers have no table; in-process subscribers (e.g. webhooks) still receive them
+                IndexedEvent::Transfer(transfer) => ChangeEvent::Transfer(transfer.clone()),
+                IndexedEvent::BalanceChange(change) => ChangeEvent::Balance(self.storage.apply_balance_change(change)?),
+                IndexedEvent::AllowanceChange(change) => ChangeEvent::Allowance(self.storage.apply_allowance_change(change)?),
+                IndexedEvent::SupplyChange(change) => ChangeEvent::Supply(SupplyChange {
+                    supply: self.storage.apply_supply_change(change)?,
+                    delta: change.delta.clone(),
+                }),
+                // Subscribers only follow token changes
+                IndexedEvent::ContractEvent(event) => {
+                    self.storage.insert_contract_event(event)?;
+                    continue;
+                }
+            };
+
+            // Notify in-process subscribers (e.g. the live subscription endpoint and webhook delivery)
+            publish(change);
+        }
+
+        Ok(())
+    }
+}
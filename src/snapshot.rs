@@ -0,0 +1,231 @@
+//! Starting balances and total supplies of ERC20 tokens at a block, for tokens whose history is not
+//! indexed from their deployment: indexing that starts after block 0, or holdings minted without a
+//! `Transfer` log. A snapshot is read from a CSV or JSON file, or from `balanceOf` and `totalSupply`
+//! at its block for the holders a file lists.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, U256};
+use log::info;
+use serde::Deserialize;
+
+use crate::constants::create_erc20_contract;
+use crate::events::{BalanceChange, TotalSupplyChange};
+use crate::storage::Storage;
+use crate::token_service::check_and_insert_token;
+use crate::TokenType;
+
+pub type SnapshotResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// An entry of a snapshot: the balance of a wallet, or without one the total supply of the token.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SnapshotEntry {
+    pub token_address: Address,
+    #[serde(default)]
+    pub wallet_address: Option<Address>,  // None for the total supply
+    #[serde(default)]
+    pub amount: Option<String>,           // Decimal; read on chain when seeding
+}
+
+/// What an import stored.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub tokens: usize,
+    pub balances: usize,
+    pub supplies: usize,
+}
+
+/// The entries of a `.csv` file with a `token_address,wallet_address,amount` header, or of a
+/// `.json` file holding an array of objects with those keys.
+pub fn read_snapshot(path: &Path) -> SnapshotResult<Vec<SnapshotEntry>> {
+    let entries = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?
+            .deserialize()
+            .collect::<Result<Vec<SnapshotEntry>, _>>()
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?,
+        Some("json") => serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?,
+        _ => return Err(format!("{} is neither a .csv nor a .json file", path.display()).into()),
+    };
+    Ok(entries)
+}
+
+/// The balances of the wallets `entries` list and the total supply of their tokens, as returned by
+/// `balanceOf` and `totalSupply` called at `block`; the amounts of `entries` are left out. The node
+/// must serve the state of `block` (an archive node, unless it is recent).
+pub async fn read_on_chain(entries: &[SnapshotEntry], provider: Arc<Provider<Http>>, block: u64) -> SnapshotResult<Vec<SnapshotEntry>> {
+    let tokens: BTreeSet<Address> = entries.iter().map(|entry| entry.token_address).collect();
+    let mut seeded = Vec::with_capacity(entries.len() + tokens.len());
+    for token in tokens {
+        let wallets: BTreeSet<Address> = entries.iter().filter(|entry| entry.token_address == token).filter_map(|entry| entry.wallet_address).collect();
+        info!("Reading the total supply of {:?} and {} balances at block {}", token, wallets.len(), block);
+        let contract = create_erc20_contract(token.as_bytes(), provider.clone())?;
+
+        let supply = contract.total_supply().block(block).call().await?;
+        seeded.push(SnapshotEntry { token_address: token, wallet_address: None, amount: Some(supply.to_string()) });
+        for wallet in wallets {
+            let balance = contract.balance_of(wallet).block(block).call().await?;
+            seeded.push(SnapshotEntry { token_address: token, wallet_address: Some(wallet), amount: Some(balance.to_string()) });
+        }
+    }
+    Ok(seeded)
+}
+
+/// Stores `entries` as the balances and total supplies at `block` of ERC20 tokens that have none
+/// yet, storing the tokens first. Nothing is stored when an entry is invalid or listed twice, or a
+/// token already has balances or supplies.
+pub async fn import_snapshot(storage: &dyn Storage, provider: Arc<Provider<Http>>, entries: &[SnapshotEntry], block: u64) -> SnapshotResult<ImportReport> {
+    let block_number = i32::try_from(block).map_err(|_| format!("Block {} is out of range", block))?;
+    let chain_id = storage.chain_id() as u64;
+
+    let mut balances = Vec::new();
+    let mut supplies = Vec::new();
+    let mut listed = BTreeSet::new();
+    for (number, entry) in entries.iter().enumerate() {
+        let Some(amount) = &entry.amount else {
+            return Err(format!("Entry {} has no amount", number + 1).into());
+        };
+        let delta = U256::from_dec_str(amount).map_err(|e| format!("Entry {}: `{}` is not a decimal amount ({})", number + 1, amount, e))?.to_string();
+        if !listed.insert((entry.token_address, entry.wallet_address)) {
+            return Err(format!("Entry {}: listed twice", number + 1).into());
+        }
+        match entry.wallet_address {
+            Some(wallet_address) => balances.push(BalanceChange {
+                wallet_address,
+                token_address: entry.token_address,
+                delta,
+                token_id: None,
+                token_type: TokenType::ERC20.as_str(),
+                block_number,
+                chain_id,
+            }),
+            None => supplies.push(TotalSupplyChange { token_address: entry.token_address, delta, block_number, chain_id }),
+        }
+    }
+
+    // Balances and supplies reference their token
+    let tokens: BTreeSet<Address> = listed.iter().map(|(token, _)| *token).collect();
+    for token in &tokens {
+        let stored = check_and_insert_token(storage, provider.clone(), token.as_bytes(), block_number, TokenType::ERC20).await?;
+        if stored.token_type != TokenType::ERC20.as_str() {
+            return Err(format!("Token {:?} is stored as {}, not ERC20", token, stored.token_type).into());
+        }
+    }
+    storage.import_snapshot(&balances, &supplies)?;
+
+    Ok(ImportReport { tokens: tokens.len(), balances: balances.len(), supplies: supplies.len() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NewToken;
+    use crate::storage::SqliteStorage;
+    use crate::testing::TempPath;
+
+    const TOKEN: &str = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+    const WALLET: &str = "0xab5801a7d398351b8be11c439e05c5b3259aec9b";
+
+    // Nothing listens there: token metadata reads fail and are stored empty
+    fn provider() -> Arc<Provider<Http>> {
+        Arc::new(Provider::<Http>::try_from("http://127.0.0.1:1").unwrap())
+    }
+
+    fn entry(wallet_address: Option<&str>, amount: &str) -> SnapshotEntry {
+        SnapshotEntry { token_address: TOKEN.parse().unwrap(), wallet_address: wallet_address.map(|wallet| wallet.parse().unwrap()), amount: Some(amount.to_string()) }
+    }
+
+    fn read(name: &str, contents: &str) -> SnapshotResult<Vec<SnapshotEntry>> {
+        let path = TempPath::new(name);
+        fs::write(path.as_str(), contents).unwrap();
+        read_snapshot(Path::new(path.as_str()))
+    }
+
+    fn summary(entries: &[SnapshotEntry]) -> Vec<(Option<Address>, Option<&str>)> {
+        entries.iter().map(|entry| (entry.wallet_address, entry.amount.as_deref())).collect()
+    }
+
+    #[test]
+    fn csv_and_json_files_list_balances_and_supplies() {
+        let wallet: Address = WALLET.parse().unwrap();
+        let csv = format!("token_address,wallet_address,amount\n{token},{wallet},100\n{token}, ,1000\n", token = TOKEN, wallet = WALLET);
+        assert_eq!(summary(&read("snapshot.csv", &csv).unwrap()), vec![(Some(wallet), Some("100")), (None, Some("1000"))]);
+
+        let json = format!(
+            r#"[{{"token_address": "{token}", "wallet_address": "{wallet}", "amount": "100"}}, {{"token_address": "{token}", "amount": "1000"}}, {{"token_address": "{token}", "wallet_address": null}}]"#,
+            token = TOKEN,
+            wallet = WALLET
+        );
+        assert_eq!(summary(&read("snapshot.json", &json).unwrap()), vec![(Some(wallet), Some("100")), (None, Some("1000")), (None, None)]);
+
+        assert!(read("snapshot.csv", "token_address,wallet_address,amount\n0x1234,,1\n").unwrap_err().to_string().starts_with("Invalid snapshot"));
+        assert!(read("snapshot.txt", "").unwrap_err().to_string().contains("neither a .csv nor a .json file"));
+    }
+
+    #[tokio::test]
+    async fn invalid_or_duplicate_entries_store_nothing() {
+        let path = TempPath::new("snapshot-invalid.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+
+        let rejected = [
+            (vec![entry(Some(WALLET), "100"), entry(Some(WALLET), "5")], "Entry 2: listed twice"),
+            (vec![entry(None, "1000"), entry(None, "1000")], "Entry 2: listed twice"),
+            (vec![entry(Some(WALLET), "-100")], "Entry 1: `-100` is not a decimal amount"),
+            (vec![SnapshotEntry { amount: None, ..entry(None, "0") }], "Entry 1 has no amount"),
+        ];
+        for (entries, message) in rejected {
+            let error = import_snapshot(&storage, provider(), &entries, 100).await.unwrap_err().to_string();
+            assert!(error.starts_with(message), "{}", error);
+        }
+        assert!(storage.count_rows().unwrap().iter().all(|(_, rows)| *rows == 0));
+    }
+
+    #[tokio::test]
+    async fn entries_are_stored_at_the_snapshot_block() {
+        let path = TempPath::new("snapshot.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        let entries = [entry(Some(WALLET), "100"), entry(None, "1000")];
+
+        let report = import_snapshot(&storage, provider(), &entries, 100).await.unwrap();
+        assert_eq!((report.tokens, report.balances, report.supplies), (1, 1, 1));
+        let token: Address = TOKEN.parse().unwrap();
+        let stored = storage.find_token(token.as_bytes()).unwrap().unwrap();
+        assert_eq!((stored.token_type.as_str(), stored.block_number), ("ERC20", 100));
+        let balances = storage.latest_balances(token.as_bytes(), 10).unwrap();
+        assert_eq!(balances.iter().map(|row| (row.wallet_address.clone(), row.balance.as_str(), row.block_number)).collect::<Vec<_>>(), vec![(WALLET.parse::<Address>().unwrap().as_bytes().to_vec(), "100", 100)]);
+        let supply = storage.latest_supply(token.as_bytes()).unwrap().unwrap();
+        assert_eq!((supply.total_supply.as_str(), supply.block_number), ("1000", 100));
+
+        // A token with rows, e.g. from an earlier import, is left alone
+        let error = import_snapshot(&storage, provider(), &[entry(None, "5")], 200).await.unwrap_err().to_string();
+        assert!(error.contains("already has 2 balance, allowance and supply rows"), "{}", error);
+        assert_eq!(storage.count_token_rows(token.as_bytes()).unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn tokens_of_another_standard_are_refused() {
+        let path = TempPath::new("snapshot-nft.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        let token: Address = TOKEN.parse().unwrap();
+        let new_token = NewToken {
+            token_address: token.as_bytes(),
+            block_number: 1,
+            token_type: "ERC721",
+            name: None,
+            symbol: None,
+            decimals: None,
+            granularity: None,
+        };
+        storage.insert_token(&new_token).unwrap();
+
+        let error = import_snapshot(&storage, provider(), &[entry(None, "5")], 100).await.unwrap_err().to_string();
+        assert!(error.contains("is stored as ERC721, not ERC20"), "{}", error);
+        assert_eq!(storage.count_token_rows(token.as_bytes()).unwrap(), 0);
+    }
+}
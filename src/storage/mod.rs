@@ -0,0 +1,642 @@
+pub mod postgres;
+pub mod sqlite;
+
+use std::sync::Arc;
+
+use ethers::types::U256;
+
+use crate::checkpoint::Stream;
+use crate::db::{DbPool, PoolConfig};
+use crate::events::{AllowanceChange, BalanceChange, ContractEvent, TotalSupplyChange};
+use crate::models::allowance::Allowance;
+use crate::models::balance::Balance;
+use crate::models::block::NewBlock;
+use crate::models::checkpoint::Checkpoint;
+use crate::models::token_supply::TokenSupply;
+use crate::models::{NewToken, NewTokenID, Token};
+
+pub use postgres::PgStorage;
+pub use sqlite::SqliteStorage;
+
+pub type StorageResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Chain of a storage opened without naming one: Ethereum mainnet, which every row indexed before
+/// chains were recorded belongs to.
+pub const DEFAULT_CHAIN_ID: i64 = 1;
+
+/// Where the scraper keeps tokens, historical balances, allowances, supplies and block headers.
+/// A storage reads and writes the rows of a single chain; `for_chain` scopes the same database to
+/// another one.
+pub trait Storage: Send + Sync {
+    /// Backend name used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// Chain whose rows this storage reads and writes.
+    fn chain_id(&self) -> i64;
+
+    /// The same database, scoped to `chain_id`.
+    fn for_chain(&self, chain_id: i64) -> Arc<dyn Storage>;
+
+    fn find_token(&self, token_address: &[u8]) -> StorageResult<Option<Token>>;
+
+    /// Stores a token; storing an already stored token is a no-op.
+    fn insert_token(&self, new_token: &NewToken) -> StorageResult<()>;
+
+    /// Whether a URI is already stored for the token ID.
+    fn has_token_uri(&self, contract_address: &[u8], token_id: i16) -> StorageResult<bool>;
+
+    fn insert_token_id(&self, new_token_id: &NewTokenID) -> StorageResult<()>;
+
+    /// Appends a balance row carrying the latest balance plus the change, and returns it.
+    fn apply_balance_change(&self, change: &BalanceChange) -> StorageResult<Balance>;
+
+    /// Appends an allowance row carrying the allowance as set by the change, and returns it.
+    fn apply_allowance_change(&self, change: &AllowanceChange) -> StorageResult<Allowance>;
+
+    /// Appends a total supply row carrying the latest supply plus the change, and returns it.
+    fn apply_supply_change(&self, change: &TotalSupplyChange) -> StorageResult<TokenSupply>;
+
+    /// Stores a block header; storing an already stored block is a no-op.
+    fn insert_block(&self, new_block: &NewBlock) -> StorageResult<()>;
+
+    /// Resolves a Unix timestamp to the last stored block produced at or before it.
+    fn find_block_number_by_timestamp(&self, timestamp: i64) -> StorageResult<Option<i32>>;
+
+    /// Stores an event decoded with a contract ABI; storing an already stored log is a no-op.
+    fn insert_contract_event(&self, event: &ContractEvent) -> StorageResult<()>;
+
+    fn find_tokens_by_type(&self, token_type: &str) -> StorageResult<Vec<Token>>;
+
+    /// The latest fungible (no token ID) balance of up to `limit` wallets of a token, leaving out the
+    /// zero address that mints and burns are recorded against.
+    fn latest_balances(&self, token_address: &[u8], limit: i64) -> StorageResult<Vec<Balance>>;
+
+    fn latest_supply(&self, token_address: &[u8]) -> StorageResult<Option<TokenSupply>>;
+
+    /// Number of rows of the chain in every indexed table.
+    fn count_rows(&self) -> StorageResult<Vec<(&'static str, i64)>>;
+
+    /// Deletes a token and its balances, allowances, supplies and token IDs; returns the number of
+    /// rows deleted along with the token, or None when it is not stored.
+    fn delete_token(&self, token_address: &[u8]) -> StorageResult<Option<usize>>;
+
+    /// Deletes the balances, allowances and supplies of a token from `block_number` on, keeping the
+    /// earlier ones and the token; returns the number of rows deleted.
+    fn delete_token_from(&self, token_address: &[u8], block_number: i32) -> StorageResult<usize>;
+
+    /// Number of balance, allowance and supply rows of a token.
+    fn count_token_rows(&self, token_address: &[u8]) -> StorageResult<i64>;
+
+    /// Number of balance, allowance and supply rows of the chain at or after `block_number`.
+    fn count_rows_from(&self, block_number: i32) -> StorageResult<i64>;
+
+    /// Stores the starting balances and total supplies of tokens that have none yet, in one
+    /// transaction; fails without storing anything when one of the tokens already has some.
+    fn import_snapshot(&self, balances: &[BalanceChange], supplies: &[TotalSupplyChange]) -> StorageResult<()>;
+
+    /// The checkpoints of the chain's streams.
+    fn checkpoints(&self) -> StorageResult<Vec<Checkpoint>>;
+
+    /// Records that `streams` are indexed up to `block_number`.
+    fn save_checkpoints(&self, streams: &[Stream], block_number: i64) -> StorageResult<()>;
+
+    /// The migrations `migrate` would apply. Fails when the schema was created by a newer,
+    /// incompatible version of the scraper.
+    fn pending_migrations(&self) -> StorageResult<Vec<String>>;
+
+    /// Brings the schema up to date and returns the names of the migrations applied.
+    fn migrate(&self) -> StorageResult<Vec<String>>;
+
+    /// The Postgres pool backing this storage, for the features that only run on Postgres
+    /// (API, webhooks and export).
+    fn postgres_pool(&self) -> Option<&DbPool> {
+        None
+    }
+}
+
+/// Opens the storage selected by `database_url`: SQLite for `sqlite://<path>` or a path ending in
+/// `.db`, `.sqlite` or `.sqlite3`, Postgres otherwise.
+pub fn open_storage(database_url: &str) -> StorageResult<Arc<dyn Storage>> {
+    open_storage_with_pool(database_url, PoolConfig::default())
+}
+
+/// Like `open_storage`, with a Postgres pool of the given size.
+pub fn open_storage_with_pool(database_url: &str, pool: PoolConfig) -> StorageResult<Arc<dyn Storage>> {
+    if let Some(path) = sqlite_path(database_url) {
+        return Ok(Arc::new(SqliteStorage::open(path)?));
+    }
+    Ok(Arc::new(PgStorage::new(crate::db::build_connection_pool(database_url, pool)?)))
+}
+
+fn sqlite_path(database_url: &str) -> Option<&str> {
+    if let Some(path) = database_url.strip_prefix("sqlite://") {
+        return Some(path);
+    }
+    [".db", ".sqlite", ".sqlite3"]
+        .iter()
+        .any(|extension| database_url.ends_with(extension))
+        .then_some(database_url)
+}
+
+/// Adds a signed decimal `value` to the decimal `current` amount (zero when there is none yet),
+/// saturating at zero and at U256::MAX.
+fn apply_delta(current: Option<&str>, value: &str) -> String {
+    let parse = |amount: &str| U256::from_dec_str(amount).unwrap_or_else(|_| U256::zero());
+    let current = current.map(parse).unwrap_or_else(U256::zero);
+
+    // Add or subtract the value from the previous amount, saturating to prevent overflow
+    let new_value = match value.strip_prefix('-') {
+        Some(amount) => current.saturating_sub(parse(amount)),
+        None => current.saturating_add(parse(value)),
+    };
+
+    new_value.to_string()
+}
+
+/// Implements `Storage` for a struct with a `pool` field of Diesel r2d2 `$conn` connections and the
+/// `chain_id` it is scoped to.
+/// The query bodies are shared by every backend; the extra items (at least `name` and
+/// `insert_contract_event`, whose JSON column differs between backends, and the migrations) go
+/// into the impl as is.
+macro_rules! impl_diesel_storage {
+    ($storage:ty, $conn:ty { $($extra:item)* }) => {
+        impl $crate::storage::Storage for $storage {
+            $($extra)*
+
+            fn chain_id(&self) -> i64 {
+                self.chain_id
+            }
+
+            fn for_chain(&self, chain_id: i64) -> std::sync::Arc<dyn $crate::storage::Storage> {
+                std::sync::Arc::new(Self { pool: self.pool.clone(), chain_id })
+            }
+
+            fn find_token(&self, token_address_value: &[u8]) -> $crate::storage::StorageResult<Option<$crate::models::Token>> {
+                use diesel::prelude::*;
+                use $crate::schema::tokens::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                Ok(tokens
+                    .filter(chain_id.eq(self.chain_id))
+                    .filter(token_address.eq(token_address_value))
+                    .first::<$crate::models::Token>(conn)
+                    .optional()?)
+            }
+
+            fn insert_token(&self, new_token: &$crate::models::NewToken) -> $crate::storage::StorageResult<()> {
+                use diesel::prelude::*;
+                use $crate::schema::tokens::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                // Logs of a new token are handled concurrently, so another task may have inserted it first
+                diesel::insert_into(tokens)
+                    .values((new_token, chain_id.eq(self.chain_id)))
+                    .on_conflict((chain_id, token_address))
+                    .do_nothing()
+                    .execute(conn)?;
+                Ok(())
+            }
+
+            fn has_token_uri(&self, contract_address_value: &[u8], token_id_value: i16) -> $crate::storage::StorageResult<bool> {
+                use diesel::prelude::*;
+                use $crate::schema::token_ids::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                let existing_token_id: Option<$crate::models::TokenID> = token_ids
+                    .filter(chain_id.eq(self.chain_id))
+                    .filter(contract_address.eq(contract_address_value))
+                    .filter(token_id.eq(token_id_value))
+                    .select($crate::models::TokenID::as_select())
+                    .first(conn)
+                    .optional()?;
+                Ok(existing_token_id.is_some_and(|existing| existing.token_uri.is_some()))
+            }
+
+            fn insert_token_id(&self, new_token_id: &$crate::models::NewTokenID) -> $crate::storage::StorageResult<()> {
+                use diesel::prelude::*;
+                use $crate::schema::token_ids::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                diesel::insert_into(token_ids).values((new_token_id, chain_id.eq(self.chain_id))).execute(conn)?;
+                Ok(())
+            }
+
+            fn apply_balance_change(&self, change: &$crate::events::BalanceChange) -> $crate::storage::StorageResult<$crate::models::balance::Balance> {
+                let conn = &mut self.pool.get()?;
+                Ok(self.balance_change_on(conn, change)?)
+            }
+
+            fn apply_allowance_change(&self, change: &$crate::events::AllowanceChange) -> $crate::storage::StorageResult<$crate::models::allowance::Allowance> {
+                let conn = &mut self.pool.get()?;
+                Ok(self.allowance_change_on(conn, change)?)
+            }
+
+            fn apply_supply_change(&self, change: &$crate::events::TotalSupplyChange) -> $crate::storage::StorageResult<$crate::models::token_supply::TokenSupply> {
+                let conn = &mut self.pool.get()?;
+                Ok(self.supply_change_on(conn, change)?)
+            }
+
+            fn insert_block(&self, new_block: &$crate::models::block::NewBlock) -> $crate::storage::StorageResult<()> {
+                use diesel::prelude::*;
+                use $crate::schema::blocks::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                // Blocks are immutable once stored, so re-processing a range is a no-op
+                diesel::insert_into(blocks)
+                    .values((new_block, chain_id.eq(self.chain_id)))
+                    .on_conflict((chain_id, number))
+                    .do_nothing()
+                    .execute(conn)?;
+                Ok(())
+            }
+
+            fn find_block_number_by_timestamp(&self, target_timestamp: i64) -> $crate::storage::StorageResult<Option<i32>> {
+                use diesel::prelude::*;
+                use $crate::models::block::Block;
+                use $crate::schema::blocks::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+
+                // Timestamps grow with block numbers, so this binary searches the stored block numbers,
+                // probing the primary key index instead of scanning by timestamp.
+                let first_block: Option<Block> = blocks
+                    .filter(chain_id.eq(self.chain_id))
+                    .order_by(number.asc())
+                    .select(Block::as_select())
+                    .first(conn)
+                    .optional()?;
+
+                // Nothing stored yet, or the timestamp predates every stored block
+                let mut low = match first_block {
+                    Some(block) if block.timestamp <= target_timestamp => block.number,
+                    _ => return Ok(None),
+                };
+
+                let mut high: i32 = blocks
+                    .filter(chain_id.eq(self.chain_id))
+                    .select(diesel::dsl::max(number))
+                    .first::<Option<i32>>(conn)?
+                    .unwrap_or(low);
+
+                // Invariant: `low` is a stored block with timestamp <= target, and no block above `high` qualifies
+                while low < high {
+                    let mid = low + (high - low + 1) / 2;
+
+                    // The first stored block within [mid, high]
+                    let probe: Option<Block> = blocks
+                        .filter(chain_id.eq(self.chain_id))
+                        .filter(number.ge(mid))
+                        .filter(number.le(high))
+                        .order_by(number.asc())
+                        .select(Block::as_select())
+                        .first(conn)
+                        .optional()?;
+
+                    match probe {
+                        Some(block) if block.timestamp <= target_timestamp => low = block.number,
+                        _ => high = mid - 1,
+                    }
+                }
+
+                Ok(Some(low))
+            }
+
+            fn find_tokens_by_type(&self, token_type_value: &str) -> $crate::storage::StorageResult<Vec<$crate::models::Token>> {
+                use diesel::prelude::*;
+                use $crate::schema::tokens::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                Ok(tokens
+                    .filter(chain_id.eq(self.chain_id))
+                    .filter(token_type.eq(token_type_value))
+                    .order_by(block_number.asc())
+                    .load::<$crate::models::Token>(conn)?)
+            }
+
+            fn latest_balances(&self, token_address_value: &[u8], limit: i64) -> $crate::storage::StorageResult<Vec<$crate::models::balance::Balance>> {
+                use diesel::prelude::*;
+                use $crate::models::balance::Balance;
+                use $crate::schema::balances::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                let wallets: Vec<Vec<u8>> = balances
+                    .filter(chain_id.eq(self.chain_id))
+                    .filter(token_address.eq(token_address_value))
+                    .filter(token_id.is_null())
+                    .filter(wallet_address.ne(ethers::types::Address::zero().as_bytes()))
+                    .select(wallet_address)
+                    .distinct()
+                    .order_by(wallet_address.asc())
+                    .limit(limit)
+                    .load(conn)?;
+
+                let mut latest = Vec::with_capacity(wallets.len());
+                for wallet in wallets {
+                    // Rows of the same block are ordered by insertion
+                    latest.push(
+                        balances
+                            .filter(chain_id.eq(self.chain_id))
+                            .filter(token_address.eq(token_address_value))
+                            .filter(wallet_address.eq(wallet))
+                            .filter(token_id.is_null())
+                            .order_by((block_number.desc(), id.desc()))
+                            .select(Balance::as_select())
+                            .first(conn)?,
+                    );
+                }
+                Ok(latest)
+            }
+
+            fn latest_supply(&self, token_address_value: &[u8]) -> $crate::storage::StorageResult<Option<$crate::models::token_supply::TokenSupply>> {
+                use diesel::prelude::*;
+                use $crate::models::token_supply::TokenSupply;
+                use $crate::schema::token_supplies::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                Ok(token_supplies
+                    .filter(chain_id.eq(self.chain_id))
+                    .filter(token_address.eq(token_address_value))
+                    .order_by((block_number.desc(), id.desc()))
+                    .select(TokenSupply::as_select())
+                    .first(conn)
+                    .optional()?)
+            }
+
+            fn count_rows(&self) -> $crate::storage::StorageResult<Vec<(&'static str, i64)>> {
+                use diesel::prelude::*;
+                use $crate::schema::{allowances, balances, blocks, contract_events, token_ids, token_supplies, tokens};
+
+                let conn = &mut self.pool.get()?;
+                Ok(vec![
+                    ("tokens", tokens::table.filter(tokens::chain_id.eq(self.chain_id)).count().get_result(conn)?),
+                    ("token_ids", token_ids::table.filter(token_ids::chain_id.eq(self.chain_id)).count().get_result(conn)?),
+                    ("balances", balances::table.filter(balances::chain_id.eq(self.chain_id)).count().get_result(conn)?),
+                    ("allowances", allowances::table.filter(allowances::chain_id.eq(self.chain_id)).count().get_result(conn)?),
+                    ("token_supplies", token_supplies::table.filter(token_supplies::chain_id.eq(self.chain_id)).count().get_result(conn)?),
+                    ("blocks", blocks::table.filter(blocks::chain_id.eq(self.chain_id)).count().get_result(conn)?),
+                    ("contract_events", contract_events::table.filter(contract_events::chain_id.eq(self.chain_id)).count().get_result(conn)?),
+                ])
+            }
+
+            fn delete_token(&self, address: &[u8]) -> $crate::storage::StorageResult<Option<usize>> {
+                use diesel::prelude::*;
+                use $crate::schema::{allowances, balances, token_ids, token_supplies, tokens};
+
+                let conn = &mut self.pool.get()?;
+                // The token row goes last, as the other tables reference it
+                Ok(conn.transaction::<Option<usize>, diesel::result::Error, _>(|conn| {
+                    let rows = diesel::delete(balances::table.filter(balances::chain_id.eq(self.chain_id)).filter(balances::token_address.eq(address))).execute(conn)?
+                        + diesel::delete(allowances::table.filter(allowances::chain_id.eq(self.chain_id)).filter(allowances::token_address.eq(address))).execute(conn)?
+                        + diesel::delete(token_supplies::table.filter(token_supplies::chain_id.eq(self.chain_id)).filter(token_supplies::token_address.eq(address))).execute(conn)?
+                        + diesel::delete(token_ids::table.filter(token_ids::chain_id.eq(self.chain_id)).filter(token_ids::contract_address.eq(address))).execute(conn)?;
+                    let deleted = diesel::delete(tokens::table.filter(tokens::chain_id.eq(self.chain_id)).filter(tokens::token_address.eq(address))).execute(conn)?;
+                    Ok((deleted > 0).then_some(rows))
+                })?)
+            }
+
+            fn delete_token_from(&self, address: &[u8], from_block: i32) -> $crate::storage::StorageResult<usize> {
+                use diesel::prelude::*;
+                use $crate::schema::{allowances, balances, token_supplies};
+
+                let conn = &mut self.pool.get()?;
+                Ok(conn.transaction::<usize, diesel::result::Error, _>(|conn| {
+                    Ok(diesel::delete(
+                        balances::table
+                            .filter(balances::chain_id.eq(self.chain_id))
+                            .filter(balances::token_address.eq(address))
+                            .filter(balances::block_number.ge(from_block)),
+                    )
+                    .execute(conn)?
+                        + diesel::delete(
+                            allowances::table
+                                .filter(allowances::chain_id.eq(self.chain_id))
+                                .filter(allowances::token_address.eq(address))
+                                .filter(allowances::block_number.ge(from_block)),
+                        )
+                        .execute(conn)?
+                        + diesel::delete(
+                            token_supplies::table
+                                .filter(token_supplies::chain_id.eq(self.chain_id))
+                                .filter(token_supplies::token_address.eq(address))
+                                .filter(token_supplies::block_number.ge(from_block)),
+                        )
+                        .execute(conn)?)
+                })?)
+            }
+
+            fn count_token_rows(&self, address: &[u8]) -> $crate::storage::StorageResult<i64> {
+                let conn = &mut self.pool.get()?;
+                Ok(self.token_rows_on(conn, address)?)
+            }
+
+            fn count_rows_from(&self, from_block: i32) -> $crate::storage::StorageResult<i64> {
+                let conn = &mut self.pool.get()?;
+                Ok(self.rows_from_on(conn, from_block)?)
+            }
+
+            fn import_snapshot(&self, balances: &[$crate::events::BalanceChange], supplies: &[$crate::events::TotalSupplyChange]) -> $crate::storage::StorageResult<()> {
+                use std::collections::BTreeSet;
+                use diesel::prelude::*;
+
+                let tokens: BTreeSet<ethers::types::Address> =
+                    balances.iter().map(|change| change.token_address).chain(supplies.iter().map(|change| change.token_address)).collect();
+                let conn = &mut self.pool.get()?;
+                conn.transaction::<(), Box<dyn std::error::Error + Send + Sync>, _>(|conn| {
+                    // The snapshot starts the running totals, which stored rows would add to
+                    for token in &tokens {
+                        let stored = self.token_rows_on(conn, token.as_bytes())?;
+                        if stored > 0 {
+                            return Err(format!("Token {:?} already has {} balance, allowance and supply rows; reset it first", token, stored).into());
+                        }
+                    }
+                    for change in balances {
+                        self.balance_change_on(conn, change)?;
+                    }
+                    for change in supplies {
+                        self.supply_change_on(conn, change)?;
+                    }
+                    Ok(())
+                })
+            }
+
+            fn checkpoints(&self) -> $crate::storage::StorageResult<Vec<$crate::models::checkpoint::Checkpoint>> {
+                use diesel::prelude::*;
+                use $crate::models::checkpoint::Checkpoint;
+                use $crate::schema::checkpoints::dsl::*;
+
+                let conn = &mut self.pool.get()?;
+                Ok(checkpoints
+                    .filter(chain_id.eq(self.chain_id))
+                    .order_by((handler.asc(), processor.asc()))
+                    .select(Checkpoint::as_select())
+                    .load(conn)?)
+            }
+
+            fn save_checkpoints(&self, streams: &[$crate::checkpoint::Stream], block_number_value: i64) -> $crate::storage::StorageResult<()> {
+                use diesel::prelude::*;
+
+                let conn = &mut self.pool.get()?;
+                conn.transaction::<(), diesel::result::Error, _>(|conn| self.save_checkpoints_on(conn, streams, block_number_value))?;
+                Ok(())
+            }
+        }
+
+        // Writes that also run within the transactions of the backfill merge
+        impl $storage {
+            /// Appends a balance row carrying the latest balance plus the change on `conn`, within the
+            /// caller's transaction if there is one.
+            pub(crate) fn balance_change_on(&self, conn: &mut $conn, change: &$crate::events::BalanceChange) -> diesel::QueryResult<$crate::models::balance::Balance> {
+                use diesel::prelude::*;
+                use $crate::models::balance::{Balance, NewBalance};
+                use $crate::schema::balances::dsl::*;
+
+                let mut query = balances
+                    .filter(chain_id.eq(self.chain_id))
+                    .filter(wallet_address.eq(change.wallet_address.as_bytes()))
+                    .filter(token_address.eq(change.token_address.as_bytes()))
+                    .order_by((block_number.desc(), id.desc()))
+                    .into_boxed(); // Use `.into_boxed()` to allow conditional filters
+
+                if let Some(other_id) = change.token_id {
+                    query = query.filter(token_id.eq(other_id)); // Add the token_id filter if it's Some
+                }
+
+                // Get the latest balance
+                let latest_balance: Option<Balance> = query.select(Balance::as_select()).first(conn).optional()?;
+
+                let new_balance = NewBalance {
+                    wallet_address: change.wallet_address.as_bytes(),
+                    token_address: change.token_address.as_bytes(),
+                    balance: $crate::storage::apply_delta(latest_balance.as_ref().map(|latest| latest.balance.as_str()), &change.delta),
+                    token_id: change.token_id,
+                    block_number: change.block_number,
+                    token_type: change.token_type,
+                    chain_id: self.chain_id,
+                };
+
+                diesel::insert_into(balances)
+                    .values(&new_balance)
+                    .returning(Balance::as_returning())
+                    .get_result(conn)
+            }
+
+            /// Like `balance_change_on`, for an allowance row.
+            pub(crate) fn allowance_change_on(&self, conn: &mut $conn, change: &$crate::events::AllowanceChange) -> diesel::QueryResult<$crate::models::allowance::Allowance> {
+                use diesel::prelude::*;
+                use $crate::models::allowance::{Allowance, NewAllowance};
+                use $crate::schema::allowances::dsl::*;
+
+                // An approval replaces the allowance rather than adding to it, so the latest row is not read
+                let new_allowance = NewAllowance {
+                    owner_address: change.owner_address.as_bytes(),
+                    spender_address: change.spender_address.as_bytes(),
+                    token_address: change.token_address.as_bytes(),
+                    allowance: Some(change.allowance.clone()),
+                    block_number: change.block_number,
+                    token_id: change.token_id,
+                    token_type: change.token_type,
+                    chain_id: self.chain_id,
+                };
+
+                diesel::insert_into(allowances)
+                    .values(&new_allowance)
+                    .returning(Allowance::as_returning())
+                    .get_result(conn)
+            }
+
+            /// Like `balance_change_on`, for a total supply row.
+            pub(crate) fn supply_change_on(&self, conn: &mut $conn, change: &$crate::events::TotalSupplyChange) -> diesel::QueryResult<$crate::models::token_supply::TokenSupply> {
+                use diesel::prelude::*;
+                use $crate::models::token_supply::{NewTokenSupply, TokenSupply};
+                use $crate::schema::token_supplies::dsl::*;
+
+                // Get the most recent total supply for the token
+                let latest_total_supply: Option<TokenSupply> = token_supplies
+                    .filter(chain_id.eq(self.chain_id))
+                    .filter(token_address.eq(change.token_address.as_bytes()))
+                    .order_by((block_number.desc(), id.desc()))
+                    .select(TokenSupply::as_select())
+                    .first(conn)
+                    .optional()?;
+
+                let new_total_supply = NewTokenSupply {
+                    token_address: change.token_address.as_bytes(),
+                    total_supply: $crate::storage::apply_delta(latest_total_supply.as_ref().map(|latest| latest.total_supply.as_str()), &change.delta),
+                    block_number: change.block_number,
+                    chain_id: self.chain_id,
+                };
+
+                diesel::insert_into(token_supplies)
+                    .values(&new_total_supply)
+                    .returning(TokenSupply::as_returning())
+                    .get_result(conn)
+            }
+
+            /// Number of balance, allowance and supply rows of a token on `conn`.
+            pub(crate) fn token_rows_on(&self, conn: &mut $conn, address: &[u8]) -> diesel::QueryResult<i64> {
+                use diesel::prelude::*;
+                use $crate::schema::{allowances, balances, token_supplies};
+
+                Ok(balances::table.filter(balances::chain_id.eq(self.chain_id)).filter(balances::token_address.eq(address)).count().get_result::<i64>(conn)?
+                    + allowances::table.filter(allowances::chain_id.eq(self.chain_id)).filter(allowances::token_address.eq(address)).count().get_result::<i64>(conn)?
+                    + token_supplies::table.filter(token_supplies::chain_id.eq(self.chain_id)).filter(token_supplies::token_address.eq(address)).count().get_result::<i64>(conn)?)
+            }
+
+            /// Number of balance, allowance and supply rows of the chain at or after `from_block` on `conn`.
+            pub(crate) fn rows_from_on(&self, conn: &mut $conn, from_block: i32) -> diesel::QueryResult<i64> {
+                use diesel::prelude::*;
+                use $crate::schema::{allowances, balances, token_supplies};
+
+                Ok(balances::table.filter(balances::chain_id.eq(self.chain_id)).filter(balances::block_number.ge(from_block)).count().get_result::<i64>(conn)?
+                    + allowances::table.filter(allowances::chain_id.eq(self.chain_id)).filter(allowances::block_number.ge(from_block)).count().get_result::<i64>(conn)?
+                    + token_supplies::table.filter(token_supplies::chain_id.eq(self.chain_id)).filter(token_supplies::block_number.ge(from_block)).count().get_result::<i64>(conn)?)
+            }
+
+            /// Records that `streams` are indexed up to `block_number` on `conn`.
+            pub(crate) fn save_checkpoints_on(&self, conn: &mut $conn, streams: &[$crate::checkpoint::Stream], block_number_value: i64) -> diesel::QueryResult<()> {
+                use diesel::prelude::*;
+                use diesel::upsert::excluded;
+                use $crate::schema::checkpoints::dsl::*;
+
+                for stream in streams {
+                    diesel::insert_into(checkpoints)
+                        .values((chain_id.eq(self.chain_id), handler.eq(&stream.handler), processor.eq(&stream.processor), block_number.eq(block_number_value)))
+                        .on_conflict((chain_id, handler, processor))
+                        .do_update()
+                        .set(block_number.eq(excluded(block_number)))
+                        .execute(conn)?;
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+pub(crate) use impl_diesel_storage;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_delta_adds_and_subtracts() {
+        assert_eq!(apply_delta(None, "5"), "5");
+        assert_eq!(apply_delta(Some("5"), "7"), "12");
+        assert_eq!(apply_delta(Some("12"), "-7"), "5");
+    }
+
+    #[test]
+    fn apply_delta_saturates() {
+        let max = U256::MAX.to_string();
+        assert_eq!(apply_delta(None, "-1"), "0");
+        assert_eq!(apply_delta(Some("3"), "-5"), "0");
+        assert_eq!(apply_delta(Some(&max), "1"), max);
+        assert_eq!(apply_delta(Some(&max), &max), max);
+    }
+
+    #[test]
+    fn apply_delta_reads_invalid_amounts_as_zero() {
+        assert_eq!(apply_delta(Some("not a number"), "4"), "4");
+        assert_eq!(apply_delta(Some("4"), "-x"), "4");
+    }
+}
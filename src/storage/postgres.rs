@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use diesel::migration::MigrationSource;
+use diesel::pg::Pg;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::{Nullable, Text};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+use crate::db::DbPool;
+use crate::events::ContractEvent;
+use crate::models::contract_event::NewContractEvent;
+use crate::storage::{impl_diesel_storage, StorageResult, DEFAULT_CHAIN_ID};
+
+/// The Diesel migrations of the `migrations` directory, built into the binary.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// Storage in a PostgreSQL database whose schema is managed by the Diesel migrations.
+pub struct PgStorage {
+    pool: DbPool,
+    chain_id: i64,
+}
+
+impl PgStorage {
+    /// Storage of the default chain; `for_chain` scopes it to another one.
+    pub fn new(pool: DbPool) -> Self {
+        PgStorage { pool, chain_id: DEFAULT_CHAIN_ID }
+    }
+
+    /// Storage of `chain_id`, for the Postgres-only features writing through it.
+    pub fn with_chain(pool: DbPool, chain_id: i64) -> Self {
+        PgStorage { pool, chain_id }
+    }
+}
+
+// Refuses a database migrated by a newer version of the scraper, whose schema this one cannot write
+fn check_compatible(conn: &mut PgConnection) -> StorageResult<()> {
+    let known: HashSet<String> = MigrationSource::<Pg>::migrations(&MIGRATIONS)?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+    let unknown: Vec<String> = conn
+        .applied_migrations()?
+        .iter()
+        .map(|version| version.to_string())
+        .filter(|version| !known.contains(version))
+        .collect();
+
+    if !unknown.is_empty() {
+        return Err(format!(
+            "The database has migrations this version of the scraper does not know ({}); it was created by a newer version",
+            unknown.join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+impl_diesel_storage!(PgStorage, PgConnection {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn postgres_pool(&self) -> Option<&DbPool> {
+        Some(&self.pool)
+    }
+
+    fn pending_migrations(&self) -> StorageResult<Vec<String>> {
+        let conn = &mut self.pool.get()?;
+        // Diesel creates the table of applied migrations when reading it; leave a new database as is
+        let tracked: Option<String> = diesel::select(sql::<Nullable<Text>>("to_regclass('__diesel_schema_migrations')::text")).get_result(conn)?;
+        if tracked.is_none() {
+            return Ok(MigrationSource::<Pg>::migrations(&MIGRATIONS)?.iter().map(|migration| migration.name().to_string()).collect());
+        }
+        check_compatible(conn)?;
+        let pending = conn.pending_migrations(MIGRATIONS)?;
+        Ok(pending.iter().map(|migration| migration.name().to_string()).collect())
+    }
+
+    fn migrate(&self) -> StorageResult<Vec<String>> {
+        let conn = &mut self.pool.get()?;
+        check_compatible(conn)?;
+        let applied = conn.run_pending_migrations(MIGRATIONS)?;
+        Ok(applied.iter().map(|version| version.to_string()).collect())
+    }
+
+    fn insert_contract_event(&self, event: &ContractEvent) -> StorageResult<()> {
+        use crate::schema::contract_events::dsl::*;
+
+        let conn = &mut self.pool.get()?;
+        let new_event = NewContractEvent {
+            contract_address: event.contract_address.as_bytes(),
+            contract_name: &event.contract_name,
+            event_name: &event.event_name,
+            event_signature: &event.event_signature,
+            params: &event.params,
+            block_number: event.block_number,
+            transaction_hash: event.transaction_hash.as_bytes(),
+            transaction_index: event.transaction_index as i32,
+            log_index: event.log_index as i32,
+            chain_id: self.chain_id,
+        };
+
+        // Re-processing a range finds its events already stored
+        diesel::insert_into(contract_events)
+            .values(&new_event)
+            .on_conflict((chain_id, transaction_hash, log_index))
+            .do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+});
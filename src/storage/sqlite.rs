@@ -0,0 +1,363 @@
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+
+use crate::events::ContractEvent;
+use crate::storage::{impl_diesel_storage, StorageResult, DEFAULT_CHAIN_ID};
+
+// SQLite serializes writers, so a handful of connections is enough
+const SQLITE_POOL_SIZE: u32 = 8;
+
+// Applied to every connection: wait for the write lock instead of failing with "database is locked",
+// let readers run alongside the writer, and enforce the same foreign keys as Postgres
+const CONNECTION_PRAGMAS: &str = "PRAGMA busy_timeout = 30000; PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;";
+
+// Tables mirroring the Postgres migrations, created when the file is opened
+const SCHEMA: &str = include_str!("sqlite_schema.sql");
+
+// Version of SCHEMA, recorded in the file's `user_version`; bumped with every schema change
+const SCHEMA_VERSION: i32 = 3;
+
+// Moves the tables of a file created before version 2 aside, then copies their rows into the
+// tables SCHEMA creates in between
+const UPGRADE_V2: &str = include_str!("sqlite_upgrade_v2.sql");
+const UPGRADE_V2_COPY: &str = include_str!("sqlite_upgrade_v2_copy.sql");
+
+// Diesel has no SQLite JSON type, so `params` is declared here as the text it is stored as
+mod schema {
+    diesel::table! {
+        contract_events (id) {
+            id -> Integer,
+            contract_address -> Binary,
+            contract_name -> Text,
+            event_name -> Text,
+            event_signature -> Text,
+            params -> Text,
+            block_number -> Integer,
+            transaction_hash -> Binary,
+            transaction_index -> Integer,
+            log_index -> Integer,
+            chain_id -> BigInt,
+        }
+    }
+}
+
+pub type SqlitePool = Pool<ConnectionManager<SqliteConnection>>;
+
+/// Storage in a single SQLite file, created along with its tables on first use. Files created by a
+/// newer version of the scraper are refused.
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    chain_id: i64,
+}
+
+#[derive(QueryableByName)]
+struct UserVersion {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    user_version: i32,
+}
+
+#[derive(QueryableByName)]
+struct TableCount {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    tables: i64,
+}
+
+#[derive(Debug)]
+struct ConnectionPragmas;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionPragmas {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(CONNECTION_PRAGMAS).map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+impl SqliteStorage {
+    /// Opens the file as the storage of the default chain; `for_chain` scopes it to another one.
+    pub fn open(path: &str) -> StorageResult<Self> {
+        let pool = Pool::builder()
+            .max_size(SQLITE_POOL_SIZE)
+            .connection_customizer(Box::new(ConnectionPragmas))
+            .build(ConnectionManager::<SqliteConnection>::new(path))?;
+
+        let conn = &mut pool.get()?;
+        let version = diesel::sql_query("PRAGMA user_version").get_result::<UserVersion>(conn)?.user_version;
+        if version > SCHEMA_VERSION {
+            return Err(format!(
+                "{} has schema version {}, but this version of the scraper only knows up to {}; it was created by a newer version",
+                path, version, SCHEMA_VERSION
+            )
+            .into());
+        }
+
+        // Files created before user_version was recorded report 0, like new ones, but have tables
+        let tables = diesel::sql_query("SELECT COUNT(*) AS tables FROM sqlite_master WHERE type = 'table' AND name = 'tokens'")
+            .get_result::<TableCount>(conn)?
+            .tables;
+        if version < 2 && tables > 0 {
+            upgrade_to_v2(conn)?;
+        }
+        conn.batch_execute(SCHEMA)?;
+        conn.batch_execute(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+
+        Ok(SqliteStorage { pool, chain_id: DEFAULT_CHAIN_ID })
+    }
+}
+
+// Foreign keys cannot be switched inside a transaction, and would reject the renamed tables
+fn upgrade_to_v2(conn: &mut SqliteConnection) -> StorageResult<()> {
+    conn.batch_execute("PRAGMA foreign_keys = OFF")?;
+    let upgraded = conn.transaction::<(), diesel::result::Error, _>(|conn| {
+        conn.batch_execute(UPGRADE_V2)?;
+        conn.batch_execute(SCHEMA)?;
+        conn.batch_execute(UPGRADE_V2_COPY)
+    });
+    conn.batch_execute("PRAGMA foreign_keys = ON")?;
+    Ok(upgraded?)
+}
+
+impl_diesel_storage!(SqliteStorage, SqliteConnection {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    // The tables are created, and the schema version checked, when the file is opened
+    fn pending_migrations(&self) -> StorageResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn migrate(&self) -> StorageResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn insert_contract_event(&self, event: &ContractEvent) -> StorageResult<()> {
+        use schema::contract_events::dsl::*;
+
+        let conn = &mut self.pool.get()?;
+        // Re-processing a range finds its events already stored
+        diesel::insert_into(contract_events)
+            .values((
+                contract_address.eq(event.contract_address.as_bytes()),
+                contract_name.eq(&event.contract_name),
+                event_name.eq(&event.event_name),
+                event_signature.eq(&event.event_signature),
+                params.eq(event.params.to_string()),
+                block_number.eq(event.block_number),
+                transaction_hash.eq(event.transaction_hash.as_bytes()),
+                transaction_index.eq(event.transaction_index as i32),
+                log_index.eq(event.log_index as i32),
+                chain_id.eq(self.chain_id),
+            ))
+            .on_conflict((chain_id, transaction_hash, log_index))
+            .do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+});
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Address;
+
+    use super::*;
+    use crate::checkpoint::Stream;
+    use crate::events::{AllowanceChange, BalanceChange, TotalSupplyChange};
+    use crate::models::NewToken;
+    use crate::storage::Storage;
+    use crate::testing::TempPath;
+
+    // The tables of a file created before chains were recorded, without their indexes
+    const SCHEMA_V1: &str = "
+        CREATE TABLE tokens (token_address BLOB PRIMARY KEY, block_number INTEGER NOT NULL, token_type TEXT NOT NULL, name TEXT, symbol TEXT, decimals INTEGER, granularity TEXT);
+        CREATE TABLE token_ids (id INTEGER PRIMARY KEY, contract_address BLOB NOT NULL REFERENCES tokens (token_address), token_id INTEGER NOT NULL, token_uri TEXT);
+        CREATE TABLE allowances (id INTEGER PRIMARY KEY, owner_address BLOB NOT NULL, spender_address BLOB NOT NULL, token_address BLOB NOT NULL REFERENCES tokens (token_address), allowance TEXT, block_number INTEGER NOT NULL, token_id INTEGER, token_type TEXT NOT NULL);
+        CREATE TABLE token_supplies (id INTEGER PRIMARY KEY, token_address BLOB NOT NULL REFERENCES tokens (token_address), total_supply TEXT NOT NULL, block_number INTEGER NOT NULL);
+        CREATE TABLE balances (id INTEGER PRIMARY KEY, wallet_address BLOB NOT NULL, token_address BLOB NOT NULL REFERENCES tokens (token_address), balance TEXT NOT NULL, token_id INTEGER, token_type TEXT NOT NULL, block_number INTEGER NOT NULL);
+        CREATE TABLE blocks (number INTEGER PRIMARY KEY, hash BLOB NOT NULL, parent_hash BLOB NOT NULL, timestamp INTEGER NOT NULL);
+        CREATE TABLE contract_events (id INTEGER PRIMARY KEY, contract_address BLOB NOT NULL, contract_name TEXT NOT NULL, event_name TEXT NOT NULL, event_signature TEXT NOT NULL, params TEXT NOT NULL, block_number INTEGER NOT NULL, transaction_hash BLOB NOT NULL, transaction_index INTEGER NOT NULL, log_index INTEGER NOT NULL, UNIQUE (transaction_hash, log_index));
+        INSERT INTO tokens VALUES (X'0000000000000000000000000000000000000001', 1, 'ERC20', 'Token', 'TKN', 18, NULL);
+        INSERT INTO balances VALUES (7, X'000000000000000000000000000000000000000b', X'0000000000000000000000000000000000000001', '250', NULL, 'ERC20', 5);
+        PRAGMA user_version = 1;
+    ";
+
+    fn user_version(storage: &SqliteStorage) -> i32 {
+        let conn = &mut storage.pool.get().unwrap();
+        diesel::sql_query("PRAGMA user_version").get_result::<UserVersion>(conn).unwrap().user_version
+    }
+
+    fn transfer(delta: &str, block_number: i32) -> BalanceChange {
+        BalanceChange {
+            wallet_address: Address::from_low_u64_be(11),
+            token_address: Address::from_low_u64_be(1),
+            delta: delta.to_string(),
+            token_id: None,
+            token_type: "ERC20",
+            block_number,
+            chain_id: DEFAULT_CHAIN_ID as u64,
+        }
+    }
+
+    fn mint(delta: &str, block_number: i32) -> TotalSupplyChange {
+        TotalSupplyChange { token_address: Address::from_low_u64_be(1), delta: delta.to_string(), block_number, chain_id: DEFAULT_CHAIN_ID as u64 }
+    }
+
+    fn approval(allowance: &str, block_number: i32) -> AllowanceChange {
+        AllowanceChange {
+            owner_address: Address::from_low_u64_be(11),
+            spender_address: Address::from_low_u64_be(12),
+            token_address: Address::from_low_u64_be(1),
+            allowance: allowance.to_string(),
+            token_id: None,
+            token_type: "ERC20",
+            block_number,
+            chain_id: DEFAULT_CHAIN_ID as u64,
+        }
+    }
+
+    fn insert_token(storage: &SqliteStorage, token: u64) {
+        let token_address = Address::from_low_u64_be(token);
+        let new_token = NewToken {
+            token_address: token_address.as_bytes(),
+            block_number: 1,
+            token_type: "ERC20",
+            name: None,
+            symbol: None,
+            decimals: Some(18),
+            granularity: None,
+        };
+        storage.insert_token(&new_token).unwrap();
+    }
+
+    #[test]
+    fn approvals_replace_the_allowance() {
+        let path = TempPath::new("approvals.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        insert_token(&storage, 1);
+
+        for (allowance, block_number) in [("100", 1), ("40", 2), ("0", 3)] {
+            let row = storage.apply_allowance_change(&approval(allowance, block_number)).unwrap();
+            assert_eq!((row.allowance.as_deref(), row.block_number), (Some(allowance), block_number));
+        }
+    }
+
+    #[test]
+    fn new_files_record_the_schema_version_and_newer_ones_are_refused() {
+        let path = TempPath::new("version.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        assert_eq!(user_version(&storage), SCHEMA_VERSION);
+        drop(storage);
+
+        // Reopening applies the idempotent schema again
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        assert_eq!(user_version(&storage), SCHEMA_VERSION);
+        storage.pool.get().unwrap().batch_execute(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1)).unwrap();
+        drop(storage);
+
+        let error = SqliteStorage::open(path.as_str()).err().unwrap();
+        assert!(error.to_string().contains("created by a newer version"), "{}", error);
+    }
+
+    #[test]
+    fn version_1_files_are_upgraded_with_their_rows_on_the_default_chain() {
+        let path = TempPath::new("upgrade.db");
+        SqliteConnection::establish(path.as_str()).unwrap().batch_execute(SCHEMA_V1).unwrap();
+
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        assert_eq!(user_version(&storage), SCHEMA_VERSION);
+        let token = storage.find_token(Address::from_low_u64_be(1).as_bytes()).unwrap().unwrap();
+        assert_eq!((token.symbol.as_deref(), token.chain_id), (Some("TKN"), DEFAULT_CHAIN_ID));
+        let balances = storage.latest_balances(Address::from_low_u64_be(1).as_bytes(), 10).unwrap();
+        assert_eq!(balances.iter().map(|row| (row.id, row.balance.as_str(), row.block_number, row.chain_id)).collect::<Vec<_>>(), vec![(7, "250", 5, DEFAULT_CHAIN_ID)]);
+        assert!(storage.for_chain(10).find_token(Address::from_low_u64_be(1).as_bytes()).unwrap().is_none());
+
+        // The upgraded rows continue their running totals
+        assert_eq!(storage.apply_balance_change(&transfer("-50", 6)).unwrap().balance, "200");
+    }
+
+    #[test]
+    fn changes_carry_the_totals_across_blocks() {
+        let path = TempPath::new("totals.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        insert_token(&storage, 1);
+
+        let balances: Vec<String> = [("100", 1), ("-30", 2), ("5", 2)].iter().map(|(delta, block)| storage.apply_balance_change(&transfer(delta, *block)).unwrap().balance).collect();
+        assert_eq!(balances, vec!["100", "70", "75"]);
+        let supplies: Vec<String> = [("1000", 1), ("-250", 3)].iter().map(|(delta, block)| storage.apply_supply_change(&mint(delta, *block)).unwrap().total_supply).collect();
+        assert_eq!(supplies, vec!["1000", "750"]);
+        storage.apply_allowance_change(&approval("100", 1)).unwrap();
+        storage.apply_allowance_change(&approval("60", 4)).unwrap();
+
+        let latest = storage.latest_balances(Address::from_low_u64_be(1).as_bytes(), 10).unwrap();
+        assert_eq!(latest.iter().map(|row| (row.balance.as_str(), row.block_number)).collect::<Vec<_>>(), vec![("75", 2)]);
+        let supply = storage.latest_supply(Address::from_low_u64_be(1).as_bytes()).unwrap().unwrap();
+        assert_eq!((supply.total_supply.as_str(), supply.block_number), ("750", 3));
+        assert_eq!(storage.count_token_rows(Address::from_low_u64_be(1).as_bytes()).unwrap(), 7);
+
+        // Another chain of the same file starts from zero
+        let other_chain = storage.for_chain(10);
+        assert_eq!(other_chain.count_token_rows(Address::from_low_u64_be(1).as_bytes()).unwrap(), 0);
+    }
+
+    #[test]
+    fn delete_token_from_keeps_the_rows_before_the_block() {
+        let path = TempPath::new("delete-from.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        insert_token(&storage, 1);
+        for block_number in 1..=3 {
+            storage.apply_balance_change(&transfer("10", block_number)).unwrap();
+            storage.apply_supply_change(&mint("10", block_number)).unwrap();
+            storage.apply_allowance_change(&approval("10", block_number)).unwrap();
+        }
+
+        let token = Address::from_low_u64_be(1);
+        assert_eq!((storage.count_rows_from(2).unwrap(), storage.count_rows_from(4).unwrap()), (6, 0));
+        assert_eq!(storage.delete_token_from(token.as_bytes(), 2).unwrap(), 6);
+        assert_eq!(storage.count_rows_from(2).unwrap(), 0);
+        assert_eq!(storage.count_token_rows(token.as_bytes()).unwrap(), 3);
+        assert_eq!(storage.latest_balances(token.as_bytes(), 10).unwrap()[0].balance, "10");
+        assert_eq!(storage.latest_supply(token.as_bytes()).unwrap().unwrap().block_number, 1);
+        assert!(storage.find_token(token.as_bytes()).unwrap().is_some());
+
+        // Indexing the range again continues from the rows left
+        assert_eq!(storage.apply_balance_change(&transfer("10", 2)).unwrap().balance, "20");
+    }
+
+    #[test]
+    fn checkpoints_are_saved_per_stream_and_chain() {
+        let path = TempPath::new("checkpoints.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        let (events, balances) = (Stream::new("erc20", "events"), Stream::new("erc20", "balances"));
+
+        storage.save_checkpoints(&[events.clone(), balances.clone()], 10).unwrap();
+        storage.save_checkpoints(std::slice::from_ref(&events), 20).unwrap();
+        storage.for_chain(10).save_checkpoints(std::slice::from_ref(&events), 5).unwrap();
+        let saved = |storage: &dyn Storage| storage.checkpoints().unwrap().into_iter().map(|checkpoint| (checkpoint.processor, checkpoint.block_number)).collect::<Vec<_>>();
+        assert_eq!(saved(&storage), vec![("balances".to_string(), 10), ("events".to_string(), 20)]);
+        assert_eq!(saved(storage.for_chain(10).as_ref()), vec![("events".to_string(), 5)]);
+
+        // Saved within a transaction, they roll back along with the rows
+        let conn = &mut storage.pool.get().unwrap();
+        let rolled_back = conn.transaction::<(), diesel::result::Error, _>(|conn| {
+            storage.save_checkpoints_on(conn, &[events.clone(), balances.clone()], 30)?;
+            Err(diesel::result::Error::RollbackTransaction)
+        });
+        assert!(rolled_back.is_err());
+        assert_eq!(saved(&storage), vec![("balances".to_string(), 10), ("events".to_string(), 20)]);
+    }
+
+    #[test]
+    fn delete_token_counts_the_rows_apart_from_the_token() {
+        let path = TempPath::new("delete.db");
+        let storage = SqliteStorage::open(path.as_str()).unwrap();
+        let token = Address::from_low_u64_be(1);
+        assert_eq!(storage.delete_token(token.as_bytes()).unwrap(), None);
+
+        insert_token(&storage, 1);
+        assert_eq!(storage.delete_token(token.as_bytes()).unwrap(), Some(0));
+        insert_token(&storage, 1);
+        storage.apply_balance_change(&transfer("10", 1)).unwrap();
+        storage.apply_supply_change(&mint("10", 1)).unwrap();
+        assert_eq!(storage.delete_token(token.as_bytes()).unwrap(), Some(2));
+        assert!(storage.find_token(token.as_bytes()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,108 @@
+-- SQLite equivalent of the tables created by the Postgres migrations, used by the SQLite storage.
+-- Every statement is idempotent, as it runs each time the file is opened.
+CREATE TABLE IF NOT EXISTS tokens (
+    token_address BLOB NOT NULL,               -- 20-byte token address (unique for each token of a chain)
+    block_number INTEGER NOT NULL,             -- Block number the token was first seen at
+    token_type TEXT NOT NULL,                  -- "ERC20", "ERC721", "ERC1155" or "ERC777"
+    name TEXT,
+    symbol TEXT,
+    decimals INTEGER,                          -- Token decimals (optional for ERC20)
+    granularity TEXT,                          -- Token granularity (optional for ERC777)
+    chain_id INTEGER NOT NULL,
+    PRIMARY KEY (chain_id, token_address)
+);
+
+CREATE TABLE IF NOT EXISTS token_ids (
+    id INTEGER PRIMARY KEY,
+    contract_address BLOB NOT NULL,
+    token_id INTEGER NOT NULL,                 -- Token ID for ERC721/1155 tokens
+    token_uri TEXT,
+    chain_id INTEGER NOT NULL,
+    FOREIGN KEY (chain_id, contract_address) REFERENCES tokens (chain_id, token_address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_token_ids_contract_address ON token_ids (chain_id, contract_address);
+CREATE INDEX IF NOT EXISTS idx_token_ids_token_id ON token_ids (token_id);
+
+CREATE TABLE IF NOT EXISTS allowances (
+    id INTEGER PRIMARY KEY,
+    owner_address BLOB NOT NULL,
+    spender_address BLOB NOT NULL,
+    token_address BLOB NOT NULL,
+    allowance TEXT,                            -- NULL for ERC721
+    block_number INTEGER NOT NULL,
+    token_id INTEGER,                          -- For ERC721 tokens
+    token_type TEXT NOT NULL,
+    chain_id INTEGER NOT NULL,
+    FOREIGN KEY (chain_id, token_address) REFERENCES tokens (chain_id, token_address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_owner_address ON allowances (chain_id, owner_address);
+CREATE INDEX IF NOT EXISTS idx_spender_address ON allowances (chain_id, spender_address);
+CREATE INDEX IF NOT EXISTS idx_token_address ON allowances (chain_id, token_address);
+CREATE INDEX IF NOT EXISTS idx_block_number ON allowances (block_number);
+
+CREATE TABLE IF NOT EXISTS token_supplies (
+    id INTEGER PRIMARY KEY,
+    token_address BLOB NOT NULL,
+    total_supply TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    chain_id INTEGER NOT NULL,
+    FOREIGN KEY (chain_id, token_address) REFERENCES tokens (chain_id, token_address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_token_supply_address ON token_supplies (chain_id, token_address);
+CREATE INDEX IF NOT EXISTS idx_token_supply_block ON token_supplies (block_number);
+
+CREATE TABLE IF NOT EXISTS balances (
+    id INTEGER PRIMARY KEY,
+    wallet_address BLOB NOT NULL,
+    token_address BLOB NOT NULL,
+    balance TEXT NOT NULL,
+    token_id INTEGER,                          -- For ERC721/1155 tokens
+    token_type TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    chain_id INTEGER NOT NULL,
+    FOREIGN KEY (chain_id, token_address) REFERENCES tokens (chain_id, token_address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_balance_wallet ON balances (chain_id, wallet_address);
+CREATE INDEX IF NOT EXISTS idx_balance_token ON balances (chain_id, token_address);
+CREATE INDEX IF NOT EXISTS idx_balance_block ON balances (block_number);
+
+CREATE TABLE IF NOT EXISTS blocks (
+    number INTEGER NOT NULL,
+    hash BLOB NOT NULL,                        -- 32-byte block hash
+    parent_hash BLOB NOT NULL,                 -- 32-byte parent block hash
+    timestamp INTEGER NOT NULL,                -- Unix timestamp (seconds) of the block
+    chain_id INTEGER NOT NULL,
+    PRIMARY KEY (chain_id, number)
+);
+
+CREATE INDEX IF NOT EXISTS idx_blocks_timestamp ON blocks (chain_id, timestamp);
+
+CREATE TABLE IF NOT EXISTS contract_events (
+    id INTEGER PRIMARY KEY,
+    contract_address BLOB NOT NULL,            -- 20-byte address of the emitting contract
+    contract_name TEXT NOT NULL,
+    event_name TEXT NOT NULL,
+    event_signature TEXT NOT NULL,             -- Canonical signature, e.g. "Deposit(address,uint256)"
+    params TEXT NOT NULL,                      -- Decoded parameters as a JSON object
+    block_number INTEGER NOT NULL,
+    transaction_hash BLOB NOT NULL,            -- 32-byte hash of the emitting transaction
+    transaction_index INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,
+    chain_id INTEGER NOT NULL,
+    UNIQUE (chain_id, transaction_hash, log_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_contract_events_contract ON contract_events (chain_id, contract_address, block_number);
+CREATE INDEX IF NOT EXISTS idx_contract_events_name ON contract_events (chain_id, event_name, block_number);
+
+CREATE TABLE IF NOT EXISTS checkpoints (
+    chain_id INTEGER NOT NULL,
+    handler TEXT NOT NULL,                     -- Handler name, e.g. "ERC20", or "*" for the block header processors
+    processor TEXT NOT NULL,                   -- "events" for the handler's own events, else the processor, e.g. "balances"
+    block_number INTEGER NOT NULL,             -- Last indexed block
+    PRIMARY KEY (chain_id, handler, processor)
+);
@@ -0,0 +1,25 @@
+-- Upgrades a file created before chains were recorded: every table gains a chain_id, and the rows
+-- already indexed are Ethereum mainnet's. SQLite cannot change a primary key in place, so the
+-- tables are renamed, recreated by the schema, and copied over.
+DROP INDEX IF EXISTS idx_token_ids_contract_address;
+DROP INDEX IF EXISTS idx_token_ids_token_id;
+DROP INDEX IF EXISTS idx_owner_address;
+DROP INDEX IF EXISTS idx_spender_address;
+DROP INDEX IF EXISTS idx_token_address;
+DROP INDEX IF EXISTS idx_block_number;
+DROP INDEX IF EXISTS idx_token_supply_address;
+DROP INDEX IF EXISTS idx_token_supply_block;
+DROP INDEX IF EXISTS idx_balance_wallet;
+DROP INDEX IF EXISTS idx_balance_token;
+DROP INDEX IF EXISTS idx_balance_block;
+DROP INDEX IF EXISTS idx_blocks_timestamp;
+DROP INDEX IF EXISTS idx_contract_events_contract;
+DROP INDEX IF EXISTS idx_contract_events_name;
+
+ALTER TABLE tokens RENAME TO tokens_v1;
+ALTER TABLE token_ids RENAME TO token_ids_v1;
+ALTER TABLE allowances RENAME TO allowances_v1;
+ALTER TABLE token_supplies RENAME TO token_supplies_v1;
+ALTER TABLE balances RENAME TO balances_v1;
+ALTER TABLE blocks RENAME TO blocks_v1;
+ALTER TABLE contract_events RENAME TO contract_events_v1;
@@ -0,0 +1,23 @@
+-- Second half of the version 2 upgrade, run once the schema has created the new tables
+INSERT INTO tokens (token_address, block_number, token_type, name, symbol, decimals, granularity, chain_id)
+    SELECT token_address, block_number, token_type, name, symbol, decimals, granularity, 1 FROM tokens_v1;
+INSERT INTO token_ids (id, contract_address, token_id, token_uri, chain_id)
+    SELECT id, contract_address, token_id, token_uri, 1 FROM token_ids_v1;
+INSERT INTO allowances (id, owner_address, spender_address, token_address, allowance, block_number, token_id, token_type, chain_id)
+    SELECT id, owner_address, spender_address, token_address, allowance, block_number, token_id, token_type, 1 FROM allowances_v1;
+INSERT INTO token_supplies (id, token_address, total_supply, block_number, chain_id)
+    SELECT id, token_address, total_supply, block_number, 1 FROM token_supplies_v1;
+INSERT INTO balances (id, wallet_address, token_address, balance, token_id, token_type, block_number, chain_id)
+    SELECT id, wallet_address, token_address, balance, token_id, token_type, block_number, 1 FROM balances_v1;
+INSERT INTO blocks (number, hash, parent_hash, timestamp, chain_id)
+    SELECT number, hash, parent_hash, timestamp, 1 FROM blocks_v1;
+INSERT INTO contract_events (id, contract_address, contract_name, event_name, event_signature, params, block_number, transaction_hash, transaction_index, log_index, chain_id)
+    SELECT id, contract_address, contract_name, event_name, event_signature, params, block_number, transaction_hash, transaction_index, log_index, 1 FROM contract_events_v1;
+
+DROP TABLE contract_events_v1;
+DROP TABLE blocks_v1;
+DROP TABLE balances_v1;
+DROP TABLE token_supplies_v1;
+DROP TABLE allowances_v1;
+DROP TABLE token_ids_v1;
+DROP TABLE tokens_v1;
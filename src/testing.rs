@@ -0,0 +1,92 @@
+//! Test fixtures: temporary files, and Postgres databases for the tests of the features that only
+//! run on Postgres (API, webhooks, export and the backfill queue). Each of those tests gets its own database
+//! on the server `TEST_DATABASE_URL` points to, created with every migration applied and dropped once
+//! the test is done; without the variable, those tests are skipped.
+
+use std::path::{Path, PathBuf};
+
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::db::{build_connection_pool, DbPool, PoolConfig};
+use crate::storage::{PgStorage, Storage};
+
+/// A migrated database dropped along with the value.
+pub struct TestDatabase {
+    pub pool: DbPool,
+    server_url: String,
+    name: String,
+}
+
+impl TestDatabase {
+    /// Creates a database named after `test`; None when `TEST_DATABASE_URL` is not set.
+    pub fn create(test: &str) -> Option<Self> {
+        let Ok(server_url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set; skipping {}", test);
+            return None;
+        };
+        let name = format!("scraper_test_{}_{}", test, std::process::id());
+        let admin = &mut PgConnection::establish(&server_url).expect("Failed to connect to TEST_DATABASE_URL");
+        diesel::sql_query(format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", name)).execute(admin).expect("Failed to drop the test database");
+        diesel::sql_query(format!("CREATE DATABASE {}", name)).execute(admin).expect("Failed to create the test database");
+
+        let (server, _) = server_url.rsplit_once('/').expect("TEST_DATABASE_URL has no database name");
+        let pool = build_connection_pool(&format!("{}/{}", server, name), PoolConfig { max_size: 8, min_idle: 0 }).expect("Failed to open the test database");
+        PgStorage::new(pool.clone()).migrate().expect("Failed to migrate the test database");
+        Some(TestDatabase { pool, server_url, name })
+    }
+
+    /// The connection URL of this database.
+    pub fn url(&self) -> String {
+        let (server, _) = self.server_url.rsplit_once('/').expect("TEST_DATABASE_URL has no database name");
+        format!("{}/{}", server, self.name)
+    }
+
+    /// The storage of `chain_id` in this database.
+    pub fn storage(&self, chain_id: i64) -> PgStorage {
+        PgStorage::with_chain(self.pool.clone(), chain_id)
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        // FORCE closes the pool's connections, still open while the fields are not dropped yet
+        if let Ok(admin) = &mut PgConnection::establish(&self.server_url) {
+            let _ = diesel::sql_query(format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", self.name)).execute(admin);
+        }
+    }
+}
+
+/// A file or directory path in the temp directory, removed along with the value (with the `-wal` and
+/// `-shm` files SQLite keeps next to a database).
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    /// A path named after `name`, cleared of what an earlier run may have left there.
+    pub fn new(name: &str) -> Self {
+        let path = TempPath(std::env::temp_dir().join(format!("scraper-test-{}-{}", std::process::id(), name)));
+        path.remove();
+        path
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.to_str().expect("The temp directory is not valid UTF-8")
+    }
+
+    fn remove(&self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.0.display(), suffix));
+        }
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
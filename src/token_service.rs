@@ -1,28 +1,21 @@
 // use diesel::prelude::*;
 use std::sync::Arc;
-use crate::models::{NewToken, NewTokenID, Token, TokenID};
-use crate::{create_erc1155_contract, create_erc20_contract, create_erc721_contract, create_erc777_contract, TokenType};
+use crate::models::{NewToken, NewTokenID, Token};
+use crate::storage::Storage;
+use crate::constants::{create_erc1155_contract, create_erc20_contract, create_erc721_contract, create_erc777_contract};
+use crate::TokenType;
 use ethers::providers::{Provider, Http};
 
-use diesel::prelude::*;
-use diesel::insert_into;
-use crate::schema::tokens::dsl::*;
-use crate::PgPooledConnection;
-
-use std::convert::TryInto;
 
 pub async fn check_and_insert_token(
-    conn: &mut PgPooledConnection,
+    storage: &dyn Storage,
     provider: Arc<Provider<Http>>,
     token_address_value: &[u8],
     current_block_number: i32,
     erc_type: TokenType,
 ) -> Result<Token, Box<dyn std::error::Error + Send + Sync>> {
     // Check if the token exists in the database
-    let token_exists = tokens
-        .filter(token_address.eq(token_address_value))
-        .first::<Token>(conn)
-        .optional()?;
+    let token_exists = storage.find_token(token_address_value)?;
 
     if let Some(existing_token) = token_exists {
         return Ok(existing_token); // Return the existing token
@@ -34,7 +27,7 @@ pub async fn check_and_insert_token(
             let contract = create_erc20_contract(token_address_value, provider.clone())?;
             let erc_name = contract.name().call().await.ok();
             let erc_symbol = contract.symbol().call().await.ok();
-            let erc_decimals: Option<i16> = contract.decimals().call().await.ok().and_then(|d| d.try_into().ok());
+            let erc_decimals: Option<i16> = contract.decimals().call().await.ok().map(i16::from);
             (erc_name, erc_symbol, erc_decimals, None)
         }
         TokenType::ERC721 => {
@@ -62,12 +55,7 @@ pub async fn check_and_insert_token(
     let new_token = NewToken {
         token_address: token_address_value,
         block_number: current_block_number,
-        token_type: match erc_type {
-            TokenType::ERC20 => "ERC20",
-            TokenType::ERC721 => "ERC721",
-            TokenType::ERC777 => "ERC777",
-            TokenType::ERC1155 => "ERC1155",
-        },
+        token_type: erc_type.as_str(),
         name: erc_name,
         symbol: erc_symbol,
         decimals: erc_decimals,
@@ -75,9 +63,7 @@ pub async fn check_and_insert_token(
     };
 
     // Insert the new token into the database
-    insert_into(tokens)
-        .values(&new_token)
-        .execute(conn)?;
+    storage.insert_token(&new_token)?;
 
     Ok(Token {
         token_address: token_address_value.to_vec(),
@@ -87,28 +73,19 @@ pub async fn check_and_insert_token(
         symbol: new_token.symbol,
         decimals: new_token.decimals,
         granularity: new_token.granularity,
+        chain_id: storage.chain_id(),
     })
 }
 // The rest of the code remains the same
 pub async fn fetch_and_store_token_uri(
-    conn: &mut PgPooledConnection,
+    storage: &dyn Storage,
     contract_address_value: &[u8],
     token_id_value: i16,
     erc_type: TokenType,
     provider: Arc<Provider<Http>>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    use crate::schema::token_ids::dsl::*;
-
-    let existing_token_id: Option<TokenID> = token_ids
-        .filter(contract_address.eq(contract_address_value))
-        .filter(token_id.eq(token_id_value))
-        .first::<TokenID>(conn)
-        .optional()?;
-
-    if let Some(existing_token) = existing_token_id {
-        if existing_token.token_uri.is_some() {
-            return Ok(()); // If metadata already exists, exit early
-        }
+    if storage.has_token_uri(contract_address_value, token_id_value)? {
+        return Ok(()); // If metadata already exists, exit early
     }
 
     // Fetch the URI based on the token type
@@ -132,7 +109,7 @@ pub async fn fetch_and_store_token_uri(
     };
 
     // Insert the new token ID into the database
-    insert_into(token_ids).values(&new_token_id).execute(conn)?;
+    storage.insert_token_id(&new_token_id)?;
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, U256};
+use log::info;
+
+use crate::constants::create_erc20_contract;
+use crate::storage::Storage;
+use crate::TokenType;
+
+pub type VerifyResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// A stored amount that differs from the one read from the chain.
+pub struct Mismatch {
+    pub token_address: Address,
+    pub wallet_address: Option<Address>,  // None for the total supply
+    pub stored: String,
+    pub on_chain: U256,
+}
+
+/// Outcome of comparing stored amounts with the chain.
+#[derive(Default)]
+pub struct VerifyReport {
+    pub tokens: usize,
+    pub checked: usize,                   // Balances and supplies compared
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Compares the latest stored balances of up to `wallets` holders of each ERC20 token (every stored
+/// one when `tokens` is empty), and its latest stored total supply, with `balanceOf` and
+/// `totalSupply` called at `block`.
+///
+/// The tokens must be indexed from their deployment up to `block`, and the node must serve the
+/// state of `block` (an archive node, unless it is recent).
+pub async fn verify_erc20(
+    storage: &dyn Storage,
+    provider: Arc<Provider<Http>>,
+    tokens: &[Address],
+    wallets: i64,
+    block: u64,
+) -> VerifyResult<VerifyReport> {
+    let tokens: Vec<Address> = if tokens.is_empty() {
+        storage
+            .find_tokens_by_type(TokenType::ERC20.as_str())?
+            .iter()
+            .map(|token| Address::from_slice(&token.token_address))
+            .collect()
+    } else {
+        tokens.to_vec()
+    };
+
+    let mut report = VerifyReport { tokens: tokens.len(), ..VerifyReport::default() };
+    for token in tokens {
+        info!("Verifying {:?} at block {}", token, block);
+        let contract = create_erc20_contract(token.as_bytes(), provider.clone())?;
+
+        for balance in storage.latest_balances(token.as_bytes(), wallets)? {
+            let wallet = Address::from_slice(&balance.wallet_address);
+            let on_chain = contract.balance_of(wallet).block(block).call().await?;
+            report.record(token, Some(wallet), balance.balance, on_chain);
+        }
+
+        if let Some(supply) = storage.latest_supply(token.as_bytes())? {
+            let on_chain = contract.total_supply().block(block).call().await?;
+            report.record(token, None, supply.total_supply, on_chain);
+        }
+    }
+
+    Ok(report)
+}
+
+impl VerifyReport {
+    fn record(&mut self, token_address: Address, wallet_address: Option<Address>, stored: String, on_chain: U256) {
+        self.checked += 1;
+        if U256::from_dec_str(&stored).ok() != Some(on_chain) {
+            self.mismatches.push(Mismatch { token_address, wallet_address, stored, on_chain });
+        }
+    }
+}
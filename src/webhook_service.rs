@@ -0,0 +1,632 @@
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+
+use clap::{Subcommand, ValueEnum};
+use ethers::types::{Address, U256};
+use ethers::utils::hex;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{oneshot, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::api::allowances::AllowanceView;
+use crate::api::checksum;
+use crate::db::DbPool;
+use crate::events::{subscribe, ChangeEvent};
+use crate::models::webhook::{
+    claim_stale_deliveries, delete_webhook, insert_delivery, insert_webhook, load_recent_deliveries, load_webhooks,
+    record_delivery_attempt, NewWebhook, NewWebhookDelivery, Webhook,
+};
+use crate::PgPooledConnection;
+
+// Attempts per delivery before it is marked failed, and the exponential backoff between them
+const MAX_DELIVERY_ATTEMPTS: i32 = 6;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// Requests in flight across all webhooks, and how long each may take
+const MAX_CONCURRENT_DELIVERIES: usize = 32;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often the registered webhooks are re-read, so changes apply without a restart
+const WEBHOOK_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+// Pending deliveries not attempted for this long (well over a backoff plus a request) were left behind
+// by a scraper that stopped, and are resumed when a dispatcher starts
+const STALE_DELIVERY_SECS: i32 = 600;
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body keyed with the webhook secret>`.
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature-256";
+
+/// Kinds of changes a webhook can subscribe to.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum WebhookEventKind {
+    Transfer,
+    Approval,
+    Supply,
+}
+
+impl WebhookEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventKind::Transfer => "transfer",
+            WebhookEventKind::Approval => "approval",
+            WebhookEventKind::Supply => "supply",
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum WebhookCommand {
+    /// Register a webhook; every filter left out matches anything
+    Add {
+        /// Endpoint receiving POSTed JSON payloads
+        #[arg(long)]
+        url: String,
+
+        /// Key used to sign payloads (HMAC-SHA256, sent in the X-Webhook-Signature-256 header)
+        #[arg(long)]
+        secret: String,
+
+        /// Only changes involving this wallet (sender/recipient, or owner/spender)
+        #[arg(long)]
+        wallet: Option<Address>,
+
+        /// Only changes of this token
+        #[arg(long)]
+        token: Option<Address>,
+
+        /// Only this kind of change
+        #[arg(long, value_enum)]
+        event: Option<WebhookEventKind>,
+
+        /// Only transfers, allowances or supply changes of at least this raw amount
+        #[arg(long)]
+        min_amount: Option<String>,
+
+        /// Only changes on this chain
+        #[arg(long, value_name = "CHAIN_ID")]
+        chain: Option<i64>,
+    },
+
+    /// List registered webhooks
+    List,
+
+    /// Remove a webhook and its delivery log
+    Remove {
+        id: i32,
+    },
+
+    /// Show the most recent deliveries
+    Deliveries {
+        /// Only deliveries to this webhook
+        #[arg(long)]
+        webhook: Option<i32>,
+
+        /// Number of deliveries to show
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
+}
+
+#[derive(Serialize)]
+struct SupplyChangeView {
+    chain_id: i64,
+    token_address: String,
+    total_supply: String,
+    delta: String,
+    block_number: i32,
+}
+
+/// A change in the shape webhooks filter on and receive.
+struct Notification {
+    kind: WebhookEventKind,
+    chain_id: i64,
+    block_number: i32,
+    token: Vec<u8>,
+    parties: Vec<Vec<u8>>,            // Wallets involved: sender and recipient, or owner and spender
+    amount: U256,                     // Transfer value, allowance, or absolute supply change
+    data: serde_json::Value,
+}
+
+impl Notification {
+    /// Balance rows are not notified on their own; the transfer that caused them is.
+    fn from_event(event: &ChangeEvent) -> Option<Self> {
+        match event {
+            ChangeEvent::Balance(_) => None,
+            ChangeEvent::Transfer(transfer) => Some(Notification {
+                kind: WebhookEventKind::Transfer,
+                chain_id: event.chain_id(),
+                block_number: transfer.block_number,
+                token: transfer.token_address.as_bytes().to_vec(),
+                parties: vec![transfer.from.as_bytes().to_vec(), transfer.to.as_bytes().to_vec()],
+                amount: U256::from_dec_str(&transfer.value).unwrap_or_default(),
+                data: serde_json::to_value(transfer).ok()?,
+            }),
+            ChangeEvent::Allowance(allowance) => Some(Notification {
+                kind: WebhookEventKind::Approval,
+                chain_id: event.chain_id(),
+                block_number: allowance.block_number,
+                token: allowance.token_address.clone(),
+                parties: vec![allowance.owner_address.clone(), allowance.spender_address.clone()],
+                amount: allowance.allowance.as_deref().and_then(|value| U256::from_dec_str(value).ok()).unwrap_or_default(),
+                data: serde_json::to_value(AllowanceView::from(allowance.clone())).ok()?,
+            }),
+            ChangeEvent::Supply(change) => Some(Notification {
+                kind: WebhookEventKind::Supply,
+                chain_id: event.chain_id(),
+                block_number: change.supply.block_number,
+                token: change.supply.token_address.clone(),
+                parties: Vec::new(),
+                amount: U256::from_dec_str(change.delta.trim_start_matches('-')).unwrap_or_default(),
+                data: serde_json::to_value(SupplyChangeView {
+                    chain_id: change.supply.chain_id,
+                    token_address: checksum(&change.supply.token_address),
+                    total_supply: change.supply.total_supply.clone(),
+                    delta: change.delta.clone(),
+                    block_number: change.supply.block_number,
+                })
+                .ok()?,
+            }),
+        }
+    }
+
+    fn matches(&self, webhook: &Webhook) -> bool {
+        webhook.chain_id.is_none_or(|chain_id| chain_id == self.chain_id)
+            && webhook.event_kind.as_deref().is_none_or(|kind| kind == self.kind.as_str())
+            && webhook.token_address.as_ref().is_none_or(|token| *token == self.token)
+            && webhook.wallet_address.as_ref().is_none_or(|wallet| self.parties.contains(wallet))
+            && webhook
+                .min_amount
+                .as_deref()
+                .is_none_or(|min_amount| U256::from_dec_str(min_amount).is_ok_and(|min_amount| self.amount >= min_amount))
+    }
+
+    fn payload(&self, webhook: &Webhook) -> serde_json::Value {
+        serde_json::json!({
+            "webhook_id": webhook.id,
+            "event": self.kind.as_str(),
+            "chain_id": self.chain_id,
+            "block_number": self.block_number,
+            "data": self.data,
+        })
+    }
+}
+
+/// Delivers changes published by the scraper to the registered webhooks.
+pub struct WebhookDispatcher {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl WebhookDispatcher {
+    /// Subscribes to the change bus and starts delivering in the background.
+    pub fn spawn(pool: DbPool) -> Self {
+        let (stop, stopped) = oneshot::channel();
+        // Subscribe here rather than in the task, so changes published right after `spawn` are not missed
+        let receiver = subscribe();
+        let handle = tokio::spawn(dispatch(pool, receiver, stopped));
+        WebhookDispatcher { stop, handle }
+    }
+
+    /// Dispatches the changes already published, then waits for their deliveries (and retries) to finish.
+    pub async fn finish(self) {
+        let _ = self.stop.send(());
+        if let Err(e) = self.handle.await {
+            error!("Webhook dispatcher failed: {:?}", e);
+        }
+    }
+}
+
+/// What the deliveries of a dispatcher share.
+#[derive(Clone)]
+struct Deliveries {
+    pool: DbPool,
+    client: reqwest::Client,
+    limiter: Arc<Semaphore>,          // Bounds the requests in flight across all webhooks
+    initial_backoff: Duration,        // Wait after the first failed attempt, doubled after every other one
+}
+
+impl Deliveries {
+    fn new(pool: DbPool, initial_backoff: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+        Deliveries { pool, client, limiter: Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES)), initial_backoff }
+    }
+}
+
+async fn dispatch(pool: DbPool, mut receiver: tokio::sync::broadcast::Receiver<ChangeEvent>, mut stopped: oneshot::Receiver<()>) {
+    let context = Deliveries::new(pool.clone(), INITIAL_BACKOFF);
+    let mut deliveries = JoinSet::new();
+    resume_stale_deliveries(&context, &mut deliveries);
+    let mut webhooks: Vec<Webhook> = Vec::new();
+    let mut refreshed_at: Option<Instant> = None;
+    let mut stopping = false;
+
+    loop {
+        let received = if stopping {
+            match receiver.try_recv() {
+                Ok(event) => Ok(event),
+                Err(TryRecvError::Lagged(skipped)) => Err(skipped),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+            }
+        } else {
+            tokio::select! {
+                result = receiver.recv() => match result {
+                    Ok(event) => Ok(event),
+                    Err(RecvError::Lagged(skipped)) => Err(skipped),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut stopped => {
+                    stopping = true;
+                    continue;
+                }
+            }
+        };
+
+        let event = match received {
+            Ok(event) => event,
+            Err(skipped) => {
+                warn!("Webhook dispatcher fell behind; {} changes were not delivered", skipped);
+                continue;
+            }
+        };
+
+        if refreshed_at.is_none_or(|refreshed_at| refreshed_at.elapsed() >= WEBHOOK_REFRESH_INTERVAL) {
+            match pool.get().map_err(|e| e.to_string()).and_then(|mut conn| load_webhooks(&mut conn, true).map_err(|e| e.to_string())) {
+                Ok(rows) => webhooks = rows,
+                Err(e) => error!("Error loading webhooks: {}", e),
+            }
+            refreshed_at = Some(Instant::now());
+        }
+
+        let Some(notification) = Notification::from_event(&event) else { continue };
+        for webhook in webhooks.iter().filter(|webhook| notification.matches(webhook)) {
+            deliveries.spawn(deliver(context.clone(), webhook.clone(), notification.kind, notification.block_number, notification.payload(webhook)));
+        }
+
+        // Reap finished deliveries so the set only holds those in flight
+        while deliveries.try_join_next().is_some() {}
+    }
+
+    if !deliveries.is_empty() {
+        info!("Waiting for {} webhook deliveries to finish", deliveries.len());
+    }
+    while deliveries.join_next().await.is_some() {}
+}
+
+/// Resumes the pending deliveries left behind by a scraper that stopped before finishing them (see
+/// `STALE_DELIVERY_SECS`), continuing from the attempts they already made.
+fn resume_stale_deliveries(context: &Deliveries, deliveries: &mut JoinSet<()>) {
+    let pending = match context
+        .pool
+        .get()
+        .map_err(|e| e.to_string())
+        .and_then(|mut conn| claim_stale_deliveries(&mut conn, STALE_DELIVERY_SECS).map_err(|e| e.to_string()))
+    {
+        Ok(pending) => pending,
+        Err(e) => {
+            error!("Error loading pending webhook deliveries: {}", e);
+            return;
+        }
+    };
+    if !pending.is_empty() {
+        info!("Resuming {} pending webhook deliveries", pending.len());
+    }
+    for delivery in pending {
+        let context = context.clone();
+        deliveries.spawn(async move {
+            let body = delivery.payload.to_string();
+            send(&context, &delivery.webhook, &delivery.event_kind, delivery.id, &body, delivery.attempts + 1).await
+        });
+    }
+}
+
+/// Logs a delivery of `payload` to a webhook up front, then sends it (see `send`).
+async fn deliver(context: Deliveries, webhook: Webhook, kind: WebhookEventKind, block_number: i32, payload: serde_json::Value) {
+    let new_delivery = NewWebhookDelivery {
+        webhook_id: webhook.id,
+        event_kind: kind.as_str(),
+        block_number,
+        payload: &payload,
+        status: "pending",
+    };
+    let delivery_id = match context
+        .pool
+        .get()
+        .map_err(|e| e.to_string())
+        .and_then(|mut conn| insert_delivery(&mut conn, &new_delivery).map_err(|e| e.to_string()))
+    {
+        Ok(delivery_id) => delivery_id,
+        Err(e) => {
+            error!("Error logging delivery to webhook {}: {}", webhook.id, e);
+            return;
+        }
+    };
+
+    send(&context, &webhook, kind.as_str(), delivery_id, &payload.to_string(), 1).await
+}
+
+/// Sends a logged delivery from attempt `first_attempt` on, retrying network errors, 429s and 5xx
+/// responses with exponential backoff. The delivery row is updated after every attempt.
+async fn send(context: &Deliveries, webhook: &Webhook, event_kind: &str, delivery_id: i32, body: &str, first_attempt: i32) {
+    let signature = sign_payload(&webhook.secret, body.as_bytes());
+    let pool = &context.pool;
+
+    let mut backoff = context.initial_backoff;
+    for _ in 1..first_attempt {
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    for attempt in first_attempt..=MAX_DELIVERY_ATTEMPTS {
+        let result = {
+            // Only hold a permit while the request is in flight, not while backing off
+            let _permit = context.limiter.acquire().await.expect("Delivery limiter is never closed");
+            context
+                .client
+                .post(&webhook.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .header("X-Webhook-Event", event_kind)
+                .header("X-Webhook-Delivery", delivery_id.to_string())
+                .body(body.to_string())
+                .send()
+                .await
+        };
+
+        let (response_status, error_message, retryable) = match result {
+            Ok(response) if response.status().is_success() => {
+                let response_status = Some(response.status().as_u16() as i32);
+                record_attempt(pool, delivery_id, "delivered", attempt, response_status, None, true);
+                return;
+            }
+            Ok(response) => {
+                let status = response.status();
+                let retryable = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                (Some(status.as_u16() as i32), format!("HTTP {}", status), retryable)
+            }
+            Err(e) => (None, e.to_string(), true),
+        };
+
+        if !retryable || attempt == MAX_DELIVERY_ATTEMPTS {
+            warn!("Giving up on delivery {} to webhook {} after {} attempts: {}", delivery_id, webhook.id, attempt, error_message);
+            record_attempt(pool, delivery_id, "failed", attempt, response_status, Some(&error_message), true);
+            return;
+        }
+
+        record_attempt(pool, delivery_id, "pending", attempt, response_status, Some(&error_message), false);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+fn record_attempt(pool: &DbPool, delivery_id: i32, status: &str, attempts: i32, response_status: Option<i32>, error_message: Option<&str>, finished: bool) {
+    let result = pool
+        .get()
+        .map_err(|e| e.to_string())
+        .and_then(|mut conn| record_delivery_attempt(&mut conn, delivery_id, status, attempts, response_status, error_message, finished).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        error!("Error recording attempt {} of delivery {}: {}", attempts, delivery_id, e);
+    }
+}
+
+/// Signs a payload body as `sha256=<hex HMAC-SHA256>`, which receivers recompute with the shared secret.
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Runs a `webhook` subcommand against the database.
+pub fn run_webhook_command(conn: &mut PgPooledConnection, command: &WebhookCommand) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match command {
+        WebhookCommand::Add { url, secret, wallet, token, event, min_amount, chain } => {
+            if let Some(min_amount) = min_amount {
+                U256::from_dec_str(min_amount).map_err(|_| format!("Invalid --min-amount {}: expected a decimal integer", min_amount))?;
+            }
+            let new_webhook = NewWebhook {
+                url,
+                secret,
+                wallet_address: wallet.as_ref().map(Address::as_bytes),
+                token_address: token.as_ref().map(Address::as_bytes),
+                event_kind: event.map(|event| event.as_str()),
+                min_amount: min_amount.as_deref(),
+                chain_id: *chain,
+            };
+            let id = insert_webhook(conn, &new_webhook)?;
+            println!("Registered webhook {}", id);
+        }
+        WebhookCommand::List => {
+            for webhook in load_webhooks(conn, false)? {
+                println!(
+                    "{}\t{}\tchain={}\twallet={}\ttoken={}\tevent={}\tmin_amount={}\t{}",
+                    webhook.id,
+                    webhook.url,
+                    webhook.chain_id.map(|chain_id| chain_id.to_string()).unwrap_or_else(|| "*".to_string()),
+                    webhook.wallet_address.as_deref().map(checksum).unwrap_or_else(|| "*".to_string()),
+                    webhook.token_address.as_deref().map(checksum).unwrap_or_else(|| "*".to_string()),
+                    webhook.event_kind.as_deref().unwrap_or("*"),
+                    webhook.min_amount.as_deref().unwrap_or("0"),
+                    if webhook.active { "active" } else { "inactive" },
+                );
+            }
+        }
+        WebhookCommand::Remove { id } => match delete_webhook(conn, *id)? {
+            0 => return Err(format!("No webhook with id {}", id).into()),
+            _ => println!("Removed webhook {}", id),
+        },
+        WebhookCommand::Deliveries { webhook, limit } => {
+            for delivery in load_recent_deliveries(conn, *webhook, *limit)? {
+                println!(
+                    "{}\twebhook={}\t{}\tblock={}\t{}\tattempts={}\tresponse={}\t{}",
+                    delivery.id,
+                    delivery.webhook_id,
+                    delivery.event_kind,
+                    delivery.block_number,
+                    delivery.status,
+                    delivery.attempts,
+                    delivery.response_status.map(|status| status.to_string()).unwrap_or_else(|| "-".to_string()),
+                    delivery.error.as_deref().unwrap_or(""),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use diesel::dsl::{now, IntervalDsl};
+    use diesel::prelude::*;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::TestDatabase;
+
+    #[test]
+    fn payloads_are_signed_with_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign_payload("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    fn webhook() -> Webhook {
+        Webhook {
+            id: 1,
+            url: String::new(),
+            secret: "secret".to_string(),
+            wallet_address: None,
+            token_address: None,
+            event_kind: None,
+            min_amount: None,
+            active: true,
+            chain_id: None,
+        }
+    }
+
+    #[test]
+    fn webhooks_match_on_every_filter() {
+        let transfer = Notification {
+            kind: WebhookEventKind::Transfer,
+            chain_id: 10,
+            block_number: 5,
+            token: vec![1; 20],
+            parties: vec![vec![2; 20], vec![3; 20]],
+            amount: U256::from(1000),
+            data: serde_json::Value::Null,
+        };
+
+        assert!(transfer.matches(&webhook()));
+        assert!(transfer.matches(&Webhook { chain_id: Some(10), ..webhook() }));
+        assert!(!transfer.matches(&Webhook { chain_id: Some(1), ..webhook() }));
+        assert!(transfer.matches(&Webhook { event_kind: Some("transfer".to_string()), ..webhook() }));
+        assert!(!transfer.matches(&Webhook { event_kind: Some("approval".to_string()), ..webhook() }));
+        assert!(transfer.matches(&Webhook { token_address: Some(vec![1; 20]), ..webhook() }));
+        assert!(!transfer.matches(&Webhook { token_address: Some(vec![4; 20]), ..webhook() }));
+        assert!(transfer.matches(&Webhook { wallet_address: Some(vec![3; 20]), ..webhook() }));
+        assert!(!transfer.matches(&Webhook { wallet_address: Some(vec![4; 20]), ..webhook() }));
+        assert!(transfer.matches(&Webhook { min_amount: Some("1000".to_string()), ..webhook() }));
+        assert!(!transfer.matches(&Webhook { min_amount: Some("1001".to_string()), ..webhook() }));
+    }
+
+    // A webhook endpoint answering 503 to its first `failures` requests and 200 to the others; returns its
+    // URL and the signature header and body of every request it received
+    async fn endpoint(failures: usize) -> (String, Arc<Mutex<Vec<(String, String)>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: String| async move {
+                let mut requests = received.lock().unwrap();
+                requests.push((headers[SIGNATURE_HEADER].to_str().unwrap().to_string(), body));
+                if requests.len() <= failures { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, requests)
+    }
+
+    fn register(database: &TestDatabase, url: &str) -> Webhook {
+        let conn = &mut database.pool.get().unwrap();
+        let new_webhook = NewWebhook { url, secret: "secret", wallet_address: None, token_address: None, event_kind: None, min_amount: None, chain_id: None };
+        insert_webhook(conn, &new_webhook).unwrap();
+        load_webhooks(conn, true).unwrap().remove(0)
+    }
+
+    #[tokio::test]
+    async fn failed_attempts_are_retried_and_logged() {
+        let Some(database) = TestDatabase::create("webhook_retries") else { return };
+        let (url, requests) = endpoint(2).await;
+        let webhook = register(&database, &url);
+
+        let context = Deliveries::new(database.pool.clone(), Duration::from_millis(10));
+        deliver(context, webhook, WebhookEventKind::Transfer, 7, json!({ "value": "1" })).await;
+
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 3);
+        for (signature, body) in &requests {
+            assert_eq!(*signature, sign_payload("secret", body.as_bytes()));
+        }
+        let deliveries = load_recent_deliveries(&mut database.pool.get().unwrap(), None, 10).unwrap();
+        assert_eq!(deliveries.len(), 1);
+        let delivery = &deliveries[0];
+        assert_eq!((delivery.status.as_str(), delivery.attempts, delivery.response_status, delivery.block_number), ("delivered", 3, Some(200), 7));
+        assert_eq!(delivery.error, None);
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_given_up_on_after_the_last_attempt() {
+        let Some(database) = TestDatabase::create("webhook_give_up") else { return };
+        let (url, requests) = endpoint(usize::MAX).await;
+        let webhook = register(&database, &url);
+
+        let context = Deliveries::new(database.pool.clone(), Duration::from_millis(10));
+        deliver(context, webhook, WebhookEventKind::Supply, 7, json!({})).await;
+
+        assert_eq!(requests.lock().unwrap().len(), MAX_DELIVERY_ATTEMPTS as usize);
+        let delivery = load_recent_deliveries(&mut database.pool.get().unwrap(), None, 10).unwrap().remove(0);
+        assert_eq!((delivery.status.as_str(), delivery.attempts, delivery.response_status), ("failed", MAX_DELIVERY_ATTEMPTS, Some(503)));
+        assert_eq!(delivery.error.as_deref(), Some("HTTP 503 Service Unavailable"));
+    }
+
+    #[tokio::test]
+    async fn stale_pending_deliveries_are_resumed() {
+        use crate::schema::webhook_deliveries::dsl::*;
+
+        let Some(database) = TestDatabase::create("webhook_resume") else { return };
+        let (url, requests) = endpoint(0).await;
+        let webhook = register(&database, &url);
+        let conn = &mut database.pool.get().unwrap();
+        let payload_value = json!({ "value": "1" });
+        let new_delivery = NewWebhookDelivery { webhook_id: webhook.id, event_kind: "transfer", block_number: 7, payload: &payload_value, status: "pending" };
+        // Left behind after two attempts by a scraper that stopped an hour ago, and being retried by another
+        let stale = insert_delivery(conn, &new_delivery).unwrap();
+        record_delivery_attempt(conn, stale, "pending", 2, Some(503), Some("HTTP 503 Service Unavailable"), false).unwrap();
+        diesel::update(webhook_deliveries.filter(id.eq(stale))).set(attempted_at.eq(now - 1.hours())).execute(conn).unwrap();
+        let retrying = insert_delivery(conn, &new_delivery).unwrap();
+
+        let context = Deliveries::new(database.pool.clone(), Duration::from_millis(10));
+        let mut deliveries = JoinSet::new();
+        resume_stale_deliveries(&context, &mut deliveries);
+        while deliveries.join_next().await.is_some() {}
+
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&requests[0].1).unwrap(), payload_value);
+        let rows: Vec<(i32, String, i32)> = webhook_deliveries.order_by(id.asc()).select((id, status, attempts)).load(conn).unwrap();
+        assert_eq!(rows, vec![(stale, "delivered".to_string(), 3), (retrying, "pending".to_string(), 0)]);
+    }
+}